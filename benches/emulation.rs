@@ -0,0 +1,77 @@
+// headless speed on a looping ROM, `cargo bench`. std only: each case runs a
+// few times and reports the best, which is the least disturbed by the rest of
+// the machine
+//
+//   instructions/s  CPU, memory and peripherals, without frame handling around it
+//   frames/s        whole frames as the frontend runs them, both renderers
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use gb_rust::ppu::Renderer;
+use gb_rust::GB;
+
+const RUNS: usize = 5;
+const INSTRUCTIONS: u64 = 2_000_000;
+const FRAMES: u64 = 300;
+
+// LCD on with BG and sprites, then adds over C000-C03F forever: loads, stores,
+// ALU, conditional and plain jumps
+const PROGRAM: &[u8] = &[
+    0x3e, 0x93, //       ld a, $93
+    0xe0, 0x40, //       ldh ($40), a
+    0x21, 0x00, 0xc0, // loop: ld hl, $c000
+    0x06, 0x40, //       ld b, $40
+    0x7e, //             inner: ld a, (hl)
+    0x80, //             add a, b
+    0x22, //             ld (hl+), a
+    0x05, //             dec b
+    0x20, 0xfa, //       jr nz, inner
+    0x18, 0xf3, //       jr loop
+];
+
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + PROGRAM.len()].copy_from_slice(PROGRAM);
+    rom
+}
+
+// best of RUNS
+fn measure(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let rom = rom();
+
+    let time = measure(|| {
+        let mut gb = GB::new(&rom).unwrap();
+        for _ in 0..INSTRUCTIONS {
+            black_box(gb.cycle());
+        }
+    });
+    println!("instructions/s  {:>12.0}", INSTRUCTIONS as f64 / time.as_secs_f64());
+
+    for renderer in [Renderer::Scanline, Renderer::Fifo] {
+        let time = measure(|| {
+            let mut gb = GB::new(&rom).unwrap();
+            gb.ppu_mut().set_renderer(renderer);
+            gb.run_frames(FRAMES);
+            black_box(gb.screenshot());
+        });
+        let per_frame = time / FRAMES as u32;
+        println!(
+            "frames/s        {:>12.0}  {:?} per frame, {} renderer",
+            FRAMES as f64 / time.as_secs_f64(),
+            per_frame,
+            renderer.name()
+        );
+    }
+}
@@ -0,0 +1,254 @@
+// kiosk mode: pick games from a folder, every game resumes where it was left.
+// the chooser is drawn on the --raw-frames stream, up and down pick a game and A
+// or Start plays it, as does its number on stdin. games run in the normal play
+// loop and the menu button (or `menu` on stdin) comes back here. the folder is
+// rescanned once a second so dropped in ROMs show up. a game that can't start
+// (a broken ROM, save or freeze file) says so on the chooser and on stderr, the
+// kiosk keeps running
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use gb_rust::osd::{self, LINE_HEIGHT};
+use gb_rust::palette::{Palette, PaletteSettings};
+use gb_rust::{ppu, savestate, Buttons};
+
+use crate::cli::Options;
+use crate::emulation::{self, Command, Emulation, Ended, Outputs};
+
+const EXTENSIONS: [&str; 2] = ["gb", "gbc"];
+// smaller files end before the cartridge header
+const MIN_ROM: u64 = 0x0150;
+// chooser frames between scans of the folder
+const RESCAN_FRAMES: u64 = 60;
+
+pub fn scan(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+        let rom_size = entry.metadata().is_ok_and(|m| m.is_file() && m.len() >= MIN_ROM);
+        if rom_size && extension.is_some_and(|e| EXTENSIONS.contains(&e.as_str())) {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+// the chooser until quit, on the emulation thread: commands come from the UI thread
+pub fn run(
+    dir: &Path,
+    options: &Options,
+    save_dir: &Path,
+    bootrom: Option<&[u8]>,
+    palettes: &PaletteSettings,
+    outputs: &mut Outputs,
+    commands: &Receiver<Command>,
+) -> Result<(), String> {
+    let (width, height) = options.hardware.display_size();
+    let frame = Duration::from_secs_f64(ppu::FRAME_DOTS as f64 / options.hardware.clock_hz() as f64);
+    let title = dir.display().to_string();
+    let mut selected = 0;
+    let mut held = Buttons::empty();
+    let mut roms = Vec::new();
+    // why the last game didn't start, shown until the next input
+    let mut status: Option<String> = None;
+    for frames in 0u64.. {
+        if frames.is_multiple_of(RESCAN_FRAMES) {
+            roms = scan(dir).map_err(|e| format!("can't read {}: {}", dir.display(), e))?;
+            selected = selected.min(roms.len().saturating_sub(1));
+        }
+        if let Some(out) = outputs.frames.as_mut() {
+            let games: Vec<(String, bool)> = roms
+                .iter()
+                .map(|rom| {
+                    let name = rom.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                    (name, savestate::default_path(save_dir, &rom.to_string_lossy()).exists())
+                })
+                .collect();
+            *out.back() = draw(&title, &games, selected, status.as_deref(), palettes.dmg(), (width, height));
+            out.publish();
+        }
+        let chosen = match commands.recv_timeout(frame) {
+            Ok(Command::Quit) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            Ok(Command::Input([now, _])) => {
+                status = None;
+                let pressed = now - held;
+                held = now;
+                if pressed.contains(Buttons::UP) {
+                    selected = selected.saturating_sub(1);
+                }
+                if pressed.contains(Buttons::DOWN) {
+                    selected = (selected + 1).min(roms.len().saturating_sub(1));
+                }
+                pressed.intersects(Buttons::A | Buttons::START).then_some(selected)
+            }
+            Ok(Command::Line(line)) => match line.trim() {
+                "q" => return Ok(()),
+                "" => None,
+                choice => match choice.parse::<usize>() {
+                    Ok(n) if (1..=roms.len()).contains(&n) => Some(n - 1),
+                    _ => {
+                        eprintln!("gb-rust: no game {}", choice);
+                        None
+                    }
+                },
+            },
+            Ok(_) | Err(RecvTimeoutError::Timeout) => None,
+        };
+        let Some(rom) = chosen.and_then(|i| roms.get(i)) else {
+            continue;
+        };
+        match play(rom, options, save_dir, bootrom, palettes, outputs, commands) {
+            Ok(Ended::Quit) => return Ok(()),
+            Ok(Ended::Menu) => {}
+            Err(e) => {
+                eprintln!("gb-rust: {}", e);
+                let name = rom.file_stem().unwrap_or_default().to_string_lossy();
+                status = Some(format!("can't play {}", name));
+            }
+        }
+        // the game took the input since, nothing is held as far as the chooser knows
+        held = Buttons::empty();
+        roms = scan(dir).map_err(|e| format!("can't read {}: {}", dir.display(), e))?;
+    }
+    Ok(())
+}
+
+// like a game given on the command line, with its battery save, cheats and the
+// rest, resumed from and saved to <rom>.state
+fn play(
+    rom: &Path,
+    options: &Options,
+    save_dir: &Path,
+    bootrom: Option<&[u8]>,
+    palettes: &PaletteSettings,
+    outputs: &mut Outputs,
+    commands: &Receiver<Command>,
+) -> Result<Ended, String> {
+    let name = rom.to_string_lossy().into_owned();
+    let data = crate::read_rom(&name)?;
+    let (mut session, script) = crate::open_session(&[(name.clone(), data)], options, save_dir, bootrom)?;
+    let path = savestate::default_path(save_dir, &name);
+    // a broken or foreign state shouldn't lock the game, start it fresh instead
+    if let Ok(state) = fs::read(&path) {
+        match savestate::load(session.active_mut(), &state) {
+            Ok(()) => eprintln!("resumed {}", path.display()),
+            Err(e) => eprintln!("starting fresh, {}: {}", path.display(), e),
+        }
+    }
+    eprintln!("playing {}, the menu button or `menu` returns to the chooser", name);
+    let mut game = Emulation::new(&mut session, script, options, save_dir, palettes, outputs);
+    game.arcade(path.clone());
+    let ended = game.run(commands);
+    if let Err(e) = emulation::autosave(session.active(), &path) {
+        eprintln!("gb-rust: {}", e);
+    }
+    crate::finish(options, &mut session, save_dir);
+    Ok(ended)
+}
+
+// the chooser's frame: the folder on top, the games below it scrolled to keep
+// the selected one in view, the ones with a state marked (saved). status
+// replaces the help at the bottom
+fn draw(
+    title: &str,
+    games: &[(String, bool)],
+    selected: usize,
+    status: Option<&str>,
+    palette: &Palette,
+    (width, height): (usize, usize),
+) -> Vec<u8> {
+    let [paper, _, _, ink] = palette.shades;
+    let mut rgb: Vec<u8> = paper.iter().copied().cycle().take(width * height * 3).collect();
+    let line = LINE_HEIGHT as i64;
+    osd::draw_text(&mut rgb, width, 2, 2, title, &ink);
+    osd::fill(&mut rgb, width, 0, line + 3, width as i64, 1, &ink);
+    let top = 2 * line + 1;
+    let footer = height as i64 - line - 1;
+    let rows = ((footer - top - 2) / line).max(1) as usize;
+    if games.is_empty() {
+        osd::draw_text(&mut rgb, width, 2, top, "no games in the folder yet", &ink);
+    }
+    let first = (selected + 1).saturating_sub(rows);
+    for (i, (name, saved)) in games.iter().enumerate().skip(first).take(rows) {
+        let y = top + (i - first) as i64 * line;
+        let text = format!("{:>2} {}{}", i + 1, name, if *saved { " (saved)" } else { "" });
+        let color = match i == selected {
+            true => {
+                osd::fill(&mut rgb, width, 0, y - 1, width as i64, line, &ink);
+                paper
+            }
+            false => ink,
+        };
+        osd::draw_text(&mut rgb, width, 2, y, &text, &color);
+    }
+    osd::draw_text(&mut rgb, width, 2, footer, status.unwrap_or("up/down to pick, A to play"), &ink);
+    rgb
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn scan_lists_roms_sorted() {
+        let dir = env::temp_dir().join(format!("gb-rust-arcade-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub.gb")).unwrap();
+        for file in ["zelda.gb", "Alleyway.GB", "tetris.gbc", "notes.txt"] {
+            fs::write(dir.join(file), [0; MIN_ROM as usize]).unwrap();
+        }
+        // not even a header
+        fs::write(dir.join("empty.gb"), b"").unwrap();
+        let names: Vec<_> = scan(&dir).unwrap().iter().map(|p| p.file_name().unwrap().to_owned()).collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, ["Alleyway.GB", "tetris.gbc", "zelda.gb"]);
+    }
+
+    #[test]
+    fn draws_the_chooser_scrolled_to_the_selected_game() {
+        let palettes = PaletteSettings::default();
+        let [paper, _, _, ink] = palettes.dmg().shades;
+        let (width, height) = (ppu::WIDTH, ppu::HEIGHT);
+        let games: Vec<(String, bool)> = (1..=40).map(|n| (format!("game {}", n), n == 40)).collect();
+        let pixel = |rgb: &[u8], x: usize, y: usize| -> [u8; 3] { rgb[(y * width + x) * 3..][..3].try_into().unwrap() };
+        let rgb = draw("roms", &games, 0, None, palettes.dmg(), (width, height));
+        assert_eq!(rgb.len(), width * height * 3);
+        // the selected row is a bar in the text color, the rest is paper
+        let top = 2 * LINE_HEIGHT + 1;
+        assert_eq!(pixel(&rgb, width - 1, top), ink);
+        assert_eq!(pixel(&rgb, width - 1, top + LINE_HEIGHT), paper);
+        // the last game selected scrolls it to the bottom row, the first row is no longer it
+        let rgb = draw("roms", &games, 39, None, palettes.dmg(), (width, height));
+        assert_eq!(pixel(&rgb, width - 1, top), paper);
+        let rows = (height - LINE_HEIGHT - 1 - top - 2) / LINE_HEIGHT;
+        assert_eq!(pixel(&rgb, width - 1, top + (rows - 1) * LINE_HEIGHT), ink);
+        let empty = draw("roms", &[], 0, None, palettes.dmg(), (width, height));
+        assert_ne!(empty, draw("", &[], 0, None, palettes.dmg(), (width, height)));
+        assert_ne!(empty, draw("roms", &[], 0, Some("can't play zelda"), palettes.dmg(), (width, height)));
+    }
+
+    #[test]
+    fn a_broken_game_goes_back_to_the_chooser() {
+        let dir = env::temp_dir().join(format!("gb-rust-arcade-broken-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x42;
+        let path = dir.join("broken.gb");
+        fs::write(&path, &rom).unwrap();
+        let dir_arg = dir.to_string_lossy().into_owned();
+        let options = crate::cli::parse(["--arcade", &dir_arg, "--raw-frames"].map(String::from)).unwrap();
+        let mut outputs = Outputs { frames: None, vram: None };
+        let (_tx, commands) = std::sync::mpsc::channel();
+        let played = play(&path, &options, &dir, None, &PaletteSettings::default(), &mut outputs, &commands);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(played, Err("unknown cartridge type 42 in header".to_string()));
+    }
+}
@@ -0,0 +1,274 @@
+// tiny SM83 assembler for building micro-ROMs without shipping binary test assets
+//
+// syntax: one instruction per line, `label:` definitions, `; comments`,
+// numbers as $ff / 0xff / %1010 / 255, and `db` / `dw` / `org` directives
+
+use std::collections::HashMap;
+
+use crate::opcodes::{CB_OPCODES, OPCODES};
+
+const ROM_SIZE: usize = 0x8000;
+const ENTRY_POINT: u16 = 0x0100;
+
+const RESERVED: [&str; 17] = [
+    "A", "B", "C", "D", "E", "H", "L", "AF", "BC", "DE", "HL", "SP", "NZ", "Z", "NC", "HL+", "HL-",
+];
+
+enum Item<'a> {
+    Instr {
+        opcode: u8,
+        cb: bool,
+        length: u8,
+        // immediate operand and its template placeholder
+        operand: Option<(&'a str, &'static str)>,
+        relative: bool,
+    },
+    Bytes(Vec<&'a str>),
+    Words(Vec<&'a str>),
+    Org(&'a str),
+}
+
+// 32KiB ROM with code assembled at the 0100 entry point, panics on errors
+pub fn micro_rom(source: &str) -> Vec<u8> {
+    let code = assemble(source, ENTRY_POINT).unwrap_or_else(|e| panic!("{}", e));
+    let mut rom = vec![0; ROM_SIZE];
+    let start = ENTRY_POINT as usize;
+    assert!(start + code.len() <= ROM_SIZE, "Micro-ROM does not fit in 32KiB");
+    rom[start..start + code.len()].copy_from_slice(&code);
+    rom
+}
+
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+
+    // pass 1 - pick opcodes, their sizes give label addresses
+    let mut addr = origin as u32;
+    for (n, line) in source.lines().enumerate() {
+        let err = |msg: String| format!("line {}: {}", n + 1, msg);
+        let mut line = line.split(';').next().unwrap().trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            labels.insert(label.trim().to_ascii_uppercase(), addr);
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = match line.split_once(char::is_whitespace) {
+            Some((mnemonic, rest)) => (mnemonic.to_ascii_uppercase(), split_operands(rest)),
+            None => (line.to_ascii_uppercase(), vec![]),
+        };
+        let item = match mnemonic.as_str() {
+            "DB" => Item::Bytes(operands),
+            "DW" => Item::Words(operands),
+            "ORG" => match operands[..] {
+                [target] => {
+                    let target = eval(target, &HashMap::new()).map_err(err)?;
+                    if target < addr as i32 {
+                        return Err(err(format!("org {:#06x} is behind current address", target)));
+                    }
+                    Item::Org(operands[0])
+                }
+                _ => return Err(err("org expects one address".to_string())),
+            },
+            _ => find_opcode(&mnemonic, &operands).ok_or_else(|| err(format!("unknown instruction `{}`", line)))?,
+        };
+        addr = match &item {
+            Item::Instr { length, .. } => addr + *length as u32,
+            Item::Bytes(values) => addr + values.len() as u32,
+            Item::Words(values) => addr + 2 * values.len() as u32,
+            Item::Org(target) => eval(target, &labels).map_err(err)? as u32,
+        };
+        items.push((n, item));
+    }
+
+    // pass 2 - encode
+    let mut out = Vec::new();
+    for (n, item) in items {
+        let err = |msg: String| format!("line {}: {}", n + 1, msg);
+        let addr = origin as i32 + out.len() as i32;
+        match item {
+            Item::Instr { opcode, cb, operand, relative, .. } => {
+                if cb {
+                    out.push(0xcb);
+                }
+                out.push(opcode);
+                if let Some((expr, placeholder)) = operand {
+                    let mut value = eval(expr, &labels).map_err(err)?;
+                    if relative {
+                        value -= addr + 2;
+                        if !(-128..=127).contains(&value) {
+                            return Err(err(format!("jump target out of range by {}", value)));
+                        }
+                    }
+                    // LDH accepts both $40 and $ff40
+                    if placeholder == "a8" && (0xff00..=0xffff).contains(&value) {
+                        value -= 0xff00;
+                    }
+                    encode(&mut out, value, size(placeholder)).map_err(err)?;
+                }
+            }
+            Item::Bytes(values) => {
+                for value in values {
+                    encode(&mut out, eval(value, &labels).map_err(err)?, 1).map_err(err)?;
+                }
+            }
+            Item::Words(values) => {
+                for value in values {
+                    encode(&mut out, eval(value, &labels).map_err(err)?, 2).map_err(err)?;
+                }
+            }
+            Item::Org(target) => {
+                let target = eval(target, &labels).map_err(err)?;
+                out.resize((target - origin as i32) as usize, 0);
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn split_operands(operands: &str) -> Vec<&str> {
+    operands.split(',').map(str::trim).filter(|o| !o.is_empty()).collect()
+}
+
+fn find_opcode<'a>(mnemonic: &str, operands: &[&'a str]) -> Option<Item<'a>> {
+    let tables = [(false, &OPCODES), (true, &CB_OPCODES)];
+    for (cb, table) in tables {
+        for (opcode, entry) in table.iter().enumerate() {
+            let template = entry.mnemonic;
+            let (name, params) = match template.split_once(' ') {
+                Some((name, params)) => (name, params.split(',').collect()),
+                None => (template, vec![]),
+            };
+            if name != mnemonic || params.len() != operands.len() || template.is_empty() {
+                continue;
+            }
+            let mut operand = None;
+            let matched = params.iter().zip(operands).all(|(param, source)| {
+                match match_operand(param, source) {
+                    Some(Some(immediate)) => {
+                        operand = Some(immediate);
+                        true
+                    }
+                    Some(None) => true,
+                    None => false,
+                }
+            });
+            if matched {
+                return Some(Item::Instr {
+                    opcode: opcode as u8,
+                    cb,
+                    length: entry.length,
+                    operand,
+                    relative: name == "JR",
+                });
+            }
+        }
+    }
+    None
+}
+
+// None - no match, Some(None) - literal match, Some(Some(..)) - immediate placeholder match
+fn match_operand<'a>(param: &'static str, source: &'a str) -> Option<Option<(&'a str, &'static str)>> {
+    let source = source.trim();
+    for placeholder in ["d16", "a16", "d8", "a8", "r8"] {
+        if let Some((prefix, suffix)) = param.split_once(placeholder) {
+            let upper = source.to_ascii_uppercase();
+            // SP+r8 also takes SP-n
+            let negative = prefix.ends_with('+') && upper.starts_with(&prefix.replace('+', "-"));
+            if !(upper.starts_with(prefix) || negative) || !upper.ends_with(suffix) {
+                return None;
+            }
+            let start = if negative { prefix.len() - 1 } else { prefix.len() };
+            let inner = source.get(start..source.len() - suffix.len())?.trim();
+            if !is_expression(inner) {
+                return None;
+            }
+            return Some(Some((inner, placeholder)));
+        }
+    }
+    let literal = source.eq_ignore_ascii_case(param)
+        || matches!((eval(param, &HashMap::new()), eval(source, &HashMap::new())), (Ok(a), Ok(b)) if a == b);
+    literal.then_some(None)
+}
+
+// number or label, registers are not
+fn is_expression(expr: &str) -> bool {
+    let expr = expr.strip_prefix('-').unwrap_or(expr);
+    let word = expr.strip_prefix(['$', '%']).unwrap_or(expr);
+    !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED.contains(&expr.to_ascii_uppercase().as_str())
+}
+
+fn size(placeholder: &str) -> u32 {
+    match placeholder {
+        "d16" | "a16" => 2,
+        _ => 1,
+    }
+}
+
+fn eval(expr: &str, labels: &HashMap<String, u32>) -> Result<i32, String> {
+    let expr = expr.trim();
+    if let Some(rest) = expr.strip_prefix('-') {
+        return eval(rest, labels).map(|v| -v);
+    }
+    let parsed = if let Some(hex) = expr.strip_prefix('$').or_else(|| expr.strip_prefix("0x")) {
+        i32::from_str_radix(hex, 16)
+    } else if let Some(bin) = expr.strip_prefix('%') {
+        i32::from_str_radix(bin, 2)
+    } else {
+        expr.parse()
+    };
+    match parsed {
+        Ok(value) => Ok(value),
+        Err(_) => match labels.get(&expr.to_ascii_uppercase()) {
+            Some(&addr) => Ok(addr as i32),
+            None => Err(format!("cannot evaluate `{}`", expr)),
+        },
+    }
+}
+
+fn encode(out: &mut Vec<u8>, value: i32, size: u32) -> Result<(), String> {
+    let (min, max) = match size {
+        1 => (-0x80, 0xff),
+        _ => (-0x8000, 0xffff),
+    };
+    if !(min..=max).contains(&value) {
+        return Err(format!("value {} does not fit in {} byte(s)", value, size));
+    }
+    out.push(value as u8);
+    if size == 2 {
+        out.push((value >> 8) as u8);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_operands() {
+        let code = assemble("ld a, $91\nldh ($ff40), a\nld hl, $c000\nld (hl+), a\nbit 7, (hl)", 0).unwrap();
+        assert_eq!(code, [0x3e, 0x91, 0xe0, 0x40, 0x21, 0x00, 0xc0, 0x22, 0xcb, 0x7e]);
+    }
+
+    #[test]
+    fn resolves_labels() {
+        let code = assemble("loop:\n  dec b\n  jr nz, loop\n  jp end\nend: rst $38", 0x0150).unwrap();
+        assert_eq!(code, [0x05, 0x20, 0xfd, 0xc3, 0x56, 0x01, 0xff]);
+    }
+
+    #[test]
+    fn distinguishes_registers_from_addresses() {
+        let code = assemble("ld a, (c)\nld a, (hl)\nld a, ($8000)\nld hl, sp-2", 0).unwrap();
+        assert_eq!(code, [0xf2, 0x7e, 0xfa, 0x00, 0x80, 0xf8, 0xfe]);
+    }
+
+    #[test]
+    fn reports_errors_with_line() {
+        assert_eq!(assemble("nop\nld a, b, c", 0).unwrap_err(), "line 2: unknown instruction `ld a, b, c`");
+        assert!(assemble("jr far\norg $200\nfar:", 0).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+// keeps the buffer between emulated audio and the sound device centered. the
+// emulated clock and the device's crystal never quite agree, so a fixed
+// resample ratio slowly fills the buffer (latency builds up over a session) or
+// drains it (crackles). a PI controller instead nudges the ratio by at most
+// half a percent, too little to hear as a pitch change.
+//
+// for a frontend or embedder with audio output: it reports the samples it
+// produced and the device consumed, resamples by ratio() and calls update()
+// once a frame. diagnostics() is the drift and correction, to show the user
+
+use std::fmt;
+
+// largest ratio correction either way
+const MAX_CORRECTION: f64 = 0.005;
+// proportional gain, a buffer empty or twice the target gets the full correction
+const KP: f64 = MAX_CORRECTION;
+// integral gain per update, cancels a constant clock mismatch
+const KI: f64 = MAX_CORRECTION / 1000.0;
+
+pub struct AudioSync {
+    // buffered samples to keep, i.e. the latency aimed for
+    target: u64,
+    ratio: f64,
+    integral: f64,
+    produced: u64,
+    consumed: u64,
+    underruns: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Diagnostics {
+    pub produced: u64,
+    pub consumed: u64,
+    pub buffered: u64,
+    // buffered minus target, positive means latency built up
+    pub drift: i64,
+    // current resample ratio correction, parts per million
+    pub correction_ppm: f64,
+    // times the device wanted more than was buffered
+    pub underruns: u64,
+}
+
+impl AudioSync {
+    // a target of 0 samples is taken as 1
+    pub fn new(target: u64) -> Self {
+        AudioSync {
+            target: target.max(1),
+            ratio: 1.0,
+            integral: 0.0,
+            produced: 0,
+            consumed: 0,
+            underruns: 0,
+        }
+    }
+
+    // output samples of the resampler, counted after applying ratio()
+    pub fn produced(&mut self, samples: u64) {
+        self.produced += samples;
+    }
+
+    // samples the device played, it plays silence for what isn't buffered
+    pub fn consumed(&mut self, samples: u64) {
+        let buffered = self.buffered();
+        if samples > buffered {
+            self.underruns += 1;
+        }
+        self.consumed += samples.min(buffered);
+    }
+
+    pub fn buffered(&self) -> u64 {
+        self.produced - self.consumed
+    }
+
+    // multiply the resampler's output rate by this
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    // call once per frame
+    pub fn update(&mut self) {
+        let error = (self.buffered() as f64 - self.target as f64) / self.target as f64;
+        // anti-windup: the integral alone never asks for more than the limit
+        self.integral = (self.integral + error).clamp(-MAX_CORRECTION / KI, MAX_CORRECTION / KI);
+        let correction = (KP * error + KI * self.integral).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.ratio = 1.0 - correction;
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            produced: self.produced,
+            consumed: self.consumed,
+            buffered: self.buffered(),
+            drift: self.buffered() as i64 - self.target as i64,
+            correction_ppm: (self.ratio - 1.0) * 1e6,
+            underruns: self.underruns,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "audio: {} buffered ({:+} from target), ratio {:+.0} ppm, {} produced, {} consumed, {} underruns",
+            self.buffered, self.drift, self.correction_ppm, self.produced, self.consumed, self.underruns
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // emulation 0.2% fast against a 48kHz device, 100ms buffer, 10 minutes
+    #[test]
+    fn corrects_clock_mismatch() {
+        let mut sync = AudioSync::new(4800);
+        sync.produced(4800);
+        let mut fractional = 0.0;
+        for _ in 0..60 * 60 * 10 {
+            fractional += 800.0 * 1.002 * sync.ratio();
+            sync.produced(fractional as u64);
+            fractional = fractional.fract();
+            sync.consumed(800);
+            sync.update();
+        }
+        let stats = sync.diagnostics();
+        // uncorrected it would have drifted by 57600 samples
+        assert!(stats.drift.abs() < 100, "{}", stats);
+        assert!((stats.correction_ppm + 1996.0).abs() < 50.0, "{}", stats);
+        assert_eq!(stats.underruns, 0);
+    }
+
+    // the device's clock 0.3% fast this time, and a burst of late frames
+    #[test]
+    fn recovers_from_underruns_and_a_fast_device() {
+        let mut sync = AudioSync::new(1600);
+        sync.produced(1600);
+        let mut fractional = 0.0;
+        for frame in 0..60 * 60 * 5 {
+            if !(1000..1010).contains(&frame) {
+                fractional += 800.0 * sync.ratio();
+                sync.produced(fractional as u64);
+                fractional = fractional.fract();
+            }
+            sync.consumed(802);
+            sync.update();
+        }
+        let stats = sync.diagnostics();
+        assert!(stats.drift.abs() < 100, "{}", stats);
+        assert!(stats.correction_ppm > 2000.0 && stats.correction_ppm <= MAX_CORRECTION * 1e6, "{}", stats);
+        assert!(stats.underruns > 0);
+        assert_eq!(AudioSync::new(0).diagnostics().drift, -1);
+    }
+
+    #[test]
+    fn counts_underruns() {
+        let mut sync = AudioSync::new(100);
+        sync.produced(50);
+        sync.consumed(80);
+        sync.update();
+        let stats = sync.diagnostics();
+        assert_eq!((stats.buffered, stats.drift, stats.underruns), (0, -100, 1));
+        // empty buffer speeds production up by the full correction
+        assert_eq!(sync.ratio(), 1.0 + MAX_CORRECTION);
+    }
+}
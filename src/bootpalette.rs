@@ -0,0 +1,59 @@
+// which of the CGB boot ROM's palettes in palette.rs it colors a DMG game with.
+// the boot ROM only does that for Nintendo's own games and goes by the sum of
+// the title's bytes, with the fourth letter telling titles of the same sum apart
+
+// title as in the header, palette
+const GAMES: [(&str, &str); 3] = [
+    ("POKEMON RED", "cgb-red"),
+    ("POKEMON BLUE", "cgb-blue"),
+    ("POKEMON GREEN", "cgb-green"),
+];
+
+fn title_hash(title: &[u8]) -> u8 {
+    title.iter().fold(0, |hash, &b| hash.wrapping_add(b))
+}
+
+// [014B] old licensee 01, or 33 and the new licensee [0144-0145] "01"
+fn by_nintendo(rom_data: &[u8]) -> bool {
+    match rom_data[0x014b] {
+        0x01 => true,
+        0x33 => &rom_data[0x0144..0x0146] == b"01",
+        _ => false,
+    }
+}
+
+// the palette's name, None for games the boot ROM leaves to the buttons held
+pub fn lookup(rom_data: &[u8]) -> Option<&'static str> {
+    let title = &rom_data[0x0134..0x0144];
+    if !by_nintendo(rom_data) {
+        return None;
+    }
+    GAMES
+        .iter()
+        .find(|(game, _)| title_hash(game.as_bytes()) == title_hash(title) && game.as_bytes()[3] == title[3])
+        .map(|&(_, palette)| palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::PaletteSettings;
+
+    #[test]
+    fn finds_nintendo_games_by_title_hash() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x013f].copy_from_slice(b"POKEMON RED");
+        assert_eq!(lookup(&rom), None, "not by Nintendo");
+        rom[0x014b] = 0x01;
+        assert_eq!(lookup(&rom), Some("cgb-red"));
+        rom[0x014b] = 0x33;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        assert_eq!(lookup(&rom), Some("cgb-red"));
+        // the same sum, the fourth letter differs
+        rom[0x0134..0x013f].copy_from_slice(b"POKMEON RED");
+        assert_eq!(lookup(&rom), None);
+        for (title, palette) in GAMES {
+            assert!(PaletteSettings::default().select(palette), "{}", title);
+        }
+    }
+}
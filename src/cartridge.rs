@@ -0,0 +1,123 @@
+// cartridge header [0100-014F]
+pub struct Header {
+    pub title: String,
+    // [0143] CGB flag - 0x80 CGB enhanced, 0xC0 CGB only
+    pub cgb: bool,
+    // [0146] = 03 with [014B] = 33, the game uses Super Game Boy functions
+    pub sgb: bool,
+    // [0147] mapper and extra hardware
+    pub cartridge_type: u8,
+    // [0148] 32KiB << n
+    pub rom_size: usize,
+    // [0149]
+    pub ram_size: usize,
+    // [014D]
+    pub checksum: u8,
+}
+
+impl Header {
+    pub fn parse(rom_data: &[u8]) -> Self {
+        let title = rom_data[0x0134..0x0144]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .filter(|c| c.is_ascii_graphic() || **c == b' ')
+            .map(|&c| c as char)
+            .collect::<String>();
+        Header {
+            title: title.trim_end().to_string(),
+            cgb: rom_data[0x0143] & 0x80 != 0,
+            sgb: rom_data[0x0146] == 0x03 && rom_data[0x014b] == 0x33,
+            cartridge_type: rom_data[0x0147],
+            rom_size: 0x8000 << rom_data[0x0148].min(8),
+            ram_size: match rom_data[0x0149] {
+                0x02 => 0x2000,
+                0x03 => 0x8000,
+                0x04 => 0x20000,
+                0x05 => 0x10000,
+                _ => 0,
+            },
+            checksum: rom_data[0x014d],
+        }
+    }
+
+    pub fn mapper(&self) -> &'static str {
+        mapper(self.cartridge_type)
+    }
+
+    // cartridge RAM kept by a battery while the power is off
+    pub fn battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xfc..=0xff)
+    }
+}
+
+// what the Pan Docs call a [0147] cartridge type, whether emulated or not
+pub fn mapper(cartridge_type: u8) -> &'static str {
+    match cartridge_type {
+        0x00 | 0x08 | 0x09 => "ROM",
+        0x01..=0x03 => "MBC1",
+        0x05 | 0x06 => "MBC2",
+        0x0b..=0x0d => "MMM01",
+        0x0f..=0x13 => "MBC3",
+        0x19..=0x1e => "MBC5",
+        0x20 => "MBC6",
+        0x22 => "MBC7",
+        0xfc => "POCKET CAMERA",
+        0xfd => "TAMA5",
+        0xfe => "HuC3",
+        0xff => "HuC1",
+        _ => "UNKNOWN",
+    }
+}
+
+// [0148] values above this are unofficial sizes
+const MAX_ROM_SIZE_CODE: u8 = 8;
+
+// makes a dump the size its header declares: a truncated one is padded with FF,
+// what reading past the end of a real ROM chip gives, an overdump is cut off.
+// returns what was changed, to warn about. errors when the header isn't all there
+pub fn fit_rom(rom: &mut Vec<u8>) -> Result<Option<String>, String> {
+    if rom.len() < 0x0150 {
+        return Err(format!("too small for a ROM ({} bytes, it ends inside the header)", rom.len()));
+    }
+    if rom[0x0148] > MAX_ROM_SIZE_CODE {
+        // no size to go by, only make whole 16KiB banks of at least 32KiB
+        let len = rom.len().max(0x8000).next_multiple_of(0x4000);
+        let message = format!("unknown ROM size {:02X} in header, using the file size", rom[0x0148]);
+        rom.resize(len, 0xff);
+        return Ok(Some(message));
+    }
+    let declared = Header::parse(rom).rom_size;
+    let message = match rom.len() {
+        len if len < declared => format!("truncated dump, {} bytes but the header says {}, padded with FF", len, declared),
+        len if len > declared => format!("overdump, {} bytes but the header says {}, cut to size", len, declared),
+        _ => return Ok(None),
+    };
+    rom.resize(declared, 0xff);
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_rom_to_header_size() {
+        let mut rom = vec![0; 0x8000];
+        assert_eq!(fit_rom(&mut rom), Ok(None));
+
+        // 64KiB declared, half dumped
+        rom[0x0148] = 0x01;
+        assert!(fit_rom(&mut rom).unwrap().unwrap().starts_with("truncated dump, 32768 bytes"));
+        assert_eq!((rom.len(), rom[0x8000]), (0x10000, 0xff));
+
+        rom[0x0148] = 0x00;
+        assert!(fit_rom(&mut rom).unwrap().unwrap().starts_with("overdump"));
+        assert_eq!(rom.len(), 0x8000);
+
+        let mut odd = vec![0; 0x9000];
+        odd[0x0148] = 0x52;
+        assert!(fit_rom(&mut odd).unwrap().is_some());
+        assert_eq!(odd.len(), 0xc000);
+        assert!(fit_rom(&mut vec![0; 0x100]).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+// Game Genie and GameShark codes
+//
+// Game Genie `ABC-DEF` or `ABC-DEF-GHI` patches ROM: reads of address FCDE
+// (F inverted) return AB, with GHI only while the ROM holds the compare value
+// GI rotated right by 2 and XORed with BA. GameShark `BBVVLLHH` pokes VV into
+// RAM at HHLL once per frame, at VBlank. only one cartridge RAM bank is
+// emulated, so its bank byte BB is kept but doesn't pick anything
+//
+// codes are numbered in the order they were added, disabling keeps the number
+
+use std::cell::Cell;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    GameGenie { addr: u16, val: u8, compare: Option<u8> },
+    GameShark { bank: u8, addr: u16, val: u8 },
+}
+
+impl Code {
+    pub fn parse(text: &str) -> Result<Code, String> {
+        let invalid = || format!("invalid cheat `{}`, expected Game Genie ABC-DEF(-GHI) or GameShark 01VVLLHH", text);
+        let digits: Vec<u8> = text
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+        match (digits.len(), text.contains('-')) {
+            (6 | 9, _) => {
+                let addr = u16::from_be_bytes([(digits[5] ^ 0x0f) << 4 | digits[2], digits[3] << 4 | digits[4]]);
+                if addr >= 0x8000 {
+                    return Err(invalid());
+                }
+                let compare = (digits.len() == 9).then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xba);
+                Ok(Code::GameGenie { addr, val: byte(0), compare })
+            }
+            (8, false) => {
+                let addr = u16::from_le_bytes([byte(4), byte(6)]);
+                if addr < 0x8000 || (0xfea0..=0xfeff).contains(&addr) {
+                    return Err(invalid());
+                }
+                Ok(Code::GameShark { bank: byte(0), addr, val: byte(2) })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub struct Cheat {
+    // as entered
+    pub text: String,
+    pub code: Code,
+    pub enabled: bool,
+    // changed what the game saw since the last frame
+    applied: Cell<bool>,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    // any Game Genie code enabled, keeps ROM reads fast without
+    genie: bool,
+}
+
+impl Cheats {
+    // enabled right away, returns the code's number
+    pub fn add(&mut self, text: &str) -> Result<usize, String> {
+        let code = Code::parse(text.trim())?;
+        self.cheats.push(Cheat { text: text.trim().to_ascii_uppercase(), code, enabled: true, applied: Cell::new(false) });
+        self.update();
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+        let cheat = self.cheats.get_mut(index).ok_or_else(|| format!("no cheat {}", index))?;
+        cheat.enabled = enabled;
+        self.update();
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.update();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    fn update(&mut self) {
+        self.genie = self.cheats.iter().any(|c| c.enabled && matches!(c.code, Code::GameGenie { .. }));
+    }
+
+    // ROM byte at addr as the game sees it
+    pub(crate) fn patch(&self, addr: u16, rom: u8) -> u8 {
+        if !self.genie {
+            return rom;
+        }
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            match cheat.code {
+                Code::GameGenie { addr: at, val, compare } if at == addr && compare.is_none_or(|c| c == rom) => {
+                    cheat.applied.set(true);
+                    return val;
+                }
+                _ => {}
+            }
+        }
+        rom
+    }
+
+    // addresses and values for GameShark codes to poke this frame
+    pub(crate) fn pokes(&self) -> Vec<(u16, u8)> {
+        let shark = |c: &Cheat| match c.code {
+            Code::GameShark { addr, val, .. } if c.enabled => Some((addr, val)),
+            _ => None,
+        };
+        self.cheats.iter().filter_map(shark).collect()
+    }
+
+    pub(crate) fn mark_applied(&self, addr: u16) {
+        for cheat in &self.cheats {
+            if matches!(cheat.code, Code::GameShark { addr: at, .. } if at == addr) && cheat.enabled {
+                cheat.applied.set(true);
+            }
+        }
+    }
+
+    // codes which changed something since the last call
+    pub(crate) fn take_applied(&self) -> Vec<&str> {
+        self.cheats.iter().filter(|c| c.applied.take()).map(|c| c.text.as_str()).collect()
+    }
+}
+
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, cheat) in self.cheats.iter().enumerate() {
+            writeln!(f, "{}: {} {}", i, cheat.text, if cheat.enabled { "on" } else { "off" })?;
+        }
+        Ok(())
+    }
+}
+
+// `add <code>`, `on <n>`, `off <n>`, `clear` or nothing to list, for the
+// debugger and stdin while playing
+pub fn command(cheats: &mut Cheats, args: &[&str]) -> Result<String, String> {
+    let index = || -> Result<usize, String> {
+        let arg = args.get(1).ok_or("missing cheat number")?;
+        arg.parse().map_err(|_| format!("invalid cheat number `{}`", arg))
+    };
+    match args.first().copied() {
+        None | Some("list") if cheats.is_empty() => Ok("no cheats".to_string()),
+        None | Some("list") => Ok(cheats.to_string().trim_end().to_string()),
+        Some("add") => {
+            let text = args.get(1).ok_or("missing code")?;
+            let index = cheats.add(text)?;
+            Ok(format!("cheat {}: {} on", index, cheats.cheats[index].text))
+        }
+        Some(state @ ("on" | "off")) => {
+            let index = index()?;
+            cheats.set_enabled(index, state == "on")?;
+            Ok(format!("cheat {}: {} {}", index, cheats.cheats[index].text, state))
+        }
+        Some("clear") => {
+            cheats.clear();
+            Ok("cheats cleared".to_string())
+        }
+        Some(other) => Err(format!("unknown cheat command `{}`, expected add, on, off, clear or list", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::eventlog::EventLog;
+    use crate::GB;
+
+    #[test]
+    fn parses_codes() {
+        assert_eq!(Code::parse("01FF16D0"), Ok(Code::GameShark { bank: 0x01, addr: 0xd016, val: 0xff }));
+        assert_eq!(Code::parse("3E1-50F"), Ok(Code::GameGenie { addr: 0x0150, val: 0x3e, compare: None }));
+        // compare 00 is stored as BA rotated left by 2, EA
+        assert_eq!(Code::parse("3e1-50f-e6a"), Ok(Code::GameGenie { addr: 0x0150, val: 0x3e, compare: Some(0x00) }));
+        assert!(Code::parse("3E1-50").is_err());
+        assert!(Code::parse("01FF1600").is_err(), "GameShark into ROM");
+        assert!(Code::parse("01-FF16D0").is_err());
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn patches_rom_and_pokes_ram_per_frame() {
+        // copies the byte at 0150 into C000 forever
+        let mut rom = micro_rom("ld a, $91\nldh ($40), a\nloop:\nld a, ($0150)\nld ($c000), a\njr loop");
+        rom[0x150] = 0x11;
+        let mut gb = GB::new(&rom).unwrap();
+        let log = Shared::default();
+        gb.set_event_log(Some(EventLog::new(Box::new(log.clone()))));
+        let cheats = gb.cheats_mut();
+        cheats.add("3e1-50f").unwrap();
+        // only while the ROM holds 00, which it doesn't
+        cheats.add("3f1-50f-e6a").unwrap();
+        cheats.add("0142C1FF").unwrap();
+        gb.run_frames(1);
+        assert_eq!(gb.read_memory(0xc000, 1), [0x3e]);
+        assert_eq!(gb.read_memory(0xffc1, 1), [0x42]);
+
+        command(gb.cheats_mut(), &["off", "0"]).unwrap();
+        gb.run_frames(1);
+        assert_eq!(gb.read_memory(0xc000, 1), [0x11]);
+        assert_eq!(command(gb.cheats_mut(), &[]).unwrap(), "0: 3E1-50F off\n1: 3F1-50F-E6A on\n2: 0142C1FF on");
+
+        gb.set_event_log(None);
+        let text = String::from_utf8(log.0.take()).unwrap();
+        let cheat_events: Vec<&str> = text.lines().filter(|l| l.contains("cheat")).collect();
+        // the GameShark poke only changed memory the first time
+        assert_eq!(cheat_events.len(), 2, "{}", text);
+        assert!(cheat_events.iter().any(|l| l.ends_with("\"code\":\"3E1-50F\"}")));
+        assert!(cheat_events.iter().any(|l| l.ends_with("\"code\":\"0142C1FF\"}")));
+    }
+}
@@ -0,0 +1,478 @@
+// command line options, `--name value` and `--name=value` both work
+
+use std::path::PathBuf;
+
+use gb_rust::ppu::Renderer;
+use gb_rust::{inputdisplay, Hardware, RtcMode};
+#[cfg(test)]
+use gb_rust::vramview;
+
+pub const USAGE: &str = "\
+usage: gb-rust [options] [--rom] <rom>...
+       gb-rust trace replay <file>              print a --trace-compressed file as text
+       gb-rust trace grep <file> <term>...      its instructions containing all terms, e.g. PC:0150 A:00
+       gb-rust self-check                       which hardware quirks this build emulates
+       gb-rust regress <file> [--update]        compare screens after n frames with golden hashes
+
+while playing, `sram export [file]` / `sram import [file]` on stdin copies cartridge
+RAM to / from <rom>.sav in the save directory without pausing, `cheat add <code>`,
+`cheat on|off <n>`, `cheat clear` and `cheat` manage cheats, `screenshot [file]`
+saves the screen as <rom>-<n>.png (also the gamepad's guide button), `video [file]`
+records to <rom>-<n>.gif or stops recording (also a right stick click), `state
+save [file]` / `state load [file]` saves / restores <rom>.state, `pause` and
+`resume` stop and continue the game, `blur` and `focus` tell that the window
+showing the frames lost or got focus (unfocused runs throttled), `insert <file>` (or dropping a ROM on the
+terminal) swaps the cartridge and powers on, `quit` exits (and writes the --record
+movie)
+
+  --rom <file>             cartridge to run, repeat for several, Enter or `next` switches
+                           between them
+  --bootrom <file>         run 256 byte DMG boot ROM before the cartridge
+  --speed <factor>         emulation speed relative to real time, default 1
+  --headless               don't pace to real time, run as fast as possible
+  --skip-idle              fast-forward while the game halts or polls LY / STAT, same
+                           results in less time, e.g. for --headless, --report and --test
+  --hardware <name>        dmg, sgb (runs ~2.4% faster) or sgb2, default dmg;
+                           SGB games get their colors and border on both
+  --overclock <n>          run CPU n times faster than the rest of the machine
+  --rtc <mode>             cartridge clocks follow the host's (default) or emulated time,
+                           the same every run, what --record and --playback default to
+  --renderer <name>        scanline, or fifo to draw pixel by pixel for mid-line effects
+  --scale <n>              integer upscaling of presented frames, 1-8
+  --filter <list>          frame post-processing, e.g. hq2x,scanlines,lcd-grid,3x
+  --cheat <code>           Game Genie ABC-DEF(-GHI) or GameShark 01VVLLHH, repeat for several
+  --script <file>          run a script along, in a small language with Lua's syntax, it can
+                           read and write memory, press buttons, save states and draw on
+                           --raw-frames every frame
+  --palette <name>         DMG palette or CGB color remap
+  --palette-file <file>    add user palettes
+  --idle-throttle <secs>   slow down after the screen stayed static that long
+  --save-dir <dir>         where savestates and battery saves go, current directory by default
+  --config <file>          settings file, default ~/.config/gb-rust/config.toml
+  --save-config            write the settings in effect to the config file
+  --debug                  start interactive debugger
+  --trace <file>           log every instruction (trace feature)
+  --trace-compressed       write the trace LZ4 compressed, for long captures
+  --link <host:port|:port> link cable over TCP, :port waits for the other side
+  --link-host <port>       wait for the other side to link on port, same as --link :port
+  --link-connect <addr>    link with a --link-host at host:port, same as --link host:port
+  --link-protocol <name>   native (gb-rust to gb-rust) or bgb (BGB, other emulators)
+  --printer <dir>          plug in a Game Boy Printer, sheets are written to <dir> as PNG
+  --two-player             link the two ROMs (or one twice) by cable and run both, first
+                           gamepad plays the left one, --raw-frames are 320x144
+  --events <-|host:port>   stream emulator events as JSON lines
+  --record <file>          record a TAS movie of the first cartridge from power on
+  --playback <file>        replay a movie, with the same --bootrom it was recorded with
+  --stdin-input            read buttons for each frame as lines from stdin, e.g. `a+right`
+  --raw-frames             write frames to stdout as raw RGB, every one with --stdin-input,
+                           else the newest whenever stdout is ready, e.g. for ffplay
+  --input-display <n>      with --raw-frames, draw the joypad and the last n frames' input
+  --vram-view <file>       write tiles, BG maps, OAM and palettes as raw 388x516 RGB frames,
+                           e.g. to a FIFO for a second ffplay
+  --record-video <file>    record gameplay from the start, .gif or through ffmpeg to e.g. .mp4
+  --dump-frames <dir>      write frames as PNG to <dir>/frame_<number>.png
+  --dump-every <n>         with --dump-frames, only every nth frame, default 1
+  --scanlines              print per-line registers of last frame once a second
+  --track-io               count accesses to unemulated IO registers, print them on exit
+  --diagnostics            report jumps into VRAM / OAM, the stack in ROM, reads of WRAM
+                           nothing wrote and hangs, with the registers and code around PC
+  --arcade <dir>           with --raw-frames, choose games from a folder on screen, each
+                           resumes where it was left, `menu` (or a left stick click) goes
+                           back to the chooser
+  --report <dir>           run headless for two minutes and write a compatibility report
+  --test                   run ROMs as Blargg / Mooneye tests, exit code tells if all passed
+  -h, --help               show this help";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkProtocol {
+    Native,
+    Bgb,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub roms: Vec<String>,
+    pub bootrom: Option<String>,
+    // None when unpaced (headless)
+    pub speed: Option<f64>,
+    pub skip_idle: bool,
+    pub hardware: Hardware,
+    pub overclock: u32,
+    // None falls back to the config file and then depends on movies
+    pub rtc: Option<RtcMode>,
+    // None falls back to the config file and then scanline
+    pub renderer: Option<Renderer>,
+    pub scale: Option<u32>,
+    pub filter: Option<String>,
+    pub cheats: Vec<String>,
+    pub script: Option<PathBuf>,
+    pub palette: Option<String>,
+    pub palette_file: Option<String>,
+    pub idle_throttle: Option<f64>,
+    // None falls back to the config file and then the current directory
+    pub save_dir: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub save_config: bool,
+    pub debug: bool,
+    pub trace: Option<String>,
+    pub trace_compressed: bool,
+    pub events: Option<String>,
+    pub link: Option<String>,
+    pub link_protocol: LinkProtocol,
+    pub two_player: bool,
+    pub printer: Option<PathBuf>,
+    pub record: Option<String>,
+    pub playback: Option<String>,
+    pub stdin_input: bool,
+    pub raw_frames: bool,
+    // frames of input history shown, None without the display
+    pub input_display: Option<usize>,
+    pub vram_view: Option<String>,
+    pub record_video: Option<PathBuf>,
+    pub dump_frames: Option<PathBuf>,
+    // None is every frame
+    pub dump_every: Option<u64>,
+    pub scanlines: bool,
+    pub track_io: bool,
+    pub diagnostics: bool,
+    pub arcade: Option<PathBuf>,
+    pub report: Option<String>,
+    pub test: bool,
+    pub help: bool,
+}
+
+impl Options {
+    // movies play back the same only on emulated time
+    pub fn rtc_mode(&self) -> RtcMode {
+        match self.rtc {
+            Some(mode) => mode,
+            None if self.record.is_some() || self.playback.is_some() => RtcMode::Emulated,
+            None => RtcMode::Host,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            roms: Vec::new(),
+            bootrom: None,
+            speed: Some(1.0),
+            skip_idle: false,
+            hardware: Hardware::Dmg,
+            overclock: 1,
+            rtc: None,
+            renderer: None,
+            scale: None,
+            filter: None,
+            cheats: Vec::new(),
+            script: None,
+            palette: None,
+            palette_file: None,
+            idle_throttle: None,
+            save_dir: None,
+            config: None,
+            save_config: false,
+            debug: false,
+            trace: None,
+            trace_compressed: false,
+            events: None,
+            link: None,
+            link_protocol: LinkProtocol::Native,
+            two_player: false,
+            printer: None,
+            record: None,
+            playback: None,
+            stdin_input: false,
+            raw_frames: false,
+            input_display: None,
+            vram_view: None,
+            record_video: None,
+            dump_frames: None,
+            dump_every: None,
+            scanlines: false,
+            track_io: false,
+            diagnostics: false,
+            arcade: None,
+            report: None,
+            test: false,
+            help: false,
+        }
+    }
+}
+
+// arguments without the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut headless = false;
+    let mut speed = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            options.roms.push(arg);
+            continue;
+        }
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match name.as_str() {
+            "--rom" => options.roms.push(value()?),
+            "--bootrom" => options.bootrom = Some(value()?),
+            "--speed" => speed = Some(number(&name, &value()?, |s: &f64| *s > 0.0 && s.is_finite())?),
+            "--headless" => headless = true,
+            "--skip-idle" => options.skip_idle = true,
+            "--hardware" => options.hardware = Hardware::parse(&value()?)?,
+            "--rtc" => options.rtc = Some(RtcMode::parse(&value()?)?),
+            "--overclock" => options.overclock = number(&name, &value()?, |n| *n >= 1)?,
+            "--renderer" => options.renderer = Some(Renderer::parse(&value()?)?),
+            "--scale" => options.scale = Some(number(&name, &value()?, |n| (1..=8).contains(n))?),
+            "--filter" => options.filter = Some(value()?),
+            "--cheat" => options.cheats.push(value()?),
+            "--script" => options.script = Some(PathBuf::from(value()?)),
+            "--palette" => options.palette = Some(value()?),
+            "--palette-file" => options.palette_file = Some(value()?),
+            "--idle-throttle" => options.idle_throttle = Some(number(&name, &value()?, |s: &f64| *s > 0.0)?),
+            "--save-dir" => options.save_dir = Some(PathBuf::from(value()?)),
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--save-config" => options.save_config = true,
+            "--debug" => options.debug = true,
+            "--trace" => options.trace = Some(value()?),
+            "--trace-compressed" => options.trace_compressed = true,
+            "--events" => options.events = Some(value()?),
+            "--link" | "--link-host" | "--link-connect" if options.link.is_some() => {
+                return Err("only one of --link, --link-host and --link-connect".to_string())
+            }
+            "--link" => options.link = Some(value()?),
+            "--link-host" => options.link = Some(format!(":{}", number::<u16>(&name, &value()?, |_| true)?)),
+            "--link-connect" => {
+                let addr = value()?;
+                if !addr.contains(':') {
+                    return Err(format!("invalid value `{}` for {}, expected host:port", addr, name));
+                }
+                options.link = Some(addr);
+            }
+            "--link-protocol" => {
+                options.link_protocol = match value()?.as_str() {
+                    "native" => LinkProtocol::Native,
+                    "bgb" => LinkProtocol::Bgb,
+                    other => return Err(format!("invalid value `{}` for {}", other, name)),
+                }
+            }
+            "--two-player" => options.two_player = true,
+            "--printer" => options.printer = Some(PathBuf::from(value()?)),
+            "--record" => options.record = Some(value()?),
+            "--playback" => options.playback = Some(value()?),
+            "--stdin-input" => options.stdin_input = true,
+            "--raw-frames" => options.raw_frames = true,
+            "--input-display" => options.input_display = Some(number(&name, &value()?, |n| *n <= inputdisplay::MAX_STRIP)?),
+            "--vram-view" => options.vram_view = Some(value()?),
+            "--record-video" => options.record_video = Some(PathBuf::from(value()?)),
+            "--dump-frames" => options.dump_frames = Some(PathBuf::from(value()?)),
+            "--dump-every" => options.dump_every = Some(number(&name, &value()?, |n| *n >= 1)?),
+            "--scanlines" => options.scanlines = true,
+            "--track-io" => options.track_io = true,
+            "--diagnostics" => options.diagnostics = true,
+            "--arcade" => options.arcade = Some(PathBuf::from(value()?)),
+            "--report" => options.report = Some(value()?),
+            "--test" => options.test = true,
+            "-h" | "--help" => options.help = true,
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+    options.speed = match (headless, speed) {
+        (true, Some(_)) => return Err("--speed and --headless exclude each other".to_string()),
+        (true, None) => None,
+        (false, speed) => Some(speed.unwrap_or(1.0)),
+    };
+    if options.trace_compressed && options.trace.is_none() {
+        return Err("--trace-compressed needs --trace".to_string());
+    }
+    if options.raw_frames && options.scanlines {
+        return Err("--scanlines would mix with frames on stdout".to_string());
+    }
+    if options.input_display.is_some() && !options.raw_frames {
+        return Err("--input-display needs --raw-frames".to_string());
+    }
+    if options.record.is_some() && options.playback.is_some() {
+        return Err("--record and --playback exclude each other".to_string());
+    }
+    if (options.stdin_input || options.raw_frames) && options.events.as_deref() == Some("-") {
+        return Err("--events - would mix with frames on stdout, use host:port".to_string());
+    }
+    if options.two_player {
+        if options.roms.len() > 2 {
+            return Err("--two-player takes one or two ROMs".to_string());
+        }
+        let excluded = [
+            (options.link.is_some(), "--link"),
+            (options.printer.is_some(), "--printer"),
+            (options.debug, "--debug"),
+            (options.stdin_input, "--stdin-input"),
+            (options.arcade.is_some(), "--arcade"),
+            (options.report.is_some(), "--report"),
+            (options.test, "--test"),
+        ];
+        for (set, name) in excluded {
+            if set {
+                return Err(format!("--two-player and {} exclude each other", name));
+            }
+        }
+    }
+    if options.dump_every.is_some() && options.dump_frames.is_none() {
+        return Err("--dump-every needs --dump-frames".to_string());
+    }
+    // both capture frames of the play loop
+    let capture = [(options.dump_frames.is_some(), "--dump-frames"), (options.record_video.is_some(), "--record-video")];
+    if let Some((_, capture)) = capture.into_iter().find(|&(set, _)| set) {
+        let excluded = [
+            (options.debug, "--debug"),
+            (options.stdin_input, "--stdin-input"),
+            (options.arcade.is_some(), "--arcade"),
+            (options.report.is_some(), "--report"),
+            (options.test, "--test"),
+        ];
+        if let Some((_, name)) = excluded.into_iter().find(|&(set, _)| set) {
+            return Err(format!("{} doesn't work with {}", capture, name));
+        }
+    }
+    // waits are cut short by emulated time only, the debugger, tracing and a linked
+    // Game Boy would see the difference
+    if options.skip_idle {
+        let excluded = [
+            (options.debug, "--debug"),
+            (options.trace.is_some(), "--trace"),
+            (options.link.is_some(), "--link"),
+            (options.two_player, "--two-player"),
+            (options.overclock > 1, "--overclock"),
+        ];
+        if let Some((_, name)) = excluded.into_iter().find(|&(set, _)| set) {
+            return Err(format!("--skip-idle doesn't work with {}", name));
+        }
+    }
+    // on_frame runs in the play loop, with the first game
+    if options.script.is_some() {
+        let excluded = [
+            (options.debug, "--debug"),
+            (options.stdin_input, "--stdin-input"),
+            (options.two_player, "--two-player"),
+            (options.arcade.is_some(), "--arcade"),
+            (options.report.is_some(), "--report"),
+            (options.test, "--test"),
+        ];
+        if let Some((_, name)) = excluded.into_iter().find(|&(set, _)| set) {
+            return Err(format!("--script doesn't work with {}", name));
+        }
+    }
+    // reported from the play loop and the debugger
+    if options.diagnostics {
+        let excluded = [
+            (options.report.is_some(), "--report"),
+            (options.test, "--test"),
+        ];
+        if let Some((_, name)) = excluded.into_iter().find(|&(set, _)| set) {
+            return Err(format!("--diagnostics doesn't work with {}", name));
+        }
+    }
+    // the chooser is drawn on the frames, its games come from the folder
+    if options.arcade.is_some() {
+        if !options.raw_frames {
+            return Err("--arcade needs --raw-frames".to_string());
+        }
+        let excluded = [
+            (!options.roms.is_empty(), "a ROM"),
+            (options.debug, "--debug"),
+            (options.stdin_input, "--stdin-input"),
+            (options.report.is_some(), "--report"),
+            (options.test, "--test"),
+        ];
+        if let Some((_, name)) = excluded.into_iter().find(|&(set, _)| set) {
+            return Err(format!("--arcade doesn't work with {}", name));
+        }
+    }
+    if options.printer.is_some() && options.link.is_some() {
+        return Err("--printer and --link both need the link port".to_string());
+    }
+    if options.roms.is_empty() && options.arcade.is_none() && !options.help {
+        return Err("no ROM given".to_string());
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str, valid: impl Fn(&T) -> bool) -> Result<T, String> {
+    match value.parse() {
+        Ok(n) if valid(&n) => Ok(n),
+        _ => Err(format!("invalid value `{}` for {}", value, name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Options, String> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parses_options_and_roms() {
+        let options = args("a.gb --rom b.gb --speed=2 --scale 3 --debug --track-io --save-dir states --renderer fifo").unwrap();
+        assert_eq!(options.roms, ["a.gb", "b.gb"]);
+        assert_eq!(options.speed, Some(2.0));
+        assert_eq!(options.scale, Some(3));
+        assert!(options.debug && options.track_io);
+        assert_eq!(options.renderer, Some(Renderer::Fifo));
+        assert_eq!(options.save_dir, Some(PathBuf::from("states")));
+        assert_eq!(args("a.gb --headless").unwrap().speed, None);
+        assert_eq!(args("a.gb --vram-view=vram.fifo").unwrap().vram_view.as_deref(), Some("vram.fifo"));
+        assert!(USAGE.contains(&format!("raw {}x{} RGB", vramview::WIDTH, vramview::HEIGHT)));
+    }
+
+    #[test]
+    fn reports_readable_errors() {
+        assert_eq!(args("").unwrap_err(), "no ROM given");
+        assert_eq!(args("a.gb --bogus").unwrap_err(), "unknown option --bogus");
+        assert_eq!(args("a.gb --trace").unwrap_err(), "--trace needs a value");
+        assert_eq!(args("a.gb --trace-compressed").unwrap_err(), "--trace-compressed needs --trace");
+        assert_eq!(args("a.gb --scale 9").unwrap_err(), "invalid value `9` for --scale");
+        assert!(args("a.gb --speed 2 --headless").is_err());
+        assert!(args("a.gb --record a.movie --playback b.movie").is_err());
+        assert!(args("a.gb --stdin-input --raw-frames --input-display 200").is_err());
+        assert!(args("a.gb --two-player").unwrap().two_player);
+        assert_eq!(args("a.gb b.gb c.gb --two-player").unwrap_err(), "--two-player takes one or two ROMs");
+        assert_eq!(args("a.gb --two-player --link :5000").unwrap_err(), "--two-player and --link exclude each other");
+        assert_eq!(args("a.gb --link-host 5000").unwrap().link.as_deref(), Some(":5000"));
+        assert_eq!(args("a.gb --link-connect=10.0.0.2:5000").unwrap().link.as_deref(), Some("10.0.0.2:5000"));
+        assert!(args("a.gb --link-host 70000").is_err());
+        assert!(args("a.gb --headless --skip-idle").unwrap().skip_idle);
+        assert_eq!(args("a.gb").unwrap().rtc_mode(), RtcMode::Host);
+        assert_eq!(args("a.gb --record a.movie").unwrap().rtc_mode(), RtcMode::Emulated);
+        assert_eq!(args("a.gb --rtc host --playback a.movie").unwrap().rtc_mode(), RtcMode::Host);
+        assert!(args("a.gb --rtc sundial").is_err());
+        assert_eq!(args("a.gb --skip-idle --overclock 2").unwrap_err(), "--skip-idle doesn't work with --overclock");
+        assert!(args("a.gb --link-connect 10.0.0.2").is_err());
+        assert!(args("a.gb --link-host 5000 --link-connect b:5000").is_err());
+        assert_eq!(args("a.gb --printer prints").unwrap().printer, Some(PathBuf::from("prints")));
+        assert!(args("a.gb --printer prints --link :5000").is_err());
+        let options = args("a.gb --dump-frames out --dump-every=30").unwrap();
+        assert_eq!((options.dump_frames, options.dump_every), (Some(PathBuf::from("out")), Some(30)));
+        assert!(args("a.gb --dump-every 30").is_err());
+        assert!(args("a.gb --dump-frames out --dump-every 0").is_err());
+        assert!(args("a.gb --dump-frames out --stdin-input").is_err());
+        assert_eq!(args("a.gb --record-video=play.gif").unwrap().record_video, Some(PathBuf::from("play.gif")));
+        assert_eq!(args("a.gb --record-video play.mp4 --debug").unwrap_err(), "--record-video doesn't work with --debug");
+        assert_eq!(args("a.gb --script bot.lua").unwrap().script, Some(PathBuf::from("bot.lua")));
+        assert_eq!(args("a.gb --script bot.lua --test").unwrap_err(), "--script doesn't work with --test");
+        assert!(args("a.gb --diagnostics --debug").unwrap().diagnostics);
+        assert_eq!(args("a.gb --diagnostics --report out").unwrap_err(), "--diagnostics doesn't work with --report");
+        assert!(args("--arcade roms --raw-frames --diagnostics --skip-idle").unwrap().diagnostics);
+        assert_eq!(args("--arcade roms").unwrap_err(), "--arcade needs --raw-frames");
+        assert_eq!(args("a.gb --arcade roms --raw-frames").unwrap_err(), "--arcade doesn't work with a ROM");
+    }
+}
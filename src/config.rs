@@ -0,0 +1,355 @@
+// persistent settings, a small subset of TOML:
+//
+//   palette = "dmg-green"
+//   scale = 3
+//   filter = "hq2x,scanlines"
+//   volume = 80            # percent, kept for the audio output (there is none yet)
+//   save_dir = "/home/me/gb-saves"
+//   bootrom = "dmg_boot.bin"
+//   cheats = "01FF16D0,3E1-50F"  # GameShark / Game Genie, for the first ROM
+//   renderer = "fifo"      # or "scanline", the default
+//   rtc = "emulated"       # or "host", how cartridge clocks count
+//
+//   [keys]                 # host key = buttons, used by --stdin-input lines
+//   z = "a"
+//   x = "b"
+//   enter = "start"
+//
+//   [gamepad]              # remaps on top of the usual layout, "" unmaps
+//   button2 = "a"
+//   axis6- = "left"
+//   screenshot = "button8" # hotkeys, these are the defaults
+//   record = "button10"
+//   menu = "button9"
+//
+// command line options win over the file
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use gb_rust::cheats::Code;
+use gb_rust::joypad::KeyBindings;
+use gb_rust::postprocess::PostProcess;
+use gb_rust::ppu::Renderer;
+use gb_rust::{Buttons, RtcMode};
+
+use crate::cli::Options;
+use crate::gamepad::{GamepadMap, Hotkey, Input};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub palette: Option<String>,
+    pub scale: Option<u32>,
+    pub filter: Option<String>,
+    pub volume: Option<u32>,
+    pub save_dir: Option<PathBuf>,
+    pub bootrom: Option<String>,
+    pub cheats: Vec<String>,
+    pub renderer: Option<Renderer>,
+    pub rtc: Option<RtcMode>,
+    pub keys: KeyBindings,
+    pub gamepad: BTreeMap<Input, Buttons>,
+    pub hotkeys: BTreeMap<Hotkey, Input>,
+}
+
+enum Value {
+    Str(String),
+    Int(i64),
+}
+
+// $XDG_CONFIG_HOME/gb-rust/config.toml, falling back to ~/.config
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("gb-rust").join("config.toml"))
+}
+
+impl Config {
+    // a missing file is an empty config
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("can't read {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_toml())
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", number + 1, e);
+            if let Some(name) = line.strip_prefix('[') {
+                section = name.strip_suffix(']').ok_or_else(|| error("unclosed [section]".to_string()))?.trim().to_string();
+                if section != "keys" && section != "gamepad" {
+                    return Err(error(format!("unknown section [{}]", section)));
+                }
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected key = value".to_string()))?;
+            let key = unquote_key(key.trim());
+            let value = parse_value(value.trim()).map_err(error)?;
+            config.set(&section, &key, value).map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: Value) -> Result<(), String> {
+        let invalid = || format!("invalid value for {}", key);
+        match (section, key, value) {
+            ("keys", key, Value::Str(buttons)) => self.keys.bind(key, Buttons::parse(&buttons)?),
+            ("gamepad", name, Value::Str(input)) if Hotkey::parse(name).is_some() => {
+                self.hotkeys.insert(Hotkey::parse(name).unwrap(), Input::parse(&input)?);
+            }
+            ("gamepad", input, Value::Str(buttons)) => {
+                self.gamepad.insert(Input::parse(input)?, Buttons::parse(&buttons)?);
+            }
+            ("", "palette", Value::Str(name)) => self.palette = Some(name),
+            ("", "scale", Value::Int(n @ 1..=8)) => self.scale = Some(n as u32),
+            ("", "filter", Value::Str(spec)) => {
+                PostProcess::parse(&spec)?;
+                self.filter = Some(spec);
+            }
+            ("", "volume", Value::Int(n @ 0..=100)) => self.volume = Some(n as u32),
+            ("", "save_dir", Value::Str(dir)) => self.save_dir = Some(PathBuf::from(dir)),
+            ("", "bootrom", Value::Str(path)) => self.bootrom = Some(path),
+            ("", "cheats", Value::Str(codes)) => {
+                self.cheats = codes.split(',').map(str::trim).filter(|c| !c.is_empty()).map(String::from).collect();
+                for code in &self.cheats {
+                    Code::parse(code)?;
+                }
+            }
+            ("", "renderer", Value::Str(name)) => self.renderer = Some(Renderer::parse(&name)?),
+            ("", "rtc", Value::Str(name)) => self.rtc = Some(RtcMode::parse(&name)?),
+            ("", "palette" | "scale" | "filter" | "volume" | "save_dir" | "bootrom" | "cheats" | "renderer" | "rtc", _)
+            | ("keys" | "gamepad", _, _) => {
+                return Err(invalid())
+            }
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut out = String::new();
+        let mut line = |key: &str, value: String| out.push_str(&format!("{} = {}\n", key, value));
+        if let Some(name) = &self.palette {
+            line("palette", quote(name));
+        }
+        if let Some(scale) = self.scale {
+            line("scale", scale.to_string());
+        }
+        if let Some(spec) = &self.filter {
+            line("filter", quote(spec));
+        }
+        if let Some(volume) = self.volume {
+            line("volume", volume.to_string());
+        }
+        if let Some(dir) = &self.save_dir {
+            line("save_dir", quote(&dir.to_string_lossy()));
+        }
+        if let Some(path) = &self.bootrom {
+            line("bootrom", quote(path));
+        }
+        if !self.cheats.is_empty() {
+            line("cheats", quote(&self.cheats.join(",")));
+        }
+        if let Some(renderer) = self.renderer {
+            line("renderer", quote(renderer.name()));
+        }
+        if let Some(mode) = self.rtc {
+            line("rtc", quote(mode.name()));
+        }
+        if self.keys.iter().next().is_some() {
+            out.push_str("\n[keys]\n");
+            for (key, buttons) in self.keys.iter() {
+                out.push_str(&format!("{} = {}\n", quote(key), quote(&buttons.names())));
+            }
+        }
+        if !self.gamepad.is_empty() || !self.hotkeys.is_empty() {
+            out.push_str("\n[gamepad]\n");
+            for (input, buttons) in &self.gamepad {
+                out.push_str(&format!("{} = {}\n", input, quote(&buttons.names())));
+            }
+            for (hotkey, input) in &self.hotkeys {
+                out.push_str(&format!("{} = {}\n", hotkey.name(), quote(&input.to_string())));
+            }
+        }
+        out
+    }
+
+    pub fn gamepad_map(&self) -> GamepadMap {
+        let mut map = GamepadMap::default();
+        for (input, buttons) in &self.gamepad {
+            map.bind(*input, *buttons);
+        }
+        map.hotkeys.extend(&self.hotkeys);
+        map
+    }
+
+    // file values fill in what wasn't given on the command line
+    pub fn apply(&self, options: &mut Options) {
+        options.palette = options.palette.take().or_else(|| self.palette.clone());
+        options.scale = options.scale.or(self.scale);
+        options.filter = options.filter.take().or_else(|| self.filter.clone());
+        options.save_dir = options.save_dir.take().or_else(|| self.save_dir.clone());
+        options.bootrom = options.bootrom.take().or_else(|| self.bootrom.clone());
+        if options.cheats.is_empty() {
+            options.cheats = self.cheats.clone();
+        }
+        options.renderer = options.renderer.or(self.renderer);
+        options.rtc = options.rtc.or(self.rtc);
+    }
+
+    // the reverse for --save-config, keeps bindings and volume from the file
+    pub fn update(&mut self, options: &Options) {
+        self.palette = options.palette.clone();
+        self.scale = options.scale;
+        self.filter = options.filter.clone();
+        self.save_dir = options.save_dir.clone();
+        self.bootrom = options.bootrom.clone();
+        self.cheats = options.cheats.clone();
+        self.renderer = options.renderer;
+        self.rtc = options.rtc;
+    }
+}
+
+// `#` outside of a string starts a comment
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote_key(key: &str) -> String {
+    match key.strip_prefix('"').and_then(|k| k.strip_suffix('"')) {
+        Some(inner) => inner.to_string(),
+        None => key.to_string(),
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(inner) = text.strip_prefix('"') {
+        let inner = inner.strip_suffix('"').ok_or("unterminated string")?;
+        let mut out = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            out.push(match c {
+                '\\' => match chars.next() {
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    other => return Err(format!("unsupported escape \\{}", other.unwrap_or(' '))),
+                },
+                '"' => return Err("stray quote in string".to_string()),
+                c => c,
+            });
+        }
+        return Ok(Value::Str(out));
+    }
+    match text.replace('_', "").parse() {
+        Ok(n) => Ok(Value::Int(n)),
+        Err(_) => Err(format!("expected a \"string\" or integer, got `{}`", text)),
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "
+        # settings
+        palette = \"dmg-green\"
+        scale = 3
+        filter = \"scale2x,scanlines\"
+        volume = 80  # percent
+        save_dir = \"C:\\\\games\\\\saves #1\"
+        cheats = \"01FF16D0, 3E1-50F\"
+        renderer = \"FIFO\"
+        rtc = \"emulated\"
+
+        [keys]
+        z = \"a\"
+        \"space\" = \"a+b\"
+
+        [gamepad]
+        axis3+ = \"start\"
+        button6 = \"\"
+        screenshot = \"button4\"
+    ";
+
+    #[test]
+    fn parses_and_round_trips() {
+        let config = Config::parse(EXAMPLE).unwrap();
+        assert_eq!(config.palette.as_deref(), Some("dmg-green"));
+        assert_eq!(config.scale, Some(3));
+        assert_eq!(config.filter.as_deref(), Some("scale2x,scanlines"));
+        assert_eq!(config.volume, Some(80));
+        assert_eq!(config.save_dir, Some(PathBuf::from("C:\\games\\saves #1")));
+        assert_eq!(config.cheats, ["01FF16D0", "3E1-50F"]);
+        assert_eq!(config.renderer, Some(Renderer::Fifo));
+        assert_eq!(config.rtc, Some(RtcMode::Emulated));
+        assert_eq!(config.keys.parse("Z+right").unwrap(), Buttons::A | Buttons::RIGHT);
+        assert_eq!(config.keys.parse("space").unwrap(), Buttons::A | Buttons::B);
+        assert_eq!(config.gamepad[&Input::Axis(3, true)], Buttons::START);
+        assert_eq!(config.gamepad[&Input::Button(6)], Buttons::empty());
+        assert_eq!(config.gamepad_map().hotkeys[&Hotkey::Screenshot], Input::Button(4));
+        assert_eq!(config.gamepad_map().hotkeys[&Hotkey::Record], Input::Button(10));
+        assert_eq!(config.gamepad_map().hotkeys[&Hotkey::Menu], Input::Button(9));
+        assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let config = Config::parse("scale = 2\npalette = \"grey\"\nbootrom = \"boot.bin\"").unwrap();
+        let mut options = Options {
+            scale: Some(4),
+            ..Options::default()
+        };
+        config.apply(&mut options);
+        assert_eq!(options.scale, Some(4));
+        assert_eq!(options.palette.as_deref(), Some("grey"));
+        assert_eq!(options.bootrom.as_deref(), Some("boot.bin"));
+    }
+
+    #[test]
+    fn reports_line_of_error() {
+        assert_eq!(Config::parse("\nscale = 9").unwrap_err(), "line 2: invalid value for scale");
+        assert_eq!(Config::parse("speed = 2").unwrap_err(), "line 1: unknown setting speed");
+        assert_eq!(Config::parse("[keys]\nz = \"jump\"").unwrap_err(), "line 2: unknown button `jump`");
+        assert!(Config::parse("[video]").is_err());
+        assert!(Config::parse("cheats = \"01FF16D0,bogus\"").unwrap_err().starts_with("line 1: invalid cheat `bogus`"));
+        assert!(Config::parse("filter = \"blur\"").unwrap_err().starts_with("line 1: unknown filter `blur`"));
+        assert!(Config::parse("renderer = \"gpu\"").unwrap_err().starts_with("line 1: unknown renderer `gpu`"));
+    }
+}
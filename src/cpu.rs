@@ -0,0 +1,1016 @@
+// SM83 core: instruction handlers dispatched through opcodes::OPCODES and the
+// fetch / interrupt / HALT logic around them. GB runs it on the Game Boy's
+// bus, Sm83 on any other Bus, so the CPU alone can be reused elsewhere:
+//
+//   struct Ram(Vec<u8>);
+//   impl Bus for Ram {
+//       fn read(&mut self, addr: u16) -> u8 { self.0[addr as usize] }
+//       fn write(&mut self, addr: u16, val: u8) { self.0[addr as usize] = val }
+//   }
+//   let mut cpu = Sm83::new(Ram(vec![0; 0x10000]));
+//   let t_cycles = cpu.step();
+//
+// every memory access takes 4 t-cycles, Bus::tick(4) runs right before it so
+// the rest of the machine can catch up; tick also gets the internal cycles.
+// illegal opcodes lock the CPU up like on the real one, interrupts included
+
+use crate::opcodes::{CB_OPCODES, OPCODES};
+use crate::{Flags, Z80};
+
+// what the CPU runs on, memory and the interrupt lines
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    // t-cycles passing
+    fn tick(&mut self, _t: u32) {}
+    // requested and enabled interrupts, bit 0 (vector 0040) wins over bit 4 (0060)
+    fn pending_interrupts(&mut self) -> u8 {
+        0
+    }
+    // the CPU started servicing bit, clear its request
+    fn acknowledge_interrupt(&mut self, _bit: u8) {}
+}
+
+// register file as seen from outside, F keeps only its upper 4 flag bits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+pub struct Sm83<B: Bus> {
+    z80: Z80,
+    bus: B,
+}
+
+impl<B: Bus> Sm83<B> {
+    // power-on state, zeroed with PC at 0000 where the boot ROM starts
+    pub fn new(bus: B) -> Self {
+        Sm83 { z80: Z80::new(), bus }
+    }
+
+    pub fn reset(&mut self) {
+        self.z80 = Z80::new();
+    }
+
+    // runs one instruction, services an interrupt or idles in HALT, returns t-cycles taken
+    pub fn step(&mut self) -> u32 {
+        step(self)
+    }
+
+    pub fn registers(&self) -> Registers {
+        let z = &self.z80;
+        Registers { a: z.a, f: z.f.bits(), b: z.b, c: z.c, d: z.d, e: z.e, h: z.h, l: z.l, sp: z.sp, pc: z.pc }
+    }
+
+    pub fn set_registers(&mut self, regs: Registers) {
+        let z = &mut self.z80;
+        (z.a, z.b, z.c, z.d, z.e, z.h, z.l, z.sp, z.pc) = (regs.a, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l, regs.sp, regs.pc);
+        z.f = Flags::from_bits_truncate(regs.f);
+    }
+
+    // interrupt master enable, set by EI one instruction late
+    pub fn ime(&self) -> bool {
+        self.z80.ime
+    }
+
+    pub fn set_ime(&mut self, ime: bool) {
+        self.z80.ime = ime;
+        self.z80.ei_pending = false;
+    }
+
+    pub fn halted(&self) -> bool {
+        self.z80.halted
+    }
+
+    // illegal opcode and its address the CPU locked up on
+    pub fn lockup(&self) -> Option<(u8, u16)> {
+        self.z80.lockup
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn into_bus(self) -> B {
+        self.bus
+    }
+}
+
+// what the handlers run on, GB and Sm83. accesses here are untimed, the helpers
+// below do the ticking
+pub(crate) trait Core {
+    fn z80(&mut self) -> &mut Z80;
+    fn bus_read(&mut self, addr: u16) -> u8;
+    fn bus_write(&mut self, addr: u16, val: u8);
+    fn tick(&mut self, t: u32);
+    fn pending_interrupts(&mut self) -> u8;
+    fn acknowledge_interrupt(&mut self, bit: u8);
+    // right before an instruction is fetched, e.g. for tracing
+    fn before_instruction(&mut self) {}
+    // before an illegal opcode locks the CPU up
+    fn illegal(&mut self, _op: u8) {}
+}
+
+impl<B: Bus> Core for Sm83<B> {
+    fn z80(&mut self) -> &mut Z80 {
+        &mut self.z80
+    }
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        self.bus.read(addr)
+    }
+    fn bus_write(&mut self, addr: u16, val: u8) {
+        self.bus.write(addr, val)
+    }
+    fn tick(&mut self, t: u32) {
+        self.bus.tick(t)
+    }
+    fn pending_interrupts(&mut self) -> u8 {
+        self.bus.pending_interrupts() & 0x1f
+    }
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.bus.acknowledge_interrupt(bit)
+    }
+}
+
+pub(crate) fn step(cpu: &mut dyn Core) -> u32 {
+    cpu.z80().instr_t = 0;
+    if cpu.z80().lockup.is_some() {
+        tick(cpu, 4);
+    } else if !interrupt(cpu) {
+        match cpu.z80().halted {
+            true => tick(cpu, 4),
+            false => {
+                cpu.before_instruction();
+                // EI enables interrupts after the instruction following it, unless that's DI
+                let enable = std::mem::take(&mut cpu.z80().ei_pending);
+                let op = fetch(cpu);
+                execute(cpu, op);
+                if enable && op != 0xf3 {
+                    cpu.z80().ime = true;
+                }
+            }
+        }
+    }
+    cpu.z80().instr_t
+}
+
+fn execute(cpu: &mut dyn Core, op: u8) {
+    // timing and handler come from opcode table shared with disassembler,
+    // handlers raise t for taken branches and CB opcodes
+    let entry = &OPCODES[op as usize];
+    cpu.z80().t = entry.cycles;
+    (entry.handler)(cpu, op);
+    let z = cpu.z80();
+    z.m = z.t / 4;
+    // internal cycles not spent on memory accesses
+    let (cycles, spent) = (z.t as u32, z.instr_t);
+    if spent < cycles {
+        tick(cpu, cycles - spent);
+    }
+}
+
+// services the highest priority pending interrupt, taking 20 t-cycles, 24 when
+// it wakes the CPU from HALT. any pending interrupt also ends HALT, even with
+// IME off
+fn interrupt(cpu: &mut dyn Core) -> bool {
+    let pending = cpu.pending_interrupts();
+    if pending == 0 {
+        return false;
+    }
+    let z = cpu.z80();
+    let woke = std::mem::take(&mut z.halted);
+    if !z.ime {
+        return false;
+    }
+    z.ime = false;
+    let bit = pending.trailing_zeros() as u8;
+    cpu.acknowledge_interrupt(bit);
+    tick(cpu, if woke { 12 } else { 8 });
+    let pc = cpu.z80().pc;
+    push_word(cpu, pc);
+    tick(cpu, 4);
+    cpu.z80().pc = 0x40 + bit as u16 * 8;
+    true
+}
+
+#[inline]
+fn tick(cpu: &mut dyn Core, t: u32) {
+    cpu.z80().instr_t += t;
+    cpu.tick(t);
+}
+
+// memory access as done by the CPU, the machine runs for its 4 t-cycles first
+#[inline]
+fn read(cpu: &mut dyn Core, addr: u16) -> u8 {
+    tick(cpu, 4);
+    cpu.bus_read(addr)
+}
+
+#[inline]
+fn write(cpu: &mut dyn Core, addr: u16, val: u8) {
+    tick(cpu, 4);
+    cpu.bus_write(addr, val);
+}
+
+// little-endian, one bus access per byte
+fn read_word(cpu: &mut dyn Core, addr: u16) -> u16 {
+    u16::from_le_bytes([read(cpu, addr), read(cpu, addr.wrapping_add(1))])
+}
+
+fn write_word(cpu: &mut dyn Core, addr: u16, val: u16) {
+    let [low, high] = val.to_le_bytes();
+    write(cpu, addr, low);
+    write(cpu, addr.wrapping_add(1), high);
+}
+
+fn push_word(cpu: &mut dyn Core, val: u16) {
+    let z = cpu.z80();
+    z.sp = z.sp.wrapping_sub(2);
+    let sp = z.sp;
+    write_word(cpu, sp, val);
+}
+
+fn pop_word(cpu: &mut dyn Core) -> u16 {
+    let sp = cpu.z80().sp;
+    let val = read_word(cpu, sp);
+    cpu.z80().sp = sp.wrapping_add(2);
+    val
+}
+
+// opcode at PC, which the HALT bug fails to step past once
+#[inline]
+fn fetch(cpu: &mut dyn Core) -> u8 {
+    let z = cpu.z80();
+    let pc = z.pc;
+    if !std::mem::take(&mut z.halt_bug) {
+        z.pc = pc.wrapping_add(1);
+    }
+    read(cpu, pc)
+}
+
+// immediate operands following the opcode
+#[inline]
+fn imm8(cpu: &mut dyn Core) -> u8 {
+    let z = cpu.z80();
+    let pc = z.pc;
+    z.pc = pc.wrapping_add(1);
+    read(cpu, pc)
+}
+
+fn imm16(cpu: &mut dyn Core) -> u16 {
+    u16::from_le_bytes([imm8(cpu), imm8(cpu)])
+}
+
+// 8 bit operand in bits 0-2 or 3-5: B C D E H L (HL) A
+fn get_r(cpu: &mut dyn Core, index: u8) -> u8 {
+    let z = cpu.z80();
+    match index & 7 {
+        0 => z.b,
+        1 => z.c,
+        2 => z.d,
+        3 => z.e,
+        4 => z.h,
+        5 => z.l,
+        6 => {
+            let hl = z.hl();
+            read(cpu, hl)
+        }
+        _ => z.a,
+    }
+}
+
+fn set_r(cpu: &mut dyn Core, index: u8, val: u8) {
+    let z = cpu.z80();
+    match index & 7 {
+        0 => z.b = val,
+        1 => z.c = val,
+        2 => z.d = val,
+        3 => z.e = val,
+        4 => z.h = val,
+        5 => z.l = val,
+        6 => {
+            let hl = z.hl();
+            write(cpu, hl, val)
+        }
+        _ => z.a = val,
+    }
+}
+
+// 16 bit operand in bits 4-5: BC DE HL SP
+fn get_rr(z: &Z80, op: u8) -> u16 {
+    match (op >> 4) & 3 {
+        0 => z.bc(),
+        1 => z.de(),
+        2 => z.hl(),
+        _ => z.sp,
+    }
+}
+
+fn set_rr(z: &mut Z80, op: u8, val: u16) {
+    match (op >> 4) & 3 {
+        0 => z.set_bc(val),
+        1 => z.set_de(val),
+        2 => z.set_hl(val),
+        _ => z.sp = val,
+    }
+}
+
+// condition in bits 3-4: NZ Z NC C
+fn condition(z: &Z80, op: u8) -> bool {
+    match (op >> 3) & 3 {
+        0 => !z.f.contains(Flags::ZERO),
+        1 => z.f.contains(Flags::ZERO),
+        2 => !z.f.contains(Flags::CARRY),
+        _ => z.f.contains(Flags::CARRY),
+    }
+}
+
+// conditional branches taking the branch cost the table's cycles_taken instead
+fn taken(z: &mut Z80, op: u8) {
+    z.t = OPCODES[op as usize].cycles_taken;
+}
+
+fn flags(zero: bool, subtraction: bool, half_carry: bool, carry: bool) -> Flags {
+    let mut f = Flags::NONE;
+    f.set(Flags::ZERO, zero);
+    f.set(Flags::SUBSTRACTION, subtraction);
+    f.set(Flags::HALF_CARRY, half_carry);
+    f.set(Flags::CARRY, carry);
+    f
+}
+
+// (BC) (DE) (HL+) (HL-) of LD (rr),A / LD A,(rr)
+fn indirect(z: &mut Z80, op: u8) -> u16 {
+    let hl = z.hl();
+    match (op >> 4) & 3 {
+        0 => z.bc(),
+        1 => z.de(),
+        2 => {
+            z.set_hl(hl.wrapping_add(1));
+            hl
+        }
+        _ => {
+            z.set_hl(hl.wrapping_sub(1));
+            hl
+        }
+    }
+}
+
+pub(crate) fn nop(_: &mut dyn Core, _: u8) {}
+
+pub(crate) fn illegal(cpu: &mut dyn Core, op: u8) {
+    cpu.illegal(op);
+    let z = cpu.z80();
+    z.lockup = Some((op, z.pc.wrapping_sub(1)));
+}
+
+pub(crate) fn ld_rr_d16(cpu: &mut dyn Core, op: u8) {
+    let val = imm16(cpu);
+    set_rr(cpu.z80(), op, val);
+}
+
+pub(crate) fn ld_ind_a(cpu: &mut dyn Core, op: u8) {
+    let z = cpu.z80();
+    let (addr, a) = (indirect(z, op), z.a);
+    write(cpu, addr, a);
+}
+
+pub(crate) fn ld_a_ind(cpu: &mut dyn Core, op: u8) {
+    let addr = indirect(cpu.z80(), op);
+    cpu.z80().a = read(cpu, addr);
+}
+
+pub(crate) fn inc_rr(cpu: &mut dyn Core, op: u8) {
+    let z = cpu.z80();
+    set_rr(z, op, get_rr(z, op).wrapping_add(1));
+}
+
+pub(crate) fn dec_rr(cpu: &mut dyn Core, op: u8) {
+    let z = cpu.z80();
+    set_rr(z, op, get_rr(z, op).wrapping_sub(1));
+}
+
+pub(crate) fn add_hl_rr(cpu: &mut dyn Core, op: u8) {
+    let z = cpu.z80();
+    let (hl, val) = (z.hl(), get_rr(z, op));
+    let (result, carry) = hl.overflowing_add(val);
+    z.f = flags(z.f.contains(Flags::ZERO), false, (hl & 0x0fff) + (val & 0x0fff) > 0x0fff, carry);
+    z.set_hl(result);
+}
+
+pub(crate) fn inc_r(cpu: &mut dyn Core, op: u8) {
+    let val = get_r(cpu, op >> 3);
+    let result = val.wrapping_add(1);
+    let z = cpu.z80();
+    z.f = flags(result == 0, false, val & 0x0f == 0x0f, z.f.contains(Flags::CARRY));
+    set_r(cpu, op >> 3, result);
+}
+
+pub(crate) fn dec_r(cpu: &mut dyn Core, op: u8) {
+    let val = get_r(cpu, op >> 3);
+    let result = val.wrapping_sub(1);
+    let z = cpu.z80();
+    z.f = flags(result == 0, true, val & 0x0f == 0, z.f.contains(Flags::CARRY));
+    set_r(cpu, op >> 3, result);
+}
+
+pub(crate) fn ld_r_d8(cpu: &mut dyn Core, op: u8) {
+    let val = imm8(cpu);
+    set_r(cpu, op >> 3, val);
+}
+
+// A rotations always clear Z, unlike their CB counterparts
+pub(crate) fn rlca(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    let a = z.a;
+    z.a = a.rotate_left(1);
+    z.f = flags(false, false, false, a & 0x80 != 0);
+}
+
+pub(crate) fn rrca(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    let a = z.a;
+    z.a = a.rotate_right(1);
+    z.f = flags(false, false, false, a & 0x01 != 0);
+}
+
+pub(crate) fn rla(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    let a = z.a;
+    z.a = a << 1 | z.f.contains(Flags::CARRY) as u8;
+    z.f = flags(false, false, false, a & 0x80 != 0);
+}
+
+pub(crate) fn rra(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    let a = z.a;
+    z.a = a >> 1 | (z.f.contains(Flags::CARRY) as u8) << 7;
+    z.f = flags(false, false, false, a & 0x01 != 0);
+}
+
+pub(crate) fn ld_a16_sp(cpu: &mut dyn Core, _: u8) {
+    let addr = imm16(cpu);
+    let sp = cpu.z80().sp;
+    write_word(cpu, addr, sp);
+}
+
+// on a DMG STOP mostly behaves like HALT, it really waits for a button press
+pub(crate) fn stop(cpu: &mut dyn Core, _: u8) {
+    imm8(cpu);
+    cpu.z80().halted = true;
+}
+
+pub(crate) fn jr(cpu: &mut dyn Core, _: u8) {
+    let offset = imm8(cpu) as i8;
+    let z = cpu.z80();
+    z.pc = z.pc.wrapping_add(offset as u16);
+}
+
+pub(crate) fn jr_cc(cpu: &mut dyn Core, op: u8) {
+    let offset = imm8(cpu) as i8;
+    let z = cpu.z80();
+    if condition(z, op) {
+        taken(z, op);
+        z.pc = z.pc.wrapping_add(offset as u16);
+    }
+}
+
+// adjusts A to BCD after an addition or subtraction of BCD numbers
+pub(crate) fn daa(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    let f = z.f;
+    let mut a = z.a;
+    let mut carry = f.contains(Flags::CARRY);
+    if !f.contains(Flags::SUBSTRACTION) {
+        if carry || a > 0x99 {
+            a = a.wrapping_add(0x60);
+            carry = true;
+        }
+        if f.contains(Flags::HALF_CARRY) || a & 0x0f > 0x09 {
+            a = a.wrapping_add(0x06);
+        }
+    } else {
+        if carry {
+            a = a.wrapping_sub(0x60);
+        }
+        if f.contains(Flags::HALF_CARRY) {
+            a = a.wrapping_sub(0x06);
+        }
+    }
+    z.a = a;
+    z.f = flags(a == 0, f.contains(Flags::SUBSTRACTION), false, carry);
+}
+
+pub(crate) fn cpl(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    z.a = !z.a;
+    z.f |= Flags::SUBSTRACTION | Flags::HALF_CARRY;
+}
+
+pub(crate) fn scf(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    z.f = flags(z.f.contains(Flags::ZERO), false, false, true);
+}
+
+pub(crate) fn ccf(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    z.f = flags(z.f.contains(Flags::ZERO), false, false, !z.f.contains(Flags::CARRY));
+}
+
+pub(crate) fn ld_r_r(cpu: &mut dyn Core, op: u8) {
+    let val = get_r(cpu, op);
+    set_r(cpu, op >> 3, val);
+}
+
+// with interrupts disabled but one pending, HALT doesn't halt and the next
+// opcode byte is read twice instead
+pub(crate) fn halt(cpu: &mut dyn Core, _: u8) {
+    let pending = cpu.pending_interrupts() != 0;
+    let z = cpu.z80();
+    match !z.ime && pending {
+        true => z.halt_bug = true,
+        false => z.halted = true,
+    }
+}
+
+pub(crate) fn alu_r(cpu: &mut dyn Core, op: u8) {
+    let val = get_r(cpu, op);
+    alu(cpu.z80(), op >> 3, val);
+}
+
+pub(crate) fn alu_d8(cpu: &mut dyn Core, op: u8) {
+    let val = imm8(cpu);
+    alu(cpu.z80(), op >> 3, val);
+}
+
+// operation in bits 3-5: ADD ADC SUB SBC AND XOR OR CP
+fn alu(z: &mut Z80, operation: u8, val: u8) {
+    let a = z.a;
+    let carry = z.f.contains(Flags::CARRY) as u8;
+    match operation & 7 {
+        0 => z.a = z.add_with_flags(a, val),
+        1 => {
+            let result = a.wrapping_add(val).wrapping_add(carry);
+            let half = (a & 0x0f) + (val & 0x0f) + carry > 0x0f;
+            z.f = flags(result == 0, false, half, a as u16 + val as u16 + carry as u16 > 0xff);
+            z.a = result;
+        }
+        2 => z.a = subtract(z, a, val, 0),
+        3 => z.a = subtract(z, a, val, carry),
+        4 => {
+            z.a = a & val;
+            z.f = flags(a & val == 0, false, true, false);
+        }
+        5 => {
+            z.a = a ^ val;
+            z.f = flags(a ^ val == 0, false, false, false);
+        }
+        6 => {
+            z.a = a | val;
+            z.f = flags(a | val == 0, false, false, false);
+        }
+        _ => {
+            subtract(z, a, val, 0);
+        }
+    }
+}
+
+fn subtract(z: &mut Z80, a: u8, val: u8, carry: u8) -> u8 {
+    let result = a.wrapping_sub(val).wrapping_sub(carry);
+    let half = (a & 0x0f) < (val & 0x0f) + carry;
+    z.f = flags(result == 0, true, half, (a as u16) < val as u16 + carry as u16);
+    result
+}
+
+pub(crate) fn ret(cpu: &mut dyn Core, _: u8) {
+    cpu.z80().pc = pop_word(cpu);
+}
+
+pub(crate) fn ret_cc(cpu: &mut dyn Core, op: u8) {
+    let z = cpu.z80();
+    if condition(z, op) {
+        taken(z, op);
+        cpu.z80().pc = pop_word(cpu);
+    }
+}
+
+pub(crate) fn reti(cpu: &mut dyn Core, _: u8) {
+    cpu.z80().pc = pop_word(cpu);
+    cpu.z80().ime = true;
+}
+
+// pairs in bits 4-5: BC DE HL AF
+pub(crate) fn pop(cpu: &mut dyn Core, op: u8) {
+    let val = pop_word(cpu);
+    let z = cpu.z80();
+    match (op >> 4) & 3 {
+        3 => z.set_af(val),
+        _ => set_rr(z, op, val),
+    }
+}
+
+pub(crate) fn push(cpu: &mut dyn Core, op: u8) {
+    let z = cpu.z80();
+    let val = match (op >> 4) & 3 {
+        3 => z.af(),
+        _ => get_rr(z, op),
+    };
+    push_word(cpu, val);
+}
+
+pub(crate) fn jp(cpu: &mut dyn Core, _: u8) {
+    cpu.z80().pc = imm16(cpu);
+}
+
+pub(crate) fn jp_cc(cpu: &mut dyn Core, op: u8) {
+    let target = imm16(cpu);
+    let z = cpu.z80();
+    if condition(z, op) {
+        taken(z, op);
+        z.pc = target;
+    }
+}
+
+pub(crate) fn jp_hl(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    z.pc = z.hl();
+}
+
+pub(crate) fn call(cpu: &mut dyn Core, _: u8) {
+    let target = imm16(cpu);
+    let pc = cpu.z80().pc;
+    push_word(cpu, pc);
+    cpu.z80().pc = target;
+}
+
+pub(crate) fn call_cc(cpu: &mut dyn Core, op: u8) {
+    let target = imm16(cpu);
+    let z = cpu.z80();
+    if condition(z, op) {
+        taken(z, op);
+        let pc = z.pc;
+        push_word(cpu, pc);
+        cpu.z80().pc = target;
+    }
+}
+
+pub(crate) fn rst(cpu: &mut dyn Core, op: u8) {
+    let pc = cpu.z80().pc;
+    push_word(cpu, pc);
+    cpu.z80().pc = (op & 0x38) as u16;
+}
+
+pub(crate) fn prefix_cb(cpu: &mut dyn Core, _: u8) {
+    let op = imm8(cpu);
+    let entry = &CB_OPCODES[op as usize];
+    cpu.z80().t = entry.cycles;
+    (entry.handler)(cpu, op);
+}
+
+// all of CB: shifts and rotations, BIT, RES and SET on the operand in bits 0-2
+pub(crate) fn cb(cpu: &mut dyn Core, op: u8) {
+    let val = get_r(cpu, op);
+    let bit = (op >> 3) & 7;
+    let z = cpu.z80();
+    let carry = z.f.contains(Flags::CARRY) as u8;
+    let (result, carry_out) = match op >> 6 {
+        0 => match bit {
+            0 => (val.rotate_left(1), val & 0x80 != 0),
+            1 => (val.rotate_right(1), val & 0x01 != 0),
+            2 => (val << 1 | carry, val & 0x80 != 0),
+            3 => (val >> 1 | carry << 7, val & 0x01 != 0),
+            4 => (val << 1, val & 0x80 != 0),
+            5 => (val >> 1 | (val & 0x80), val & 0x01 != 0),
+            6 => (val.rotate_left(4), false),
+            _ => (val >> 1, val & 0x01 != 0),
+        },
+        1 => {
+            z.f = flags(val & (1 << bit) == 0, false, true, carry != 0);
+            return;
+        }
+        2 => {
+            set_r(cpu, op, val & !(1 << bit));
+            return;
+        }
+        _ => {
+            set_r(cpu, op, val | (1 << bit));
+            return;
+        }
+    };
+    z.f = flags(result == 0, false, false, carry_out);
+    set_r(cpu, op, result);
+}
+
+pub(crate) fn ldh_a8_a(cpu: &mut dyn Core, _: u8) {
+    let addr = 0xff00 | imm8(cpu) as u16;
+    let a = cpu.z80().a;
+    write(cpu, addr, a);
+}
+
+pub(crate) fn ldh_a_a8(cpu: &mut dyn Core, _: u8) {
+    let addr = 0xff00 | imm8(cpu) as u16;
+    cpu.z80().a = read(cpu, addr);
+}
+
+pub(crate) fn ldh_c_a(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    let (addr, a) = (0xff00 | z.c as u16, z.a);
+    write(cpu, addr, a);
+}
+
+pub(crate) fn ldh_a_c(cpu: &mut dyn Core, _: u8) {
+    let addr = 0xff00 | cpu.z80().c as u16;
+    cpu.z80().a = read(cpu, addr);
+}
+
+// SP plus signed offset, flags come from the unsigned low byte addition
+fn sp_offset(cpu: &mut dyn Core) -> u16 {
+    let offset = imm8(cpu) as i8 as u16;
+    let z = cpu.z80();
+    let sp = z.sp;
+    z.f = flags(false, false, (sp & 0x0f) + (offset & 0x0f) > 0x0f, (sp & 0xff) + (offset & 0xff) > 0xff);
+    sp.wrapping_add(offset)
+}
+
+pub(crate) fn add_sp_r8(cpu: &mut dyn Core, _: u8) {
+    cpu.z80().sp = sp_offset(cpu);
+}
+
+pub(crate) fn ld_hl_sp_r8(cpu: &mut dyn Core, _: u8) {
+    let val = sp_offset(cpu);
+    cpu.z80().set_hl(val);
+}
+
+pub(crate) fn ld_sp_hl(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    z.sp = z.hl();
+}
+
+pub(crate) fn ld_a16_a(cpu: &mut dyn Core, _: u8) {
+    let addr = imm16(cpu);
+    let a = cpu.z80().a;
+    write(cpu, addr, a);
+}
+
+pub(crate) fn ld_a_a16(cpu: &mut dyn Core, _: u8) {
+    let addr = imm16(cpu);
+    cpu.z80().a = read(cpu, addr);
+}
+
+pub(crate) fn di(cpu: &mut dyn Core, _: u8) {
+    let z = cpu.z80();
+    z.ime = false;
+    z.ei_pending = false;
+}
+
+// takes effect after the next instruction
+pub(crate) fn ei(cpu: &mut dyn Core, _: u8) {
+    cpu.z80().ei_pending = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, micro_rom};
+    use crate::GB;
+
+    fn run(gb: &mut GB, instructions: usize) {
+        for _ in 0..instructions {
+            gb.cycle();
+        }
+    }
+
+    // every opcode which doesn't jump advances PC by its length and takes its cycles
+    #[test]
+    fn length_and_cycles_match_table() {
+        let jumps = [0x10, 0x18, 0x76, 0xc3, 0xc7, 0xc9, 0xcd, 0xd9, 0xe9];
+        let mut cases: Vec<(Vec<u8>, u8, u8)> = Vec::new();
+        for (op, entry) in OPCODES.iter().enumerate() {
+            let branch = entry.cycles != entry.cycles_taken;
+            let is_rst = op & 0xc7 == 0xc7;
+            if entry.mnemonic.is_empty() || op == 0xcb || branch || is_rst || jumps.contains(&(op as u8)) {
+                continue;
+            }
+            cases.push((vec![op as u8], entry.length, entry.cycles));
+        }
+        for (op, entry) in CB_OPCODES.iter().enumerate() {
+            cases.push((vec![0xcb, op as u8], entry.length, entry.cycles));
+        }
+        for (bytes, length, cycles) in cases {
+            let mut rom = micro_rom("nop");
+            rom[0x100..0x100 + bytes.len()].copy_from_slice(&bytes);
+            // immediates read as $C100 / $00, pointers go to WRAM
+            rom[0x100 + bytes.len() + 1] = 0xc1;
+            let mut gb = GB::new(&rom).unwrap();
+            gb.z80.set_bc(0xc000);
+            gb.z80.set_de(0xc000);
+            gb.z80.set_hl(0xc000);
+            gb.cycle();
+            assert_eq!((gb.z80.pc - 0x100, gb.clockT), (length as u16, cycles as u64), "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn taken_branches_take_longer() {
+        for (op, entry) in OPCODES.iter().enumerate().filter(|(_, e)| e.cycles != e.cycles_taken) {
+            for f in [Flags::NONE, Flags::ZERO | Flags::CARRY] {
+                let mut rom = micro_rom("nop");
+                rom[0x100] = op as u8;
+                rom[0x102] = 0xc1;
+                let mut gb = GB::new(&rom).unwrap();
+                gb.z80.f = f;
+                let taken = condition(&gb.z80, op as u8);
+                gb.cycle();
+                let cycles = if taken { entry.cycles_taken } else { entry.cycles };
+                assert_eq!(gb.clockT, cycles as u64, "{} taken: {}", entry.mnemonic, taken);
+                assert_eq!(gb.z80.m, cycles / 4);
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_sets_flags() {
+        let rom = micro_rom(
+            "
+            ld a, $45
+            add a, $38     ; BCD 45 + 38
+            daa            ; 83
+            ld b, a
+            ld a, $10
+            sub $20        ; borrow
+            ld c, a
+            ld a, $0f
+            scf
+            adc a, $00     ; half carry from carry in
+            ld d, a
+            ld hl, $c000
+            ld (hl), $ff
+            inc (hl)
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        run(&mut gb, 3);
+        assert_eq!(gb.z80.a, 0x83);
+        run(&mut gb, 4);
+        assert_eq!((gb.z80.c, gb.z80.f), (0xf0, Flags::SUBSTRACTION | Flags::CARRY));
+        run(&mut gb, 4);
+        assert_eq!((gb.z80.d, gb.z80.f), (0x10, Flags::HALF_CARRY));
+        run(&mut gb, 3);
+        assert_eq!(gb.read_memory(0xc000, 1), [0x00]);
+        // INC leaves carry alone
+        assert_eq!(gb.z80.f, Flags::ZERO | Flags::HALF_CARRY);
+    }
+
+    #[test]
+    fn loops_and_bit_operations() {
+        let rom = micro_rom(
+            "
+            ld b, 5
+            xor a
+            loop:
+            add a, b
+            dec b
+            jr nz, loop    ; 5+4+3+2+1
+            swap a         ; $0F -> $F0
+            ld hl, $c010
+            ld (hl), a
+            set 0, (hl)
+            srl (hl)       ; $F1 -> $78, carry
+            bit 7, (hl)
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        run(&mut gb, 2 + 5 * 3);
+        assert_eq!(gb.z80.a, 15);
+        run(&mut gb, 6);
+        assert_eq!(gb.read_memory(0xc010, 1), [0x78]);
+        assert_eq!(gb.z80.f, Flags::ZERO | Flags::HALF_CARRY | Flags::CARRY);
+    }
+
+    #[test]
+    fn vblank_interrupt_wakes_halt() {
+        let mut rom = micro_rom(
+            "
+            xor a
+            ldh ($0f), a   ; the VBlank the boot ROM left pending
+            inc a
+            ldh ($ff), a   ; IE = VBlank
+            ld a, $91
+            ldh ($40), a
+            ei
+            wait:
+            halt
+            jr wait
+            ",
+        );
+        let handler = assemble("ld hl, $ff80\ninc (hl)\nreti", 0x40).unwrap();
+        rom[0x40..0x40 + handler.len()].copy_from_slice(&handler);
+        let mut gb = GB::new(&rom).unwrap();
+        gb.run_frames(2);
+        run(&mut gb, 10);
+        assert_eq!(gb.read_memory(0xff80, 1), [2]);
+        assert_eq!(gb.mmu.io[0x0f] & 0x01, 0);
+        assert!(gb.z80.ime);
+    }
+
+    #[test]
+    fn halt_bug_repeats_next_byte() {
+        let rom = micro_rom(
+            "
+            ld a, $01
+            ldh ($ff), a
+            ldh ($0f), a   ; VBlank pending, interrupts disabled
+            halt
+            inc b          ; runs twice
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        run(&mut gb, 6);
+        assert_eq!(gb.z80.b, 2);
+    }
+
+    // flat 64K of RAM, IF / IE as plain bits
+    struct Ram {
+        mem: Vec<u8>,
+        t: u32,
+        requested: u8,
+    }
+
+    impl Bus for Ram {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.mem[addr as usize]
+        }
+        fn write(&mut self, addr: u16, val: u8) {
+            self.mem[addr as usize] = val;
+        }
+        fn tick(&mut self, t: u32) {
+            self.t += t;
+        }
+        fn pending_interrupts(&mut self) -> u8 {
+            self.requested
+        }
+        fn acknowledge_interrupt(&mut self, bit: u8) {
+            self.requested &= !(1 << bit);
+        }
+    }
+
+    fn sm83(src: &str) -> Sm83<Ram> {
+        let mut mem = vec![0; 0x10000];
+        let code = assemble(src, 0).unwrap();
+        mem[..code.len()].copy_from_slice(&code);
+        Sm83::new(Ram { mem, t: 0, requested: 0 })
+    }
+
+    #[test]
+    fn sm83_runs_on_any_bus() {
+        let mut cpu = sm83("ld sp, $fffe\nld b, 3\nxor a\nloop:\nadd a, b\ndec b\njr nz, loop\nld ($8000), a\nhalt");
+        let mut t = 0;
+        while !cpu.halted() {
+            t += cpu.step();
+        }
+        assert_eq!(cpu.bus().mem[0x8000], 6);
+        assert_eq!(cpu.registers().a, 6);
+        // every step's cycles went through Bus::tick
+        assert_eq!(t, cpu.bus().t);
+        assert_eq!(t, 12 + 8 + 4 + 3 * 8 + 2 * 12 + 8 + 16 + 4);
+        // F drops its low nibble
+        cpu.set_registers(Registers { f: 0xff, pc: 0x1234, ..cpu.registers() });
+        assert_eq!((cpu.registers().f, cpu.registers().pc), (0xf0, 0x1234));
+        cpu.reset();
+        assert_eq!(cpu.registers(), Registers::default());
+    }
+
+    #[test]
+    fn sm83_services_bus_interrupts() {
+        let mut cpu = sm83("ld sp, $fffe\nei\nwait:\nhalt\njr wait");
+        cpu.bus_mut().mem[0x50] = 0xd9; // RETI at the timer vector
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert!(cpu.halted() && cpu.ime());
+        cpu.bus_mut().requested = 0x04;
+        // waking from HALT takes an M-cycle more than the dispatch
+        assert_eq!(cpu.step(), 24);
+        assert_eq!((cpu.registers().pc, cpu.bus().requested, cpu.ime()), (0x50, 0, false));
+        cpu.step();
+        assert_eq!(cpu.registers().pc, 0x0005);
+        assert!(cpu.ime());
+        // back at `jr wait`, not halted
+        cpu.bus_mut().requested = 0x04;
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.registers().pc, 0x50);
+    }
+}
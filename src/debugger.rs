@@ -0,0 +1,560 @@
+// interactive debugger on stdin, started with --debug
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::cheats;
+use crate::disasm;
+use crate::freeze::{self, Hold};
+use crate::hexedit::HexEditor;
+use crate::memsearch::MemorySearch;
+use crate::palette::PaletteSettings;
+use crate::png;
+use crate::ppu::{PPUEvents, Renderer};
+use crate::savestate;
+use crate::session::Session;
+use crate::sram;
+use crate::vramview;
+use crate::{Flags, Subsystem, GB};
+
+// cycles between checks whether user asked to pause
+const PAUSE_POLL_CYCLES: u32 = 4096;
+
+const HELP: &str = "\
+commands (addresses in hex, counts in decimal, empty line repeats last command):
+  c, continue            run until breakpoint / watchpoint, Enter pauses
+  s, step [n]            execute n instructions
+  f, frame [n]           run until n frames finished
+  b, break <addr>        add PC breakpoint
+  w, watch <addr> [r|w]  stop on memory read / write, both by default
+  d, delete <addr>       remove breakpoint and watchpoint
+  l, list                list breakpoints and watchpoints
+  r, regs                show registers and flags
+  x, mem <addr> [len]    dump memory
+  disas [addr] [n]       disassemble n instructions, from PC by default
+  poke <addr> <val>      write memory
+  hex [addr]             hex editor with search and freezing, at C000 by default
+  search [<val>|inc|dec|same|changed|start]  narrow down WRAM / HRAM addresses, `search help`
+  display <addr> [len]   print memory after every frame while running, lists them by default
+  undisplay <addr>       stop printing it
+  freeze <addr> <val> [frame]  hold memory at a value, on writes or once per frame
+  unfreeze <addr>        release held memory
+  frozen                 list held memory, kept in <rom>.freeze in save dir
+  save [file]            write savestate, <rom>.state in save dir by default
+  load [file]            restore savestate
+  sram export|import [file]  cartridge RAM to / from <rom>.sav in save dir
+  cheat [add <code>|on <n>|off <n>|clear]  Game Genie / GameShark codes, lists them by default
+  cart [n]               list cartridges or switch to cartridge n
+  insert <file>          put another cartridge in and power on, the battery save is written first
+  reset <part>           power on cpu, ppu, timer, serial, joypad, hdma or mapper alone
+  renderer [name]        show or switch the PPU's renderer, scanline or fifo
+  vram <file>            PNG of tiles, both BG maps with the screen outlined, OAM and palettes
+  oam                    list the 40 sprites
+  trace on|off           toggle instruction trace (--trace, trace feature)
+  q, quit                exit";
+
+bitflags::bitflags! {
+    pub struct Access: u8 {
+        const READ = 0x01;
+        const WRITE = 0x02;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub access: Access,
+    pub value: Option<u8>,
+}
+
+// checked by MMU::rb / MMU::wb, reads only have &self so the hit goes into a Cell
+#[derive(Default)]
+pub struct Watchpoints {
+    watched: BTreeMap<u16, Access>,
+    hit: Cell<Option<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn read(&self, addr: u16) {
+        if self.watched.is_empty() {
+            return;
+        }
+        if self.watched.get(&addr).is_some_and(|a| a.contains(Access::READ)) {
+            self.hit.set(Some(WatchHit { addr, access: Access::READ, value: None }));
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        if self.watched.is_empty() {
+            return;
+        }
+        if self.watched.get(&addr).is_some_and(|a| a.contains(Access::WRITE)) {
+            self.hit.set(Some(WatchHit { addr, access: Access::WRITE, value: Some(val) }));
+        }
+    }
+
+    pub fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+enum Stop {
+    Done,
+    Breakpoint(u16),
+    Watchpoint(WatchHit),
+    Paused,
+    Crashed(String),
+    // --diagnostics found something
+    Diagnostic(String),
+}
+
+struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    input: Receiver<String>,
+    // line typed while running, it paused emulation and runs next
+    pending: Option<String>,
+    // lines go to the editor while it is open
+    hex: Option<HexEditor>,
+    search: MemorySearch,
+    // start and length of memory printed every frame
+    displays: BTreeMap<u16, usize>,
+}
+
+pub fn run(session: &mut Session, save_dir: &Path) {
+    let (tx, input) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut debugger = Debugger {
+        breakpoints: BTreeSet::new(),
+        input,
+        pending: None,
+        hex: None,
+        search: MemorySearch::default(),
+        displays: BTreeMap::new(),
+    };
+    println!("gb-rust debugger, `help` lists commands");
+    print_regs(session.active());
+    let mut last = String::new();
+    loop {
+        print!("{}", if debugger.hex.is_some() { "hex> " } else { "> " });
+        io::stdout().flush().ok();
+        let next = debugger.pending.take().map_or_else(|| debugger.input.recv(), Ok);
+        if let Some(editor) = debugger.hex.as_mut() {
+            let Ok(line) = next else {
+                return;
+            };
+            let before = session.active().frozen().clone();
+            match editor.command(session.active_mut(), &line) {
+                Ok(true) => {}
+                Ok(false) => debugger.hex = None,
+                Err(e) => println!("{}", e),
+            }
+            if *session.active().frozen() != before {
+                persist_frozen(session, save_dir);
+            }
+            continue;
+        }
+        let line = match next {
+            Ok(line) if line.trim().is_empty() => last.clone(),
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        last = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.first().copied() {
+            Some("cart") => cart(session, words.get(1).copied()),
+            Some("insert") => match line.trim_start()["insert".len()..].trim() {
+                "" => Err("missing argument".to_string()),
+                file => session.load(save_dir, file).map(|message| {
+                    println!("{}", message);
+                    print_regs(session.active());
+                }),
+            },
+            Some(command @ ("save" | "load")) => state(session, save_dir, command, words.get(1).copied()),
+            Some(command @ ("freeze" | "unfreeze" | "frozen")) => frozen(session, save_dir, command, &words[1..]),
+            Some("cheat") => cheats::command(session.active_mut().cheats_mut(), &words[1..]).map(|message| println!("{}", message)),
+            Some("sram") => {
+                let name = session.active_name().to_string();
+                sram::command(session.active_mut(), save_dir, &name, &words[1..]).map(|message| println!("{}", message))
+            }
+            _ => debugger.command(session.active_mut(), &words),
+        };
+        if let Err(e) = result {
+            match e.as_str() {
+                "quit" => return,
+                _ => println!("{}", e),
+            }
+        }
+    }
+}
+
+impl Debugger {
+    fn command(&mut self, gb: &mut GB, words: &[&str]) -> Result<(), String> {
+        let arg = |n: usize| words.get(n).copied().ok_or_else(|| "missing argument".to_string());
+        match words.first().copied().unwrap_or("") {
+            "" => {}
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Err("quit".to_string()),
+            "c" | "continue" => {
+                let stop = self.run_until(gb, |_| false);
+                report(gb, stop);
+            }
+            "s" | "step" => {
+                let mut left = count(words.get(1))?;
+                let stop = self.run_until(gb, |_| {
+                    left -= 1;
+                    left == 0
+                });
+                report(gb, stop);
+            }
+            "f" | "frame" => {
+                let mut left = count(words.get(1))?;
+                let stop = self.run_until(gb, |events| {
+                    if events.contains(PPUEvents::VBLANK) {
+                        left -= 1;
+                    }
+                    left == 0
+                });
+                report(gb, stop);
+            }
+            "b" | "break" => {
+                self.breakpoints.insert(addr(arg(1)?)?);
+            }
+            "w" | "watch" => {
+                let access = match words.get(2).copied() {
+                    None | Some("rw") => Access::READ | Access::WRITE,
+                    Some("r") => Access::READ,
+                    Some("w") => Access::WRITE,
+                    Some(other) => return Err(format!("unknown access `{}`, expected r, w or rw", other)),
+                };
+                gb.mmu.watchpoints.watched.insert(addr(arg(1)?)?, access);
+            }
+            "d" | "delete" => {
+                let addr = addr(arg(1)?)?;
+                self.breakpoints.remove(&addr);
+                gb.mmu.watchpoints.watched.remove(&addr);
+            }
+            "l" | "list" => {
+                for addr in &self.breakpoints {
+                    println!("break {:04X}", addr);
+                }
+                for (addr, access) in &gb.mmu.watchpoints.watched {
+                    println!("watch {:04X} {:?}", addr, access);
+                }
+            }
+            "r" | "regs" => print_regs(gb),
+            "x" | "mem" => {
+                let start = addr(arg(1)?)?;
+                let len = match words.get(2) {
+                    Some(_) => count(words.get(2))?,
+                    None => 64,
+                };
+                dump(gb, start, len);
+            }
+            "disas" => {
+                let start = match words.get(1) {
+                    Some(word) => addr(word)?,
+                    None => gb.z80.pc,
+                };
+                let count = match words.get(2) {
+                    Some(_) => count(words.get(2))?,
+                    None => 10,
+                };
+                for instr in disasm::disassemble(|addr| peek(gb, addr), start, count) {
+                    println!("{:04X}: {}", instr.addr, instr.text);
+                }
+            }
+            "poke" => {
+                let addr = addr(arg(1)?)?;
+                let val = u8::from_str_radix(hex(arg(2)?), 16).map_err(|e| e.to_string())?;
+                gb.mmu.poke(addr, val)?;
+            }
+            "reset" => {
+                let part = Subsystem::parse(arg(1)?)?;
+                gb.reset_subsystem(part);
+                println!("{} reset", part.name());
+            }
+            "renderer" => {
+                if let Some(name) = words.get(1) {
+                    gb.ppu_mut().set_renderer(Renderer::parse(name)?);
+                }
+                println!("{} renderer", gb.ppu().renderer().name());
+            }
+            "vram" => {
+                let path = arg(1)?;
+                let rgb = vramview::render(gb, &PaletteSettings::default());
+                let data = png::encode(vramview::WIDTH as u32, vramview::HEIGHT as u32, 3, &rgb);
+                fs::write(path, data).map_err(|e| format!("can't write {}: {}", path, e))?;
+                println!("wrote tiles, BG maps, OAM and palettes to {}", path);
+            }
+            "oam" => print!("{}", vramview::sprite_list(gb)),
+            "search" => println!("{}", self.search.command(gb, &words[1..])?),
+            "display" if words.len() == 1 => {
+                for (&start, &len) in &self.displays {
+                    println!("display {:04X} {}", start, len);
+                }
+            }
+            "display" => {
+                let len = match words.get(2) {
+                    Some(_) => count(words.get(2))?,
+                    None => 1,
+                };
+                self.displays.insert(addr(arg(1)?)?, len);
+            }
+            "undisplay" => {
+                let addr = addr(arg(1)?)?;
+                if self.displays.remove(&addr).is_none() {
+                    return Err(format!("{:04X} isn't displayed", addr));
+                }
+            }
+            "hex" => {
+                let start = match words.get(1) {
+                    Some(word) => addr(word)?,
+                    None => 0xc000,
+                };
+                let editor = HexEditor::new(start);
+                print!("{}", editor.render(gb));
+                println!("`help` lists editor commands, `q` leaves");
+                self.hex = Some(editor);
+            }
+            #[cfg(feature = "trace")]
+            "trace" => {
+                let enabled = match arg(1)? {
+                    "on" => true,
+                    "off" => false,
+                    other => return Err(format!("expected on or off, got `{}`", other)),
+                };
+                let tracer = gb.tracer.as_mut().ok_or("no trace file, start with --trace <file>")?;
+                tracer.set_enabled(enabled).map_err(|e| e.to_string())?;
+            }
+            other => return Err(format!("unknown command `{}`, try `help`", other)),
+        }
+        Ok(())
+    }
+
+    // runs until a breakpoint, watchpoint, user pause or done(events) returns true
+    fn run_until(&mut self, gb: &mut GB, mut done: impl FnMut(PPUEvents) -> bool) -> Stop {
+        let mut first = true;
+        let mut poll = 0;
+        loop {
+            // stepping off the breakpoint we are stopped at is fine
+            if !first && self.breakpoints.contains(&gb.z80.pc) {
+                return Stop::Breakpoint(gb.z80.pc);
+            }
+            first = false;
+            let events = gb.cycle();
+            if let Some(e) = gb.crash() {
+                return Stop::Crashed(e.to_string());
+            }
+            if let Some(diagnostic) = gb.take_diagnostics().into_iter().next() {
+                return Stop::Diagnostic(diagnostic.to_string());
+            }
+            if events.contains(PPUEvents::VBLANK) && !self.displays.is_empty() {
+                println!("{}", self.display_line(gb));
+            }
+            if let Some(hit) = gb.mmu.watchpoints.take_hit() {
+                return Stop::Watchpoint(hit);
+            }
+            if done(events) {
+                return Stop::Done;
+            }
+            poll += 1;
+            if poll == PAUSE_POLL_CYCLES {
+                poll = 0;
+                match self.input.try_recv() {
+                    Ok(line) => {
+                        self.pending = Some(line);
+                        return Stop::Paused;
+                    }
+                    Err(TryRecvError::Disconnected) => return Stop::Paused,
+                    Err(TryRecvError::Empty) => {}
+                }
+            }
+        }
+    }
+
+    // e.g. `frame 120: C0A0=03 00 FF85=01`
+    fn display_line(&self, gb: &GB) -> String {
+        let shown: Vec<String> = self
+            .displays
+            .iter()
+            .map(|(&start, &len)| {
+                let bytes: Vec<String> = gb.read_memory(start, len).iter().map(|b| format!("{:02X}", b)).collect();
+                format!("{:04X}={}", start, bytes.join(" "))
+            })
+            .collect();
+        format!("frame {}: {}", gb.frames_elapsed(), shown.join(" "))
+    }
+}
+
+fn cart(session: &mut Session, index: Option<&str>) -> Result<(), String> {
+    match index {
+        None => {
+            for (i, name) in session.names().enumerate() {
+                let marker = if i == session.active_index() { '*' } else { ' ' };
+                println!("{}{}: {}", marker, i, name);
+            }
+        }
+        Some(index) => {
+            let switched = index.parse().is_ok_and(|index| session.switch(index));
+            if !switched {
+                return Err(format!("no cartridge `{}`", index));
+            }
+            println!("switched to {}", session.active_name());
+            print_regs(session.active());
+        }
+    }
+    Ok(())
+}
+
+// relative paths are inside the save directory
+fn state(session: &mut Session, save_dir: &Path, command: &str, file: Option<&str>) -> Result<(), String> {
+    let path = match file {
+        Some(file) => save_dir.join(file),
+        None => savestate::default_path(save_dir, session.active_name()),
+    };
+    let gb = session.active_mut();
+    match command {
+        "save" => {
+            let state = savestate::save(gb);
+            fs::create_dir_all(save_dir).map_err(|e| e.to_string())?;
+            fs::write(&path, &state).map_err(|e| format!("{}: {}", path.display(), e))?;
+            println!("saved {} bytes to {}", state.len(), path.display());
+        }
+        _ => {
+            let state = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            savestate::load(gb, &state).map_err(|e| e.to_string())?;
+            print_regs(gb);
+        }
+    }
+    Ok(())
+}
+
+fn frozen(session: &mut Session, save_dir: &Path, command: &str, args: &[&str]) -> Result<(), String> {
+    let arg = |n: usize| args.get(n).copied().ok_or_else(|| "missing argument".to_string());
+    let gb = session.active_mut();
+    match command {
+        "freeze" => {
+            let val = u8::from_str_radix(hex(arg(1)?), 16).map_err(|_| format!("invalid value `{}`", args[1]))?;
+            let hold = match args.get(2).copied() {
+                None => Hold::Write,
+                Some("frame") => Hold::Frame,
+                Some(other) => return Err(format!("expected `frame`, got `{}`", other)),
+            };
+            gb.freeze(addr(arg(0)?)?, val, hold)?;
+        }
+        "unfreeze" => {
+            let addr = addr(arg(0)?)?;
+            if !gb.unfreeze(addr) {
+                return Err(format!("{:04X} isn't frozen", addr));
+            }
+        }
+        _ => {
+            print!("{}", gb.frozen().to_text());
+            return Ok(());
+        }
+    }
+    persist_frozen(session, save_dir);
+    Ok(())
+}
+
+fn persist_frozen(session: &Session, save_dir: &Path) {
+    let path = freeze::default_path(save_dir, session.active_name());
+    if let Err(e) = freeze::save(&path, session.active().frozen()) {
+        println!("can't write {}: {}", path.display(), e);
+    }
+}
+
+fn report(gb: &GB, stop: Stop) {
+    match stop {
+        Stop::Done => {}
+        Stop::Breakpoint(addr) => println!("breakpoint at {:04X}", addr),
+        Stop::Watchpoint(hit) => match hit.value {
+            Some(val) => println!("watchpoint: write {:02X} to {:04X}", val, hit.addr),
+            None => println!("watchpoint: read {:04X}", hit.addr),
+        },
+        Stop::Paused => println!("paused"),
+        Stop::Crashed(message) => println!("crashed: {}", message),
+        Stop::Diagnostic(report) => println!("diagnostics: {}", report),
+    }
+    print_regs(gb);
+}
+
+fn print_regs(gb: &GB) {
+    let z80 = &gb.z80;
+    let flag = |flag: Flags, c: char| if z80.f.contains(flag) { c } else { '-' };
+    println!(
+        "A:{:02X} F:{}{}{}{} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} LY:{:02X}",
+        z80.a,
+        flag(Flags::ZERO, 'Z'),
+        flag(Flags::SUBSTRACTION, 'N'),
+        flag(Flags::HALF_CARRY, 'H'),
+        flag(Flags::CARRY, 'C'),
+        z80.b,
+        z80.c,
+        z80.d,
+        z80.e,
+        z80.h,
+        z80.l,
+        z80.sp,
+        z80.pc,
+        gb.mmu.io[0x44],
+    );
+    println!(
+        "frame {} cycle {} time {:.3}s",
+        gb.frames_elapsed(),
+        gb.cycles_elapsed(),
+        gb.emulated_time().as_secs_f64()
+    );
+    println!("{:04X}: {}", z80.pc, disasm::decode(|addr| peek(gb, addr), z80.pc).text);
+}
+
+fn peek(gb: &GB, addr: u16) -> u8 {
+    match addr {
+        // unusable memory
+        0xfea0..=0xfeff => 0xff,
+        _ => gb.mmu.peek(addr),
+    }
+}
+
+fn dump(gb: &GB, start: u16, len: usize) {
+    for row in (0..len).step_by(16) {
+        let base = start.wrapping_add(row as u16);
+        let bytes: Vec<String> = (0..16.min(len - row) as u16)
+            .map(|i| base.wrapping_add(i))
+            .map(|addr| match addr {
+                0xfea0..=0xfeff => "--".to_string(),
+                _ => format!("{:02X}", peek(gb, addr)),
+            })
+            .collect();
+        println!("{:04X}: {}", base, bytes.join(" "));
+    }
+}
+
+fn hex(word: &str) -> &str {
+    word.strip_prefix("0x").or_else(|| word.strip_prefix('$')).unwrap_or(word)
+}
+
+fn addr(word: &str) -> Result<u16, String> {
+    u16::from_str_radix(hex(word), 16).map_err(|_| format!("invalid address `{}`", word))
+}
+
+fn count(word: Option<&&str>) -> Result<usize, String> {
+    match word {
+        None => Ok(1),
+        Some(word) => match word.parse() {
+            Ok(0) | Err(_) => Err(format!("invalid count `{}`", word)),
+            Ok(n) => Ok(n),
+        },
+    }
+}
@@ -0,0 +1,183 @@
+// --diagnostics: what usually ends in a game (or the emulator) quietly going
+// wrong, reported with the registers and the code around PC when it happens:
+//
+//   running code in VRAM or OAM, nearly always a jump through a bad pointer
+//   SP moving into ROM, a push then writes to the mapper, or a pop past FFFF
+//   reading WRAM nothing wrote since power on, random on a real Game Boy
+//   a jump to itself while no interrupt can be taken, the game hung
+//
+// each kind is reported once per instruction address, the game runs on
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::disasm;
+use crate::{Z80, GB};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    // from is the instruction that went there
+    ExecutingVideoRam { from: u16 },
+    StackInRom { sp: u16 },
+    UninitializedRead { addr: u16 },
+    Stuck,
+}
+
+impl Problem {
+    fn kind(self) -> u8 {
+        match self {
+            Problem::ExecutingVideoRam { .. } => 0,
+            Problem::StackInRom { .. } => 1,
+            Problem::UninitializedRead { .. } => 2,
+            Problem::Stuck => 3,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::ExecutingVideoRam { from } => write!(f, "running code in video memory, jumped from {:04X}", from),
+            Problem::StackInRom { sp } => write!(f, "stack pointer {:04X} is in ROM", sp),
+            Problem::UninitializedRead { addr } => write!(f, "read of WRAM {:04X}, which nothing wrote", addr),
+            Problem::Stuck => write!(f, "jump to itself with no interrupt able to end it, the game hung"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub problem: Problem,
+    // the instruction that ran into it
+    pub pc: u16,
+    // registers and disassembly, several lines
+    pub dump: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}: {}\n{}", self.pc, self.problem, self.dump)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Diagnostics {
+    // of the instruction before, and SP then
+    last_pc: Option<u16>,
+    last_sp: Option<u16>,
+    reported: HashSet<(u8, u16)>,
+    pub(crate) found: Vec<Diagnostic>,
+}
+
+// a push writes below SP, from 8000 down that's the mapper
+fn stack_in_rom(sp: u16) -> bool {
+    sp <= 0x8000
+}
+
+impl Diagnostics {
+    // what the instruction about to run at PC ran into
+    pub(crate) fn instruction(&mut self, z80: &Z80, interrupts_enabled: u8) -> Vec<Problem> {
+        let from = self.last_pc.replace(z80.pc);
+        let last_sp = self.last_sp.replace(z80.sp);
+        let mut problems = Vec::new();
+        if matches!(z80.pc, 0x8000..=0x9fff | 0xfe00..=0xfeff) {
+            problems.push(Problem::ExecutingVideoRam { from: from.unwrap_or(z80.pc) });
+        }
+        // SP starts at 0 without the boot ROM, only moving there counts
+        if stack_in_rom(z80.sp) && last_sp.is_some_and(|sp| !stack_in_rom(sp)) {
+            problems.push(Problem::StackInRom { sp: z80.sp });
+        }
+        let no_interrupts = !(z80.ime || z80.ei_pending) || interrupts_enabled & 0x1f == 0;
+        if from == Some(z80.pc) && no_interrupts && !z80.halt_bug {
+            problems.push(Problem::Stuck);
+        }
+        problems
+    }
+
+    // where the instruction running began
+    pub(crate) fn pc(&self) -> u16 {
+        self.last_pc.unwrap_or_default()
+    }
+
+    // not seen at pc yet
+    pub(crate) fn new_at(&self, problem: Problem, pc: u16) -> bool {
+        !self.reported.contains(&(problem.kind(), pc))
+    }
+
+    pub(crate) fn report(&mut self, problem: Problem, pc: u16, dump: String) {
+        self.reported.insert((problem.kind(), pc));
+        self.found.push(Diagnostic { problem, pc, dump });
+    }
+}
+
+// the registers, the top of the stack and the next few instructions at pc
+pub(crate) fn dump(gb: &GB, pc: u16) -> String {
+    let z80 = &gb.z80;
+    let peek = |addr: u16| gb.mmu.peek(addr);
+    let mut out = format!(
+        "  AF:{:02X}{:02X} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} IME:{} IE:{:02X} IF:{:02X} LY:{:02X}\n  stack:",
+        z80.a,
+        z80.f.bits(),
+        z80.b,
+        z80.c,
+        z80.d,
+        z80.e,
+        z80.h,
+        z80.l,
+        z80.sp,
+        z80.ime as u8,
+        gb.mmu.work_ram[0x7f],
+        gb.mmu.io[0x0f],
+        gb.mmu.io[0x44],
+    );
+    for i in 0..4 {
+        let addr = z80.sp.wrapping_add(i * 2);
+        out += &format!(" {:04X}", u16::from_le_bytes([peek(addr), peek(addr.wrapping_add(1))]));
+    }
+    for instr in disasm::disassemble(peek, pc, 4) {
+        out += &format!("\n  {}{:04X}: {}", if instr.addr == pc { "> " } else { "  " }, instr.addr, instr.text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::micro_rom;
+    use crate::GB;
+
+    use super::*;
+
+    #[test]
+    fn reports_what_goes_wrong() {
+        // turns the LCD off to write VRAM, reads C100 before writing it, then puts
+        // `jr @` in VRAM and jumps there
+        let rom = micro_rom(
+            "xor a\nldh ($40), a\nld sp, $d000\nld a, ($c100)\nld ($c100), a\nld a, ($c100)\nld a, $18\nld ($8000), a\nld a, $fe\nld ($8001), a\n\
+             jp $8000",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        gb.set_diagnostics(true);
+        // IME is off
+        gb.run_until(1, |gb| gb.z80.pc == 0x8000 && gb.diagnostics.as_ref().unwrap().found.len() >= 3);
+        let found = gb.take_diagnostics();
+        let problems: Vec<(u16, Problem)> = found.iter().map(|d| (d.pc, d.problem)).collect();
+        assert_eq!(
+            problems,
+            [
+                (0x0106, Problem::UninitializedRead { addr: 0xc100 }),
+                (0x8000, Problem::ExecutingVideoRam { from: 0x0119 }),
+                (0x8000, Problem::Stuck),
+            ]
+        );
+        assert!(found[1].dump.contains("> 8000: JR $8000"), "{}", found[1].dump);
+        assert!(found[1].to_string().starts_with("8000: running code in video memory"));
+
+        gb.z80.sp = 0x4000;
+        gb.cycle();
+        let found = gb.take_diagnostics();
+        assert_eq!(found[0].problem, Problem::StackInRom { sp: 0x4000 });
+        // reported once
+        gb.run_frames(1);
+        assert!(gb.take_diagnostics().is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+// LR35902 disassembler, decodes through the same opcode table as the interpreter
+
+use crate::opcodes::{Opcode, CB_OPCODES, OPCODES};
+
+pub struct Instruction {
+    pub addr: u16,
+    pub length: u8,
+    // e.g. `LD A,$91` or `JR NZ,$0150`, undefined opcodes show as `DB $D3`
+    pub text: String,
+}
+
+// decodes a single instruction, read gives bytes of a slice or live memory
+pub fn decode(read: impl Fn(u16) -> u8, addr: u16) -> Instruction {
+    let opcode = read(addr);
+    let (entry, operand_addr): (&Opcode, u16) = match opcode {
+        0xcb => (&CB_OPCODES[read(addr.wrapping_add(1)) as usize], addr.wrapping_add(2)),
+        _ => (&OPCODES[opcode as usize], addr.wrapping_add(1)),
+    };
+    if entry.mnemonic.is_empty() {
+        return Instruction {
+            addr,
+            length: 1,
+            text: format!("DB ${:02X}", opcode),
+        };
+    }
+    let d8 = read(operand_addr);
+    let d16 = u16::from_le_bytes([d8, read(operand_addr.wrapping_add(1))]);
+    let mnemonic = entry.mnemonic;
+    let text = if mnemonic.contains("d16") {
+        mnemonic.replace("d16", &format!("${:04X}", d16))
+    } else if mnemonic.contains("a16") {
+        mnemonic.replace("a16", &format!("${:04X}", d16))
+    } else if mnemonic.contains("d8") {
+        mnemonic.replace("d8", &format!("${:02X}", d8))
+    } else if mnemonic.contains("a8") {
+        mnemonic.replace("a8", &format!("$FF{:02X}", d8))
+    } else if mnemonic.starts_with("JR") {
+        let target = addr.wrapping_add(entry.length as u16).wrapping_add(d8 as i8 as u16);
+        mnemonic.replace("r8", &format!("${:04X}", target))
+    } else if mnemonic.contains("SP+r8") && (d8 as i8) < 0 {
+        mnemonic.replace("+r8", &format!("-{}", -(d8 as i8 as i16)))
+    } else {
+        mnemonic.replace("r8", &format!("{}", d8 as i8))
+    };
+    Instruction {
+        addr,
+        length: entry.length,
+        text,
+    }
+}
+
+// decodes count instructions starting at addr
+pub fn disassemble(read: impl Fn(u16) -> u8, mut addr: u16, count: usize) -> Vec<Instruction> {
+    let mut out = Vec::with_capacity(count);
+    for _ in 0..count {
+        let instr = decode(&read, addr);
+        addr = addr.wrapping_add(instr.length as u16);
+        out.push(instr);
+    }
+    out
+}
+
+// decodes a whole byte slice loaded at origin, truncated last instruction reads zeros
+pub fn disassemble_bytes(bytes: &[u8], origin: u16) -> Vec<Instruction> {
+    let read = |addr: u16| bytes.get(addr.wrapping_sub(origin) as usize).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instr = decode(read, origin.wrapping_add(offset as u16));
+        offset += instr.length as usize;
+        out.push(instr);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn formats_operands() {
+        let code = [0x3e, 0x91, 0xe0, 0x40, 0x20, 0xfe, 0xf8, 0xfe, 0xcb, 0x7e, 0xd3];
+        let text: Vec<String> = disassemble_bytes(&code, 0x0150).into_iter().map(|i| i.text).collect();
+        assert_eq!(text, ["LD A,$91", "LDH ($FF40),A", "JR NZ,$0154", "LD HL,SP-2", "BIT 7,(HL)", "DB $D3"]);
+    }
+
+    #[test]
+    fn round_trips_through_assembler() {
+        for (prefix, table) in [(None, &OPCODES), (Some(0xcb), &CB_OPCODES)] {
+            for (opcode, entry) in table.iter().enumerate() {
+                if entry.mnemonic.is_empty() || entry.mnemonic == "PREFIX CB" {
+                    continue;
+                }
+                let mut bytes: Vec<u8> = prefix.into_iter().chain([opcode as u8, 0x12, 0x34]).collect();
+                bytes.truncate(entry.length as usize);
+                let instr = decode(|addr| bytes.get(addr as usize).copied().unwrap_or(0), 0);
+                assert_eq!(instr.length as usize, bytes.len());
+                assert_eq!(assemble(&instr.text, 0).unwrap(), bytes, "{}", instr.text);
+            }
+        }
+    }
+}
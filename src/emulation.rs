@@ -0,0 +1,545 @@
+// the emulation thread: the session runs here, paced by the throttle, and
+// hands finished frames to the presenters through triple buffers. the UI
+// thread polls gamepads and stdin and sends what it gets as commands, so a
+// turbo run, a slow frame or a paused game never keeps input waiting. the game
+// sees the buttons last sent at its next frame
+//
+// commands are taken every 4096 cycles, often enough that a button press is
+// never a frame late. pause blocks on the queue until resume or quit
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::thread::{self, ScopedJoinHandle};
+use std::time::Duration;
+
+use gb_rust::inputdisplay::InputDisplay;
+use gb_rust::palette::PaletteSettings;
+use gb_rust::ppu::{self, PPUEvents};
+use gb_rust::recorder::{self, Recorder};
+use gb_rust::screenshot::{self, FrameDump};
+use gb_rust::script::Script;
+use gb_rust::session::Session;
+use gb_rust::throttle::Throttle;
+use gb_rust::triplebuffer::Producer;
+use gb_rust::{cheats, savestate, sram, vramview, Buttons, GB};
+
+use crate::cli::Options;
+use crate::fail;
+use crate::gamepad::{Gamepads, Hotkey};
+
+// how long the UI thread waits between polls
+const POLL: Duration = Duration::from_millis(4);
+// --arcade autosaves once a minute too, kiosks get switched off without asking
+const AUTOSAVE_FRAMES: u64 = 60 * 60;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    // held on each player's pad, the second one only counts with --two-player
+    Input([Buttons; 2]),
+    Hotkey(Hotkey),
+    Pause,
+    Resume,
+    // relative paths are inside the save directory, none is <rom>.state
+    SaveState(Option<String>),
+    LoadState(Option<String>),
+    // another cartridge in the active game's slot, see Session::load
+    Insert(String),
+    // back to the --arcade chooser
+    Menu,
+    // the window showing the frames got or lost focus, unfocused is throttled
+    Focus(bool),
+    // any other stdin line: sram, screenshot, video, cheat and switching games
+    Line(String),
+    Quit,
+}
+
+// why Emulation::run returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ended {
+    Quit,
+    Menu,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Command {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["quit", ..] => Command::Quit,
+            ["pause"] => Command::Pause,
+            ["resume"] => Command::Resume,
+            ["menu"] => Command::Menu,
+            ["focus"] => Command::Focus(true),
+            ["blur"] => Command::Focus(false),
+            ["state", "save"] => Command::SaveState(None),
+            ["state", "save", file] => Command::SaveState(Some(file.to_string())),
+            ["state", "load"] => Command::LoadState(None),
+            ["state", "load", file] => Command::LoadState(Some(file.to_string())),
+            ["insert", ..] => Command::Insert(line.trim_start()["insert".len()..].trim().to_string()),
+            _ => match dropped(line) {
+                Some(path) => Command::Insert(path.to_string()),
+                None => Command::Line(line.to_string()),
+            },
+        }
+    }
+}
+
+// a file dropped on the terminal comes in as its path, quoted when it has spaces
+fn dropped(line: &str) -> Option<&str> {
+    let line = line.trim();
+    let path = ['\'', '"'].into_iter().find_map(|q| line.strip_prefix(q)?.strip_suffix(q)).unwrap_or(line);
+    Path::new(path).is_file().then_some(path)
+}
+
+// the UI thread's side until the emulation thread ends
+pub fn ui(pads: &mut Gamepads, lines: Receiver<String>, commands: Sender<Command>, two_player: bool, core: &ScopedJoinHandle<()>) {
+    let mut held = [Buttons::empty(); 2];
+    while !core.is_finished() {
+        let now = match two_player {
+            true => pads.poll_players(),
+            false => [pads.poll(), Buttons::empty()],
+        };
+        if now != held {
+            held = now;
+            commands.send(Command::Input(held)).ok();
+        }
+        for hotkey in Hotkey::ALL {
+            if pads.pressed(hotkey) {
+                commands.send(Command::Hotkey(hotkey)).ok();
+            }
+        }
+        while let Ok(line) = lines.try_recv() {
+            commands.send(Command::parse(&line)).ok();
+        }
+        thread::sleep(POLL);
+    }
+}
+
+// where finished frames go: --raw-frames and --vram-view
+pub struct Outputs {
+    pub frames: Option<Producer<Vec<u8>>>,
+    pub vram: Option<Producer<Vec<u8>>>,
+}
+
+pub struct Emulation<'s> {
+    session: &'s mut Session,
+    script: Option<Script>,
+    options: &'s Options,
+    save_dir: &'s Path,
+    palettes: &'s PaletteSettings,
+    outputs: &'s mut Outputs,
+    // --arcade: the state the game autosaves to, the menu goes back to the chooser
+    arcade: Option<PathBuf>,
+    throttle: Throttle,
+    held: [Buttons; 2],
+    // a --playback movie still running
+    playing: bool,
+    dump: Option<FrameDump>,
+    fps: f64,
+    recorder: Option<Recorder>,
+    input_display: Option<InputDisplay>,
+    crash_reported: bool,
+}
+
+impl<'s> Emulation<'s> {
+    pub fn new(
+        session: &'s mut Session,
+        script: Option<Script>,
+        options: &'s Options,
+        save_dir: &'s Path,
+        palettes: &'s PaletteSettings,
+        outputs: &'s mut Outputs,
+    ) -> Self {
+        let idle_after = options.idle_throttle.map(Duration::from_secs_f64);
+        let mut throttle = Throttle::new(options.speed, idle_after);
+        throttle.set_clock(options.hardware.clock_hz());
+        let dump = options.dump_frames.as_ref().map(|dir| {
+            FrameDump::new(dir, options.dump_every.unwrap_or(1))
+                .unwrap_or_else(|e| fail(&format!("can't dump frames to {}: {}", dir.display(), e)))
+        });
+        let fps = options.hardware.clock_hz() as f64 / ppu::FRAME_DOTS as f64;
+        let recorder = options.record_video.as_ref().map(|path| {
+            let (width, height) = session.active().display_size();
+            Recorder::start(path, width as u16, height as u16, fps)
+                .unwrap_or_else(|e| fail(&format!("can't record to {}: {}", path.display(), e)))
+        });
+        Emulation {
+            session,
+            script,
+            options,
+            save_dir,
+            palettes,
+            outputs,
+            arcade: None,
+            throttle,
+            held: [Buttons::empty(); 2],
+            playing: options.playback.is_some(),
+            dump,
+            fps,
+            recorder,
+            input_display: options.input_display.map(InputDisplay::new),
+            crash_reported: false,
+        }
+    }
+
+    // a game chosen in --arcade, saved to state once a minute
+    pub fn arcade(&mut self, state: PathBuf) {
+        self.arcade = Some(state);
+    }
+
+    // until quit, the script quitting, the UI thread going away or, in --arcade,
+    // the menu
+    pub fn run(mut self, commands: &Receiver<Command>) -> Ended {
+        let mut ended = Ended::Quit;
+        for cycles in 0u64.. {
+            // two players run both games, the shown one's frames pace them
+            let (index, events) = match self.session.linked() {
+                true => self.session.cycle_linked(),
+                false => (self.session.active_index(), self.session.active_mut().cycle()),
+            };
+            if index == self.session.active_index() && events.contains(PPUEvents::VBLANK) && !self.frame(index) {
+                break;
+            }
+            if !cycles.is_multiple_of(4096) {
+                continue;
+            }
+            self.report();
+            if let Some(stop) = self.commands(commands) {
+                ended = stop;
+                break;
+            }
+        }
+        if let Some(video) = self.recorder {
+            stop_recording(video);
+        }
+        ended
+    }
+
+    // the active game finished a frame, false once the script quit
+    fn frame(&mut self, index: usize) -> bool {
+        let mut buttons = match self.session.linked() {
+            true => {
+                // each pad stays with its game, whichever one is shown
+                self.session.games_mut().nth(1 - index).unwrap().set_buttons(self.held[1 - index]);
+                self.held[index]
+            }
+            false => self.held[0],
+        };
+        let first = index == 0;
+        let gb = self.session.active_mut();
+        if self.playing && first && !gb.movie_playing() {
+            self.playing = false;
+            eprintln!("movie ended at frame {}, input is live again", gb.frames_elapsed());
+        }
+        if let Some(running) = self.script.as_mut().filter(|_| first) {
+            let result = running.frame(gb);
+            for line in running.take_output() {
+                eprintln!("{}", line);
+            }
+            match result {
+                Ok(()) => buttons |= running.buttons(),
+                Err(e) => {
+                    eprintln!("gb-rust: {}, the script stopped", e);
+                    self.script = None;
+                }
+            }
+        }
+        if buttons != gb.buttons() && !gb.movie_playing() {
+            self.throttle.input();
+            gb.set_buttons(buttons);
+        }
+        match gb.ppu().scanline_capture() {
+            Some(capture) if gb.frames_elapsed().is_multiple_of(60) => println!("{}", capture),
+            _ => {}
+        }
+        let palettes = self.palettes;
+        let gb = self.session.active();
+        if let Some(frames) = &self.dump {
+            if let Err(e) = frames.frame(gb, palettes) {
+                eprintln!("gb-rust: can't dump frame {}, stopping: {}", gb.frames_elapsed(), e);
+                self.dump = None;
+            }
+        }
+        if let Some(video) = self.recorder.as_mut() {
+            if let Err(e) = video.frame(&gb.display(palettes)) {
+                eprintln!("gb-rust: can't record to {}, stopping: {}", video.path().display(), e);
+                self.recorder = None;
+            }
+        }
+        if let Some(state) = self.arcade.as_ref().filter(|_| gb.frames_elapsed().is_multiple_of(AUTOSAVE_FRAMES)) {
+            if let Err(e) = autosave(gb, state) {
+                eprintln!("gb-rust: {}", e);
+            }
+        }
+        if let Some(view) = self.outputs.vram.as_mut() {
+            *view.back() = vramview::render(gb, palettes);
+            view.publish();
+        }
+        let pause = self.throttle.frame(gb.screenshot());
+        if let Some(frames) = self.outputs.frames.as_mut() {
+            let mut rgb = gb.display(palettes);
+            if let Some(display) = self.input_display.as_mut() {
+                display.push(gb.frame_buttons());
+                display.draw(&mut rgb, gb.display_size().0);
+            }
+            if let Some(running) = &self.script {
+                running.draw(&mut rgb, gb.display_size().0);
+            }
+            *frames.back() = match self.session.linked() {
+                true => side_by_side(self.session, palettes, index, &rgb),
+                false => rgb,
+            };
+            frames.publish();
+        }
+        thread::sleep(pause);
+        !self.script.as_ref().is_some_and(Script::quit)
+    }
+
+    fn report(&mut self) {
+        // a locked up CPU looks like a hung game, say why once
+        if !self.crash_reported {
+            if let Some((name, e)) = self.session.games().find_map(|(name, gb)| Some((name, gb.crash()?))) {
+                eprintln!("gb-rust: {}: {}", name, e);
+                self.crash_reported = true;
+            }
+        }
+        let names: Vec<String> = self.session.games().map(|(name, _)| name.to_string()).collect();
+        for (name, gb) in names.iter().zip(self.session.games_mut()) {
+            while let Some(e) = gb.take_serial_error() {
+                eprintln!("gb-rust: {}: {}", name, e);
+            }
+            if self.options.diagnostics {
+                for diagnostic in gb.take_diagnostics() {
+                    eprintln!("gb-rust: {}: {}", name, diagnostic);
+                }
+            }
+        }
+    }
+
+    // everything sent since the last check, why to stop if it's time to
+    fn commands(&mut self, commands: &Receiver<Command>) -> Option<Ended> {
+        loop {
+            let command = match commands.try_recv() {
+                Ok(command) => command,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(Ended::Quit),
+            };
+            match command {
+                Command::Quit => return Some(Ended::Quit),
+                Command::Menu | Command::Hotkey(Hotkey::Menu) if self.arcade.is_some() => return Some(Ended::Menu),
+                Command::Pause => {
+                    eprintln!("paused at frame {}", self.session.active().frames_elapsed());
+                    if let Some(stop) = self.paused(commands) {
+                        return Some(stop);
+                    }
+                    // the time paused isn't owed to the game
+                    self.throttle.input();
+                    eprintln!("resumed");
+                }
+                command => self.command(command),
+            }
+        }
+    }
+
+    // runs the commands that come in until resume, why to stop instead
+    fn paused(&mut self, commands: &Receiver<Command>) -> Option<Ended> {
+        loop {
+            match commands.recv() {
+                Ok(Command::Resume) => return None,
+                Ok(Command::Quit) | Err(_) => return Some(Ended::Quit),
+                Ok(Command::Menu | Command::Hotkey(Hotkey::Menu)) if self.arcade.is_some() => return Some(Ended::Menu),
+                Ok(Command::Pause) => {}
+                Ok(command) => self.command(command),
+            }
+        }
+    }
+
+    fn command(&mut self, command: Command) {
+        let save_dir = self.save_dir;
+        let result = match command {
+            Command::Input(held) => {
+                self.held = held;
+                return;
+            }
+            Command::Focus(focused) => {
+                self.throttle.set_focused(focused);
+                return;
+            }
+            Command::Hotkey(Hotkey::Record) => {
+                let path = recorder::default_path(save_dir, self.session.active_name());
+                self.toggle_recording(&path);
+                return;
+            }
+            Command::Hotkey(Hotkey::Screenshot) => {
+                screenshot::command(self.session.active(), self.palettes, save_dir, self.session.active_name(), &[])
+            }
+            Command::SaveState(file) => {
+                let path = match file {
+                    Some(file) => save_dir.join(file),
+                    None => savestate::default_path(save_dir, self.session.active_name()),
+                };
+                let state = savestate::save(self.session.active());
+                fs::create_dir_all(save_dir)
+                    .and_then(|_| fs::write(&path, &state))
+                    .map(|_| format!("saved state to {}", path.display()))
+                    .map_err(|e| format!("can't write {}: {}", path.display(), e))
+            }
+            Command::LoadState(file) => {
+                let path = match file {
+                    Some(file) => save_dir.join(file),
+                    None => savestate::default_path(save_dir, self.session.active_name()),
+                };
+                fs::read(&path)
+                    .map_err(|e| format!("can't read {}: {}", path.display(), e))
+                    .and_then(|state| {
+                        savestate::load(self.session.active_mut(), &state).map_err(|e| format!("{}: {}", path.display(), e))
+                    })
+                    .map(|_| format!("loaded state from {}", path.display()))
+            }
+            Command::Insert(file) if file.is_empty() => Err("expected `insert <file>`".to_string()),
+            Command::Insert(file) => {
+                self.throttle.input();
+                self.playing = false;
+                self.session.load(save_dir, &file)
+            }
+            Command::Line(line) => {
+                self.line(&line);
+                return;
+            }
+            Command::Menu => Err("no chooser to go back to outside --arcade".to_string()),
+            Command::Hotkey(Hotkey::Menu) | Command::Pause | Command::Resume | Command::Quit => return,
+        };
+        match result {
+            Ok(message) => eprintln!("{}", message),
+            Err(e) => eprintln!("gb-rust: {}", e),
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let session = &mut *self.session;
+        let result = match words.first().copied() {
+            Some("sram") => {
+                let name = session.active_name().to_string();
+                sram::command(session.active_mut(), self.save_dir, &name, &words[1..])
+            }
+            Some("screenshot") => {
+                screenshot::command(session.active(), self.palettes, self.save_dir, session.active_name(), &words[1..])
+            }
+            Some("video") => {
+                let path = match words.get(1) {
+                    Some(file) => self.save_dir.join(file),
+                    None => recorder::default_path(self.save_dir, session.active_name()),
+                };
+                self.toggle_recording(&path);
+                return;
+            }
+            Some("cheat") => cheats::command(session.active_mut().cheats_mut(), &words[1..]),
+            Some("state") => Err("expected `state save [file]` or `state load [file]`".to_string()),
+            // Enter or `next` goes on to the next of several games, a number to that one
+            None if session.len() == 1 => return,
+            None | Some("next") => {
+                self.throttle.input();
+                session.next();
+                Ok(format!("switched to {}: {}", session.active_index(), session.active_name()))
+            }
+            Some(word) => match word.parse::<usize>() {
+                Ok(index) if words.len() == 1 && session.switch(index) => {
+                    self.throttle.input();
+                    Ok(format!("switched to {}: {}", session.active_index(), session.active_name()))
+                }
+                Ok(index) if words.len() == 1 => Err(format!("no game {}", index)),
+                _ => Err(format!("unknown command `{}`", word)),
+            },
+        };
+        match result {
+            Ok(message) => eprintln!("{}", message),
+            Err(e) => eprintln!("gb-rust: {}", e),
+        }
+    }
+
+    // starts a video, or stops the one running
+    fn toggle_recording(&mut self, path: &Path) {
+        if let Some(running) = self.recorder.take() {
+            stop_recording(running);
+            return;
+        }
+        let (width, height) = self.session.active().display_size();
+        match Recorder::start(path, width as u16, height as u16, self.fps) {
+            Ok(started) => {
+                eprintln!("recording to {}", path.display());
+                self.recorder = Some(started);
+            }
+            Err(e) => eprintln!("gb-rust: can't record to {}: {}", path.display(), e),
+        }
+    }
+}
+
+// the state an --arcade game resumes from
+pub fn autosave(gb: &GB, path: &Path) -> Result<(), String> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    fs::write(path, savestate::save(gb)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn stop_recording(recorder: Recorder) {
+    let path = recorder.path().to_path_buf();
+    match recorder.stop() {
+        Ok(frames) => eprintln!("recorded {} frames to {}", frames, path.display()),
+        Err(e) => eprintln!("gb-rust: can't finish {}: {}", path.display(), e),
+    }
+}
+
+// --two-player frames, the first game on the left. shown is the active game's
+// screen as already drawn, with the input display
+fn side_by_side(session: &Session, palettes: &PaletteSettings, shown: usize, rgb: &[u8]) -> Vec<u8> {
+    let screens: Vec<Vec<u8>> = session
+        .games()
+        .take(2)
+        .enumerate()
+        .map(|(i, (_, gb))| if i == shown { rgb.to_vec() } else { gb.display(palettes) })
+        .collect();
+    let (width, height) = session.active().display_size();
+    let row = width * 3;
+    (0..height).flat_map(|y| screens.iter().flat_map(move |screen| &screen[y * row..(y + 1) * row])).copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("quit now"), Command::Quit);
+        assert_eq!(Command::parse(" pause "), Command::Pause);
+        assert_eq!(Command::parse("menu"), Command::Menu);
+        assert_eq!(Command::parse("blur"), Command::Focus(false));
+        assert_eq!(Command::parse("state save"), Command::SaveState(None));
+        assert_eq!(Command::parse("state load boss.state"), Command::LoadState(Some("boss.state".into())));
+        assert_eq!(Command::parse("state swap"), Command::Line("state swap".into()));
+        assert_eq!(Command::parse("insert  my game.gb "), Command::Insert("my game.gb".into()));
+        let rom = env::temp_dir().join(format!("gb-rust-dropped {}.gb", std::process::id()));
+        fs::write(&rom, [0]).unwrap();
+        let path = rom.to_string_lossy().into_owned();
+        assert_eq!(Command::parse(&format!("'{}'", path)), Command::Insert(path.clone()));
+        fs::remove_file(&rom).ok();
+        assert_eq!(Command::parse(&path), Command::Line(path.clone()));
+        assert_eq!(Command::parse("cheat add 00A-17B-C49"), Command::Line("cheat add 00A-17B-C49".into()));
+    }
+
+    #[test]
+    fn switches_games_only_when_asked() {
+        let rom = vec![0; 0x8000];
+        let roms = [("a.gb".to_string(), rom.clone()), ("b.gb".to_string(), rom)];
+        let mut session = Session::new(&roms).unwrap();
+        let options = crate::cli::parse(["a.gb".to_string()]).unwrap();
+        let (palettes, mut outputs) = (PaletteSettings::default(), Outputs { frames: None, vram: None });
+        let mut emulation = Emulation::new(&mut session, None, &options, Path::new(""), &palettes, &mut outputs);
+        for (line, active) in [("screnshot", 0), ("", 1), ("0", 0), ("next", 1), ("5", 1), ("1 2", 1), ("next", 0)] {
+            emulation.line(line);
+            assert_eq!(emulation.session.active_index(), active, "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// what can go wrong setting up or running a GB, so an embedding application
+// can tell its user instead of the emulator taking the process down. String
+// errors elsewhere take these with `?` through From
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::cartridge;
+use crate::mbc::BANK_SIZE;
+
+#[derive(Debug)]
+pub enum Error {
+    // less than the fixed bank 0
+    RomTooSmall { len: usize },
+    // [0147] not a cartridge type mbc.rs emulates
+    UnsupportedMbc(u8),
+    InvalidBootRom { len: usize },
+    InvalidOverclock(u32),
+    // a session without cartridges
+    NoRom,
+    SaveIo { path: PathBuf, source: io::Error },
+    SaveTooLarge { path: PathBuf, len: usize, capacity: usize },
+    // a Game Boy Printer sheet that couldn't be written
+    PrintIo { path: PathBuf, source: io::Error },
+    // the CPU ran op at addr and locked up, like the real one
+    IllegalOpcode { op: u8, addr: u16 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::RomTooSmall { len } => write!(f, "ROM has {} bytes, less than the {} of bank 0", len, BANK_SIZE),
+            Error::UnsupportedMbc(kind) => match cartridge::mapper(*kind) {
+                "UNKNOWN" => write!(f, "unknown cartridge type {:02X} in header", kind),
+                mapper => write!(f, "cartridge type {:02X} ({}) isn't emulated", kind, mapper),
+            },
+            Error::InvalidBootRom { len } => write!(f, "expected 256 byte DMG boot ROM, got {} bytes", len),
+            Error::InvalidOverclock(multiplier) => {
+                write!(f, "CPU clock multiplier {} is invalid, expected at least 1", multiplier)
+            }
+            Error::NoRom => write!(f, "no ROM to run"),
+            Error::SaveIo { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::SaveTooLarge { path, len, capacity } => {
+                write!(f, "{} has {} bytes, cartridge RAM only {}", path.display(), len, capacity)
+            }
+            Error::PrintIo { path, source } => write!(f, "can't write print {}: {}", path.display(), source),
+            Error::IllegalOpcode { op, addr } => write!(f, "illegal opcode {:#04x} at {:#06x}, the CPU locked up", op, addr),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::SaveIo { source, .. } | Error::PrintIo { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for String {
+    fn from(e: Error) -> String {
+        e.to_string()
+    }
+}
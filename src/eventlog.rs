@@ -0,0 +1,134 @@
+// JSON lines stream of emulator activity for external tools, one object per event:
+//   {"cycle":70224,"event":"frame","frame":1}
+//   {"cycle":75010,"event":"serial","sent":65,"received":255}
+//   {"cycle":75010,"event":"interrupt","interrupt":"serial"}
+//   {"cycle":80000,"event":"cheat","code":"01FF16D0"}
+// cycle is the t-cycle count at which the event was seen
+
+use std::io::{self, BufWriter, Write};
+use std::net::TcpStream;
+
+use crate::report::json_string;
+use crate::Interrupts;
+
+const INTERRUPT_NAMES: [(Interrupts, &str); 5] = [
+    (Interrupts::VBLANK, "vblank"),
+    (Interrupts::LCD_STAT, "stat"),
+    (Interrupts::TIMER, "timer"),
+    (Interrupts::SERIAL, "serial"),
+    (Interrupts::JOYPAD, "joypad"),
+];
+
+pub enum Event<'a> {
+    Frame,
+    Serial { sent: u8, received: u8 },
+    // a single interrupt
+    Interrupt(Interrupts),
+    // at VBlank, a code changed what the game saw during the frame
+    Cheat { code: &'a str },
+}
+
+pub struct EventLog {
+    // None once the consumer went away, emulation carries on without it
+    out: Option<BufWriter<Box<dyn Write>>>,
+    frames: u64,
+}
+
+impl EventLog {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        EventLog {
+            out: Some(BufWriter::new(out)),
+            frames: 0,
+        }
+    }
+
+    // `-` for stdout, otherwise host:port of a listening TCP socket
+    pub fn open(target: &str) -> io::Result<Self> {
+        let out: Box<dyn Write> = match target {
+            "-" => Box::new(io::stdout()),
+            addr => Box::new(TcpStream::connect(addr)?),
+        };
+        Ok(EventLog::new(out))
+    }
+
+    // one event per requested interrupt
+    pub fn interrupts(&mut self, cycle: u64, requested: Interrupts) {
+        for (interrupt, _) in INTERRUPT_NAMES.iter().filter(|(i, _)| requested.contains(*i)) {
+            self.log(cycle, Event::Interrupt(*interrupt));
+        }
+    }
+
+    pub fn log(&mut self, cycle: u64, event: Event) {
+        let Some(out) = self.out.as_mut() else {
+            return;
+        };
+        // frames are a natural point for consumers to see what happened so far
+        let flush = matches!(event, Event::Frame);
+        let fields = match event {
+            Event::Frame => {
+                self.frames += 1;
+                format!("\"frame\",\"frame\":{}", self.frames)
+            }
+            Event::Serial { sent, received } => format!("\"serial\",\"sent\":{},\"received\":{}", sent, received),
+            Event::Interrupt(interrupt) => {
+                let (_, name) = INTERRUPT_NAMES.iter().find(|(i, _)| interrupt.contains(*i)).unwrap();
+                format!("\"interrupt\",\"interrupt\":\"{}\"", name)
+            }
+            Event::Cheat { code } => format!("\"cheat\",\"code\":{}", json_string(code)),
+        };
+        let mut result = writeln!(out, "{{\"cycle\":{},\"event\":{}}}", cycle, fields);
+        if result.is_ok() && flush {
+            result = out.flush();
+        }
+        if let Err(e) = result {
+            eprintln!("event log closed: {}", e);
+            self.out = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::GB;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn logs_serial_transfer_and_interrupt() {
+        let rom = micro_rom(
+            "
+            ld a, $41
+            ldh ($01), a
+            ld a, $81
+            ldh ($02), a  ; internal clock, 8 bits * 512 t
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        let shared = Shared::default();
+        gb.event_log = Some(EventLog::new(Box::new(shared.clone())));
+        for _ in 0..4 + 8 * 512 / 4 + 2 {
+            gb.cycle();
+        }
+        gb.event_log = None;
+        let text = String::from_utf8(shared.0.take()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", text);
+        assert!(lines[0].ends_with("\"event\":\"serial\",\"sent\":65,\"received\":255}"));
+        assert!(lines[1].ends_with("\"event\":\"interrupt\",\"interrupt\":\"serial\"}"));
+    }
+}
@@ -0,0 +1,308 @@
+// pixel FIFO renderer: draws a line one dot at a time through mode 3 like the
+// hardware does, reading registers as it goes, so writes in the middle of a
+// line (palettes, scroll, window position, LCDC) show from the next pixel or
+// tile fetched instead of at the next line
+//
+// every dot the fetcher works on the next 8 BG / window pixels, 2 dots each
+// for tile number, low and high data, then pushes them once the BG FIFO ran
+// empty. a pixel leaves the FIFO per dot, mixed with the sprite FIFO. mode 3
+// takes 172 dots plus the stalls:
+// - the first fetch of a line is thrown away, 6 dots before any pixel
+// - SCX % 8 pixels are dropped from the first tile, a dot each
+// - reaching WX clears the FIFO and restarts the fetcher on the window map
+// - a sprite starting at the current pixel halts everything while its row is
+//   fetched and mixed into the sprite FIFO, 6 dots
+
+use crate::ppu::{palette_shade, sprite_height, tile_row, SPRITES_PER_LINE, WIDTH};
+use crate::savestate::{StateError, StateReader, StateWriter};
+use crate::MMU;
+
+const FETCH_STALL: u8 = 6;
+const SPRITE_STALL: u8 = 6;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Step {
+    #[default]
+    Tile,
+    Low,
+    High,
+    Push,
+}
+
+#[derive(Clone, Copy, Default)]
+struct SpritePixel {
+    color: u8,
+    // OBP1 instead of OBP0
+    obp1: bool,
+    // behind BG colors 1-3
+    behind: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct PixelFifo {
+    // pixels sent to the LCD this line
+    x: u8,
+    // pixels still to drop from the FIFO, for SCX and WX < 7
+    discard: u8,
+    // dots nothing moves for
+    stall: u8,
+    // WY matched LY on some line of this frame
+    wy_hit: bool,
+    window: bool,
+    step: Step,
+    // second dot of a step
+    half: bool,
+    // tile column the fetcher is on, from SCX / 8 or the window's left edge
+    fetch_x: u8,
+    tile: u8,
+    lo: u8,
+    hi: u8,
+    // BG FIFO as a pair of shift registers, leaving from bit 7
+    bg_lo: u8,
+    bg_hi: u8,
+    bg_len: u8,
+    sprites: [SpritePixel; 8],
+    sprite_len: u8,
+    // OAM indices found on this line, X order is found at runtime
+    oam: [u8; SPRITES_PER_LINE],
+    oam_len: u8,
+    // bit per found sprite already fetched
+    fetched: u16,
+}
+
+impl PixelFifo {
+    pub(crate) fn start_frame(&mut self) {
+        self.wy_hit = false;
+    }
+
+    // at the start of mode 3, picks the line's sprites as OAM scan would have
+    pub(crate) fn start_line(&mut self, mmu: &MMU, ly: u8) {
+        let wy_hit = self.wy_hit || ly == mmu.io[0x4a];
+        *self = PixelFifo { wy_hit, stall: FETCH_STALL, discard: mmu.io[0x43] % 8, ..Default::default() };
+        let height = sprite_height(mmu.io[0x40]);
+        for (i, sprite) in mmu.sprites.chunks(4).enumerate() {
+            let top = sprite[0] as u16;
+            if (ly as u16 + 16) >= top && (ly as u16 + 16) < top + height as u16 {
+                self.oam[self.oam_len as usize] = i as u8;
+                self.oam_len += 1;
+                if self.oam_len as usize == SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+    }
+
+    // for a line the scanline renderer already drew
+    pub(crate) fn finish_line(&mut self) {
+        self.x = WIDTH as u8;
+    }
+
+    // all of the line sent, mode 3 is over
+    pub(crate) fn done(&self) -> bool {
+        self.x as usize == WIDTH
+    }
+
+    // the window's line counter moves on after lines showing it
+    pub(crate) fn showed_window(&self) -> bool {
+        self.window
+    }
+
+    // one dot of mode 3, row is the line's shades
+    pub(crate) fn tick(&mut self, mmu: &MMU, ly: u8, window_line: u8, row: &mut [u8]) {
+        if self.done() {
+            return;
+        }
+        if self.stall > 0 {
+            self.stall -= 1;
+            return;
+        }
+        let lcdc = mmu.io[0x40];
+        // LCDC bit 5 - window enable
+        let wx = mmu.io[0x4b];
+        if !self.window && lcdc & 0x20 != 0 && self.wy_hit && self.x as u16 + 7 >= wx as u16 && wx <= 166 {
+            self.window = true;
+            self.discard = 7u8.saturating_sub(wx);
+            self.bg_len = 0;
+            self.step = Step::Tile;
+            self.half = false;
+            self.fetch_x = 0;
+        }
+        // LCDC bit 1 - sprites enable
+        if self.bg_len > 0 && self.discard == 0 && lcdc & 0x02 != 0 {
+            if let Some(i) = self.sprite_at(mmu) {
+                self.fetched |= 1 << i;
+                self.fetch_sprite(mmu, ly, self.oam[i]);
+                self.stall = SPRITE_STALL - 1;
+                return;
+            }
+        }
+        self.fetch(mmu, ly, window_line);
+        if self.bg_len == 0 {
+            return;
+        }
+        let mut color = (self.bg_hi >> 7) << 1 | self.bg_lo >> 7;
+        self.bg_lo <<= 1;
+        self.bg_hi <<= 1;
+        self.bg_len -= 1;
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        // LCDC bit 0 - BG and window enable
+        if lcdc & 0x01 == 0 {
+            color = 0;
+        }
+        let sprite = self.pop_sprite();
+        row[self.x as usize] = match sprite.color != 0 && lcdc & 0x02 != 0 && (!sprite.behind || color == 0) {
+            true => palette_shade(if sprite.obp1 { mmu.io[0x49] } else { mmu.io[0x48] }, sprite.color),
+            false => palette_shade(mmu.io[0x47], color),
+        };
+        self.x += 1;
+    }
+
+    // first unfetched sprite reaching the current pixel, lower X first and
+    // lower OAM index on ties, which is also who wins overlaps
+    fn sprite_at(&self, mmu: &MMU) -> Option<usize> {
+        (0..self.oam_len as usize)
+            .filter(|i| self.fetched & (1 << i) == 0)
+            .map(|i| (mmu.sprites[self.oam[i] as usize * 4 + 1], i))
+            .filter(|&(x, _)| x as u16 <= self.x as u16 + 8)
+            .min()
+            .map(|(_, i)| i)
+    }
+
+    fn fetch_sprite(&mut self, mmu: &MMU, ly: u8, index: u8) {
+        let sprite = &mmu.sprites[index as usize * 4..index as usize * 4 + 4];
+        let (y, x, mut tile, attrs) = (sprite[0], sprite[1], sprite[2], sprite[3]);
+        let height = sprite_height(mmu.io[0x40]);
+        // LCDC may have shrunk the sprite since the scan
+        let mut line = (ly + 16).wrapping_sub(y) % height;
+        if attrs & 0x40 != 0 {
+            line = height - 1 - line;
+        }
+        if height == 16 {
+            tile &= 0xfe;
+        }
+        let addr = tile as usize * 16 + line as usize * 2;
+        let (lo, hi) = (mmu.graphics[addr], mmu.graphics[addr + 1]);
+        // pixels left of the screen are gone already
+        let skip = self.x + 8 - x;
+        for px in skip..8 {
+            let bit = if attrs & 0x20 != 0 { px } else { 7 - px };
+            let pixel = SpritePixel {
+                color: ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1),
+                obp1: attrs & 0x10 != 0,
+                behind: attrs & 0x80 != 0,
+            };
+            let slot = (px - skip) as usize;
+            if slot >= self.sprite_len as usize {
+                self.sprites[slot] = pixel;
+                self.sprite_len += 1;
+            } else if self.sprites[slot].color == 0 {
+                // earlier sprites keep their opaque pixels
+                self.sprites[slot] = pixel;
+            }
+        }
+    }
+
+    fn pop_sprite(&mut self) -> SpritePixel {
+        if self.sprite_len == 0 {
+            return SpritePixel::default();
+        }
+        let pixel = self.sprites[0];
+        self.sprites.copy_within(1.., 0);
+        self.sprite_len -= 1;
+        pixel
+    }
+
+    fn fetch(&mut self, mmu: &MMU, ly: u8, window_line: u8) {
+        if self.step == Step::Push {
+            if self.bg_len == 0 {
+                (self.bg_lo, self.bg_hi, self.bg_len) = (self.lo, self.hi, 8);
+                self.fetch_x = self.fetch_x.wrapping_add(1);
+                self.step = Step::Tile;
+            }
+            return;
+        }
+        if !self.half {
+            self.half = true;
+            return;
+        }
+        self.half = false;
+        let lcdc = mmu.io[0x40];
+        let (high_map, column, y) = match self.window {
+            // LCDC bit 6 - window tile map
+            true => (lcdc & 0x40 != 0, self.fetch_x, window_line),
+            // LCDC bit 3 - BG tile map
+            false => (lcdc & 0x08 != 0, (mmu.io[0x43] / 8).wrapping_add(self.fetch_x), ly.wrapping_add(mmu.io[0x42])),
+        };
+        match self.step {
+            Step::Tile => {
+                let map = if high_map { 0x1c00 } else { 0x1800 };
+                self.tile = mmu.graphics[map + (y as usize / 8) * 32 + (column % 32) as usize];
+                self.step = Step::Low;
+            }
+            Step::Low => {
+                self.lo = mmu.graphics[tile_row(lcdc, self.tile, y)];
+                self.step = Step::High;
+            }
+            Step::High => {
+                self.hi = mmu.graphics[tile_row(lcdc, self.tile, y) + 1];
+                self.step = Step::Push;
+            }
+            Step::Push => unreachable!(),
+        }
+    }
+
+    pub(crate) fn save(&self, w: &mut StateWriter) {
+        for byte in [self.x, self.discard, self.stall, self.step as u8, self.fetch_x, self.tile, self.lo, self.hi] {
+            w.u8(byte);
+        }
+        w.bool(self.wy_hit);
+        w.bool(self.window);
+        w.bool(self.half);
+        for byte in [self.bg_lo, self.bg_hi, self.bg_len, self.sprite_len, self.oam_len] {
+            w.u8(byte);
+        }
+        for pixel in &self.sprites {
+            w.u8(pixel.color | (pixel.obp1 as u8) << 2 | (pixel.behind as u8) << 3);
+        }
+        w.bytes(&self.oam);
+        w.u16(self.fetched);
+    }
+
+    pub(crate) fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.x = r.u8()?;
+        self.discard = r.u8()?;
+        self.stall = r.u8()?;
+        self.step = match r.u8()? {
+            0 => Step::Tile,
+            1 => Step::Low,
+            2 => Step::High,
+            3 => Step::Push,
+            step => return Err(StateError::Corrupt(format!("invalid fetcher step {}", step))),
+        };
+        self.fetch_x = r.u8()?;
+        self.tile = r.u8()?;
+        self.lo = r.u8()?;
+        self.hi = r.u8()?;
+        self.wy_hit = r.bool()?;
+        self.window = r.bool()?;
+        self.half = r.bool()?;
+        self.bg_lo = r.u8()?;
+        self.bg_hi = r.u8()?;
+        self.bg_len = r.u8()?;
+        self.sprite_len = r.u8()?;
+        self.oam_len = r.u8()?;
+        for pixel in &mut self.sprites {
+            let byte = r.u8()?;
+            *pixel = SpritePixel { color: byte & 0x03, obp1: byte & 0x04 != 0, behind: byte & 0x08 != 0 };
+        }
+        r.bytes(&mut self.oam)?;
+        self.fetched = r.u16()?;
+        if self.x as usize > WIDTH || self.bg_len > 8 || self.sprite_len > 8 || self.oam_len as usize > SPRITES_PER_LINE {
+            return Err(StateError::Corrupt("invalid pixel FIFO".to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+// memory held at a value, e.g. infinite lives. kept per game next to its
+// savestates in <rom>.freeze, one `addr = value [frame]` line per address:
+//
+//   C0A0 = 99
+//   D31A = 03 frame
+//
+// held bytes replace every CPU write, `frame` ones are instead written back
+// once per frame, for games that get confused when a write doesn't stick
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hold {
+    Write,
+    Frame,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frozen {
+    held: BTreeMap<u16, (u8, Hold)>,
+}
+
+impl Frozen {
+    pub fn insert(&mut self, addr: u16, val: u8, hold: Hold) {
+        self.held.insert(addr, (val, hold));
+    }
+
+    pub fn remove(&mut self, addr: u16) -> bool {
+        self.held.remove(&addr).is_some()
+    }
+
+    pub fn get(&self, addr: u16) -> Option<(u8, Hold)> {
+        self.held.get(&addr).copied()
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        self.held.contains_key(&addr)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8, Hold)> + '_ {
+        self.held.iter().map(|(addr, (val, hold))| (*addr, *val, *hold))
+    }
+
+    // value a CPU write to addr ends up as
+    pub fn on_write(&self, addr: u16, val: u8) -> u8 {
+        if self.held.is_empty() {
+            return val;
+        }
+        match self.held.get(&addr) {
+            Some((held, Hold::Write)) => *held,
+            _ => val,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Frozen, String> {
+        let mut frozen = Frozen::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("line {}: expected `addr = value [frame]`, got `{}`", number + 1, line);
+            let (addr, rest) = line.split_once('=').ok_or_else(invalid)?;
+            let mut words = rest.split_whitespace();
+            let addr = u16::from_str_radix(addr.trim(), 16).map_err(|_| invalid())?;
+            let val = words.next().and_then(|v| u8::from_str_radix(v, 16).ok()).ok_or_else(invalid)?;
+            let hold = match words.next() {
+                None => Hold::Write,
+                Some("frame") => Hold::Frame,
+                Some(_) => return Err(invalid()),
+            };
+            frozen.insert(addr, val, hold);
+        }
+        Ok(frozen)
+    }
+
+    pub fn to_text(&self) -> String {
+        self.iter()
+            .map(|(addr, val, hold)| match hold {
+                Hold::Write => format!("{:04X} = {:02X}\n", addr, val),
+                Hold::Frame => format!("{:04X} = {:02X} frame\n", addr, val),
+            })
+            .collect()
+    }
+}
+
+// <save_dir>/<rom file stem>.freeze
+pub fn default_path(save_dir: &Path, rom: &str) -> PathBuf {
+    let stem = Path::new(rom).file_stem().unwrap_or_default();
+    save_dir.join(format!("{}.freeze", stem.to_string_lossy()))
+}
+
+// a missing file holds nothing
+pub fn load(path: &Path) -> Result<Frozen, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Frozen::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Frozen::default()),
+        Err(e) => Err(format!("can't read {}: {}", path.display(), e)),
+    }
+}
+
+// nothing held removes the file
+pub fn save(path: &Path, frozen: &Frozen) -> io::Result<()> {
+    if frozen.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, frozen.to_text())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::GB;
+
+    #[test]
+    fn parses_and_round_trips() {
+        let frozen = Frozen::parse("# lives\nc0a0 = 99\nD31A = 3 frame\n").unwrap();
+        assert_eq!(frozen.get(0xc0a0), Some((0x99, Hold::Write)));
+        assert_eq!(frozen.get(0xd31a), Some((0x03, Hold::Frame)));
+        assert_eq!(Frozen::parse(&frozen.to_text()).unwrap(), frozen);
+        assert!(Frozen::parse("c0a0 = 99 always").is_err());
+    }
+
+    #[test]
+    fn holds_on_write_or_per_frame() {
+        let rom = micro_rom("ld a, $91\nldh ($40), a\nld a, $07\nldh ($80), a\nldh ($81), a");
+        let mut gb = GB::new(&rom).unwrap();
+        gb.freeze(0xff80, 0x63, Hold::Write).unwrap();
+        gb.freeze(0xff81, 0x42, Hold::Frame).unwrap();
+        for _ in 0..5 {
+            gb.cycle();
+        }
+        assert_eq!(gb.read_memory(0xff80, 2), [0x63, 0x07]);
+        gb.run_frames(1);
+        assert_eq!(gb.read_memory(0xff80, 2), [0x63, 0x42]);
+        gb.unfreeze(0xff80);
+        assert!(!gb.frozen().contains(0xff80));
+    }
+}
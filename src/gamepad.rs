@@ -0,0 +1,338 @@
+// game controllers through the Linux joystick API (/dev/input/js*), read with plain
+// file IO so no SDL is needed. devices are picked up when plugged in and dropped
+// when reads fail. on other systems no devices show up and input stays keyboardless
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use gb_rust::Buttons;
+
+const DEVICE_DIR: &str = "/dev/input";
+const RESCAN: Duration = Duration::from_secs(1);
+// struct js_event: u32 time, i16 value, u8 type, u8 number
+const EVENT_LEN: usize = 8;
+const JS_EVENT_BUTTON: u8 = 0x01;
+const JS_EVENT_AXIS: u8 = 0x02;
+// initial state events sent after opening carry this on top
+const JS_EVENT_INIT: u8 = 0x80;
+// half deflection counts as pressed
+const AXIS_THRESHOLD: i16 = 16384;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Input {
+    Button(u8),
+    // axis and direction, true for positive
+    Axis(u8, bool),
+}
+
+impl Input {
+    // `button3`, `axis1-`, `axis6+`
+    pub fn parse(text: &str) -> Result<Input, String> {
+        let invalid = || format!("unknown gamepad input `{}`, expected e.g. button0 or axis1-", text);
+        let number = |n: &str| n.parse().map_err(|_| invalid());
+        if let Some(n) = text.strip_prefix("button") {
+            return Ok(Input::Button(number(n)?));
+        }
+        let axis = text.strip_prefix("axis").ok_or_else(invalid)?;
+        match (axis.strip_suffix('+'), axis.strip_suffix('-')) {
+            (Some(n), _) => Ok(Input::Axis(number(n)?, true)),
+            (_, Some(n)) => Ok(Input::Axis(number(n)?, false)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Button(n) => write!(f, "button{}", n),
+            Input::Axis(n, positive) => write!(f, "axis{}{}", n, if *positive { '+' } else { '-' }),
+        }
+    }
+}
+
+// frontend actions on a pad input instead of game buttons
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Hotkey {
+    Screenshot,
+    // starts and stops a video
+    Record,
+    // back to the --arcade chooser
+    Menu,
+}
+
+impl Hotkey {
+    pub const ALL: [Hotkey; 3] = [Hotkey::Screenshot, Hotkey::Record, Hotkey::Menu];
+
+    // as in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            Hotkey::Screenshot => "screenshot",
+            Hotkey::Record => "record",
+            Hotkey::Menu => "menu",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Hotkey> {
+        Hotkey::ALL.into_iter().find(|hotkey| hotkey.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadMap {
+    inputs: BTreeMap<Input, Buttons>,
+    pub hotkeys: BTreeMap<Hotkey, Input>,
+}
+
+// xpad layout, which most pads mimic: face buttons 0-1, back/start 6-7,
+// left stick axes 0-1 and the d-pad hat as axes 6-7. the guide button 8
+// takes screenshots, clicking the right stick (10) records, the left one (9)
+// goes back to the --arcade chooser
+impl Default for GamepadMap {
+    fn default() -> Self {
+        let inputs = [
+            (Input::Button(0), Buttons::A),
+            (Input::Button(1), Buttons::B),
+            (Input::Button(6), Buttons::SELECT),
+            (Input::Button(7), Buttons::START),
+            (Input::Axis(0, false), Buttons::LEFT),
+            (Input::Axis(0, true), Buttons::RIGHT),
+            (Input::Axis(1, false), Buttons::UP),
+            (Input::Axis(1, true), Buttons::DOWN),
+            (Input::Axis(6, false), Buttons::LEFT),
+            (Input::Axis(6, true), Buttons::RIGHT),
+            (Input::Axis(7, false), Buttons::UP),
+            (Input::Axis(7, true), Buttons::DOWN),
+        ];
+        GamepadMap {
+            inputs: inputs.into_iter().collect(),
+            hotkeys: [(Hotkey::Screenshot, Input::Button(8)), (Hotkey::Record, Input::Button(10)), (Hotkey::Menu, Input::Button(9))]
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl GamepadMap {
+    // empty buttons unmaps the input
+    pub fn bind(&mut self, input: Input, buttons: Buttons) {
+        self.inputs.insert(input, buttons);
+    }
+
+    fn get(&self, input: Input) -> Buttons {
+        self.inputs.get(&input).copied().unwrap_or_default()
+    }
+}
+
+enum Message {
+    // device, joystick event type and number, value
+    Event(u32, u8, u8, i16),
+    Gone(u32),
+}
+
+#[derive(Default)]
+struct Pad {
+    buttons: HashSet<u8>,
+    axes: HashMap<u8, i16>,
+}
+
+pub struct Gamepads {
+    map: GamepadMap,
+    messages: Receiver<Message>,
+    pads: HashMap<u32, Pad>,
+    // hotkeys held at the last check
+    hotkeys_held: HashSet<Hotkey>,
+}
+
+impl Gamepads {
+    // starts watching for devices
+    pub fn open(map: GamepadMap) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let open = Arc::new(Mutex::new(HashSet::new()));
+        thread::spawn(move || loop {
+            scan(&tx, &open);
+            thread::sleep(RESCAN);
+        });
+        Gamepads {
+            map,
+            messages: rx,
+            pads: HashMap::new(),
+            hotkeys_held: HashSet::new(),
+        }
+    }
+
+    // buttons held on all pads together
+    pub fn poll(&mut self) -> Buttons {
+        self.receive();
+        self.pads.values().fold(Buttons::empty(), |held, pad| held | pad.buttons(&self.map))
+    }
+
+    // for two players: the lowest numbered pad plays the first game, the next one
+    // the second, further pads join the second player
+    pub fn poll_players(&mut self) -> [Buttons; 2] {
+        self.receive();
+        let mut ids: Vec<_> = self.pads.keys().copied().collect();
+        ids.sort();
+        let mut held = [Buttons::empty(); 2];
+        for (i, id) in ids.iter().enumerate() {
+            held[i.min(1)] |= self.pads[id].buttons(&self.map);
+        }
+        held
+    }
+
+    // true once per press of the hotkey's input on any pad
+    pub fn pressed(&mut self, hotkey: Hotkey) -> bool {
+        self.receive();
+        let Some(&input) = self.map.hotkeys.get(&hotkey) else {
+            return false;
+        };
+        match self.pads.values().any(|pad| pad.held(input)) {
+            true => self.hotkeys_held.insert(hotkey),
+            false => {
+                self.hotkeys_held.remove(&hotkey);
+                false
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Message::Event(id, kind, number, value) => self.pads.entry(id).or_default().update(kind, number, value),
+                Message::Gone(id) => {
+                    self.pads.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+impl Pad {
+    fn update(&mut self, kind: u8, number: u8, value: i16) {
+        match kind & !JS_EVENT_INIT {
+            JS_EVENT_BUTTON if value != 0 => {
+                self.buttons.insert(number);
+            }
+            JS_EVENT_BUTTON => {
+                self.buttons.remove(&number);
+            }
+            JS_EVENT_AXIS => {
+                self.axes.insert(number, value);
+            }
+            _ => {}
+        }
+    }
+
+    fn buttons(&self, map: &GamepadMap) -> Buttons {
+        let mut held = Buttons::empty();
+        for button in &self.buttons {
+            held |= map.get(Input::Button(*button));
+        }
+        for (axis, value) in &self.axes {
+            if *value >= AXIS_THRESHOLD {
+                held |= map.get(Input::Axis(*axis, true));
+            } else if *value <= -AXIS_THRESHOLD {
+                held |= map.get(Input::Axis(*axis, false));
+            }
+        }
+        held
+    }
+
+    fn held(&self, input: Input) -> bool {
+        match input {
+            Input::Button(n) => self.buttons.contains(&n),
+            Input::Axis(n, true) => self.axes.get(&n).is_some_and(|v| *v >= AXIS_THRESHOLD),
+            Input::Axis(n, false) => self.axes.get(&n).is_some_and(|v| *v <= -AXIS_THRESHOLD),
+        }
+    }
+}
+
+// opens js devices that aren't read yet, one thread each
+fn scan(tx: &Sender<Message>, open: &Arc<Mutex<HashSet<PathBuf>>>) {
+    let Ok(entries) = fs::read_dir(DEVICE_DIR) else {
+        return;
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let Some(id) = path.file_name().and_then(|n| n.to_str()?.strip_prefix("js")?.parse::<u32>().ok()) else {
+            continue;
+        };
+        if open.lock().unwrap().contains(&path) {
+            continue;
+        }
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        eprintln!("gamepad connected: {}", path.display());
+        open.lock().unwrap().insert(path.clone());
+        let (tx, open) = (tx.clone(), open.clone());
+        thread::spawn(move || {
+            read_events(file, id, &tx);
+            eprintln!("gamepad disconnected: {}", path.display());
+            open.lock().unwrap().remove(&path);
+            tx.send(Message::Gone(id)).ok();
+        });
+    }
+}
+
+fn read_events(mut file: File, id: u32, tx: &Sender<Message>) {
+    let mut event = [0; EVENT_LEN];
+    while file.read_exact(&mut event).is_ok() {
+        let value = i16::from_le_bytes([event[4], event[5]]);
+        if tx.send(Message::Event(id, event[6], event[7], value)).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_buttons_and_axes() {
+        assert_eq!(Input::parse("axis1-").unwrap(), Input::Axis(1, false));
+        assert_eq!(Input::parse("button12").unwrap().to_string(), "button12");
+        assert!(Input::parse("stick").is_err());
+
+        let mut map = GamepadMap::default();
+        map.bind(Input::Button(2), Buttons::A | Buttons::B);
+        let mut pad = Pad::default();
+        pad.update(JS_EVENT_BUTTON | JS_EVENT_INIT, 2, 1);
+        pad.update(JS_EVENT_AXIS, 0, -20000);
+        pad.update(JS_EVENT_AXIS, 1, 8000);
+        assert_eq!(pad.buttons(&map), Buttons::A | Buttons::B | Buttons::LEFT);
+        pad.update(JS_EVENT_BUTTON, 2, 0);
+        pad.update(JS_EVENT_AXIS, 0, 0);
+        assert_eq!(pad.buttons(&map), Buttons::empty());
+    }
+
+    #[test]
+    fn splits_pads_between_players() {
+        let (tx, messages) = mpsc::channel();
+        let mut pads = Gamepads { map: GamepadMap::default(), messages, pads: HashMap::new(), hotkeys_held: HashSet::new() };
+        tx.send(Message::Event(3, JS_EVENT_BUTTON, 0, 1)).unwrap();
+        tx.send(Message::Event(1, JS_EVENT_BUTTON, 7, 1)).unwrap();
+        tx.send(Message::Event(4, JS_EVENT_BUTTON, 1, 1)).unwrap();
+        assert_eq!(pads.poll_players(), [Buttons::START, Buttons::A | Buttons::B]);
+        tx.send(Message::Gone(1)).unwrap();
+        assert_eq!(pads.poll_players(), [Buttons::A, Buttons::B]);
+        assert_eq!(pads.poll(), Buttons::A | Buttons::B);
+
+        // hotkeys count once per press and don't reach the game
+        tx.send(Message::Event(3, JS_EVENT_BUTTON, 8, 1)).unwrap();
+        assert!(pads.pressed(Hotkey::Screenshot));
+        assert!(!pads.pressed(Hotkey::Screenshot) && !pads.pressed(Hotkey::Record));
+        assert_eq!(pads.poll(), Buttons::A | Buttons::B);
+        tx.send(Message::Event(3, JS_EVENT_BUTTON, 8, 0)).unwrap();
+        assert!(!pads.pressed(Hotkey::Screenshot));
+        tx.send(Message::Event(4, JS_EVENT_BUTTON, 8, 1)).unwrap();
+        assert!(pads.pressed(Hotkey::Screenshot));
+    }
+}
@@ -0,0 +1,222 @@
+// minimal animated GIF encoder, frames are written as they come. every frame
+// gets its own color table, a DMG screen has 4 colors so frames stay small;
+// past 256 colors pixels are cut down to 3-3-2 bit RGB
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::palette::Rgb;
+
+const MAX_CODE: u16 = 4095;
+
+pub struct Encoder<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> Encoder<W> {
+    // header and the NETSCAPE2.0 extension looping forever
+    pub fn new(mut out: W, width: u16, height: u16) -> io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // no global color table, background 0, square pixels
+        out.write_all(&[0, 0, 0])?;
+        out.write_all(&[0x21, 0xff, 0x0b])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+        Ok(Encoder { out, width, height })
+    }
+
+    // packed RGB, shown for delay hundredths of a second
+    pub fn frame(&mut self, rgb: &[u8], delay: u16) -> io::Result<()> {
+        assert_eq!(rgb.len(), self.width as usize * self.height as usize * 3, "GIF frame size mismatch");
+        let (colors, indices) = index_colors(rgb);
+        // table sizes are powers of two, at least 2 entries
+        let bits = (usize::BITS - (colors.len() - 1).max(1).leading_zeros()) as u8;
+        // graphic control: leave the frame in place, no transparency
+        self.out.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x80 | (bits - 1)])?;
+        let mut table = vec![0; 3 << bits];
+        for (i, color) in colors.iter().enumerate() {
+            table[i * 3..i * 3 + 3].copy_from_slice(color);
+        }
+        self.out.write_all(&table)?;
+        let min_code_size = bits.max(2);
+        self.out.write_all(&[min_code_size])?;
+        for block in lzw(&indices, min_code_size).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    // writes the trailer, the file is complete after this
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// the frame's colors in order of appearance and every pixel's index into them
+fn index_colors(rgb: &[u8]) -> (Vec<Rgb>, Vec<u8>) {
+    let mut colors = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+    for pixel in rgb.chunks_exact(3) {
+        let color: Rgb = [pixel[0], pixel[1], pixel[2]];
+        let next = colors.len();
+        let index = *lookup.entry(color).or_insert(next);
+        if index == next {
+            if next == 256 {
+                return reduced(rgb);
+            }
+            colors.push(color);
+        }
+        indices.push(index as u8);
+    }
+    (colors, indices)
+}
+
+fn reduced(rgb: &[u8]) -> (Vec<Rgb>, Vec<u8>) {
+    let colors = (0..=255u8).map(|c| [c & 0xe0, (c << 3) & 0xe0, (c << 6) & 0xc0]).collect();
+    let indices = rgb.chunks_exact(3).map(|p| (p[0] & 0xe0) | ((p[1] >> 3) & 0x1c) | (p[2] >> 6)).collect();
+    (colors, indices)
+}
+
+// variable width LZW as GIF has it, codes packed from the low bit up. the code
+// width grows once the next code wouldn't fit, the table starts over when full
+fn lzw(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let mut out = Bits::default();
+    let mut size = min_code_size + 1;
+    let mut next = clear + 2;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    out.push(clear, size);
+    let Some((&first, rest)) = indices.split_first() else {
+        out.push(clear + 1, size);
+        return out.finish();
+    };
+    let mut current = first as u16;
+    for &index in rest {
+        if let Some(&code) = table.get(&(current, index)) {
+            current = code;
+            continue;
+        }
+        out.push(current, size);
+        if next >= 1 << size {
+            size += 1;
+        }
+        if next < MAX_CODE {
+            table.insert((current, index), next);
+            next += 1;
+        } else {
+            out.push(clear, size);
+            table.clear();
+            size = min_code_size + 1;
+            next = clear + 2;
+        }
+        current = index as u16;
+    }
+    out.push(current, size);
+    if next >= 1 << size {
+        size += 1;
+    }
+    out.push(clear + 1, size);
+    out.finish()
+}
+
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    // pending bits, low ones first
+    buffer: u32,
+    count: u8,
+}
+
+impl Bits {
+    fn push(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // with the last byte filled up with zeros
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the decoder side, to check the codes read back
+    fn unlzw(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let reset = || (0..clear).map(|i| vec![i as u8]).chain([vec![], vec![]]).collect::<Vec<_>>();
+        let (mut table, mut size, mut prev) = (reset(), min_code_size + 1, None::<Vec<u8>>);
+        let (mut out, mut bit) = (Vec::new(), 0);
+        loop {
+            let code = (0..size as usize).fold(0, |code, i| code | ((data[(bit + i) / 8] >> ((bit + i) % 8) & 1) as usize) << i);
+            bit += size as usize;
+            if code == clear {
+                (table, size, prev) = (reset(), min_code_size + 1, None);
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [prev.as_slice(), &prev[..1]].concat(),
+                (None, None) => panic!("code {} before any other", code),
+            };
+            out.extend_from_slice(&entry);
+            if let Some(prev) = prev {
+                table.push([prev.as_slice(), &entry[..1]].concat());
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn compresses_what_decoders_read_back() {
+        // long enough to fill the table and start over
+        let indices: Vec<u8> = (0..60_000u32).map(|i| ((i * 7 / 3) ^ (i >> 5)) as u8 & 0x03).collect();
+        assert_eq!(unlzw(&lzw(&indices, 2), 2), indices);
+        let indices: Vec<u8> = (0..20_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        assert_eq!(unlzw(&lzw(&indices, 8), 8), indices);
+        assert!(unlzw(&lzw(&[], 2), 2).is_empty());
+
+        let mut gif = Encoder::new(Vec::new(), 2, 2).unwrap();
+        gif.frame(&[0, 0, 0, 255, 255, 255, 0, 0, 0, 0, 0, 0], 4).unwrap();
+        let data = gif.finish().unwrap();
+        assert_eq!(&data[..10], b"GIF89a\x02\x00\x02\x00");
+        assert_eq!(data.last(), Some(&0x3b));
+        // a 2 color table after the image descriptor
+        let image = data.iter().position(|&b| b == 0x2c).unwrap();
+        assert_eq!(data[image + 9..image + 16], [0x80, 0, 0, 0, 255, 255, 255]);
+
+        let many: Vec<u8> = (0..300u32).flat_map(|i| [i as u8, (i >> 8) as u8, 7]).collect();
+        let (colors, indices) = index_colors(&many);
+        assert_eq!((colors.len(), indices[299]), (256, 0x20));
+    }
+}
@@ -0,0 +1,163 @@
+// CGB VRAM DMA, registers [FF51-FF55]
+//
+// General purpose DMA copies the whole block as soon as HDMA5 is written,
+// HBlank DMA copies 16 bytes every time the PPU enters mode 0.
+
+use crate::savestate::{Component, StateError, StateReader, StateWriter};
+
+// CPU is stalled for 8 m-cycles (32 t-cycles) per transferred 16 byte block
+const BLOCK_STALL_T: u32 = 32;
+
+#[derive(Default)]
+pub struct HDMA {
+    // HDMA1 / HDMA2, lower 4 bits are ignored
+    src: u16,
+    // HDMA3 / HDMA4, only [8000-9FF0] is addressable
+    dst: u16,
+    // 16 byte blocks left to copy
+    blocks: u8,
+    // HBlank transfer in progress
+    hblank_active: bool,
+    // t-cycles the CPU has to wait because of past transfers
+    stall: u32,
+}
+
+impl HDMA {
+    pub fn rb(&self, addr: u16) -> u8 {
+        match addr {
+            // HDMA5 - bit 7 is 0 while HBlank transfer is active
+            0xff55 => match self.hblank_active {
+                true => (self.blocks - 1) & 0x7f,
+                false => 0x80 | (self.blocks.wrapping_sub(1) & 0x7f),
+            },
+            // HDMA1-4 are write only
+            _ => 0xff,
+        }
+    }
+
+    // returns number of blocks to copy right away (general purpose DMA)
+    pub fn wb(&mut self, addr: u16, val: u8) -> u8 {
+        match addr {
+            0xff51 => self.src = (self.src & 0x00ff) | ((val as u16) << 8),
+            0xff52 => self.src = (self.src & 0xff00) | (val & 0xf0) as u16,
+            0xff53 => self.dst = (self.dst & 0x00ff) | (((val & 0x1f) as u16) << 8),
+            0xff54 => self.dst = (self.dst & 0xff00) | (val & 0xf0) as u16,
+            0xff55 => {
+                // writing bit 7 = 0 during HBlank transfer stops it
+                if self.hblank_active && val & 0x80 == 0 {
+                    self.hblank_active = false;
+                    return 0;
+                }
+                self.blocks = (val & 0x7f) + 1;
+                self.hblank_active = val & 0x80 != 0;
+                if !self.hblank_active {
+                    return self.blocks;
+                }
+            }
+            _ => unreachable!(),
+        }
+        0
+    }
+
+    // source and VRAM destination of next block, advances the transfer. the
+    // transfer ends early once the destination runs off the end of VRAM
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if self.blocks == 0 {
+            return None;
+        }
+        let block = (self.src, 0x8000 | (self.dst & 0x1ff0));
+        self.src = self.src.wrapping_add(16);
+        self.dst = (self.dst & 0x1ff0) + 16;
+        self.blocks = match self.dst {
+            0x2000.. => 0,
+            _ => self.blocks - 1,
+        };
+        if self.blocks == 0 {
+            self.hblank_active = false;
+        }
+        self.stall += BLOCK_STALL_T;
+        Some(block)
+    }
+
+    pub fn hblank_pending(&self) -> bool {
+        self.hblank_active
+    }
+
+    pub fn take_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.stall)
+    }
+}
+
+impl Component for HDMA {
+    const TAG: [u8; 4] = *b"HDMA";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.src);
+        w.u16(self.dst);
+        w.u8(self.blocks);
+        w.bool(self.hblank_active);
+        w.u32(self.stall);
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.src = r.u16()?;
+        self.dst = r.u16()?;
+        self.blocks = r.u8()?;
+        self.hblank_active = r.bool()?;
+        self.stall = r.u32()?;
+        if self.blocks > 0x80 || (self.blocks == 0 && self.hblank_active) {
+            return Err(StateError::Corrupt(format!("HBlank DMA with {} blocks left", self.blocks)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(hdma: &mut HDMA, src: u16, dst: u16, hdma5: u8) -> u8 {
+        hdma.wb(0xff51, (src >> 8) as u8);
+        hdma.wb(0xff52, src as u8);
+        hdma.wb(0xff53, (dst >> 8) as u8);
+        hdma.wb(0xff54, dst as u8);
+        hdma.wb(0xff55, hdma5)
+    }
+
+    #[test]
+    fn general_purpose_dma_copies_the_whole_length_at_once() {
+        let mut hdma = HDMA::default();
+        assert_eq!(start(&mut hdma, 0xc123, 0x8456, 0x02), 3);
+        assert!(!hdma.hblank_pending());
+        assert_eq!(hdma.next_block(), Some((0xc120, 0x8450)));
+        assert_eq!(hdma.next_block(), Some((0xc130, 0x8460)));
+        assert_eq!(hdma.next_block(), Some((0xc140, 0x8470)));
+        assert_eq!(hdma.next_block(), None);
+        assert_eq!(hdma.rb(0xff55), 0xff);
+        assert_eq!(hdma.take_stall(), 3 * BLOCK_STALL_T);
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_at_a_time_until_cancelled() {
+        let mut hdma = HDMA::default();
+        assert_eq!(start(&mut hdma, 0x4000, 0x9000, 0x83), 0);
+        assert!(hdma.hblank_pending());
+        assert_eq!(hdma.rb(0xff55), 0x03);
+        assert_eq!(hdma.next_block(), Some((0x4000, 0x9000)));
+        assert_eq!(hdma.rb(0xff55), 0x02);
+        assert_eq!(hdma.next_block(), Some((0x4010, 0x9010)));
+        // bit 7 clear stops it, the blocks left read back with bit 7 set
+        assert_eq!(hdma.wb(0xff55, 0x00), 0);
+        assert!(!hdma.hblank_pending());
+        assert_eq!(hdma.rb(0xff55), 0x81);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_vram() {
+        let mut hdma = HDMA::default();
+        assert_eq!(start(&mut hdma, 0xd000, 0x9fe0, 0x84), 0);
+        assert_eq!(hdma.next_block(), Some((0xd000, 0x9fe0)));
+        assert_eq!(hdma.next_block(), Some((0xd010, 0x9ff0)));
+        assert!(!hdma.hblank_pending());
+        assert_eq!(hdma.next_block(), None);
+    }
+}
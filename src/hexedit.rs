@@ -0,0 +1,202 @@
+// hex editor over the whole address space, entered with `hex` in the debugger.
+// edits go through MMU::poke, which may set read-only IO bits and reach locked
+// VRAM / OAM, but leaves ROM alone
+
+use crate::freeze::Hold;
+use crate::GB;
+
+const ROWS: u16 = 8;
+const PAGE: u16 = ROWS * 16;
+
+pub const HELP: &str = "\
+hex editor commands (values in hex):
+  <bytes>                write bytes at the cursor and move past them, e.g. 3e 91
+  g, goto <addr>         move the cursor
+  n, p                   next / previous page, Enter shows the next page too
+  / <bytes> | /\"text\"    search from after the cursor, wraps around
+  f, freeze [val]        hold the byte at the cursor, at its current value by default
+  u, unfreeze            let it change again
+  frozen                 list frozen addresses, kept in <rom>.freeze in the save dir
+  q                      back to the debugger";
+
+pub struct HexEditor {
+    pub cursor: u16,
+    // first address shown
+    top: u16,
+}
+
+impl HexEditor {
+    pub fn new(cursor: u16) -> Self {
+        HexEditor {
+            cursor,
+            top: cursor & !0x0f,
+        }
+    }
+
+    // false when the editor was left
+    pub fn command(&mut self, gb: &mut GB, line: &str) -> Result<bool, String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "q" => return Ok(false),
+            "h" | "help" => println!("{}", HELP),
+            "" | "n" => self.top = self.top.wrapping_add(PAGE),
+            "p" => self.top = self.top.wrapping_sub(PAGE),
+            "g" | "goto" => self.goto(parse_hex(rest.trim())?),
+            "f" | "freeze" => {
+                let val = match rest.trim() {
+                    "" => gb.mmu.peek(self.cursor),
+                    val => byte(val)?,
+                };
+                gb.freeze(self.cursor, val, Hold::Write)?;
+            }
+            "u" | "unfreeze" => {
+                gb.unfreeze(self.cursor);
+            }
+            "frozen" => {
+                print!("{}", gb.frozen().to_text());
+                return Ok(true);
+            }
+            _ if line.starts_with('/') => {
+                let needle = pattern(line[1..].trim())?;
+                let found = search(gb, self.cursor.wrapping_add(1), &needle).ok_or("not found")?;
+                self.goto(found);
+            }
+            _ => {
+                let bytes = line.split_whitespace().map(byte).collect::<Result<Vec<u8>, String>>()?;
+                for val in bytes {
+                    // editing a frozen byte changes what it's held at
+                    match gb.frozen().get(self.cursor) {
+                        Some((_, hold)) => gb.freeze(self.cursor, val, hold)?,
+                        None => gb.mmu.poke(self.cursor, val)?,
+                    }
+                    self.cursor = self.cursor.wrapping_add(1);
+                }
+                self.goto(self.cursor);
+            }
+        }
+        print!("{}", self.render(gb));
+        Ok(true)
+    }
+
+    // keeps the cursor on screen
+    fn goto(&mut self, addr: u16) {
+        self.cursor = addr;
+        if addr.wrapping_sub(self.top) >= PAGE {
+            self.top = addr & !0x0f;
+        }
+    }
+
+    // cursor byte in [], frozen ones marked with *
+    pub fn render(&self, gb: &GB) -> String {
+        let mut out = String::new();
+        for row in 0..ROWS {
+            let base = self.top.wrapping_add(row * 16);
+            out.push_str(&format!("{:<4} {:04X}:", region(base), base));
+            let mut text = String::new();
+            for i in 0..16 {
+                let addr = base.wrapping_add(i);
+                let (open, close) = match (addr == self.cursor, gb.frozen().contains(addr)) {
+                    (true, _) => ('[', ']'),
+                    (false, true) => (' ', '*'),
+                    (false, false) => (' ', ' '),
+                };
+                match addr {
+                    0xfea0..=0xfeff => {
+                        out.push_str(&format!("{}--{}", open, close));
+                        text.push(' ');
+                    }
+                    _ => {
+                        let val = gb.mmu.peek(addr);
+                        out.push_str(&format!("{}{:02X}{}", open, val, close));
+                        text.push(if val.is_ascii_graphic() { val as char } else { '.' });
+                    }
+                }
+            }
+            out.push_str(&format!(" {}\n", text));
+        }
+        out
+    }
+}
+
+// where an address goes, banked areas show what is mapped right now
+fn region(addr: u16) -> &'static str {
+    match addr {
+        0x0000..=0x3fff => "ROM0",
+        0x4000..=0x7fff => "ROMX",
+        0x8000..=0x9fff => "VRAM",
+        0xa000..=0xbfff => "SRAM",
+        0xc000..=0xdfff => "WRAM",
+        0xe000..=0xfdff => "ECHO",
+        0xfe00..=0xfe9f => "OAM",
+        0xfea0..=0xfeff => "----",
+        0xff00..=0xff7f => "IO",
+        0xff80..=0xffff => "HRAM",
+    }
+}
+
+// first address at or after start holding needle, searching the whole address space once
+fn search(gb: &GB, start: u16, needle: &[u8]) -> Option<u16> {
+    if needle.is_empty() {
+        return None;
+    }
+    (0..=0xffffu32).map(|i| start.wrapping_add(i as u16)).find(|&addr| {
+        needle.iter().enumerate().all(|(i, &b)| match addr.wrapping_add(i as u16) {
+            0xfea0..=0xfeff => false,
+            at => gb.mmu.peek(at) == b,
+        })
+    })
+}
+
+// hex bytes or "quoted text"
+fn pattern(text: &str) -> Result<Vec<u8>, String> {
+    match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(inner) => Ok(inner.as_bytes().to_vec()),
+        None => text.split_whitespace().map(byte).collect(),
+    }
+}
+
+fn parse_hex(word: &str) -> Result<u16, String> {
+    let digits = word.strip_prefix("0x").or_else(|| word.strip_prefix('$')).unwrap_or(word);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address `{}`", word))
+}
+
+fn byte(word: &str) -> Result<u8, String> {
+    match parse_hex(word) {
+        Ok(val) if val <= 0xff => Ok(val as u8),
+        _ => Err(format!("invalid byte `{}`", word)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+
+    #[test]
+    fn edits_searches_and_freezes() {
+        let rom = micro_rom("nop");
+        let mut gb = GB::new(&rom).unwrap();
+        let mut editor = HexEditor::new(0xc100);
+        editor.command(&mut gb, "48 49 21").unwrap();
+        assert_eq!(gb.read_memory(0xc100, 3), [0x48, 0x49, 0x21]);
+        assert_eq!(editor.cursor, 0xc103);
+        // read-only LY bits and locked VRAM are reachable
+        editor.command(&mut gb, "g ff44").unwrap();
+        editor.command(&mut gb, "99").unwrap();
+        assert_eq!(gb.mmu.io[0x44], 0x99);
+        assert!(editor.command(&mut gb, "g 0150").and_then(|_| editor.command(&mut gb, "00")).is_err());
+
+        editor.command(&mut gb, "g c000").unwrap();
+        editor.command(&mut gb, "/\"HI\"").unwrap();
+        assert_eq!(editor.cursor, 0xc100);
+        editor.command(&mut gb, "f").unwrap();
+        gb.mmu.wb(0xc100, 0x00);
+        assert_eq!(gb.mmu.rb(0xc100), 0x48);
+        assert!(editor.render(&gb).contains("[48]"));
+        editor.command(&mut gb, "u").unwrap();
+        gb.mmu.wb(0xc100, 0x00);
+        assert_eq!(gb.mmu.rb(0xc100), 0x00);
+        assert!(!editor.command(&mut gb, "q").unwrap());
+    }
+}
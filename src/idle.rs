@@ -0,0 +1,120 @@
+// --skip-idle: time the CPU only spends waiting is fast-forwarded to just short
+// of the next event that could end the wait, a PPU mode or LY change, a timer
+// or serial interrupt, or a frame boundary with the LCD off. the machine ends up
+// exactly where running it cycle by cycle would have taken it
+//
+// waiting is HALT (or STOP, or a locked up CPU) with no interrupt pending, or a
+// polling loop: a jump back lands where the last one did with the same
+// registers, the iteration in between wrote nothing, read only memory that
+// can't change before that event, and ran entirely before it. the loop then
+// repeats unchanged until the event, in whole iterations
+
+use crate::Z80;
+
+// longest jump back that can close a polling loop, in bytes
+const MAX_LOOP: u16 = 64;
+
+// A B C D E H L F, SP, then IME, EI pending and the HALT bug
+type Registers = ([u8; 8], u16, [bool; 3]);
+
+struct Iteration {
+    pc: u16,
+    registers: Registers,
+    // t-cycles elapsed when it began, and when the next event was then
+    start: u64,
+    event: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Idle {
+    iteration: Option<Iteration>,
+    // the current iteration only read what stays put and wrote nothing
+    quiet: bool,
+    // t-cycles fast-forwarded so far
+    pub(crate) skipped: u64,
+}
+
+fn registers(z80: &Z80) -> Registers {
+    ([z80.a, z80.b, z80.c, z80.d, z80.e, z80.h, z80.l, z80.f.bits()], z80.sp, [z80.ime, z80.ei_pending, z80.halt_bug])
+}
+
+// memory only the CPU writes, and registers that only change at the events the
+// skip stops short of. cartridge RAM can be an RTC, DIV and TIMA count on and
+// the joypad is up to the frontend
+fn unchanging(addr: u16) -> bool {
+    match addr {
+        0xa000..=0xbfff => false,
+        0xff0f | 0xff40..=0xff4b => true,
+        0xff00..=0xff7f => false,
+        _ => true,
+    }
+}
+
+impl Idle {
+    pub(crate) fn read(&mut self, addr: u16) {
+        self.quiet &= unchanging(addr);
+    }
+
+    pub(crate) fn write(&mut self) {
+        self.quiet = false;
+    }
+
+    // after a jump back, with the PC the jump started at, the t-cycles elapsed
+    // and those until the next event. returns how many to fast-forward
+    pub(crate) fn executed(&mut self, from: u16, z80: &Z80, now: u64, until_event: u64) -> u64 {
+        if z80.pc > from || from - z80.pc > MAX_LOOP {
+            return 0;
+        }
+        let (registers, event) = (registers(z80), now + until_event);
+        let skip = match &self.iteration {
+            Some(last) if self.quiet && last.pc == z80.pc && last.registers == registers && now < last.event => {
+                let length = now - last.start;
+                (last.event - now - 1) / length * length
+            }
+            _ => 0,
+        };
+        self.skipped += skip;
+        self.iteration = Some(Iteration { pc: z80.pc, registers, start: now + skip, event });
+        self.quiet = true;
+        skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::micro_rom;
+    use crate::ppu::Renderer;
+    use crate::{savestate, GB};
+
+    // HALT until the timer interrupt, then poll LY for line 144, count the rounds
+    fn rom(lcdc: u8) -> Vec<u8> {
+        micro_rom(&format!(
+            "ld a, $05\nldh ($07), a\nld a, $04\nldh ($ff), a\nld a, ${:02x}\nldh ($40), a\n\
+             loop: halt\nxor a\nldh ($0f), a\n\
+             wait: ldh a, ($44)\ncp $90\njr nz, wait\n\
+             ld hl, $c000\ninc (hl)\njr loop",
+            lcdc
+        ))
+    }
+
+    #[test]
+    fn skipping_ends_up_in_the_same_state() {
+        for (lcdc, renderer) in [(0x91, Renderer::Scanline), (0x91, Renderer::Fifo), (0x11, Renderer::Scanline)] {
+            let rom = rom(lcdc);
+            let run = |skip| {
+                let mut gb = GB::new(&rom).unwrap();
+                gb.ppu_mut().set_renderer(renderer);
+                gb.set_skip_idle(skip);
+                gb.run_frames(10);
+                (savestate::save(&gb), gb.idle_skipped(), gb.mmu.rb(0xc000))
+            };
+            let ((stepped, _, rounds), (skipped, fast_forwarded, _)) = (run(false), run(true));
+            assert!(stepped == skipped, "LCDC {:02x} {:?}", lcdc, renderer);
+            if lcdc & 0x80 != 0 {
+                assert!(rounds >= 9, "{}", rounds);
+            }
+            // much of the time is waiting, less with the FIFO deciding when mode 3 ends
+            assert!(fast_forwarded > 10 * crate::ppu::FRAME_DOTS / 4, "{}", fast_forwarded);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// on-screen joypad drawn into the bottom left of an RGB frame before
+// post-processing, lit buttons were held when the frame ended, the same state
+// movies record. above it an optional strip of the last frames scrolls up, one
+// pixel row per frame with a column per button, newest at the bottom
+//
+//    . U .            strip columns: R L U D A B select start
+//    L . R  - -  B A
+//    . D .
+
+use std::collections::VecDeque;
+
+use crate::ppu::HEIGHT;
+use crate::Buttons;
+
+const CELL: usize = 3;
+const PAD_WIDTH: usize = 11 * CELL;
+const PAD_HEIGHT: usize = 3 * CELL;
+const MARGIN: usize = 2;
+// strip column per button, in Buttons bit order
+const STRIP_COLUMN: usize = 3;
+// frames of history that fit above the pad
+pub const MAX_STRIP: usize = HEIGHT - PAD_HEIGHT - 2 * MARGIN - 1;
+
+const LIT: [u8; 3] = [255, 255, 255];
+const UNLIT: [u8; 3] = [96, 96, 96];
+
+// (button, x, y) in cells from the top left of the pad
+const LAYOUT: [(Buttons, usize, usize); 8] = [
+    (Buttons::UP, 1, 0),
+    (Buttons::LEFT, 0, 1),
+    (Buttons::RIGHT, 2, 1),
+    (Buttons::DOWN, 1, 2),
+    (Buttons::SELECT, 4, 1),
+    (Buttons::START, 6, 1),
+    (Buttons::B, 8, 1),
+    (Buttons::A, 10, 1),
+];
+
+pub struct InputDisplay {
+    // newest last
+    history: VecDeque<Buttons>,
+    strip: usize,
+}
+
+impl InputDisplay {
+    // strip of the last `strip` frames, up to MAX_STRIP, 0 for the pad alone
+    pub fn new(strip: usize) -> Self {
+        let strip = strip.min(MAX_STRIP);
+        InputDisplay { history: VecDeque::with_capacity(strip + 1), strip }
+    }
+
+    // once per frame, e.g. with GB::frame_buttons
+    pub fn push(&mut self, buttons: Buttons) {
+        if self.history.len() > self.strip {
+            self.history.pop_front();
+        }
+        self.history.push_back(buttons);
+    }
+
+    // packed RGB, width pixels a row, the screen or the SGB picture around it
+    pub fn draw(&self, rgb: &mut [u8], width: usize) {
+        let current = self.history.back().copied().unwrap_or_default();
+        let mut frame = Frame { rgb, width };
+        let top = frame.rgb.len() / 3 / width - MARGIN - PAD_HEIGHT;
+        frame.darken(MARGIN - 1, top - 1, PAD_WIDTH + 2, PAD_HEIGHT + 2);
+        for (button, x, y) in LAYOUT {
+            let color = if current.contains(button) { LIT } else { UNLIT };
+            frame.fill(MARGIN + x * CELL, top + y * CELL, CELL - 1, CELL - 1, color);
+        }
+        if self.strip == 0 {
+            return;
+        }
+        // frames before the current one
+        let bottom = top - 2;
+        frame.darken(MARGIN - 1, bottom - self.strip, 8 * STRIP_COLUMN + 1, self.strip + 1);
+        for (age, buttons) in self.history.iter().rev().skip(1).enumerate() {
+            for bit in 0..8 {
+                if buttons.bits() & (1 << bit) != 0 {
+                    frame.fill(MARGIN + bit * STRIP_COLUMN, bottom - 1 - age, STRIP_COLUMN - 1, 1, LIT);
+                }
+            }
+        }
+    }
+}
+
+struct Frame<'a> {
+    rgb: &'a mut [u8],
+    width: usize,
+}
+
+impl Frame<'_> {
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        for row in y..y + height {
+            for col in x..x + width {
+                let i = (row * self.width + col) * 3;
+                self.rgb[i..i + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    // halves the brightness behind the display so it reads on any background
+    fn darken(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        for row in y..y + height {
+            let i = (row * self.width + x) * 3;
+            for c in &mut self.rgb[i..i + width * 3] {
+                *c /= 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::WIDTH;
+
+    fn pixel(rgb: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let i = (y * WIDTH + x) * 3;
+        [rgb[i], rgb[i + 1], rgb[i + 2]]
+    }
+
+    #[test]
+    fn lights_held_buttons_and_scrolls() {
+        let mut display = InputDisplay::new(2);
+        display.push(Buttons::RIGHT);
+        display.push(Buttons::A);
+        display.push(Buttons::A | Buttons::UP);
+        let mut rgb = vec![200; WIDTH * HEIGHT * 3];
+        display.draw(&mut rgb, WIDTH);
+        let top = HEIGHT - MARGIN - PAD_HEIGHT;
+        assert_eq!(pixel(&rgb, MARGIN + CELL, top), LIT, "up");
+        assert_eq!(pixel(&rgb, MARGIN + 10 * CELL, top + CELL), LIT, "A");
+        assert_eq!(pixel(&rgb, MARGIN, top + CELL), UNLIT, "left");
+        assert_eq!(pixel(&rgb, MARGIN + 2, top + 2), [100; 3], "gap between cells");
+        assert_eq!(pixel(&rgb, WIDTH - 1, 0), [200; 3]);
+
+        // A the frame before, right the one before that
+        let bottom = top - 2;
+        assert_eq!(pixel(&rgb, MARGIN + 4 * STRIP_COLUMN, bottom - 1), LIT);
+        assert_eq!(pixel(&rgb, MARGIN, bottom - 1), [100; 3]);
+        assert_eq!(pixel(&rgb, MARGIN, bottom - 2), LIT);
+    }
+}
@@ -0,0 +1,1242 @@
+// the interpreter --script runs: a small language of its own with Lua's syntax,
+// enough for bots, overlays and test scripts without an interpreter crate.
+// scripts written for Lua mostly won't run. it has
+//
+//   values      nil, booleans, numbers (doubles, integral ones print without a
+//               fraction), strings and functions
+//   statements  local and global assignment of one name, calls, if / elseif /
+//               else, while, repeat / until, numeric for, do, break, return of
+//               one value, function and local function definitions
+//   operators   Lua 5.3's with its precedence: or and, comparisons, | ~ & <<
+//               >>, .., + -, * / // %, unary not # - ~, ^
+//   builtins    floor format max print tonumber tostring type, format takes
+//               %d %x %X %f %s with - and 0 flags, width and precision
+//   comments    -- to the end of the line and --[[ long ones ]]
+//
+// and nothing else. a function sees its parameters, its own locals and the
+// globals, never the locals around its definition. locals at the top of the
+// script are globals, so the functions it defines see them, and a local
+// function recurses only there
+//
+// the host names its functions when the interpreter starts and provides them
+// through Host. every call from the host has a budget of steps, a script stuck
+// in a loop errors out instead of hanging the emulator
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// statements and calls one run from the host may take
+const MAX_STEPS: u64 = 10_000_000;
+const MAX_DEPTH: usize = 200;
+
+const BUILTINS: [&str; 7] = ["floor", "format", "max", "print", "tonumber", "tostring", "type"];
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Function(Rc<Function>),
+    // the interpreter's own or the host's
+    Builtin(Rc<str>),
+}
+
+impl Value {
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Function(_) | Value::Builtin(_) => "function",
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) if n.abs() >= 1e15 => write!(f, "{:e}", n),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Function(function) => write!(f, "function: {}", function.name),
+            Value::Builtin(name) => write!(f, "builtin: {}", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Function {
+    name: Rc<str>,
+    params: Vec<Rc<str>>,
+    body: Block,
+}
+
+pub trait Host {
+    // one of the functions the host named, with its arguments
+    fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String>;
+    fn print(&mut self, text: &str);
+}
+
+// a message and the line it happened on, 0 until a statement claims it
+struct Error {
+    line: u32,
+    message: String,
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error { line: 0, message }
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Error {
+        Error { line: 0, message: message.to_string() }
+    }
+}
+
+// lexer
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(Rc<str>),
+    Name(Rc<str>),
+    // keywords and symbols
+    Op(&'static str),
+    Eof,
+}
+
+const KEYWORDS: [&str; 21] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "local", "nil", "not", "or",
+    "repeat", "return", "then", "true", "until", "while", "goto",
+];
+
+// longest first, so `..` isn't read as two `.`
+const SYMBOLS: [&str; 26] = [
+    "...", "..", "==", "~=", "<=", ">=", "<<", ">>", "//", "+", "-", "*", "/", "%", "^", "#", "&", "~", "|", "<", ">",
+    "=", "(", ")", ",", ".",
+];
+
+fn lex(source: &str) -> Result<Vec<(Token, u32)>, Error> {
+    let bytes = source.as_bytes();
+    let (mut tokens, mut i, mut line) = (Vec::new(), 0, 1);
+    let error = |line, message: String| Error { line, message };
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+        } else if source[i..].starts_with("--") {
+            i += 2;
+            if let Some(end) = source[i..].starts_with("[[").then(|| source[i..].find("]]")) {
+                let end = end.ok_or_else(|| error(line, "unfinished long comment".into()))?;
+                line += source[i..i + end].matches('\n').count() as u32;
+                i += end + 2;
+            } else {
+                i = source[i..].find('\n').map_or(bytes.len(), |end| i + end);
+            }
+        } else if c.is_ascii_digit() || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) {
+            let start = i;
+            let hex = source[i..].starts_with("0x") || source[i..].starts_with("0X");
+            i += if hex { 2 } else { 0 };
+            while i < bytes.len() {
+                let d = bytes[i];
+                let exponent = !hex && (d == b'e' || d == b'E');
+                let sign = (d == b'+' || d == b'-') && !hex && matches!(bytes[i - 1], b'e' | b'E');
+                if d.is_ascii_hexdigit() && (hex || d.is_ascii_digit() || exponent) || d == b'.' || exponent || sign {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            let text = &source[start..i];
+            let number = match hex {
+                true => i64::from_str_radix(&text[2..], 16).ok().map(|n| n as f64),
+                false => text.parse().ok(),
+            };
+            let number = number.ok_or_else(|| error(line, format!("malformed number near '{}'", text)))?;
+            tokens.push((Token::Number(number), line));
+        } else if c == b'"' || c == b'\'' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                let Some(ch) = source[i..].chars().next() else {
+                    return Err(error(line, "unfinished string".into()));
+                };
+                i += ch.len_utf8();
+                match ch {
+                    '\n' => return Err(error(line, "unfinished string".into())),
+                    '\\' => {
+                        let escaped = bytes.get(i).copied().ok_or_else(|| error(line, "unfinished string".into()))?;
+                        i += 1;
+                        text.push(match escaped {
+                            b'n' => '\n',
+                            b't' => '\t',
+                            b'\\' | b'"' | b'\'' => escaped as char,
+                            _ => return Err(error(line, format!("invalid escape sequence '\\{}'", escaped as char))),
+                        });
+                    }
+                    ch if ch as u32 == c as u32 => break,
+                    ch => text.push(ch),
+                }
+            }
+            tokens.push((Token::Str(text.into()), line));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let word = &source[start..i];
+            let token = match KEYWORDS.iter().find(|&&k| k == word) {
+                Some(keyword) => Token::Op(keyword),
+                None => Token::Name(word.into()),
+            };
+            tokens.push((token, line));
+        } else {
+            let symbol = SYMBOLS.iter().find(|s| source[i..].starts_with(**s));
+            let symbol = symbol.ok_or_else(|| error(line, format!("unexpected symbol near '{}'", c as char)))?;
+            tokens.push((Token::Op(symbol), line));
+            i += symbol.len();
+        }
+    }
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+// syntax tree
+
+type Block = Vec<Stat>;
+
+#[derive(Debug)]
+struct Stat {
+    line: u32,
+    kind: StatKind,
+}
+
+#[derive(Debug)]
+enum StatKind {
+    Local(Rc<str>, Option<Expr>),
+    Assign(Rc<str>, Expr),
+    Call(Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    For { var: Rc<str>, start: Expr, limit: Expr, step: Option<Expr>, body: Block },
+    Do(Block),
+    Return(Option<Expr>),
+    Break,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Constant(Value),
+    Name(Rc<str>),
+    Call(Box<Expr>, Vec<Expr>),
+    Function(Rc<Function>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+// left and right precedence as Lua has them, concatenation and ^ go right to left
+fn precedence(op: &str) -> Option<(u8, u8)> {
+    Some(match op {
+        "or" => (1, 1),
+        "and" => (2, 2),
+        "<" | ">" | "<=" | ">=" | "~=" | "==" => (3, 3),
+        "|" => (4, 4),
+        "~" => (5, 5),
+        "&" => (6, 6),
+        "<<" | ">>" => (7, 7),
+        ".." => (9, 8),
+        "+" | "-" => (10, 10),
+        "*" | "/" | "//" | "%" => (11, 11),
+        "^" => (14, 13),
+        _ => return None,
+    })
+}
+
+const UNARY_PRIORITY: u8 = 12;
+
+struct Parser {
+    tokens: Vec<(Token, u32)>,
+    next: usize,
+    // loops around the statement being parsed, in the current function
+    loops: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.next].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.next].0.clone();
+        self.next = (self.next + 1).min(self.tokens.len() - 1);
+        token
+    }
+
+    fn error(&self, message: &str) -> Error {
+        let near = match self.peek() {
+            Token::Number(n) => Value::Number(*n).to_string(),
+            Token::Str(s) => format!("\"{}\"", s),
+            Token::Name(name) => name.to_string(),
+            Token::Op(op) => op.to_string(),
+            Token::Eof => "<eof>".to_string(),
+        };
+        Error { line: self.line(), message: format!("{} near '{}'", message, near) }
+    }
+
+    fn accept(&mut self, op: &str) -> bool {
+        let found = self.is(op);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn is(&self, op: &str) -> bool {
+        matches!(self.peek(), Token::Op(o) if *o == op)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), Error> {
+        match self.accept(op) {
+            true => Ok(()),
+            false => Err(self.error(&format!("'{}' expected", op))),
+        }
+    }
+
+    fn name(&mut self) -> Result<Rc<str>, Error> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error("<name> expected")),
+        }
+    }
+
+    fn block_ends(&self) -> bool {
+        matches!(self.peek(), Token::Eof) || ["end", "else", "elseif", "until"].iter().any(|op| self.is(op))
+    }
+
+    fn block(&mut self) -> Result<Block, Error> {
+        let mut block = Vec::new();
+        while !self.block_ends() {
+            let line = self.line();
+            if self.accept("return") {
+                let value = match self.block_ends() {
+                    true => None,
+                    false => Some(self.expr(0)?),
+                };
+                block.push(Stat { line, kind: StatKind::Return(value) });
+                if !self.block_ends() {
+                    return Err(self.error("'end' expected"));
+                }
+                break;
+            }
+            let kind = self.statement()?;
+            block.push(Stat { line, kind });
+        }
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<StatKind, Error> {
+        if self.accept("local") {
+            if self.accept("function") {
+                let name = self.name()?;
+                return Ok(StatKind::Local(name.clone(), Some(Expr::Function(self.function(name)?))));
+            }
+            let name = self.name()?;
+            let value = if self.accept("=") { Some(self.expr(0)?) } else { None };
+            return Ok(StatKind::Local(name, value));
+        }
+        if self.accept("function") {
+            let name = self.name()?;
+            return Ok(StatKind::Assign(name.clone(), Expr::Function(self.function(name)?)));
+        }
+        if self.accept("if") {
+            let mut branches = Vec::new();
+            loop {
+                let condition = self.expr(0)?;
+                self.expect("then")?;
+                branches.push((condition, self.block()?));
+                if !self.accept("elseif") {
+                    break;
+                }
+            }
+            let otherwise = if self.accept("else") { Some(self.block()?) } else { None };
+            self.expect("end")?;
+            return Ok(StatKind::If(branches, otherwise));
+        }
+        if self.accept("while") {
+            let condition = self.expr(0)?;
+            self.expect("do")?;
+            let body = self.loop_body()?;
+            self.expect("end")?;
+            return Ok(StatKind::While(condition, body));
+        }
+        if self.accept("repeat") {
+            let body = self.loop_body()?;
+            self.expect("until")?;
+            return Ok(StatKind::Repeat(body, self.expr(0)?));
+        }
+        if self.accept("for") {
+            let var = self.name()?;
+            self.expect("=")?;
+            let start = self.expr(0)?;
+            self.expect(",")?;
+            let limit = self.expr(0)?;
+            let step = if self.accept(",") { Some(self.expr(0)?) } else { None };
+            self.expect("do")?;
+            let body = self.loop_body()?;
+            self.expect("end")?;
+            return Ok(StatKind::For { var, start, limit, step, body });
+        }
+        if self.accept("do") {
+            let body = self.block()?;
+            self.expect("end")?;
+            return Ok(StatKind::Do(body));
+        }
+        if self.is("break") {
+            if self.loops == 0 {
+                return Err(self.error("break outside a loop"));
+            }
+            self.advance();
+            return Ok(StatKind::Break);
+        }
+        let target = self.suffixed()?;
+        match target {
+            Expr::Name(name) if self.accept("=") => Ok(StatKind::Assign(name, self.expr(0)?)),
+            Expr::Call(..) => Ok(StatKind::Call(target)),
+            _ => Err(self.error("syntax error")),
+        }
+    }
+
+    fn loop_body(&mut self) -> Result<Block, Error> {
+        self.loops += 1;
+        let body = self.block();
+        self.loops -= 1;
+        body
+    }
+
+    // parameters and body, after `function name`
+    fn function(&mut self, name: Rc<str>) -> Result<Rc<Function>, Error> {
+        self.expect("(")?;
+        let mut params = Vec::new();
+        if !self.accept(")") {
+            loop {
+                params.push(self.name()?);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+        let loops = std::mem::replace(&mut self.loops, 0);
+        let body = self.block();
+        self.loops = loops;
+        let body = body?;
+        self.expect("end")?;
+        Ok(Rc::new(Function { name, params, body }))
+    }
+
+    // a name or parenthesized expression, and the calls after it
+    fn suffixed(&mut self) -> Result<Expr, Error> {
+        let mut expr = match self.peek().clone() {
+            Token::Name(name) => {
+                self.advance();
+                Expr::Name(name)
+            }
+            Token::Op("(") => {
+                self.advance();
+                let inner = self.expr(0)?;
+                self.expect(")")?;
+                inner
+            }
+            _ => return Err(self.error("unexpected symbol")),
+        };
+        while self.accept("(") {
+            let mut args = Vec::new();
+            if !self.accept(")") {
+                loop {
+                    args.push(self.expr(0)?);
+                    if !self.accept(",") {
+                        break;
+                    }
+                }
+                self.expect(")")?;
+            }
+            expr = Expr::Call(Box::new(expr), args);
+        }
+        Ok(expr)
+    }
+
+    fn simple(&mut self) -> Result<Expr, Error> {
+        let constant = match self.peek().clone() {
+            Token::Number(n) => Value::Number(n),
+            Token::Str(s) => Value::Str(s),
+            Token::Op("nil") => Value::Nil,
+            Token::Op("true") => Value::Bool(true),
+            Token::Op("false") => Value::Bool(false),
+            Token::Op("function") => {
+                self.advance();
+                return Ok(Expr::Function(self.function("anonymous".into())?));
+            }
+            _ => return self.suffixed(),
+        };
+        self.advance();
+        Ok(Expr::Constant(constant))
+    }
+
+    // operators binding tighter than limit
+    fn expr(&mut self, limit: u8) -> Result<Expr, Error> {
+        let mut left = match self.peek().clone() {
+            Token::Op(op @ ("not" | "-" | "#" | "~")) => {
+                self.advance();
+                Expr::Unary(op, Box::new(self.expr(UNARY_PRIORITY)?))
+            }
+            _ => self.simple()?,
+        };
+        while let Token::Op(op) = *self.peek() {
+            let Some((_, right_priority)) = precedence(op).filter(|(left, _)| *left > limit) else {
+                break;
+            };
+            self.advance();
+            let right = self.expr(right_priority)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+}
+
+// interpreter
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Value),
+}
+
+struct Frame {
+    locals: Vec<(Rc<str>, Value)>,
+    // the script's top level, outside any block, where locals are globals
+    top: bool,
+}
+
+pub struct Interpreter {
+    // script name, errors start with it
+    chunk: String,
+    globals: HashMap<Rc<str>, Value>,
+    steps: u64,
+    depth: usize,
+}
+
+impl Interpreter {
+    // with host_functions as globals, provided by the Host passed to run and call
+    pub fn new(chunk: &str, host_functions: &[&str]) -> Interpreter {
+        let globals = BUILTINS.iter().chain(host_functions).map(|&name| (name.into(), Value::Builtin(name.into()))).collect();
+        Interpreter { chunk: chunk.to_string(), globals, steps: 0, depth: 0 }
+    }
+
+    // parses source and runs its top level
+    pub fn run(&mut self, source: &str, host: &mut dyn Host) -> Result<(), String> {
+        let tokens = lex(source).map_err(|e| self.describe(e))?;
+        let mut parser = Parser { tokens, next: 0, loops: 0 };
+        let block = parser.block().and_then(|block| match parser.peek() {
+            Token::Eof => Ok(block),
+            _ => Err(parser.error("'<eof>' expected")),
+        });
+        let block = block.map_err(|e| self.describe(e))?;
+        self.steps = 0;
+        let mut frame = Frame { locals: Vec::new(), top: true };
+        self.statements(&block, &mut frame, host).map(|_| ()).map_err(|e| self.describe(e))
+    }
+
+    // the global function name, None if the script defines none
+    pub fn call(&mut self, name: &str, args: &[Value], host: &mut dyn Host) -> Result<Option<Value>, String> {
+        let Some(function @ Value::Function(_)) = self.globals.get(name).cloned() else {
+            return Ok(None);
+        };
+        self.steps = 0;
+        self.call_value(&function, args.to_vec(), host).map(Some).map_err(|e| self.describe(e))
+    }
+
+    pub fn global(&self, name: &str) -> Value {
+        self.globals.get(name).cloned().unwrap_or(Value::Nil)
+    }
+
+    fn describe(&self, e: Error) -> String {
+        format!("{}:{}: {}", self.chunk, e.line, e.message)
+    }
+
+    fn statements(&mut self, block: &[Stat], frame: &mut Frame, host: &mut dyn Host) -> Result<Flow, Error> {
+        for stat in block {
+            let flow = self.statement(&stat.kind, frame, host).map_err(|mut e| {
+                if e.line == 0 {
+                    e.line = stat.line;
+                }
+                e
+            })?;
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // locals declared in the block go out of scope after it
+    fn block(&mut self, block: &[Stat], frame: &mut Frame, host: &mut dyn Host) -> Result<Flow, Error> {
+        let (mark, top) = (frame.locals.len(), std::mem::replace(&mut frame.top, false));
+        let flow = self.statements(block, frame, host);
+        frame.locals.truncate(mark);
+        frame.top = top;
+        flow
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        match self.steps > MAX_STEPS {
+            true => Err("script ran too long, stuck in a loop?".into()),
+            false => Ok(()),
+        }
+    }
+
+    fn statement(&mut self, stat: &StatKind, frame: &mut Frame, host: &mut dyn Host) -> Result<Flow, Error> {
+        self.step()?;
+        match stat {
+            StatKind::Local(name, value) => {
+                let value = match value {
+                    Some(value) => self.eval(value, frame, host)?,
+                    None => Value::Nil,
+                };
+                match frame.top {
+                    true => self.set_global(name, value),
+                    false => frame.locals.push((name.clone(), value)),
+                }
+            }
+            StatKind::Assign(name, value) => {
+                let value = self.eval(value, frame, host)?;
+                match frame.locals.iter_mut().rev().find(|(local, _)| local == name) {
+                    Some((_, local)) => *local = value,
+                    None => self.set_global(name, value),
+                }
+            }
+            StatKind::Call(call) => {
+                self.eval(call, frame, host)?;
+            }
+            StatKind::If(branches, otherwise) => {
+                for (condition, body) in branches {
+                    if self.eval(condition, frame, host)?.truthy() {
+                        return self.block(body, frame, host);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.block(body, frame, host);
+                }
+            }
+            StatKind::While(condition, body) => {
+                while self.eval(condition, frame, host)?.truthy() {
+                    self.step()?;
+                    match self.block(body, frame, host)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            StatKind::Repeat(body, condition) => loop {
+                self.step()?;
+                // the condition sees the body's locals
+                let (mark, top) = (frame.locals.len(), std::mem::replace(&mut frame.top, false));
+                let flow = self.statements(body, frame, host).and_then(|flow| match flow {
+                    Flow::Normal => Ok((Flow::Normal, self.eval(condition, frame, host)?.truthy())),
+                    flow => Ok((flow, true)),
+                });
+                frame.locals.truncate(mark);
+                frame.top = top;
+                match flow? {
+                    (Flow::Normal, false) => {}
+                    (Flow::Normal | Flow::Break, true) => break,
+                    (flow, _) => return Ok(flow),
+                }
+            },
+            StatKind::For { var, start, limit, step, body } => {
+                let start = self.number(start, frame, host, "'for' initial value")?;
+                let limit = self.number(limit, frame, host, "'for' limit")?;
+                let step = match step {
+                    Some(step) => self.number(step, frame, host, "'for' step")?,
+                    None => 1.0,
+                };
+                if step == 0.0 {
+                    return Err("'for' step is zero".into());
+                }
+                let mut i = start;
+                while (step > 0.0 && i <= limit) || (step < 0.0 && i >= limit) {
+                    frame.locals.push((var.clone(), Value::Number(i)));
+                    let flow = self.block(body, frame, host);
+                    frame.locals.pop();
+                    match flow? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                    i += step;
+                }
+            }
+            StatKind::Do(body) => return self.block(body, frame, host),
+            StatKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value, frame, host)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StatKind::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn set_global(&mut self, name: &Rc<str>, value: Value) {
+        match value {
+            Value::Nil => self.globals.remove(name),
+            value => self.globals.insert(name.clone(), value),
+        };
+    }
+
+    fn number(&mut self, expr: &Expr, frame: &mut Frame, host: &mut dyn Host, what: &str) -> Result<f64, Error> {
+        match self.eval(expr, frame, host)? {
+            Value::Number(n) => Ok(n),
+            other => Err(format!("{} must be a number, got {}", what, other.type_name()).into()),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, frame: &mut Frame, host: &mut dyn Host) -> Result<Value, Error> {
+        Ok(match expr {
+            Expr::Constant(value) => value.clone(),
+            Expr::Name(name) => match frame.locals.iter().rev().find(|(local, _)| local == name) {
+                Some((_, value)) => value.clone(),
+                None => self.global(name),
+            },
+            Expr::Call(function, args) => {
+                let callee = self.eval(function, frame, host)?;
+                let args = args.iter().map(|arg| self.eval(arg, frame, host)).collect::<Result<Vec<_>, _>>()?;
+                if let (Value::Nil, Expr::Name(name)) = (&callee, function.as_ref()) {
+                    return Err(format!("attempt to call a nil value ('{}')", name).into());
+                }
+                self.call_value(&callee, args, host)?
+            }
+            Expr::Function(function) => Value::Function(function.clone()),
+            Expr::Unary(op, operand) => unary(op, self.eval(operand, frame, host)?)?,
+            Expr::Binary("and", left, right) => match self.eval(left, frame, host)? {
+                left if !left.truthy() => left,
+                _ => self.eval(right, frame, host)?,
+            },
+            Expr::Binary("or", left, right) => match self.eval(left, frame, host)? {
+                left if left.truthy() => left,
+                _ => self.eval(right, frame, host)?,
+            },
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left, frame, host)?;
+                binary(op, left, self.eval(right, frame, host)?)?
+            }
+        })
+    }
+
+    fn call_value(&mut self, callee: &Value, args: Vec<Value>, host: &mut dyn Host) -> Result<Value, Error> {
+        self.step()?;
+        match callee {
+            Value::Function(function) => {
+                if self.depth >= MAX_DEPTH {
+                    return Err("stack overflow".into());
+                }
+                let mut args = args.into_iter();
+                let locals = function.params.iter().map(|param| (param.clone(), args.next().unwrap_or(Value::Nil)));
+                let mut frame = Frame { locals: locals.collect(), top: false };
+                self.depth += 1;
+                let flow = self.statements(&function.body, &mut frame, host);
+                self.depth -= 1;
+                Ok(match flow? {
+                    Flow::Return(value) => value,
+                    _ => Value::Nil,
+                })
+            }
+            Value::Builtin(name) => match builtin(name, &args, host) {
+                Some(result) => result.map_err(Error::from),
+                None => host.call(name, &args).map_err(Error::from),
+            },
+            other => Err(format!("attempt to call a {} value", other.type_name()).into()),
+        }
+    }
+}
+
+fn arithmetic(op: &str, a: f64, b: f64) -> f64 {
+    match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        "//" => (a / b).floor(),
+        "%" => a - (a / b).floor() * b,
+        _ => a.powf(b),
+    }
+}
+
+fn integer(value: &Value) -> Result<i64, Error> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 9.2e18 => Ok(*n as i64),
+        Value::Number(_) => Err("number has no integer representation".into()),
+        other => Err(format!("attempt to perform bitwise operation on a {} value", other.type_name()).into()),
+    }
+}
+
+// logical shift left, negative amounts shift right
+fn shift(a: i64, by: i64) -> i64 {
+    match by {
+        64.. | ..=-64 => 0,
+        0.. => ((a as u64) << by) as i64,
+        _ => ((a as u64) >> -by) as i64,
+    }
+}
+
+fn unary(op: &str, value: Value) -> Result<Value, Error> {
+    Ok(match (op, &value) {
+        ("not", _) => Value::Bool(!value.truthy()),
+        ("-", Value::Number(n)) => Value::Number(-n),
+        ("#", Value::Str(s)) => Value::Number(s.len() as f64),
+        ("~", _) => Value::Number(!integer(&value)? as f64),
+        ("#", _) => return Err(format!("attempt to get length of a {} value", value.type_name()).into()),
+        _ => return Err(format!("attempt to perform arithmetic on a {} value", value.type_name()).into()),
+    })
+}
+
+fn binary(op: &str, left: Value, right: Value) -> Result<Value, Error> {
+    Ok(match (op, &left, &right) {
+        ("==", ..) => Value::Bool(left == right),
+        ("~=", ..) => Value::Bool(left != right),
+        ("+" | "-" | "*" | "/" | "//" | "%" | "^", Value::Number(a), Value::Number(b)) => {
+            Value::Number(arithmetic(op, *a, *b))
+        }
+        ("+" | "-" | "*" | "/" | "//" | "%" | "^", ..) => {
+            let bad = if matches!(left, Value::Number(_)) { &right } else { &left };
+            return Err(format!("attempt to perform arithmetic on a {} value", bad.type_name()).into());
+        }
+        ("&" | "|" | "~" | "<<" | ">>", ..) => {
+            let (a, b) = (integer(&left)?, integer(&right)?);
+            Value::Number(match op {
+                "&" => a & b,
+                "|" => a | b,
+                "~" => a ^ b,
+                "<<" => shift(a, b),
+                _ => shift(a, b.saturating_neg()),
+            } as f64)
+        }
+        ("..", Value::Str(_) | Value::Number(_), Value::Str(_) | Value::Number(_)) => {
+            Value::Str(format!("{}{}", left, right).into())
+        }
+        ("..", ..) => {
+            let bad = if matches!(left, Value::Str(_) | Value::Number(_)) { &right } else { &left };
+            return Err(format!("attempt to concatenate a {} value", bad.type_name()).into());
+        }
+        (_, Value::Number(a), Value::Number(b)) => Value::Bool(compare(op, a.partial_cmp(b))),
+        (_, Value::Str(a), Value::Str(b)) => Value::Bool(compare(op, Some(a.cmp(b)))),
+        _ => return Err(format!("attempt to compare {} with {}", left.type_name(), right.type_name()).into()),
+    })
+}
+
+fn compare(op: &str, ordering: Option<std::cmp::Ordering>) -> bool {
+    use std::cmp::Ordering::*;
+    matches!((op, ordering), ("<", Some(Less)) | ("<=", Some(Less | Equal)) | (">", Some(Greater)) | (">=", Some(Greater | Equal)))
+}
+
+// argument i of function name, a number
+pub fn number_arg(name: &str, args: &[Value], i: usize) -> Result<f64, String> {
+    match args.get(i) {
+        Some(Value::Number(n)) => Ok(*n),
+        other => Err(format!(
+            "bad argument #{} to '{}' (number expected, got {})",
+            i + 1,
+            name,
+            other.map_or("no value", Value::type_name)
+        )),
+    }
+}
+
+// the interpreter's own functions, None for the host's
+fn builtin(name: &str, args: &[Value], host: &mut dyn Host) -> Option<Result<Value, String>> {
+    let n = |i| number_arg(name, args, i);
+    let first = args.first().cloned().unwrap_or(Value::Nil);
+    Some(match name {
+        "print" => {
+            let text: Vec<String> = args.iter().map(Value::to_string).collect();
+            host.print(&text.join("\t"));
+            Ok(Value::Nil)
+        }
+        "tostring" => Ok(Value::Str(first.to_string().into())),
+        "tonumber" => Ok(match &first {
+            Value::Number(_) => first,
+            Value::Str(s) => {
+                let s = s.trim();
+                let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"));
+                let parsed = match hex {
+                    Some(digits) => i64::from_str_radix(digits, 16).ok().map(|n| n as f64),
+                    None => s.parse().ok(),
+                };
+                parsed.map_or(Value::Nil, Value::Number)
+            }
+            _ => Value::Nil,
+        }),
+        "type" => Ok(Value::Str(first.type_name().into())),
+        "floor" => n(0).map(|a| Value::Number(a.floor())),
+        "max" => max(args).map(Value::Number),
+        "format" => format(args).map(|s| Value::Str(s.into())),
+        _ => return None,
+    })
+}
+
+fn max(args: &[Value]) -> Result<f64, String> {
+    let mut best = number_arg("max", args, 0)?;
+    for i in 1..args.len() {
+        best = best.max(number_arg("max", args, i)?);
+    }
+    Ok(best)
+}
+
+// string.format's %d %x %X %f %s, with - and 0, width and precision
+fn format(args: &[Value]) -> Result<String, String> {
+    let Some(Value::Str(pattern)) = args.first() else {
+        return Err("bad argument #1 to 'format' (string expected)".to_string());
+    };
+    let (mut out, mut next, mut chars) = (String::new(), 1, pattern.chars().peekable());
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek().filter(|c| "-.".contains(**c) || c.is_ascii_digit()) {
+            spec.push(c);
+            chars.next();
+        }
+        let conversion = chars.next().ok_or("invalid conversion '%' to 'format'")?;
+        let (left, zero) = (spec.starts_with('-'), spec.trim_start_matches('-').starts_with('0'));
+        let digits = spec.trim_start_matches(['-', '0']);
+        let (width, precision): (usize, Option<usize>) = match digits.split_once('.') {
+            Some((width, precision)) => (width.parse().unwrap_or(0), Some(precision.parse().unwrap_or(0))),
+            None => (digits.parse().unwrap_or(0), None),
+        };
+        let arg = args.get(next).cloned().unwrap_or(Value::Nil);
+        next += 1;
+        let number = || number_arg("format", args, next - 1);
+        let integral = || {
+            let n = number()?;
+            match n.fract() == 0.0 {
+                true => Ok(n as i64),
+                false => Err(format!("bad argument #{} to 'format' (number has no integer representation)", next)),
+            }
+        };
+        let text = match conversion {
+            'd' => integral()?.to_string(),
+            'x' => format!("{:x}", integral()?),
+            'X' => format!("{:X}", integral()?),
+            'f' => format!("{:.*}", precision.unwrap_or(6), number()?),
+            's' => arg.to_string(),
+            other => return Err(format!("invalid conversion '%{}' to 'format'", other)),
+        };
+        let pad = width.saturating_sub(text.chars().count());
+        match (left, zero && conversion != 's') {
+            (true, _) => {
+                out.push_str(&text);
+                out.extend(std::iter::repeat_n(' ', pad));
+            }
+            (false, true) => {
+                let (sign, digits) = text.split_at(if text.starts_with('-') { 1 } else { 0 });
+                out.push_str(sign);
+                out.extend(std::iter::repeat_n('0', pad));
+                out.push_str(digits);
+            }
+            (false, false) => {
+                out.extend(std::iter::repeat_n(' ', pad));
+                out.push_str(&text);
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Printed(Vec<String>);
+
+    impl Host for Printed {
+        fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+            match name {
+                "double" => Ok(Value::Number(number_arg(name, args, 0)? * 2.0)),
+                _ => Err(format!("no {}", name)),
+            }
+        }
+        fn print(&mut self, text: &str) {
+            self.0.push(text.to_string());
+        }
+    }
+
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let mut host = Printed::default();
+        Interpreter::new("test", &["double"]).run(source, &mut host)?;
+        Ok(host.0)
+    }
+
+    #[test]
+    fn runs_scripts() {
+        let source = "
+            -- the usual suspects
+            local total = 0
+            for i = 1, 10 do
+                if i % 2 == 0 then total = total + i elseif i == 5 then total = total + 100 end
+            end
+            print(total, 7 / 2, 7 // 2, 2 ^ 10, -2 ^ 2, 1 .. 2)
+            local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+            print(fib(15), double(21), type(fib), type(nil))
+            local s = 'a' .. \"b\\tc\"
+            print(s, #s, 0xff & ~0x0f, 1 << 4 | 1, 0x80 >> 7, 5 ~ 3)
+            local n = 0
+            while true do n = n + 1 if n >= 3 then break end end
+            repeat local m = n n = n - 1 until m <= 1
+            print(n, nil == false, 'a' < 'b', not nil, 1 and 2, nil or 'x')
+            print(format('%04X %d %-3s| %.2f %5s %x', 0xbeef, -3, 'ab', 3.14159, 'r', 255))
+            print(tonumber('0x10'), tonumber(' 2.5 '), tonumber('z'), tostring(1e100), floor(-1.5), max(3, 9, 4))
+            if nil then print('never') else local unset print(unset) end
+        ";
+        assert_eq!(
+            run(source).unwrap(),
+            [
+                "130\t3.5\t3\t1024\t-4\t12",
+                "610\t42\tfunction\tnil",
+                "ab\tc\t4\t240\t17\t1\t6",
+                "0\tfalse\ttrue\ttrue\t2\tx",
+                "BEEF -3 ab | 3.14     r ff",
+                "16\t2.5\tnil\t1e100\t-2\t9",
+                "nil",
+            ]
+        );
+    }
+
+    #[test]
+    fn globals_outlive_the_run() {
+        let mut host = Printed::default();
+        let mut interp = Interpreter::new("test", &[]);
+        interp.run("local count = 10\nfunction tick(by) count = count + by return count end", &mut host).unwrap();
+        assert_eq!(interp.call("tick", &[Value::Number(2.0)], &mut host), Ok(Some(Value::Number(12.0))));
+        assert_eq!(interp.call("tick", &[Value::Number(3.0)], &mut host), Ok(Some(Value::Number(15.0))));
+        assert_eq!(interp.call("missing", &[], &mut host), Ok(None));
+        assert_eq!(interp.global("count"), Value::Number(15.0));
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        assert_eq!(run("x = 1\ny = x + nil").unwrap_err(), "test:2: attempt to perform arithmetic on a nil value");
+        assert_eq!(run("\n\nfoo(1)").unwrap_err(), "test:3: attempt to call a nil value ('foo')");
+        assert_eq!(run("if x then").unwrap_err(), "test:1: 'end' expected near '<eof>'");
+        assert_eq!(run("x = 'open").unwrap_err(), "test:1: unfinished string");
+        assert_eq!(run("break").unwrap_err(), "test:1: break outside a loop near 'break'");
+        assert_eq!(run("double('x')").unwrap_err(), "test:1: bad argument #1 to 'double' (number expected, got string)");
+        assert_eq!(run("x = 1.5 | 1").unwrap_err(), "test:1: number has no integer representation");
+        assert_eq!(run("function f() return f() end\nf()").unwrap_err(), "test:1: stack overflow");
+        assert_eq!(run("while true do end").unwrap_err(), "test:1: script ran too long, stuck in a loop?");
+    }
+
+    #[test]
+    fn operators_bind_like_lua() {
+        let source = "
+            print(1 + 2 * 3, (1 + 2) * 3, 2 ^ 3 ^ 2, -2 ^ 2, 2 * -3, 2 ^ -1)
+            print(1 .. 2 .. 3, 'a' .. 1 + 2, 10 - 2 - 3, 8 / 2 / 2, 7 // 2 * 2, 7 % 3 ^ 2)
+            print(1 + 2 < 4, not 1 == 2, not (1 == 2), 1 < 2 == true, 'b' >= 'a', 2 ~= 2.0)
+            print(1 | 2 & 3, 1 | 6 ~ 3, 1 << 2 + 1, 256 >> 4 >> 2, ~0 & 0xff, -1 >> 63)
+            print(nil or false and 1, false or nil, 1 and nil or 'd', #'abc' + 1, -#'ab', - - 3)
+            print(7 // -2, -7 % 3, 7.5 % 2, 1 / 0, -1 / 0, 0x10 + 1e2 + .5)
+        ";
+        assert_eq!(
+            run(source).unwrap(),
+            [
+                "7\t9\t512\t-4\t-6\t0.5",
+                "123\ta3\t5\t2\t6\t7",
+                "true\tfalse\ttrue\ttrue\ttrue\tfalse",
+                "3\t5\t8\t4\t255\t1",
+                "false\tnil\td\t4\t-2\t3",
+                "-4\t2\t1.5\tinf\t-inf\t116.5",
+            ]
+        );
+    }
+
+    #[test]
+    fn scopes_locals_like_lua() {
+        let source = "
+            x = 'global'
+            local top = 'top'
+            do local x = 'block' print(x) end
+            print(x)
+            if true then local x = 'if' x = 'assigned' print(x) end
+            print(x)
+            -- top level locals are globals, functions see them
+            local function show() return x .. ' ' .. top end
+            print(show())
+            -- but never the locals of their caller or where they were defined
+            function outer() local x = 'outer' return inner() end
+            function inner() return x end
+            function nested() local v = 1 local function helper() return v end return helper() end
+            print(outer(), nested())
+            for i = 1, 2 do local i = i * 10 print(i) end
+            print(i)
+            function bump(x) x = x + 1 return x end
+            print(bump(1), x)
+            -- until sees the body's locals
+            n = 0
+            repeat local done = n >= 2 n = n + 1 until done
+            print(n, done)
+        ";
+        assert_eq!(
+            run(source).unwrap(),
+            ["block", "global", "assigned", "global", "global top", "global\tnil", "10", "20", "nil", "2\tglobal", "3\tnil"]
+        );
+    }
+
+    #[test]
+    fn runs_loops() {
+        let source = "
+            local s = ''
+            for i = 3, 1, -1 do s = s .. i end
+            for i = 0, 1, 0.5 do s = s .. ' ' .. i end
+            for i = 1, 0 do s = s .. ' never' end
+            print(s)
+            -- the limit is taken once, break leaves the innermost loop
+            local limit = 3
+            local count = 0
+            for i = 1, limit do limit = 1 count = count + 1 end
+            local found = nil
+            for i = 1, 3 do
+                for j = 1, 3 do
+                    if i * j == 6 then found = i .. 'x' .. j break end
+                end
+                if found then break end
+            end
+            print(count, found)
+            local k = 0
+            while k < 5 do k = k + 1 if k == 2 then k = 10 end end
+            local r = 0
+            repeat r = r + 1 if r == 5 then break end until false
+            while false do r = 'never' end
+            print(k, r)
+            -- return leaves every loop around it
+            function first_even(a, b) for i = a, b do while true do if i % 2 == 0 then return i end break end end return 'none' end
+            print(first_even(3, 9), first_even(5, 5))
+        ";
+        assert_eq!(run(source).unwrap(), ["321 0 0.5 1", "3\t2x3", "10\t5", "4\tnone"]);
+        assert_eq!(run("for i = 1, 'x' do end").unwrap_err(), "test:1: 'for' limit must be a number, got string");
+        assert_eq!(run("for i = 1, 2, 0 do end").unwrap_err(), "test:1: 'for' step is zero");
+        assert_eq!(run("for i = 1 do end").unwrap_err(), "test:1: ',' expected near 'do'");
+        let source = "while true do\nfunction f() break end\nend";
+        assert_eq!(run(source).unwrap_err(), "test:2: break outside a loop near 'break'");
+    }
+
+    #[test]
+    fn calls_functions() {
+        let source = "
+            function pair(a, b) return tostring(a) .. ',' .. tostring(b) end
+            print(pair(1), pair(1, 2, 3), pair())
+            function nothing() end
+            function bare() return end
+            print(nothing(), bare(), type(nothing), pair == pair, pair == nothing)
+            local twice = function(f, v) return f(f(v)) end
+            print(twice(double, 5), twice(function(n) return n .. '!' end, 'hi'), (double)(2))
+            log = ''
+            function note(v) log = log .. v return v end
+            print(note(1) + note(2) * note(3), log)
+            function fact(n) if n <= 1 then return 1 end return n * fact(n - 1) end
+            print(fact(10), tostring(print), tostring(fact))
+        ";
+        assert_eq!(
+            run(source).unwrap(),
+            [
+                "1,nil\t1,2\tnil,nil",
+                "nil\tnil\tfunction\ttrue\tfalse",
+                "20\thi!!\t4",
+                "7\t123",
+                "3628800\tbuiltin: print\tfunction: fact",
+            ]
+        );
+        assert_eq!(run("f = 5\nf()").unwrap_err(), "test:2: attempt to call a number value");
+        assert_eq!(
+            run("function f(v)\n  return v + nil\nend\nf(1)").unwrap_err(),
+            "test:2: attempt to perform arithmetic on a nil value"
+        );
+        // no closures, a local function only sees itself at the top level
+        let source = "function count(n)\n\
+             local function down(k) if k == 0 then return 0 end return down(k - 1) end\n\
+             return down(n)\nend\ncount(2)";
+        assert_eq!(run(source).unwrap_err(), "test:2: attempt to call a nil value ('down')");
+        assert_eq!(run("function f(...) end").unwrap_err(), "test:1: <name> expected near '...'");
+    }
+
+    #[test]
+    fn rejects_what_the_dialect_lacks() {
+        for (source, error) in [
+            ("local t = {}", "test:1: unexpected symbol near '{'"),
+            ("x = t.y", "test:1: unexpected symbol near '.'"),
+            ("x = t[1]", "test:1: unexpected symbol near '['"),
+            ("a, b = 1, 2", "test:1: syntax error near ','"),
+            ("goto done", "test:1: unexpected symbol near 'goto'"),
+            ("x = 'a' + 1", "test:1: attempt to perform arithmetic on a string value"),
+            ("x = = 1", "test:1: unexpected symbol near '='"),
+            ("x = 1 +", "test:1: unexpected symbol near '<eof>'"),
+            ("f(1, )", "test:1: unexpected symbol near ')'"),
+            ("local 1 = 2", "test:1: <name> expected near '1'"),
+            ("x", "test:1: syntax error near '<eof>'"),
+            ("return 1\nx = 2", "test:2: 'end' expected near 'x'"),
+            ("x = 0x", "test:1: malformed number near '0x'"),
+            ("print('a\\q')", "test:1: invalid escape sequence '\\q'"),
+            ("--[[ open", "test:1: unfinished long comment"),
+            ("x = [[long]]", "test:1: unexpected symbol near '['"),
+            ("x = 1; y = 2", "test:1: unexpected symbol near ';'"),
+            ("print(format('%i', 1))", "test:1: invalid conversion '%i' to 'format'"),
+            ("x = #5", "test:1: attempt to get length of a number value"),
+            ("x = 1 < 'a'", "test:1: attempt to compare number with string"),
+            ("x = 'a' .. nil", "test:1: attempt to concatenate a nil value"),
+            ("x = ~'a'", "test:1: attempt to perform bitwise operation on a string value"),
+        ] {
+            assert_eq!(run(source).unwrap_err(), error, "{}", source);
+        }
+    }
+}
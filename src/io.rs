@@ -0,0 +1,95 @@
+// [FF00-FF7F] registers without a device of their own, indexed from FF00
+//
+// reads set the bits a register doesn't have, and its write-only bits, to 1;
+// unmapped addresses read FF. writes only change the bits software may set.
+// the PPU and interrupt logic reach the stored bytes directly by index
+
+use std::ops::{Index, IndexMut};
+
+pub struct IoRegisters {
+    regs: [u8; 128],
+}
+
+impl Default for IoRegisters {
+    fn default() -> Self {
+        IoRegisters { regs: [0; 128] }
+    }
+}
+
+// (bits read as 1, bits writes change)
+fn masks(index: usize, cgb: bool) -> (u8, u8) {
+    match index {
+        // IF
+        0x0f => (0xe0, 0x1f),
+        // NR10
+        0x10 => (0x80, 0x7f),
+        // NR11 / NR21, length is write-only
+        0x11 | 0x16 => (0x3f, 0xff),
+        // NR13 / NR23 / NR33 period low, NR31 length
+        0x13 | 0x18 | 0x1b | 0x1d => (0xff, 0xff),
+        // NRx4, trigger and period high are write-only
+        0x14 | 0x19 | 0x1e => (0xbf, 0xc7),
+        // NR30
+        0x1a => (0x7f, 0x80),
+        // NR32
+        0x1c => (0x9f, 0x60),
+        // NR41
+        0x20 => (0xff, 0x3f),
+        // NR44
+        0x23 => (0xbf, 0xc0),
+        // NR52, channel status bits are read-only
+        0x26 => (0x70, 0x80),
+        // envelopes, NR43, NR50, NR51
+        0x12 | 0x17 | 0x21 | 0x22 | 0x24 | 0x25 => (0x00, 0xff),
+        // wave RAM
+        0x30..=0x3f => (0x00, 0xff),
+        // STAT, mode and coincidence are set by the PPU
+        0x41 => (0x80, 0x78),
+        // LY
+        0x44 => (0x00, 0x00),
+        // LCDC SCY SCX LYC DMA BGP OBP0 OBP1 WY WX
+        0x40 | 0x42 | 0x43 | 0x45..=0x4b => (0x00, 0xff),
+        // KEY1 VBK RP, BCPS BCPD OCPS OCPD, SVBK
+        0x4d if cgb => (0x7e, 0x01),
+        0x4f if cgb => (0xfe, 0x01),
+        0x56 if cgb => (0x3c, 0xc1),
+        0x68 | 0x6a if cgb => (0x40, 0xbf),
+        0x69 | 0x6b if cgb => (0x00, 0xff),
+        0x70 if cgb => (0xf8, 0x07),
+        _ => (0xff, 0x00),
+    }
+}
+
+impl IoRegisters {
+    pub fn read(&self, addr: u16, cgb: bool) -> u8 {
+        let index = (addr - 0xff00) as usize;
+        self.regs[index] | masks(index, cgb).0
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8, cgb: bool) {
+        let index = (addr - 0xff00) as usize;
+        let writable = masks(index, cgb).1;
+        self.regs[index] = (self.regs[index] & !writable) | (val & writable);
+    }
+
+    pub fn bytes(&self) -> &[u8; 128] {
+        &self.regs
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8; 128] {
+        &mut self.regs
+    }
+}
+
+impl Index<usize> for IoRegisters {
+    type Output = u8;
+    fn index(&self, index: usize) -> &u8 {
+        &self.regs[index]
+    }
+}
+
+impl IndexMut<usize> for IoRegisters {
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        &mut self.regs[index]
+    }
+}
@@ -0,0 +1,149 @@
+// [FF00] P1 - bit 5 low selects buttons, bit 4 low the d-pad, lower nibble
+// reads 0 for pressed keys of the selected groups
+
+use std::collections::BTreeMap;
+
+use crate::savestate::{Component, StateError, StateReader, StateWriter};
+
+bitflags::bitflags! {
+    #[derive(Default)]
+    pub struct Buttons: u8 {
+        const RIGHT = 0x01;
+        const LEFT = 0x02;
+        const UP = 0x04;
+        const DOWN = 0x08;
+        const A = 0x10;
+        const B = 0x20;
+        const SELECT = 0x40;
+        const START = 0x80;
+    }
+}
+
+const NAMES: [(&str, Buttons); 8] = [
+    ("right", Buttons::RIGHT),
+    ("left", Buttons::LEFT),
+    ("up", Buttons::UP),
+    ("down", Buttons::DOWN),
+    ("a", Buttons::A),
+    ("b", Buttons::B),
+    ("select", Buttons::SELECT),
+    ("start", Buttons::START),
+];
+
+impl Buttons {
+    // inverse of parse, `a+start`
+    pub fn names(self) -> String {
+        let names: Vec<&str> = NAMES.iter().filter(|(_, b)| self.contains(*b)).map(|(n, _)| *n).collect();
+        names.join("+")
+    }
+
+    // names separated by spaces, commas or +, e.g. `a+right`, empty for none
+    pub fn parse(text: &str) -> Result<Buttons, String> {
+        let mut buttons = Buttons::empty();
+        for name in text.split([' ', ',', '+']).filter(|n| !n.is_empty()) {
+            let lower = name.to_ascii_lowercase();
+            match NAMES.iter().find(|(n, _)| *n == lower) {
+                Some((_, button)) => buttons |= *button,
+                None => return Err(format!("unknown button `{}`", name)),
+            }
+        }
+        Ok(buttons)
+    }
+}
+
+// host key names to buttons, e.g. from the config file, one key can press several
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyBindings {
+    keys: BTreeMap<String, Buttons>,
+}
+
+impl KeyBindings {
+    pub fn bind(&mut self, key: &str, buttons: Buttons) {
+        self.keys.insert(key.to_ascii_lowercase(), buttons);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Buttons)> {
+        self.keys.iter().map(|(key, buttons)| (key.as_str(), *buttons))
+    }
+
+    // like Buttons::parse, bound keys are accepted besides button names
+    pub fn parse(&self, text: &str) -> Result<Buttons, String> {
+        let mut buttons = Buttons::empty();
+        for name in text.split([' ', ',', '+']).filter(|n| !n.is_empty()) {
+            buttons |= match self.keys.get(&name.to_ascii_lowercase()) {
+                Some(bound) => *bound,
+                None => Buttons::parse(name)?,
+            };
+        }
+        Ok(buttons)
+    }
+}
+
+pub struct Joypad {
+    // P1 bits 4-5
+    select: u8,
+    pressed: Buttons,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad {
+            select: 0x30,
+            pressed: Buttons::empty(),
+        }
+    }
+}
+
+impl Joypad {
+    // deselects both button groups, whatever is held stays held
+    pub fn reset(&mut self) {
+        self.select = Joypad::default().select;
+    }
+
+    pub fn rb(&self) -> u8 {
+        0xc0 | self.select | self.lines()
+    }
+
+    // returns whether a line went low, which requests the joypad interrupt
+    pub fn wb(&mut self, val: u8) -> bool {
+        let before = self.lines();
+        self.select = val & 0x30;
+        before & !self.lines() != 0
+    }
+
+    pub fn set(&mut self, buttons: Buttons) -> bool {
+        let before = self.lines();
+        self.pressed = buttons;
+        before & !self.lines() != 0
+    }
+
+    pub fn pressed(&self) -> Buttons {
+        self.pressed
+    }
+
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0f;
+        if self.select & 0x10 == 0 {
+            lines &= !(self.pressed.bits() & 0x0f);
+        }
+        if self.select & 0x20 == 0 {
+            lines &= !(self.pressed.bits() >> 4);
+        }
+        lines
+    }
+}
+
+// held buttons are saved too so replays continue with the same input
+impl Component for Joypad {
+    const TAG: [u8; 4] = *b"JOYP";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.select);
+        w.u8(self.pressed.bits());
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.select = r.u8()? & 0x30;
+        self.pressed = Buttons::from_bits_truncate(r.u8()?);
+        Ok(())
+    }
+}
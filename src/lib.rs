@@ -0,0 +1,1664 @@
+#![allow(clippy::upper_case_acronyms)]
+
+// Game Boy emulator core. Headless use, e.g. for TAS tools or tests:
+//
+//   let rom = std::fs::read("game.gb")?;
+//   let mut gb = gb_rust::GB::new(&rom)?;
+//   gb.set_buttons(Buttons::START);
+//   gb.run_frames(60);
+//   let score = gb.read_memory(0xc0a0, 2);
+//   let shades = gb.screenshot();
+//   std::fs::write("shot.png", gb.screenshot_png(&PaletteSettings::default()))?;
+//
+// Emulation is deterministic, the same ROM and inputs always give the same state.
+
+use std::mem;
+use std::time::Duration;
+
+mod asm;
+pub mod audiosync;
+pub mod bootpalette;
+pub mod cartridge;
+pub mod cheats;
+pub mod cpu;
+pub mod debugger;
+pub mod diagnostics;
+pub mod disasm;
+pub mod error;
+pub mod eventlog;
+mod fifo;
+pub mod freeze;
+mod gif;
+mod hdma;
+mod hexedit;
+mod idle;
+pub mod inputdisplay;
+mod interp;
+mod io;
+pub mod joypad;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+mod lz4;
+mod mbc;
+mod memsearch;
+pub mod movie;
+pub mod observer;
+mod opcodes;
+pub mod osd;
+pub mod palette;
+pub mod pipeline;
+mod png;
+pub mod postprocess;
+pub mod ppu;
+pub mod printer;
+pub mod recorder;
+pub mod regress;
+pub mod report;
+mod rtc;
+pub mod savestate;
+mod scheduler;
+mod scanlines;
+pub mod screenshot;
+pub mod script;
+pub mod selfcheck;
+pub mod serial;
+pub mod session;
+pub mod sgb;
+pub mod sram;
+pub mod testrom;
+pub mod throttle;
+mod timer;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod triplebuffer;
+pub mod unimplemented;
+pub mod videofilter;
+pub mod vramview;
+
+use cartridge::Header;
+use cheats::Cheats;
+use debugger::Watchpoints;
+pub use error::Error;
+use eventlog::{Event, EventLog};
+use freeze::{Frozen, Hold};
+use hdma::HDMA;
+use diagnostics::{Diagnostic, Diagnostics, Problem};
+use idle::Idle;
+use io::IoRegisters;
+pub use joypad::Buttons;
+use joypad::Joypad;
+use mbc::{Mbc, BANK_SIZE, MAX_RAM};
+use movie::{Deck, Movie};
+pub use observer::Observer;
+use palette::PaletteSettings;
+use ppu::{PPUEvents, PPU};
+pub use rtc::RtcMode;
+use scheduler::{Scheduler, Source};
+use serial::Serial;
+use sgb::Sgb;
+use timer::Timer;
+use unimplemented::Unimplemented;
+
+// t-cycles per second at nominal speed
+pub const CLOCK_HZ: u64 = 4_194_304;
+
+// (FF00 + index, value) the DMG boot ROM leaves behind: a VBlank requested, the
+// sound registers it set up for the chime, the LCD on, DMA's last value and the
+// logo's palette.
+// the rest is 0
+const POST_BOOT_IO: [(usize, u8); 19] = [
+    (0x0f, 0xe1),
+    (0x10, 0x80),
+    (0x11, 0xbf),
+    (0x12, 0xf3),
+    (0x14, 0xbf),
+    (0x16, 0x3f),
+    (0x19, 0xbf),
+    (0x1a, 0x7f),
+    (0x1b, 0xff),
+    (0x1c, 0x9f),
+    (0x1e, 0xbf),
+    (0x20, 0xff),
+    (0x23, 0xbf),
+    (0x24, 0x77),
+    (0x25, 0xf3),
+    (0x26, 0xf1),
+    (0x40, 0x91),
+    (0x46, 0xff),
+    (0x47, 0xfc),
+];
+
+// console the machine is timed as. the SGB derives its clock from the SNES
+// master clock (21.477MHz / 5), so games, music and frame rate run ~2.4% fast;
+// the SGB2 has its own crystal again
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Hardware {
+    #[default]
+    Dmg,
+    Sgb,
+    Sgb2,
+}
+
+impl Hardware {
+    pub fn parse(name: &str) -> Result<Hardware, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(Hardware::Dmg),
+            "sgb" => Ok(Hardware::Sgb),
+            "sgb2" => Ok(Hardware::Sgb2),
+            _ => Err(format!("unknown hardware `{}`, expected dmg, sgb or sgb2", name)),
+        }
+    }
+
+    // t-cycles per second
+    pub fn clock_hz(self) -> u64 {
+        match self {
+            Hardware::Dmg | Hardware::Sgb2 => CLOCK_HZ,
+            Hardware::Sgb => 4_295_454,
+        }
+    }
+
+    // what the console shows, with the border on an SGB
+    pub fn display_size(self) -> (usize, usize) {
+        match self {
+            Hardware::Dmg => (ppu::WIDTH, ppu::HEIGHT),
+            Hardware::Sgb | Hardware::Sgb2 => (sgb::WIDTH, sgb::HEIGHT),
+        }
+    }
+}
+
+// parts of the machine that can be reset on their own, to compare two runs
+// that differ in one of them or to see whether a bug lives there. sound isn't
+// emulated, so there's no APU among them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsystem {
+    Cpu,
+    // keeps the renderer and scanline capture
+    Ppu,
+    Timer,
+    // keeps the link cable
+    Serial,
+    // keeps the buttons held
+    Joypad,
+    Hdma,
+    // bank registers, not the cartridge RAM
+    Mapper,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 7] =
+        [Subsystem::Cpu, Subsystem::Ppu, Subsystem::Timer, Subsystem::Serial, Subsystem::Joypad, Subsystem::Hdma, Subsystem::Mapper];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Cpu => "cpu",
+            Subsystem::Ppu => "ppu",
+            Subsystem::Timer => "timer",
+            Subsystem::Serial => "serial",
+            Subsystem::Joypad => "joypad",
+            Subsystem::Hdma => "hdma",
+            Subsystem::Mapper => "mapper",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Subsystem, String> {
+        let name = name.to_ascii_lowercase();
+        Subsystem::ALL.into_iter().find(|part| part.name() == name).ok_or_else(|| {
+            let names: Vec<_> = Subsystem::ALL.iter().map(|part| part.name()).collect();
+            format!("unknown subsystem `{}`, expected {}", name, names.join(", "))
+        })
+    }
+}
+
+extern crate bitflags;
+
+bitflags::bitflags! {
+    struct Flags: u8 {
+        const NONE = 0x00;
+        const CARRY = 0x10;
+        const HALF_CARRY = 0x20;
+        const SUBSTRACTION = 0x40;
+        const ZERO = 0x80;
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags::NONE
+    }
+}
+
+bitflags::bitflags! {
+    // IF [FF0F] / IE [FFFF] bits
+    pub struct Interrupts: u8 {
+        const VBLANK = 0x01;
+        const LCD_STAT = 0x02;
+        const TIMER = 0x04;
+        const SERIAL = 0x08;
+        const JOYPAD = 0x10;
+    }
+}
+
+struct MMU {
+    booted: bool,
+    // CGB features enabled by cartridge header
+    cgb: bool,
+    // [0000-00FF] bios during boot
+    bios: [u8; 256],
+
+    // the whole cartridge ROM: [0000-3FFF] is its first bank after boot, with
+    // the header at [0100-014F], banks are mapped from it to [4000-7FFF]
+    rom: Box<[u8]>,
+
+    // where the banks at [0000-3FFF] and [4000-7FFF] start in rom
+    rom0_start: usize,
+    rom_bank_start: usize,
+
+    // bank switching and cartridge RAM access
+    mbc: Mbc,
+
+    // [8000-9FFF] graphics
+    graphics: [u8; 8192],
+
+    // [A000-BFFF] external cartridge ram, banked by some mappers. 128KiB of it
+    // is kept off the stack
+    external_ram: Box<[u8]>,
+
+    // [C000-DFFF] internal working ram, [E000-FDFF] echoes its first 7.5KiB
+    ram: [u8; 8192],
+
+    // [FE00-FE9F] sprites
+    sprites: [u8; 160],
+
+    // [FF00-FF7F] IO
+    io: IoRegisters,
+
+    // [FF80-FFFF]
+    work_ram: [u8; 128],
+
+    // [FF00] buttons
+    joypad: Joypad,
+
+    // [FF01-FF02] link cable
+    serial: Serial,
+
+    // Super Game Boy commands sent through [FF00]
+    sgb: Sgb,
+
+    // [FF04-FF07] divider and timer
+    timer: Timer,
+
+    // [FF51-FF55] CGB VRAM DMA
+    hdma: HDMA,
+
+    // features touched by the game which are not emulated yet
+    unimplemented: Unimplemented,
+
+    // a bit per WRAM byte written since power on, kept for --diagnostics
+    wram_written: Option<Box<[u64; 128]>>,
+
+    // debugger memory watchpoints
+    watchpoints: Watchpoints,
+
+    // addresses held at a value, see freeze.rs
+    frozen: Frozen,
+
+    // Game Genie patches ROM reads, GameShark pokes at VBlank
+    cheats: Cheats,
+
+    // interrupts requested since the event log last looked
+    requested_interrupts: Interrupts,
+}
+
+impl Default for MMU {
+    fn default() -> Self {
+        MMU {
+            booted: false,
+            cgb: false,
+            bios: [0; 256],
+            rom: vec![0; 2 * BANK_SIZE].into_boxed_slice(),
+            rom0_start: 0,
+            rom_bank_start: BANK_SIZE,
+            mbc: Default::default(),
+            graphics: [0; 8192],
+            external_ram: vec![0; MAX_RAM].into_boxed_slice(),
+            ram: [0; 8192],
+            sprites: [0; 160],
+            io: Default::default(),
+            work_ram: [0; 128],
+            joypad: Default::default(),
+            serial: Default::default(),
+            sgb: Default::default(),
+            timer: Default::default(),
+            hdma: Default::default(),
+            unimplemented: Default::default(),
+            wram_written: None,
+            watchpoints: Default::default(),
+            frozen: Default::default(),
+            cheats: Default::default(),
+            requested_interrupts: Interrupts::empty(),
+        }
+    }
+}
+
+// what each 256 byte page of the address space holds, accesses look their
+// region up here instead of matching address ranges every time
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Region {
+    Rom0,
+    RomX,
+    Vram,
+    CartRam,
+    Wram,
+    Echo,
+    // and the unusable [FEA0-FEFF]
+    Oam,
+    // IO and high RAM
+    High,
+}
+
+const PAGES: [Region; 256] = {
+    let mut pages = [Region::Rom0; 256];
+    let mut page = 0;
+    while page < 256 {
+        pages[page] = match page {
+            0x00..=0x3f => Region::Rom0,
+            0x40..=0x7f => Region::RomX,
+            0x80..=0x9f => Region::Vram,
+            0xa0..=0xbf => Region::CartRam,
+            0xc0..=0xdf => Region::Wram,
+            0xe0..=0xfd => Region::Echo,
+            0xfe => Region::Oam,
+            _ => Region::High,
+        };
+        page += 1;
+    }
+    pages
+};
+
+impl MMU {
+    fn new() -> Self {
+        Default::default()
+    }
+    fn rb(&self, addr: u16) -> u8 {
+        self.watchpoints.read(addr);
+        let region = PAGES[addr as usize >> 8];
+        if region == Region::High && addr < 0xff80 {
+            self.unimplemented.io_read(addr);
+        }
+        if self.ppu_locked(region) {
+            return 0xff;
+        }
+        self.peek(addr)
+    }
+    // CPU can't reach OAM during modes 2/3, nor VRAM during mode 3
+    fn ppu_locked(&self, region: Region) -> bool {
+        let mode = self.io[0x41] & 0x03;
+        match region {
+            Region::Vram => mode == 3,
+            Region::Oam => mode >= 2,
+            _ => false,
+        }
+    }
+    // read without debugger / tracking side effects
+    fn peek(&self, addr: u16) -> u8 {
+        match PAGES[addr as usize >> 8] {
+            // bank 0 & bios
+            Region::Rom0 if addr < 0x0100 && !self.booted => self.bios[addr as usize],
+            Region::Rom0 => self.cheats.patch(addr, self.rom[self.rom0_start + addr as usize]),
+
+            Region::RomX => self.cheats.patch(addr, self.rom[self.rom_bank_start + (addr - 0x4000) as usize]),
+
+            Region::Vram => self.graphics[(addr - 0x8000) as usize],
+
+            Region::CartRam => self.mbc.read_ram(&self.external_ram, addr),
+
+            Region::Wram => self.ram[(addr - 0xc000) as usize],
+
+            Region::Echo => self.ram[(addr - 0xe000) as usize],
+
+            Region::Oam => match addr {
+                0xfe00..=0xfe9f => self.sprites[(addr - 0xfe00) as usize],
+                // unusable, reads open bus
+                _ => 0xff,
+            },
+
+            Region::High => match addr {
+                0xff00 => match self.sgb.joypad_id() {
+                    Some(id) => 0xf0 | id,
+                    None => self.joypad.rb(),
+                },
+
+                0xff01..=0xff02 => self.serial.rb(addr, self.cgb),
+
+                0xff04..=0xff07 => self.timer.rb(addr),
+
+                0xff51..=0xff55 if self.cgb => self.hdma.rb(addr),
+
+                0xff03..=0xff7f => self.io.read(addr, self.cgb),
+
+                _ => self.work_ram[(addr - 0xff80) as usize],
+            },
+        }
+    }
+    fn wb(&mut self, addr: u16, val: u8) {
+        let val = self.frozen.on_write(addr, val);
+        self.watchpoints.write(addr, val);
+        let region = PAGES[addr as usize >> 8];
+        if self.ppu_locked(region) {
+            return;
+        }
+        match region {
+            // ROM is read-only, the mapper may take the write
+            Region::Rom0 | Region::RomX => {
+                if self.mbc.write_rom(addr, val) {
+                    self.map_rom_bank();
+                }
+            }
+
+            Region::Vram => self.graphics[(addr - 0x8000) as usize] = val,
+
+            Region::CartRam => self.mbc.write_ram(&mut self.external_ram, addr, val),
+
+            Region::Wram | Region::Echo => {
+                let i = (addr & 0x1fff) as usize;
+                self.ram[i] = val;
+                if let Some(written) = self.wram_written.as_mut() {
+                    written[i / 64] |= 1 << (i % 64);
+                }
+            }
+
+            // [FEA0-FEFF] unusable, writes go nowhere
+            Region::Oam if addr >= 0xfea0 => {}
+            Region::Oam => self.sprites[(addr - 0xfe00) as usize] = val,
+
+            Region::High => self.write_high(addr, val),
+        }
+    }
+    // [FF00-FFFF] IO and high RAM
+    fn write_high(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff00 => {
+                self.sgb.write_p1(val);
+                if self.joypad.wb(val) {
+                    self.request_interrupt(Interrupts::JOYPAD);
+                }
+            }
+
+            0xff01..=0xff02 => self.serial.wb(addr, val, self.cgb),
+
+            0xff04..=0xff07 => self.timer.wb(addr, val),
+
+            // boot ROM unmaps itself for good
+            0xff50 => self.booted |= val != 0,
+
+            0xff51..=0xff55 if self.cgb => {
+                let blocks = self.hdma.wb(addr, val);
+                for _ in 0..blocks {
+                    self.hdma_copy_block();
+                }
+            }
+
+            0xff03..=0xff7f => {
+                self.unimplemented.io_write(addr);
+                self.io.write(addr, val, self.cgb)
+            }
+
+            _ => self.work_ram[(addr - 0xff80) as usize] = val,
+        }
+    }
+    // debugger / editor write: ignores PPU locks and read-only IO bits, devices
+    // with their own state still get a normal write. ROM can't be changed
+    fn poke(&mut self, addr: u16, val: u8) -> Result<(), String> {
+        match addr {
+            0x0000..=0x7fff => return Err(format!("{:04X} is ROM, which is read-only", addr)),
+            0x8000..=0x9fff => self.graphics[(addr - 0x8000) as usize] = val,
+            0xa000..=0xbfff => self.mbc.poke_ram(&mut self.external_ram, addr, val),
+            0xfe00..=0xfe9f => self.sprites[(addr - 0xfe00) as usize] = val,
+            0xfea0..=0xfeff => return Err(format!("{:04X} is unusable memory", addr)),
+            0xff03 | 0xff08..=0xff50 => self.io[(addr - 0xff00) as usize] = val,
+            _ => {
+                self.wb(addr, val);
+                self.watchpoints.take_hit();
+            }
+        }
+        Ok(())
+    }
+    // banks the mapper selected, past the end of the ROM wraps around. the ROM
+    // holds at least a bank
+    fn map_rom_bank(&mut self) {
+        let banks = self.rom.len() / BANK_SIZE;
+        self.rom0_start = self.mbc.rom0_bank() % banks * BANK_SIZE;
+        self.rom_bank_start = self.mbc.rom_bank() % banks * BANK_SIZE;
+    }
+    // requested in IF and enabled in IE
+    fn pending_interrupts(&self) -> u8 {
+        self.io[0x0f] & self.work_ram[0x7f] & 0x1f
+    }
+    fn request_interrupt(&mut self, interrupt: Interrupts) {
+        self.io[0x0f] |= interrupt.bits();
+        self.requested_interrupts |= interrupt;
+    }
+    fn hdma_copy_block(&mut self) {
+        let Some((src, dst)) = self.hdma.next_block() else {
+            return;
+        };
+        for i in 0..16 {
+            let val = self.rb(src.wrapping_add(i));
+            self.graphics[((dst + i) - 0x8000) as usize] = val;
+        }
+    }
+    // called by PPU when entering mode 0
+    fn hblank(&mut self) {
+        if self.hdma.hblank_pending() {
+            self.hdma_copy_block();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Z80 {
+    // clock for last istr
+    m: u8,
+    t: u8,
+    // registers
+    b: u8,
+    a: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    h: u8,
+    l: u8,
+    // special registers
+    f: Flags, // flags
+    pc: u16,  // program counter
+    sp: u16,  // stack pointer
+    // interrupt master enable, EI sets it one instruction late
+    ime: bool,
+    ei_pending: bool,
+    // waiting for an interrupt after HALT / STOP
+    halted: bool,
+    // next opcode fetch doesn't advance PC
+    halt_bug: bool,
+    // illegal opcode run and its address, nothing runs after it
+    lockup: Option<(u8, u16)>,
+    // t-cycles spent on the current instruction so far
+    instr_t: u32,
+}
+
+impl Z80 {
+    fn new() -> Self {
+        Default::default()
+    }
+
+    // register pairs, first register is the high byte
+    #[inline]
+    fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f.bits()])
+    }
+    // lower nibble of F doesn't exist and always reads 0
+    #[inline]
+    fn set_af(&mut self, val: u16) {
+        let [a, f] = val.to_be_bytes();
+        self.a = a;
+        self.f = Flags::from_bits_truncate(f);
+    }
+    #[inline]
+    fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+    #[inline]
+    fn set_bc(&mut self, val: u16) {
+        [self.b, self.c] = val.to_be_bytes();
+    }
+    #[inline]
+    fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+    #[inline]
+    fn set_de(&mut self, val: u16) {
+        [self.d, self.e] = val.to_be_bytes();
+    }
+    #[inline]
+    fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+    #[inline]
+    fn set_hl(&mut self, val: u16) {
+        [self.h, self.l] = val.to_be_bytes();
+    }
+
+    #[inline]
+    fn set_flag(&mut self, flag: Flags, on: bool) {
+        self.f.set(flag, on);
+    }
+    #[inline]
+    fn set_zero_from(&mut self, result: u8) {
+        self.f.set(Flags::ZERO, result == 0);
+    }
+    // 8 bit ADD, sets Z, H and C, clears N
+    #[inline]
+    fn add_with_flags(&mut self, a: u8, b: u8) -> u8 {
+        let (result, carry) = a.overflowing_add(b);
+        self.set_zero_from(result);
+        self.set_flag(Flags::SUBSTRACTION, false);
+        self.set_flag(Flags::HALF_CARRY, (a & 0x0f) + (b & 0x0f) > 0x0f);
+        self.set_flag(Flags::CARRY, carry);
+        result
+    }
+}
+
+#[allow(non_snake_case)]
+pub struct GB {
+    z80: Z80,
+    mmu: MMU,
+    ppu: PPU,
+    clockT: u64,
+    // VBlanks entered since power on
+    frames: u64,
+    // where the current frame began, frames end at VBlank or after FRAME_DOTS while the LCD is off
+    frame_start: u64,
+    // frames ended that way, what run_frames and movies count
+    frame_boundaries: u64,
+    // held when the last frame ended
+    frame_buttons: Buttons,
+    movie: Option<Deck>,
+    // how emulated time maps to real time, and whether there is an SGB
+    hardware: Hardware,
+    // how an MBC3 clock counts, kept for the next cartridge
+    rtc_mode: RtcMode,
+    // load_bootrom was called, a cartridge change runs it again
+    has_bootrom: bool,
+    // CPU clock multiplier, peripherals keep running at nominal speed
+    overclock: u32,
+    // CPU t-cycles not yet passed on to peripherals
+    overclock_remainder: u32,
+    // PPU modes entered during the current instruction
+    events: PPUEvents,
+    // when the PPU, timer and serial port need stepping next
+    scheduler: Scheduler,
+    // fast-forwards waits, None runs every cycle
+    idle: Option<Idle>,
+    // --diagnostics, None checks nothing
+    diagnostics: Option<Diagnostics>,
+    #[cfg(feature = "trace")]
+    tracer: Option<trace::Tracer>,
+    // JSON lines stream for external tools
+    event_log: Option<EventLog>,
+    // hooks of tools watching in-process
+    observers: Vec<Box<dyn Observer>>,
+}
+
+impl GB {
+    // errors on ROMs that can't be a cartridge, see error::Error
+    pub fn new(rom_data: &[u8]) -> Result<Self, Error> {
+        let mut instance = Self {
+            z80: Default::default(),
+            mmu: Default::default(),
+            ppu: Default::default(),
+            clockT: Default::default(),
+            frames: 0,
+            frame_start: 0,
+            frame_boundaries: 0,
+            frame_buttons: Buttons::empty(),
+            movie: None,
+            hardware: Hardware::Dmg,
+            rtc_mode: RtcMode::default(),
+            has_bootrom: false,
+            overclock: 1,
+            overclock_remainder: 0,
+            events: PPUEvents::NONE,
+            scheduler: Scheduler::default(),
+            idle: None,
+            diagnostics: None,
+            #[cfg(feature = "trace")]
+            tracer: None,
+            event_log: None,
+            observers: Vec::new(),
+        };
+        instance.load_rom(rom_data)?;
+        instance.skip_boot();
+        Ok(instance)
+    }
+
+    // registers and IO as the DMG boot ROM leaves them when it jumps to 0100,
+    // the state a game starts from without --bootrom
+    fn skip_boot(&mut self) {
+        self.mmu.booted = true;
+        self.z80 = Z80::new();
+        self.z80.set_af(0x01b0);
+        self.z80.set_bc(0x0013);
+        self.z80.set_de(0x00d8);
+        self.z80.set_hl(0x014d);
+        self.z80.sp = 0xfffe;
+        self.z80.pc = 0x0100;
+        self.mmu.timer = Timer::after_boot();
+        self.mmu.io = Default::default();
+        for (index, val) in POST_BOOT_IO {
+            self.mmu.io[index] = val;
+        }
+    }
+
+    // what can't be a cartridge
+    pub(crate) fn check_rom(rom_data: &[u8]) -> Result<Header, Error> {
+        if rom_data.len() < BANK_SIZE {
+            return Err(Error::RomTooSmall { len: rom_data.len() });
+        }
+        let header = Header::parse(rom_data);
+        if !Mbc::emulated(header.cartridge_type) {
+            return Err(Error::UnsupportedMbc(header.cartridge_type));
+        }
+        Ok(header)
+    }
+
+    fn load_rom(&mut self, rom_data: &[u8]) -> Result<(), Error> {
+        let header = Self::check_rom(rom_data)?;
+        self.mmu.rom = rom_data.into();
+        self.mmu.cgb = header.cgb;
+        self.mmu.mbc = Mbc::new(&header)?;
+        self.mmu.mbc.set_rtc_mode(self.rtc_mode);
+        self.mmu.map_rom_bank();
+        Ok(())
+    }
+
+    // DMG boot ROM, mapped over [0000-00FF] until the boot ROM writes FF50. it
+    // runs from power on, with the registers and IO cleared
+    pub fn load_bootrom(&mut self, bootrom: &[u8]) -> Result<(), Error> {
+        if bootrom.len() != self.mmu.bios.len() {
+            return Err(Error::InvalidBootRom { len: bootrom.len() });
+        }
+        self.mmu.bios.copy_from_slice(bootrom);
+        self.mmu.booted = false;
+        self.has_bootrom = true;
+        self.z80 = Z80::new();
+        self.mmu.timer = Default::default();
+        self.mmu.io = Default::default();
+        Ok(())
+    }
+
+    // another cartridge, from power on as if the console was switched off to
+    // swap it: nothing of the last game's RAM, mapper, cheats, frozen addresses
+    // or movie is left. the boot ROM, hardware, speed settings, renderer, link
+    // cable, logs, watchpoints and observers stay. the boot ROM runs again, or
+    // without one the game starts at 0100 as from GB::new. a ROM that can't be
+    // a cartridge leaves the running game alone
+    pub fn change_cartridge(&mut self, rom_data: &[u8]) -> Result<(), Error> {
+        Self::check_rom(rom_data)?;
+        let old = mem::take(&mut self.mmu);
+        self.mmu.bios = old.bios;
+        self.mmu.serial = old.serial;
+        self.mmu.serial.reset();
+        self.mmu.watchpoints = old.watchpoints;
+        self.mmu.wram_written = old.wram_written.map(|_| Box::new([0; 128]));
+        self.z80 = Z80::new();
+        self.ppu.reset();
+        self.clockT = 0;
+        (self.frames, self.frame_start, self.frame_boundaries) = (0, 0, 0);
+        self.frame_buttons = Buttons::empty();
+        self.movie = None;
+        self.overclock_remainder = 0;
+        self.events = PPUEvents::NONE;
+        self.scheduler = Scheduler::default();
+        self.idle = self.idle.as_ref().map(|_| Idle::default());
+        self.diagnostics = self.diagnostics.as_ref().map(|_| Diagnostics::default());
+        self.load_rom(rom_data)?;
+        if !self.has_bootrom {
+            self.skip_boot();
+        }
+        self.set_hardware(self.hardware);
+        Ok(())
+    }
+
+    // an SGB or SGB2 also takes the commands of games made for it
+    pub fn set_hardware(&mut self, hardware: Hardware) {
+        self.hardware = hardware;
+        self.mmu.sgb.set_enabled(hardware != Hardware::Dmg && self.header().sgb);
+    }
+
+    pub fn hardware(&self) -> Hardware {
+        self.hardware
+    }
+
+    pub fn set_overclock(&mut self, multiplier: u32) -> Result<(), Error> {
+        if multiplier == 0 {
+            return Err(Error::InvalidOverclock(multiplier));
+        }
+        self.overclock = multiplier;
+        self.overclock_remainder = 0;
+        Ok(())
+    }
+
+    // skip through HALT and polling loops, see idle.rs. not while overclocked
+    pub fn set_skip_idle(&mut self, skip: bool) {
+        self.idle = skip.then(Idle::default);
+    }
+
+    // t-cycles fast-forwarded since skipping was turned on
+    pub fn idle_skipped(&self) -> u64 {
+        self.idle.as_ref().map_or(0, |idle| idle.skipped)
+    }
+
+    // watch for jumps into VRAM, the stack in ROM, reads of uninitialized WRAM
+    // and hangs, see diagnostics.rs. what was written before doesn't count
+    pub fn set_diagnostics(&mut self, on: bool) {
+        self.diagnostics = on.then(Diagnostics::default);
+        self.mmu.wram_written = on.then(|| Box::new([0; 128]));
+    }
+
+    // found since the last call
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.as_mut().map(|d| std::mem::take(&mut d.found)).unwrap_or_default()
+    }
+
+    // the CPU ran an illegal opcode and locked up, the rest of the machine runs on
+    pub fn crash(&self) -> Option<Error> {
+        self.z80.lockup.map(|(op, addr)| Error::IllegalOpcode { op, addr })
+    }
+
+    // runs one instruction, returns PPU modes entered meanwhile
+    pub fn cycle(&mut self) -> PPUEvents {
+        for source in Source::ALL {
+            let until = self.until_event(source);
+            self.scheduler.schedule(source, self.clockT, until);
+        }
+        let pc = self.z80.pc;
+        match self.idle_halt() {
+            0 => {
+                cpu::step(self);
+                self.sync_all();
+                self.idle_loop(pc);
+            }
+            t => {
+                self.tick(t as u32);
+                self.sync_all();
+            }
+        }
+        if self.events.contains(PPUEvents::VBLANK) {
+            self.frames += 1;
+            self.mmu.sgb.frame(self.ppu.framebuffer());
+            for observer in &mut self.observers {
+                observer.on_frame(self.ppu.framebuffer());
+            }
+            self.apply_frame_holds();
+            self.apply_cheats();
+        }
+        let lcd_off = self.mmu.io[0x40] & 0x80 == 0;
+        if self.events.contains(PPUEvents::VBLANK) || (lcd_off && self.clockT - self.frame_start >= ppu::FRAME_DOTS) {
+            self.frame_start = self.clockT;
+            self.frame_boundaries += 1;
+            self.frame_buttons = self.mmu.joypad.pressed();
+            self.movie_frame();
+        }
+        let requested = std::mem::replace(&mut self.mmu.requested_interrupts, Interrupts::empty());
+        if let Some(log) = self.event_log.as_mut() {
+            log.interrupts(self.clockT, requested);
+            if self.events.contains(PPUEvents::VBLANK) {
+                log.log(self.clockT, Event::Frame);
+            }
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    // runs until n more frames ended (VBlank entered), or n frames worth of time while LCD is off
+    pub fn run_frames(&mut self, frames: u64) {
+        let end = self.frame_boundaries + frames;
+        while self.frame_boundaries < end {
+            self.cycle();
+        }
+    }
+
+    // frames ended so far, stays put while the LCD is off
+    pub fn frames_elapsed(&self) -> u64 {
+        self.frames
+    }
+
+    // t-cycles at nominal speed, overclocking doesn't change it
+    pub fn cycles_elapsed(&self) -> u64 {
+        self.clockT
+    }
+
+    // time on the emulated clock, the source for anything measured in game time
+    pub fn emulated_time(&self) -> Duration {
+        let hz = self.hardware.clock_hz() as u128;
+        Duration::from_nanos((self.clockT as u128 * 1_000_000_000 / hz) as u64)
+    }
+
+    // holds addr at val from now on, it is written right away
+    pub fn freeze(&mut self, addr: u16, val: u8, hold: Hold) -> Result<(), String> {
+        self.mmu.frozen.remove(addr);
+        self.mmu.poke(addr, val)?;
+        self.mmu.frozen.insert(addr, val, hold);
+        Ok(())
+    }
+
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        self.mmu.frozen.remove(addr)
+    }
+
+    pub fn frozen(&self) -> &Frozen {
+        &self.mmu.frozen
+    }
+
+    // replaces all held addresses, e.g. from the game's freeze file
+    pub fn set_frozen(&mut self, frozen: Frozen) -> Result<(), String> {
+        self.mmu.frozen = Frozen::default();
+        for (addr, val, hold) in frozen.iter() {
+            self.freeze(addr, val, hold)?;
+        }
+        Ok(())
+    }
+
+    fn apply_frame_holds(&mut self) {
+        let holds: Vec<(u16, u8)> = self.mmu.frozen.iter().filter(|h| h.2 == Hold::Frame).map(|h| (h.0, h.1)).collect();
+        for (addr, val) in holds {
+            // only writable addresses get frozen
+            self.mmu.poke(addr, val).ok();
+        }
+    }
+
+    // GameShark codes, once per frame like the real one hooked into VBlank
+    fn apply_cheats(&mut self) {
+        for (addr, val) in self.mmu.cheats.pokes() {
+            if self.mmu.peek(addr) != val && self.mmu.poke(addr, val).is_ok() {
+                self.mmu.cheats.mark_applied(addr);
+            }
+        }
+        let applied = self.mmu.cheats.take_applied();
+        if let Some(log) = self.event_log.as_mut() {
+            for code in applied {
+                log.log(self.clockT, Event::Cheat { code });
+            }
+        }
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.mmu.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.mmu.cheats
+    }
+
+    // IO registers and opcodes the game used that aren't emulated, since power on
+    pub fn unimplemented(&self) -> &Unimplemented {
+        &self.mmu.unimplemented
+    }
+
+    // buttons held from now on, until the next call. ignored while a movie plays
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        if !self.movie_playing() {
+            self.press(buttons);
+        }
+    }
+
+    fn press(&mut self, buttons: Buttons) {
+        if self.mmu.joypad.set(buttons) {
+            self.mmu.request_interrupt(Interrupts::JOYPAD);
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.mmu.joypad.pressed()
+    }
+
+    // held when the last frame ended, what movies record and the input display shows.
+    // a playing movie has already pressed the next frame's buttons by then
+    pub fn frame_buttons(&self) -> Buttons {
+        self.frame_buttons
+    }
+
+    // records the buttons of every frame from here on, until stop_movie
+    pub fn record_movie(&mut self) {
+        let state = savestate::save(self);
+        self.frame_start = self.clockT;
+        self.movie = Some(Deck::Recording(Movie::new(state)));
+    }
+
+    // restores the state the movie began in and replays its buttons, live input
+    // takes over again after its last frame
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        savestate::load(self, movie.state()).map_err(|e| format!("can't play movie: {}", e))?;
+        if let Some(&first) = movie.frames().first() {
+            self.press(first);
+            self.movie = Some(Deck::Playing { movie, next: 1 });
+        }
+        Ok(())
+    }
+
+    // the movie recorded or playing
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match self.movie.take()? {
+            Deck::Recording(movie) | Deck::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    pub fn movie_playing(&self) -> bool {
+        matches!(self.movie, Some(Deck::Playing { .. }))
+    }
+
+    fn movie_frame(&mut self) {
+        match &mut self.movie {
+            Some(Deck::Recording(movie)) => movie.push(self.frame_buttons),
+            Some(Deck::Playing { movie, next }) => match movie.frames().get(*next) {
+                Some(&buttons) => {
+                    *next += 1;
+                    self.press(buttons);
+                }
+                None => self.movie = None,
+            },
+            None => {}
+        }
+    }
+
+    // memory as the CPU sees it, without side effects, unusable [FEA0-FEFF] reads 0xFF
+    pub fn read_memory(&self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| match addr.wrapping_add(i as u16) {
+                0xfea0..=0xfeff => 0xff,
+                addr => self.mmu.peek(addr),
+            })
+            .collect()
+    }
+
+    // the cartridge's ROM as loaded, fitted to its banks
+    pub fn rom(&self) -> &[u8] {
+        &self.mmu.rom
+    }
+
+    pub fn header(&self) -> Header {
+        Header::parse(&self.mmu.rom)
+    }
+
+    // cartridge RAM [A000-BFFF], what battery backed games keep their saves in.
+    // MBC2 has 512 bytes, of which only the lower nibble is used
+    pub fn cartridge_ram(&self) -> &[u8] {
+        &self.mmu.external_ram[..self.mmu.mbc.ram_len()]
+    }
+
+    pub fn cartridge_ram_mut(&mut self) -> &mut [u8] {
+        let len = self.mmu.mbc.ram_len();
+        &mut self.mmu.external_ram[..len]
+    }
+
+    // how an MBC3 clock counts, the host's clock or emulated time. a cartridge
+    // without one ignores it
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.rtc_mode = mode;
+        self.mmu.mbc.set_rtc_mode(mode);
+    }
+
+    pub fn rtc_mode(&self) -> RtcMode {
+        self.rtc_mode
+    }
+
+    fn sync_rtc(&mut self) {
+        let now = self.emulated_time();
+        if let Some(rtc) = self.mmu.mbc.rtc_mut() {
+            rtc.sync(now);
+        }
+    }
+
+    // what follows cartridge RAM in a .sav file of a cartridge with a clock
+    pub fn rtc_footer(&self) -> Option<[u8; rtc::FOOTER_LEN]> {
+        let mut rtc = self.mmu.mbc.rtc()?.clone();
+        rtc.sync(self.emulated_time());
+        Some(rtc.footer())
+    }
+
+    pub fn load_rtc_footer(&mut self, footer: &[u8]) -> Result<(), String> {
+        match self.mmu.mbc.rtc_mut() {
+            Some(rtc) => rtc.load_footer(footer),
+            None => Err("the cartridge has no clock".to_string()),
+        }
+    }
+
+    // last frame, shades 0-3 row by row, see palette::PaletteSettings::colorize for RGB
+    pub fn screenshot(&self) -> &[u8] {
+        self.ppu.framebuffer()
+    }
+
+    // last frame as a PNG file, as display shows it
+    pub fn screenshot_png(&self, palettes: &PaletteSettings) -> Vec<u8> {
+        let (width, height) = self.display_size();
+        png::encode(width as u32, height as u32, 3, &self.display(palettes))
+    }
+
+    // size of what display gives, the SGB shows the screen inside a border
+    pub fn display_size(&self) -> (usize, usize) {
+        self.hardware.display_size()
+    }
+
+    // last frame as the console shows it, packed RGB: the screen in the colors of
+    // palettes, or on an SGB in the border with the game's colors
+    pub fn display(&self, palettes: &PaletteSettings) -> Vec<u8> {
+        match self.hardware {
+            Hardware::Dmg => palettes.colorize(self.screenshot()),
+            Hardware::Sgb | Hardware::Sgb2 => self.mmu.sgb.render(self.screenshot(), &palettes.dmg().shades),
+        }
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        &mut self.ppu
+    }
+
+    // back to power-on state while everything else keeps running, IO registers
+    // kept in io.rs aren't part of any subsystem
+    pub fn reset_subsystem(&mut self, part: Subsystem) {
+        match part {
+            Subsystem::Cpu => self.z80 = Z80::new(),
+            Subsystem::Ppu => self.ppu.reset(),
+            Subsystem::Timer => self.mmu.timer = Default::default(),
+            Subsystem::Serial => self.mmu.serial.reset(),
+            Subsystem::Joypad => self.mmu.joypad.reset(),
+            Subsystem::Hdma => self.mmu.hdma = Default::default(),
+            Subsystem::Mapper => {
+                // the cartridge got past check_rom going in
+                self.mmu.mbc = Mbc::new(&self.header()).unwrap_or_default();
+                self.mmu.mbc.set_rtc_mode(self.rtc_mode);
+                self.mmu.map_rom_bank();
+            }
+        }
+    }
+
+    // link cable, e.g. serial::TcpSerial or serial::BgbLink
+    pub fn connect_serial(&mut self, device: Box<dyn serial::SerialDevice>) {
+        self.mmu.serial.connect(device);
+    }
+
+    // a failure of the serial device since the last call, oldest first, e.g. a
+    // printer sheet that couldn't be written
+    pub fn take_serial_error(&mut self) -> Option<Error> {
+        self.mmu.serial.take_error()
+    }
+
+    // before the game is turned off: the serial device finishes what it holds,
+    // like a printer's last sheet, failures come from take_serial_error
+    pub fn power_off_serial(&mut self) {
+        self.mmu.serial.power_off();
+    }
+
+    pub fn set_event_log(&mut self, log: Option<EventLog>) {
+        self.event_log = log;
+    }
+
+    // called from here on, after the ones added before
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Option<trace::Tracer>) {
+        self.tracer = tracer;
+    }
+
+    // advances peripherals by CPU t-cycles
+    fn tick(&mut self, cpu_t: u32) {
+        // overclocked CPU needs multiple instructions for one peripheral t-cycle
+        let cpu_t = cpu_t + self.overclock_remainder;
+        self.overclock_remainder = cpu_t % self.overclock;
+        let mut t = cpu_t / self.overclock;
+        // PPU keeps running while CPU is stalled by HDMA
+        while t > 0 {
+            let start = self.clockT;
+            self.clockT += t as u64;
+            for source in Source::ALL {
+                if self.scheduler.due(source, self.clockT) {
+                    self.sync(source, start);
+                }
+            }
+            t = self.mmu.hdma.take_stall();
+        }
+    }
+
+    // steps a peripheral up to clockT and schedules its next event, start is
+    // when the tick doing it began
+    fn sync(&mut self, source: Source, start: u64) {
+        let t = self.scheduler.catch_up(source, self.clockT);
+        if t > 0 {
+            match source {
+                Source::Ppu => {
+                    let step_events = self.ppu.step(&mut self.mmu, t);
+                    if step_events.contains(PPUEvents::HBLANK) {
+                        self.mmu.hblank();
+                    }
+                    self.events |= step_events;
+                }
+                Source::Serial => {
+                    // only the event log and observers want the byte sent, don't read it every step
+                    let watched = self.event_log.is_some() || !self.observers.is_empty();
+                    let sent = watched.then(|| self.mmu.serial.rb(0xff01, self.mmu.cgb));
+                    if self.mmu.serial.step(t) {
+                        self.mmu.request_interrupt(Interrupts::SERIAL);
+                        if let Some(sent) = sent {
+                            let received = self.mmu.serial.rb(0xff01, self.mmu.cgb);
+                            if let Some(log) = self.event_log.as_mut() {
+                                log.log(start, Event::Serial { sent, received });
+                            }
+                            for observer in &mut self.observers {
+                                observer.on_serial_byte(sent, received);
+                            }
+                        }
+                    }
+                }
+                Source::Timer => {
+                    if self.mmu.timer.step(t) {
+                        self.mmu.request_interrupt(Interrupts::TIMER);
+                    }
+                }
+            }
+        }
+        let until = self.until_event(source);
+        self.scheduler.schedule(source, self.clockT, until);
+    }
+
+    fn sync_all(&mut self) {
+        for source in Source::ALL {
+            self.sync(source, self.clockT);
+        }
+    }
+
+    // t-cycles until the source next has to be stepped
+    fn until_event(&self, source: Source) -> u32 {
+        match source {
+            Source::Ppu => self.ppu.next_event(&self.mmu),
+            Source::Serial => self.mmu.serial.next_interrupt(),
+            Source::Timer => self.mmu.timer.next_interrupt(),
+        }
+    }
+
+    // t-cycles of HALT (or a lockup) to fast-forward through at once, as many
+    // 4 cycle steps as fit before the next event
+    fn idle_halt(&mut self) -> u64 {
+        let waiting = self.z80.lockup.is_some() || (self.z80.halted && self.mmu.pending_interrupts() == 0);
+        if self.idle.is_none() || !waiting || self.overclock != 1 {
+            return 0;
+        }
+        let t = (self.next_event() - 1) / 4 * 4;
+        if let Some(idle) = self.idle.as_mut() {
+            idle.skipped += t;
+        }
+        t
+    }
+
+    // fast-forwards polling loops after a jump back, from is where the
+    // instruction just run began
+    fn idle_loop(&mut self, from: u16) {
+        let waiting = self.z80.halted || self.z80.lockup.is_some();
+        if self.idle.is_none() || waiting || self.overclock != 1 || self.z80.pc > from {
+            return;
+        }
+        let until = self.next_event();
+        let t = self.idle.as_mut().map_or(0, |idle| idle.executed(from, &self.z80, self.clockT, until));
+        if t > 0 {
+            self.tick(t as u32);
+            self.sync_all();
+        }
+    }
+
+    // t-cycles until the next event a wait can end at: the PPU changing mode or
+    // LY, a timer or serial interrupt, or a frame boundary while the LCD is off
+    fn next_event(&self) -> u64 {
+        let next = self.scheduler.until_next(self.clockT);
+        match self.mmu.io[0x40] & 0x80 {
+            0 => next.min((self.frame_start + ppu::FRAME_DOTS).saturating_sub(self.clockT).max(1)),
+            _ => next,
+        }
+    }
+
+    // once per kind and instruction
+    fn diagnose(&mut self, problem: Problem) {
+        let Some(diagnostics) = self.diagnostics.as_ref() else {
+            return;
+        };
+        let pc = diagnostics.pc();
+        if diagnostics.new_at(problem, pc) {
+            let dump = diagnostics::dump(self, pc);
+            self.diagnostics.as_mut().unwrap().report(problem, pc, dump);
+        }
+    }
+
+    // runs until done returns true (checked before every instruction) or
+    // max_frames worth of time passed, returns whether done was reached
+    fn run_until(&mut self, max_frames: u64, mut done: impl FnMut(&GB) -> bool) -> bool {
+        let deadline = self.clockT + max_frames * ppu::FRAME_DOTS;
+        while self.clockT < deadline {
+            if done(self) {
+                return true;
+            }
+            self.cycle();
+        }
+        done(self)
+    }
+}
+
+// the SM83 core on the Game Boy bus
+impl cpu::Core for GB {
+    fn z80(&mut self) -> &mut Z80 {
+        &mut self.z80
+    }
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        if let Some(idle) = self.idle.as_mut() {
+            idle.read(addr);
+        }
+        // the registers read are up to date
+        if let Some(source) = Source::at(addr) {
+            self.sync(source, self.clockT);
+        }
+        if let Some(written) = &self.mmu.wram_written {
+            let i = (addr & 0x1fff) as usize;
+            if matches!(addr, 0xc000..=0xfdff) && written[i / 64] & (1 << (i % 64)) == 0 {
+                self.diagnose(Problem::UninitializedRead { addr });
+            }
+        }
+        self.mmu.rb(addr)
+    }
+    fn bus_write(&mut self, addr: u16, val: u8) {
+        if let Some(idle) = self.idle.as_mut() {
+            idle.write();
+        }
+        let source = Source::at(addr);
+        if let Some(source) = source {
+            self.sync(source, self.clockT);
+        }
+        // latching or setting the clock sees the time up to now
+        if matches!(addr, 0x6000..=0x7fff | 0xa000..=0xbfff) {
+            self.sync_rtc();
+        }
+        self.mmu.wb(addr, val);
+        if let Some(source) = source {
+            self.scheduler.written(source, self.clockT);
+        }
+        for observer in &mut self.observers {
+            observer.on_memory_write(addr, val);
+        }
+    }
+    fn tick(&mut self, t: u32) {
+        GB::tick(self, t)
+    }
+    // enabled in IE and requested in IF
+    fn pending_interrupts(&mut self) -> u8 {
+        self.mmu.pending_interrupts()
+    }
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.mmu.io[0x0f] &= !(1 << bit);
+    }
+    // one line per instruction executed, none while halted
+    fn before_instruction(&mut self) {
+        #[cfg(feature = "trace")]
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.log(&self.z80, &self.mmu);
+        }
+        if let Some(diagnostics) = self.diagnostics.as_mut() {
+            for problem in diagnostics.instruction(&self.z80, self.mmu.work_ram[0x7f]) {
+                self.diagnose(problem);
+            }
+        }
+        if !self.observers.is_empty() {
+            let (pc, opcode) = (self.z80.pc, self.mmu.peek(self.z80.pc));
+            for observer in &mut self.observers {
+                observer.on_instruction(pc, opcode);
+            }
+        }
+    }
+    fn illegal(&mut self, op: u8) {
+        self.mmu.unimplemented.opcode(op);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, micro_rom};
+    use crate::opcodes::OPCODES;
+
+    #[test]
+    fn starts_where_the_boot_rom_leaves_off() {
+        // the VBlank handler at 0040 counts in C000, after clearing the VBlank
+        // the boot ROM left pending
+        let mut rom = micro_rom("xor a\nldh ($0f), a\ninc a\nldh ($ff), a\nei\nwait: halt\njr wait");
+        let handler = assemble("ld hl, $c000\ninc (hl)\nreti", 0x40).unwrap();
+        rom[0x40..0x40 + handler.len()].copy_from_slice(&handler);
+        let mut gb = GB::new(&rom).unwrap();
+        let z80 = &gb.z80;
+        assert_eq!(
+            (z80.af(), z80.bc(), z80.de(), z80.hl(), z80.sp, z80.pc),
+            (0x01b0, 0x0013, 0x00d8, 0x014d, 0xfffe, 0x0100)
+        );
+        assert_eq!(gb.read_memory(0xff04, 1), [0xab]);
+        assert_eq!([0xff0f, 0xff40, 0xff47].map(|addr| gb.read_memory(addr, 1)[0]), [0xe1, 0x91, 0xfc]);
+        // the third VBlank is only requested as run_frames returns
+        gb.run_frames(3);
+        assert_eq!(gb.read_memory(0xc000, 1), [2]);
+        assert_eq!(gb.z80.pc & 0xff00, 0x0100);
+
+        // a boot ROM starts from power on instead
+        gb.load_bootrom(&[0; 256]).unwrap();
+        assert_eq!((gb.z80.pc, gb.z80.sp, gb.read_memory(0xff40, 1)[0]), (0, 0, 0));
+    }
+
+    #[test]
+    fn run_frames_is_deterministic() {
+        let rom = micro_rom("ld a, $91\nldh ($40), a");
+        let mut first = GB::new(&rom).unwrap();
+        let mut second = GB::new(&rom).unwrap();
+        first.run_frames(1);
+        second.run_frames(1);
+        assert_eq!(first.clockT, second.clockT);
+        assert_eq!(first.screenshot(), second.screenshot());
+        // VBlank starts 144 lines into the frame
+        assert_eq!(first.mmu.io[0x44], 144);
+    }
+
+    #[test]
+    fn counts_frames_and_emulated_time() {
+        let rom = micro_rom("ld a, $91\nldh ($40), a");
+        let mut gb = GB::new(&rom).unwrap();
+        gb.run_frames(1);
+        assert_eq!(gb.frames_elapsed(), 1);
+        assert_eq!(gb.cycles_elapsed(), gb.clockT);
+        // first VBlank 144 lines after LCD on, ~16ms
+        let millis = gb.emulated_time().as_millis();
+        assert!((15..17).contains(&millis), "{}", millis);
+        // same cycles take less time on the faster SGB clock
+        let dmg = gb.emulated_time();
+        gb.set_hardware(Hardware::Sgb);
+        let ratio = dmg.as_secs_f64() / gb.emulated_time().as_secs_f64();
+        assert!((1.023..1.025).contains(&ratio), "{}", ratio);
+    }
+
+    #[test]
+    fn run_frames_with_lcd_off() {
+        let rom = micro_rom("xor a\nldh ($40), a");
+        let mut gb = GB::new(&rom).unwrap();
+        gb.run_frames(2);
+        assert!(gb.clockT >= 2 * ppu::FRAME_DOTS && gb.clockT < 2 * ppu::FRAME_DOTS + 8);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_work_ram() {
+        let mut mmu = MMU::new();
+        mmu.wb(0xc000, 0x11);
+        mmu.wb(0xfdff, 0x22);
+        assert_eq!((mmu.rb(0xe000), mmu.rb(0xddff)), (0x11, 0x22));
+        // nothing echoes the last 512 bytes
+        mmu.wb(0xde00, 0x33);
+        assert_eq!(mmu.rb(0xfe00), 0x00);
+    }
+
+    #[test]
+    fn io_reads_unused_bits_as_set() {
+        let mut mmu = MMU::new();
+        for (addr, val, read) in [
+            (0xff0f, 0x00, 0xe0),
+            (0xff41, 0xff, 0xf8),
+            (0xff44, 0x12, 0x00),
+            (0xff13, 0x12, 0xff),
+            (0xff14, 0x40, 0xff),
+            (0xff26, 0x8f, 0xf0),
+            (0xff03, 0x00, 0xff),
+            (0xff4f, 0x00, 0xff),
+            (0xff4a, 0x5a, 0x5a),
+        ] {
+            mmu.wb(addr, val);
+            assert_eq!(mmu.rb(addr), read, "{:04X}", addr);
+        }
+        mmu.cgb = true;
+        assert_eq!(mmu.rb(0xff4f), 0xfe);
+    }
+
+    #[test]
+    fn stack_and_calls_use_little_endian() {
+        let rom = micro_rom(
+            "
+            call sub       ; 0100, returns to 0103
+            ld ($c010), sp
+            pop de
+            push de
+            org $0200
+            sub:
+            ld a, $42
+            ret
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        gb.z80.sp = 0xd000;
+        gb.z80.d = 0xbe;
+        gb.z80.e = 0xef;
+        gb.mmu.ram[0x1000..0x1002].copy_from_slice(&[0xad, 0x0b]);
+        gb.cycle();
+        assert_eq!((gb.z80.pc, gb.z80.sp), (0x0200, 0xcffe));
+        assert_eq!(gb.mmu.ram[0x0ffe..0x1000], [0x03, 0x01]);
+        gb.cycle();
+        gb.cycle();
+        assert_eq!((gb.z80.pc, gb.z80.sp, gb.z80.a), (0x0103, 0xd000, 0x42));
+        gb.cycle();
+        assert_eq!(gb.mmu.ram[0x10..0x12], [0x00, 0xd0]);
+        gb.cycle();
+        assert_eq!((gb.z80.d, gb.z80.e), (0x0b, 0xad));
+        gb.cycle();
+        assert_eq!((gb.z80.sp, &gb.mmu.ram[0x1000..0x1002]), (0xd000, &[0xad, 0x0b][..]));
+        // CALL is 24 t-cycles
+        assert_eq!(OPCODES[0xcd].cycles, 24);
+    }
+
+    #[test]
+    fn register_pairs_and_flags() {
+        let mut z80 = Z80::new();
+        z80.set_bc(0x1234);
+        z80.set_hl(0xbeef);
+        assert_eq!((z80.b, z80.c, z80.bc()), (0x12, 0x34, 0x1234));
+        assert_eq!((z80.h, z80.l, z80.hl()), (0xbe, 0xef, 0xbeef));
+        z80.set_af(0x42ff);
+        assert_eq!(z80.af(), 0x42f0);
+        assert_eq!(z80.add_with_flags(0x0f, 0x01), 0x10);
+        assert_eq!(z80.f, Flags::HALF_CARRY);
+        assert_eq!(z80.add_with_flags(0xf0, 0x10), 0x00);
+        assert_eq!(z80.f, Flags::ZERO | Flags::CARRY);
+    }
+
+    #[test]
+    fn buttons_show_up_in_p1() {
+        let rom = micro_rom(
+            "
+            ld a, $10     ; select buttons
+            ldh ($00), a
+            ldh a, ($00)
+            ldh ($80), a
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        gb.set_buttons(Buttons::A | Buttons::START | Buttons::LEFT);
+        for _ in 0..4 {
+            gb.cycle();
+        }
+        assert_eq!(gb.read_memory(0xff80, 1), [0xd0 | 0x06]);
+        assert_ne!(gb.mmu.io[0x0f] & Interrupts::JOYPAD.bits(), 0);
+        assert_eq!(gb.read_memory(0xfe9f, 3), [0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn subsystems_reset_alone() {
+        // timer on, a BG tile row and palette, then counts in B forever
+        let rom = micro_rom(
+            "
+            ld a, $05
+            ldh ($07), a
+            ld hl, $8000
+            ld a, $5a
+            ld (hl+), a
+            ld (hl), a
+            ld a, $e4
+            ldh ($47), a
+            ld a, $91
+            ldh ($40), a
+            loop:
+            inc b
+            jr loop
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        gb.run_frames(2);
+        let scanline = gb.screenshot().to_vec();
+        gb.ppu_mut().set_renderer(ppu::Renderer::Fifo);
+        gb.run_frames(2);
+        assert_eq!(gb.screenshot(), scanline);
+        assert_ne!(scanline[..8], [0; 8]);
+
+        let (pc, tima) = (gb.z80.pc, gb.mmu.timer.rb(0xff05));
+        assert_ne!(tima, 0);
+        gb.reset_subsystem(Subsystem::Timer);
+        assert_eq!(gb.mmu.timer.rb(0xff05), 0);
+        assert_eq!(gb.z80.pc, pc);
+        gb.reset_subsystem(Subsystem::Ppu);
+        assert_eq!(gb.ppu().renderer(), ppu::Renderer::Fifo);
+        assert_eq!(gb.screenshot()[..8], [0; 8]);
+        gb.reset_subsystem(Subsystem::Cpu);
+        assert_eq!((gb.z80.pc, gb.z80.b), (0, 0));
+        assert_eq!(Subsystem::parse("MAPPER"), Ok(Subsystem::Mapper));
+        assert!(Subsystem::parse("apu").unwrap_err().starts_with("unknown subsystem `apu`"));
+    }
+
+    #[test]
+    fn rejects_what_cant_be_a_cartridge() {
+        assert!(matches!(GB::new(&vec![0; 0x2000]), Err(Error::RomTooSmall { len: 0x2000 })));
+        let mut rom = micro_rom("nop");
+        rom[0x0147] = 0x42;
+        assert!(matches!(GB::new(&rom), Err(Error::UnsupportedMbc(0x42))));
+        // MMM01, a mapper the Pan Docs list that isn't emulated
+        rom[0x0147] = 0x0b;
+        let error = GB::new(&rom).err().unwrap();
+        assert_eq!(error.to_string(), "cartridge type 0B (MMM01) isn't emulated");
+        rom[0x0147] = 0x00;
+        let mut gb = GB::new(&rom).unwrap();
+        assert!(matches!(gb.load_bootrom(&[0; 100]), Err(Error::InvalidBootRom { len: 100 })));
+        assert!(matches!(gb.set_overclock(0), Err(Error::InvalidOverclock(0))));
+        assert!(gb.set_overclock(2).is_ok());
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up() {
+        let mut rom = micro_rom("nop");
+        rom[0x0101] = 0xd3;
+        let mut gb = GB::new(&rom).unwrap();
+        gb.run_frames(1);
+        assert!(matches!(gb.crash(), Some(Error::IllegalOpcode { op: 0xd3, addr: 0x0101 })));
+        // not even an interrupt gets the CPU going again, time still passes
+        (gb.z80.ime, gb.mmu.io[0x0f]) = (true, 0x01);
+        gb.mmu.wb(0xffff, 0x01);
+        let before = gb.cycles_elapsed();
+        gb.run_frames(1);
+        assert_eq!(gb.z80.pc, 0x0102);
+        assert!(gb.cycles_elapsed() > before);
+        assert_eq!(gb.crash().unwrap().to_string(), "illegal opcode 0xd3 at 0x0101, the CPU locked up");
+        // unusable memory after OAM doesn't exist either
+        gb.mmu.wb(0xfea0, 0x12);
+        assert_eq!(gb.mmu.peek(0xfea0), 0xff);
+    }
+}
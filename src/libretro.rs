@@ -0,0 +1,464 @@
+// libretro core, so RetroArch and other libretro frontends can run gb-rust.
+// built as a cdylib with `cargo rustc --release --lib --features libretro
+// --crate-type cdylib`, the frontend loads target/release/libgb_rust.so (.dll,
+// .dylib) and calls the retro_* functions below from its main thread, following
+// libretro.h
+//
+// RetroPad maps onto the joypad, frames go out as XRGB8888 in the default DMG
+// palette, savestates are the uncompressed gb-rust format and cartridge RAM is
+// exposed as SAVE_RAM so the frontend writes .srm files, cheats take Game Genie
+// and GameShark codes. there is no APU yet, each frame sends silence for
+// frontends which sync to audio
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+
+use crate::cartridge;
+use crate::joypad::Buttons;
+use crate::palette::PaletteSettings;
+use crate::ppu::{FRAME_DOTS, HEIGHT, WIDTH};
+use crate::{savestate, CLOCK_HZ, GB};
+
+const API_VERSION: u32 = 1;
+const SAMPLE_RATE: f64 = 44100.0;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: u32 = 10;
+const PIXEL_FORMAT_XRGB8888: u32 = 1;
+const DEVICE_JOYPAD: u32 = 1;
+const MEMORY_SAVE_RAM: u32 = 0;
+const REGION_NTSC: u32 = 0;
+
+// RetroPad button ids
+const JOYPAD: [(u32, Buttons); 8] = [
+    (0, Buttons::B),
+    (2, Buttons::SELECT),
+    (3, Buttons::START),
+    (4, Buttons::UP),
+    (5, Buttons::DOWN),
+    (6, Buttons::LEFT),
+    (7, Buttons::RIGHT),
+    (8, Buttons::A),
+];
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: u32, data: *mut c_void) -> bool;
+type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: u32, height: u32, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn = unsafe extern "C" fn(port: u32, device: u32, index: u32, id: u32) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: u32,
+    base_height: u32,
+    max_width: u32,
+    max_height: u32,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+// the loaded game
+struct Game {
+    gb: GB,
+    palettes: PaletteSettings,
+    frame: Vec<u32>,
+    // audio frames owed to the frontend, fractional
+    samples: f64,
+}
+
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static GAME: RefCell<Option<Game>> = const { RefCell::new(None) };
+}
+
+fn with_game<T>(f: impl FnOnce(&mut Game) -> T) -> Option<T> {
+    GAME.with(|game| game.borrow_mut().as_mut().map(f))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> u32 {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    CALLBACKS.with(|c| c.borrow_mut().environment = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    CALLBACKS.with(|c| c.borrow_mut().video_refresh = Some(cb));
+}
+
+// frames are sent in one batch, single samples are never used
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    CALLBACKS.with(|c| c.borrow_mut().audio_sample_batch = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    CALLBACKS.with(|c| c.borrow_mut().input_poll = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    CALLBACKS.with(|c| c.borrow_mut().input_state = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    GAME.with(|game| game.borrow_mut().take());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"gb-rust".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"gb|gbc".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as u32,
+            base_height: HEIGHT as u32,
+            max_width: WIDTH as u32,
+            max_height: HEIGHT as u32,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: SystemTiming { fps: CLOCK_HZ as f64 / FRAME_DOTS as f64, sample_rate: SAMPLE_RATE },
+    };
+}
+
+// only the RetroPad is supported
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: u32, _device: u32) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const GameInfo) -> bool {
+    if info.is_null() || (*info).data.is_null() {
+        return false;
+    }
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    let environment = CALLBACKS.with(|c| c.borrow().environment);
+    match environment {
+        Some(environment) if environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut u32 as *mut c_void) => {}
+        _ => return false,
+    }
+    let mut data = std::slice::from_raw_parts((*info).data as *const u8, (*info).size).to_vec();
+    if cartridge::fit_rom(&mut data).is_err() {
+        return false;
+    }
+    let gb = match GB::new(&data) {
+        Ok(gb) => gb,
+        Err(_) => return false,
+    };
+    let game = Game {
+        gb,
+        palettes: PaletteSettings::default(),
+        frame: vec![0; WIDTH * HEIGHT],
+        samples: 0.0,
+    };
+    GAME.with(|g| *g.borrow_mut() = Some(game));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: u32, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    GAME.with(|game| game.borrow_mut().take());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> u32 {
+    REGION_NTSC
+}
+
+// power cycle, cartridge RAM survives like on the battery and the cheats stay
+// entered like on a cheat cartridge
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_game(|game| {
+        let ram = game.gb.cartridge_ram_mut().to_vec();
+        // the ROM loaded before, it loads again
+        if let Ok(gb) = GB::new(game.gb.rom()) {
+            let cheats = std::mem::take(game.gb.cheats_mut());
+            game.gb = gb;
+            game.gb.cartridge_ram_mut().copy_from_slice(&ram);
+            *game.gb.cheats_mut() = cheats;
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = CALLBACKS.with(|c| {
+        let c = c.borrow();
+        (c.input_poll, c.input_state, c.video_refresh, c.audio_sample_batch)
+    });
+    let (input_poll, input_state, video_refresh, audio_sample_batch) = callbacks;
+    with_game(|game| {
+        let mut buttons = Buttons::empty();
+        if let (Some(poll), Some(state)) = (input_poll, input_state) {
+            unsafe { poll() };
+            for (id, button) in JOYPAD {
+                if unsafe { state(0, DEVICE_JOYPAD, 0, id) } != 0 {
+                    buttons |= button;
+                }
+            }
+        }
+        game.gb.set_buttons(buttons);
+        game.gb.run_frames(1);
+
+        // only the lines the game changed are colored again
+        for lines in game.gb.ppu_mut().take_changed_lines() {
+            let pixels = lines.start * WIDTH..lines.end * WIDTH;
+            let rgb = game.palettes.colorize(&game.gb.screenshot()[pixels.clone()]);
+            for (pixel, rgb) in game.frame[pixels].iter_mut().zip(rgb.chunks_exact(3)) {
+                *pixel = u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]);
+            }
+        }
+        if let Some(video_refresh) = video_refresh {
+            let pitch = WIDTH * std::mem::size_of::<u32>();
+            unsafe { video_refresh(game.frame.as_ptr() as *const c_void, WIDTH as u32, HEIGHT as u32, pitch) };
+        }
+
+        game.samples += SAMPLE_RATE * FRAME_DOTS as f64 / CLOCK_HZ as f64;
+        let frames = game.samples as usize;
+        game.samples -= frames as f64;
+        if let Some(audio_sample_batch) = audio_sample_batch {
+            let silence = vec![0i16; frames * 2];
+            unsafe { audio_sample_batch(silence.as_ptr(), frames) };
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_game(|game| savestate::save_stored(&game.gb).len()).unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let Some(state) = with_game(|game| savestate::save_stored(&game.gb)) else {
+        return false;
+    };
+    if data.is_null() || size < state.len() {
+        return false;
+    }
+    ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_game(|game| savestate::load(&mut game.gb, state).is_ok()).unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_game(|game| game.gb.cheats_mut().clear());
+}
+
+// frontends send every enabled cheat after a reset, a cheat may hold several
+// codes joined by `+`. the index is theirs, codes that don't parse are skipped
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: u32, enabled: bool, code: *const c_char) {
+    if !enabled || code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy().into_owned();
+    with_game(|game| {
+        for part in code.split('+') {
+            game.gb.cheats_mut().add(part).ok();
+        }
+    });
+}
+
+// the frontend reads and writes SAVE_RAM directly, for as long as the game is
+// loaded. it's the battery backed RAM, a cartridge without battery has none
+fn save_ram(game: &mut Game) -> &mut [u8] {
+    match game.gb.header().battery() {
+        true => game.gb.cartridge_ram_mut(),
+        false => &mut [],
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: u32) -> *mut c_void {
+    let data = |game: &mut Game| match save_ram(game) {
+        [] => ptr::null_mut(),
+        ram => ram.as_mut_ptr() as *mut c_void,
+    };
+    match id {
+        MEMORY_SAVE_RAM => with_game(data).unwrap_or(ptr::null_mut()),
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: u32) -> usize {
+    match id {
+        MEMORY_SAVE_RAM => with_game(|game| save_ram(game).len()).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::asm::micro_rom;
+
+    thread_local! {
+        static FRAMES: Cell<u32> = const { Cell::new(0) };
+        static FIRST_PIXEL: Cell<u32> = const { Cell::new(0) };
+        static AUDIO_FRAMES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe extern "C" fn environment(cmd: u32, data: *mut c_void) -> bool {
+        cmd == ENVIRONMENT_SET_PIXEL_FORMAT && *(data as *const u32) == PIXEL_FORMAT_XRGB8888
+    }
+
+    unsafe extern "C" fn video_refresh(data: *const c_void, width: u32, height: u32, pitch: usize) {
+        assert_eq!((width, height, pitch), (160, 144, 640));
+        FRAMES.with(|f| f.set(f.get() + 1));
+        FIRST_PIXEL.with(|p| p.set(*(data as *const u32)));
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+        AUDIO_FRAMES.with(|a| a.set(a.get() + frames));
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    // start held
+    unsafe extern "C" fn input_state(_port: u32, _device: u32, _index: u32, id: u32) -> i16 {
+        (id == 3) as i16
+    }
+
+    #[test]
+    fn runs_as_libretro_core() {
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        // MBC1 with 8 KiB of battery RAM
+        let mut rom = micro_rom("ld a, $0a\nld ($0000), a\nld a, $12\nld ($a000), a\nloop:\njr loop");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let info = GameInfo { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        assert!(unsafe { retro_load_game(&info) });
+        unsafe { retro_cheat_set(0, true, c"0177C2FF+bogus".as_ptr()) };
+        for _ in 0..60 {
+            retro_run();
+        }
+        assert_eq!(FRAMES.with(|f| f.get()), 60);
+        // lightest shade of the default palette, LCD shows nothing
+        let [_, r, g, b] = FIRST_PIXEL.with(|p| p.get()).to_be_bytes();
+        assert_eq!([r, g, b], PaletteSettings::default().dmg().shades[0]);
+        // 44100 Hz at 59.73 fps, a bit over a second
+        assert_eq!(AUDIO_FRAMES.with(|a| a.get()), 44301);
+        assert_eq!(with_game(|game| game.gb.buttons()), Some(Buttons::START));
+        assert_eq!(with_game(|game| game.gb.read_memory(0xffc2, 1)), Some(vec![0x77]));
+        retro_cheat_reset();
+
+        assert_eq!(retro_get_memory_size(MEMORY_SAVE_RAM), 8192);
+        let ram = retro_get_memory_data(MEMORY_SAVE_RAM) as *mut u8;
+        assert_eq!(unsafe { *ram }, 0x12);
+
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        let cycles = with_game(|game| game.gb.cycles_elapsed()).unwrap();
+        retro_run();
+        assert_eq!(retro_serialize_size(), state.len());
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        assert_eq!(with_game(|game| game.gb.cycles_elapsed()), Some(cycles));
+
+        // the frontend's .srm and cheats survive a reset, which starts where the
+        // boot ROM leaves off
+        unsafe { *ram = 0x34 };
+        unsafe { retro_cheat_set(0, true, c"0177C2FF".as_ptr()) };
+        retro_reset();
+        assert_eq!(with_game(|game| (game.gb.cycles_elapsed(), game.gb.z80.pc, game.gb.z80.sp)), Some((0, 0x0100, 0xfffe)));
+        assert_eq!(unsafe { *ram }, 0x34);
+        retro_run();
+        assert_eq!(with_game(|game| game.gb.read_memory(0xffc2, 1)), Some(vec![0x77]));
+        retro_unload_game();
+        assert!(retro_get_memory_data(MEMORY_SAVE_RAM).is_null());
+
+        // nothing for the frontend to save without a battery
+        let rom = micro_rom("loop:\njr loop");
+        let info = GameInfo { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        assert!(unsafe { retro_load_game(&info) });
+        assert_eq!(retro_get_memory_size(MEMORY_SAVE_RAM), 0);
+        assert!(retro_get_memory_data(MEMORY_SAVE_RAM).is_null());
+        retro_unload_game();
+        retro_deinit();
+    }
+}
@@ -0,0 +1,115 @@
+// LZ4 block format (no frame header), enough for savestates without extra dependencies
+
+const MIN_MATCH: usize = 4;
+// spec: last 5 bytes are always literals, no match starts in the last 12
+const LAST_LITERALS: usize = 5;
+const MATCH_LIMIT: usize = 12;
+const HASH_BITS: u32 = 12;
+
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(src.len() / 2);
+    // last position + 1 of each hashed 4 byte sequence, 0 when empty
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut i = 0;
+    while i + MATCH_LIMIT < src.len() {
+        let seq = u32::from_le_bytes([src[i], src[i + 1], src[i + 2], src[i + 3]]);
+        let hash = (seq.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize;
+        let candidate = table[hash];
+        table[hash] = i + 1;
+        if candidate > 0 {
+            let start = candidate - 1;
+            if i - start <= 0xffff && src[start..start + MIN_MATCH] == src[i..i + MIN_MATCH] {
+                let max = src.len() - LAST_LITERALS - i;
+                let mut len = MIN_MATCH;
+                while len < max && src[start + len] == src[i + len] {
+                    len += 1;
+                }
+                sequence(&mut out, &src[anchor..i], Some((i - start, len)));
+                i += len;
+                anchor = i;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    sequence(&mut out, &src[anchor..], None);
+    out
+}
+
+// len comes from the savestate header, a block can't expand more than 255
+// times so a damaged one doesn't get to reserve more than that
+pub fn decompress(src: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(len.min(src.len().saturating_mul(255)));
+    let mut i = 0;
+    let byte = |i: &mut usize| -> Result<u8, String> {
+        let b = *src.get(*i).ok_or("truncated LZ4 block")?;
+        *i += 1;
+        Ok(b)
+    };
+    loop {
+        let token = byte(&mut i)?;
+        let literals = length(token >> 4, &mut || byte(&mut i))?;
+        let end = i.checked_add(literals).filter(|&end| end <= src.len()).ok_or("truncated LZ4 literals")?;
+        out.extend_from_slice(&src[i..end]);
+        i = end;
+        if i == src.len() {
+            break;
+        }
+        let offset = u16::from_le_bytes([byte(&mut i)?, byte(&mut i)?]) as usize;
+        if offset == 0 || offset > out.len() {
+            return Err(format!("LZ4 match offset {} out of range", offset));
+        }
+        let matched = length(token & 0x0f, &mut || byte(&mut i))? + MIN_MATCH;
+        if out.len() + matched > len {
+            return Err("LZ4 block longer than expected".to_string());
+        }
+        // byte by byte, matches may overlap their own output
+        let start = out.len() - offset;
+        for n in 0..matched {
+            out.push(out[start + n]);
+        }
+    }
+    if out.len() != len {
+        return Err(format!("LZ4 block has {} bytes, expected {}", out.len(), len));
+    }
+    Ok(out)
+}
+
+fn sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((literals.len().min(15) as u8) << 4 | match_len.min(15) as u8);
+    extra_length(out, literals.len());
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        extra_length(out, match_len);
+    }
+}
+
+// lengths >= 15 continue in following bytes, 255 meaning "more follows"
+fn extra_length(out: &mut Vec<u8>, len: usize) {
+    if len < 15 {
+        return;
+    }
+    let mut rest = len - 15;
+    while rest >= 255 {
+        out.push(255);
+        rest -= 255;
+    }
+    out.push(rest as u8);
+}
+
+fn length(nibble: u8, next: &mut impl FnMut() -> Result<u8, String>) -> Result<usize, String> {
+    let mut len = nibble as usize;
+    if nibble == 15 {
+        loop {
+            let b = next()?;
+            len += b as usize;
+            if b != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
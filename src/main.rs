@@ -1,223 +1,384 @@
-#![feature(is_some_with)]
-
 use std::env;
 use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+
+use gb_rust::cartridge;
+use gb_rust::eventlog::EventLog;
+use gb_rust::inputdisplay::InputDisplay;
+use gb_rust::palette::{self, PaletteSettings};
+use gb_rust::postprocess::PostProcess;
+use gb_rust::printer::Printer;
+use gb_rust::serial::{self, BgbLink, SerialDevice, TcpSerial};
+use gb_rust::session::Session;
+use gb_rust::triplebuffer::{triple_buffer, Consumer};
+use gb_rust::script::Script;
+use gb_rust::{bootpalette, debugger, freeze, movie, pipeline, regress, report, selfcheck, sram, testrom};
+
+mod arcade;
+mod cli;
+mod config;
+mod emulation;
+mod gamepad;
+
+use emulation::Emulation;
+
+// problems with the user's input end the program with a message instead of a panic
+fn fail(message: &str) -> ! {
+    eprintln!("gb-rust: {}", message);
+    process::exit(2);
+}
 
-extern crate bitflags;
-
-bitflags::bitflags! {
-    struct Flags: u8 {
-        const NONE = 0x00;
-        const CARRY = 0x10;
-        const HALF_CARRY = 0x20;
-        const SUBSTRACTION = 0x40;
-        const ZERO = 0x80;
+// imperfect dumps are fitted to the size in their header, with a warning
+fn read_rom(path: &str) -> Result<Vec<u8>, String> {
+    let mut data = fs::read(path).map_err(|e| format!("can't read ROM {}: {}", path, e))?;
+    match cartridge::fit_rom(&mut data) {
+        Ok(Some(warning)) => eprintln!("gb-rust: {}: {}", path, warning),
+        Ok(None) => {}
+        Err(e) => return Err(format!("{} is {}", path, e)),
     }
+    Ok(data)
 }
 
-impl Default for Flags {
-    fn default() -> Self {
-        Flags::NONE
+// `gb-rust trace replay <file>` and `gb-rust trace grep <file> <term>...`
+#[cfg(feature = "trace")]
+fn trace_tool(args: &[String]) {
+    use gb_rust::trace;
+    use std::io::Write;
+    let usage = "usage: gb-rust trace replay <file> | gb-rust trace grep <file> <term>...";
+    let [command, path, terms @ ..] = args else {
+        fail(usage);
+    };
+    let file = fs::File::open(path).unwrap_or_else(|e| fail(&format!("can't read {}: {}", path, e)));
+    let input = io::BufReader::new(file);
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let found = match command.as_str() {
+        "replay" if terms.is_empty() => trace::replay(input, &mut out),
+        "grep" if !terms.is_empty() => trace::grep(input, terms, &mut out),
+        _ => fail(usage),
+    };
+    let found = match found.and_then(|n| out.flush().map(|_| n)) {
+        Ok(n) => n,
+        // piped into head and the like
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return,
+        Err(e) => fail(&format!("{}: {}", path, e)),
+    };
+    // like grep, nothing found exits with 1
+    if found == 0 && command == "grep" {
+        process::exit(1);
     }
 }
 
-struct MMU<'a> {
-    booted: bool,
-    // [0000-00FF] bios during boot
-    bios: [u8; 256],
-
-    // [0000-3FFF] cartridge bank0 after boot
-    // [0100-014F] cartridge header
-    bank0: &'a [u8],
-
-    // [4000-7FFF] cartridge other banks
-    loaded_bank: &'a [u8],
-
-    // [8000-9FFF] graphics
-    graphics: [u8; 8192],
-
-    // [A000-BFFF] external cartridge ram
-    external_ram: [u8; 8192],
-
-    // [C000-DFFF] (+ repeat at [E000-FDFF]) internal working ram
-    ram: [u8; 8192],
-
-    // [FE00-FE9F] sprites
-    sprites: [u8; 160],
-
-    // [FF00-FF7F] IO
-    io: [u8; 128],
-
-    // [FF80-FFFF]
-    work_ram: [u8; 128],
+#[cfg(not(feature = "trace"))]
+fn trace_tool(_: &[String]) {
+    fail("trace replay / grep need gb-rust built with the trace feature");
 }
 
-impl Default for MMU<'_> {
-    fn default() -> Self {
-        MMU {
-            booted: false,
-            bios: [0; 256],
-            bank0: &[0; 16384],
-            loaded_bank: &[0; 16384],
-            graphics: [0; 8192],
-            external_ram: [0; 8192],
-            ram: [0; 8192],
-            sprites: [0; 160],
-            io: [0; 128],
-            work_ram: [0; 128],
+// battery saves, --track-io summary of every cartridge run and the --record movie
+fn finish(options: &cli::Options, session: &mut Session, save_dir: &Path) {
+    let names: Vec<String> = session.names().map(String::from).collect();
+    for (name, gb) in names.iter().zip(session.games_mut()) {
+        gb.power_off_serial();
+        while let Some(e) = gb.take_serial_error() {
+            eprintln!("gb-rust: {}: {}", name, e);
         }
     }
-}
-
-impl<'a> MMU<'a> {
-    fn new() -> Self {
-        Default::default()
-    }
-    fn rb(&self, addr: u16) -> u8 {
-        match addr {
-            // bank 0 & bios
-            0x000..=0x00ff => match self.booted {
-                false => self.bios[(addr - 0x000) as usize],
-                true => self.bank0[(addr - 0x000) as usize],
-            },
-            0x0100..=0x3fff => self.bank0[(addr - 0x000) as usize],
-
-            0x4000..=0x7fff => self.loaded_bank[(addr - 0x4000) as usize],
-
-            0x8000..=0x9fff => self.graphics[(addr - 0x8000) as usize],
-
-            0xa000..=0xbfff => self.external_ram[(addr - 0xa000) as usize],
-
-            0xc000..=0xfdff => self.ram[(addr % 8192) as usize],
-
-            0xfe00..=0xfe9f => self.sprites[(addr - 0xfe00) as usize],
-
-            0xfea0..=0xfeff => panic!("Trying to read non-existent memory"),
-
-            0xff00..=0xff7f => self.io[(addr - 0xff00) as usize],
-
-            0xff80..=0xffff => self.work_ram[(addr - 0xff80) as usize],
+    for (name, gb) in session.games().filter(|(_, gb)| gb.header().battery()) {
+        let path = sram::default_path(save_dir, name);
+        if let Err(e) = sram::export(gb, &path) {
+            eprintln!("gb-rust: can't write {}", e);
         }
     }
-    fn r2b(&self, addr: u16) -> u16 {
-        let head = self.rb(addr) as u16;
-        let tail = self.rb(addr + 1) as u16;
-        (head << 8) | tail 
+    if options.track_io {
+        for (name, gb) in session.games() {
+            eprint!("{}: {}", name, gb.unimplemented());
+        }
     }
-    fn wb(&mut self, addr: u16, val: u8) {
-        match addr {
-            // bank 0 & bios
-            0x000..=0x00ff => panic!("Trying to write to non-writable memory - bios / bank 0"),
-            0x0100..=0x3fff => panic!("Trying to write to non-writable memory - bank 0"),
-
-            0x4000..=0x7fff => panic!("Trying to write to non-writable memory - loaded bank"),
-
-            0x8000..=0x9fff => self.graphics[(addr - 0x8000) as usize] = val,
-
-            0xa000..=0xbfff => self.external_ram[(addr - 0xa000) as usize] = val,
-
-            0xc000..=0xfdff => self.ram[(addr % 8192) as usize] = val,
-
-            0xfe00..=0xfe9f => self.sprites[(addr - 0xfe00) as usize] = val,
-
-            0xfea0..=0xfeff => panic!("Trying to write non-existent memory"),
-
-            0xff00..=0xff7f => self.io[(addr - 0xff00) as usize] = val,
-
-            0xff80..=0xffff => self.work_ram[(addr - 0xff80) as usize] = val,
+    if let (Some(path), Some(movie)) = (&options.record, session.games_mut().next().and_then(|gb| gb.stop_movie())) {
+        match movie::save(path.as_ref(), &movie) {
+            Ok(()) => eprintln!("recorded {} frames to {}", movie.frames().len(), path),
+            Err(e) => fail(&format!("can't write movie {}: {}", path, e)),
         }
     }
 }
 
-#[derive(Default)]
-struct Z80 {
-    // clock for last istr
-    m: u8,
-    t: u8,
-    // registers
-    b: u8,
-    a: u8,
-    c: u8,
-    d: u8,
-    e: u8,
-    h: u8,
-    l: u8,
-    // special registers
-    f: Flags, // flags
-    pc: u16,  // program counter
-    sp: u16,  // stack pointer
+// the session with everything the options attach to its games, and the --script
+fn open_session(
+    roms: &[(String, Vec<u8>)],
+    options: &cli::Options,
+    save_dir: &Path,
+    bootrom: Option<&[u8]>,
+) -> Result<(Session, Option<Script>), String> {
+    let mut session = Session::new(roms)?;
+    for ((name, _), gb) in roms.iter().zip(session.games_mut()) {
+        gb.set_hardware(options.hardware);
+        gb.set_overclock(options.overclock)?;
+        gb.set_skip_idle(options.skip_idle);
+        gb.set_diagnostics(options.diagnostics);
+        gb.set_rtc_mode(options.rtc_mode());
+        if let Some(bootrom) = bootrom {
+            gb.load_bootrom(bootrom)?;
+        }
+        gb.ppu_mut().set_scanline_capture(options.scanlines);
+        gb.ppu_mut().set_renderer(options.renderer.unwrap_or_default());
+        let frozen = freeze::load(&freeze::default_path(save_dir, name))?;
+        gb.set_frozen(frozen).map_err(|e| format!("{}: {}", name, e))?;
+        let sav = sram::default_path(save_dir, name);
+        if gb.header().battery() && sav.exists() {
+            sram::import(gb, &sav)?;
+        }
+    }
+    if options.two_player {
+        session.link_first_two();
+    }
+    // trace, link cable, event log, cheats, movies and scripts follow the first cartridge
+    if let Some(path) = &options.trace {
+        #[cfg(feature = "trace")]
+        {
+            let tracer = match options.trace_compressed {
+                true => gb_rust::trace::Tracer::compressed_file(path.as_ref()),
+                false => gb_rust::trace::Tracer::to_file(path.as_ref()),
+            };
+            let tracer = tracer.map_err(|e| format!("can't create trace file {}: {}", path, e))?;
+            session.active_mut().set_tracer(Some(tracer));
+        }
+        #[cfg(not(feature = "trace"))]
+        return Err(format!("--trace {} needs gb-rust built with the trace feature", path));
+    }
+    if let Some(addr) = &options.link {
+        let link = serial::open_stream(addr).and_then(|stream| -> io::Result<Box<dyn SerialDevice>> {
+            Ok(match options.link_protocol {
+                cli::LinkProtocol::Native => Box::new(TcpSerial::new(stream)?),
+                cli::LinkProtocol::Bgb => Box::new(BgbLink::new(stream)?),
+            })
+        });
+        session.active_mut().connect_serial(link.map_err(|e| format!("can't link with {}: {}", addr, e))?);
+    }
+    if let Some(dir) = &options.printer {
+        session.active_mut().connect_serial(Box::new(Printer::new(dir)));
+    }
+    if let Some(target) = &options.events {
+        let log = EventLog::open(target).map_err(|e| format!("can't open event log {}: {}", target, e))?;
+        session.active_mut().set_event_log(Some(log));
+    }
+    for code in &options.cheats {
+        session.active_mut().cheats_mut().add(code)?;
+    }
+    if let Some(path) = &options.playback {
+        let movie = movie::load(path.as_ref())?;
+        session.active_mut().play_movie(movie).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if options.record.is_some() {
+        session.active_mut().record_movie();
+    }
+    let script = match &options.script {
+        Some(path) => {
+            let source = fs::read_to_string(path).map_err(|e| format!("can't read script {}: {}", path.display(), e))?;
+            let mut loaded = Script::load(&path.display().to_string(), &source, session.active_mut())?;
+            for line in loaded.take_output() {
+                eprintln!("{}", line);
+            }
+            Some(loaded)
+        }
+        None => None,
+    };
+    Ok((session, script))
 }
 
-impl Z80 {
-    fn new() -> Self {
-        Default::default()
+// --raw-frames while playing in real time, ends with the emulation
+fn present(mut frames: Consumer<Vec<u8>>, post: &PostProcess, (width, height): (usize, usize)) {
+    let mut stdout = io::stdout().lock();
+    while let Some(rgb) = frames.wait() {
+        let (_, _, pixels) = post.apply(rgb, width as u32, height as u32);
+        if stdout.write_all(&pixels).and_then(|_| stdout.flush()).is_err() {
+            // reader went away
+            return;
+        }
     }
 }
 
-struct GB<'a> {
-    z80: Z80,
-    mmu: MMU<'a>,
-    clockM: u64,
-    clockT: u64,
-    rom_data: &'a Vec<u8>,
+// --vram-view, opening a FIFO waits for its reader so that's on this thread too
+fn present_vram(mut frames: Consumer<Vec<u8>>, path: &str) {
+    let mut file = match fs::File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("gb-rust: can't write VRAM view to {}: {}", path, e);
+            return;
+        }
+    };
+    while let Some(rgb) = frames.wait() {
+        if file.write_all(rgb).and_then(|_| file.flush()).is_err() {
+            return;
+        }
+    }
 }
 
-impl<'a> GB<'a> {
-    fn new(rom_data: &'a Vec<u8>) -> Self {
-        let mut instance = Self {
-            z80: Default::default(),
-            mmu: Default::default(),
-            clockM: Default::default(),
-            clockT: Default::default(),
-            rom_data
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "trace") {
+        trace_tool(&args[1..]);
+        return;
+    }
+    if args.first().is_some_and(|command| command == "self-check") {
+        let report = selfcheck::run();
+        print!("{}", report);
+        process::exit(if report.passed() { 0 } else { 1 });
+    }
+    if args.first().is_some_and(|command| command == "regress") {
+        let (file, update) = match &args[1..] {
+            [file] => (file, false),
+            [file, flag] if flag == "--update" => (file, true),
+            _ => fail("expected `gb-rust regress <file> [--update]`"),
         };
-        instance.mmu.bank0 = &rom_data[0..16384];
-        instance
+        let report = regress::run(Path::new(file), update).unwrap_or_else(|e| fail(&e));
+        print!("{}", report);
+        process::exit(if report.passed() { 0 } else { 1 });
     }
-
-    fn load_rom(&mut self, rom_data: &'a Vec<u8>) {
-        self.rom_data = rom_data;
-        self.mmu.bank0 = &rom_data[0..16384]
+    let mut options = cli::parse(args).unwrap_or_else(|e| fail(&format!("{}, see --help", e)));
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
     }
-
-    fn cycle(&mut self) {
-        let instr = self.mmu.rb(self.z80.pc);
-        self.run_instr(instr);
-        self.clockM += self.z80.m as u64;
-        self.clockT += self.z80.t as u64;
+    let config_path = options.config.clone().or_else(config::default_path);
+    let mut config = match &config_path {
+        Some(path) => config::Config::load(path).unwrap_or_else(|e| fail(&e)),
+        None => config::Config::default(),
+    };
+    config.apply(&mut options);
+    if options.save_config {
+        let path = config_path.unwrap_or_else(|| fail("no config path, set --config or HOME"));
+        config.update(&options);
+        config.save(&path).unwrap_or_else(|e| fail(&format!("can't write {}: {}", path.display(), e)));
+        eprintln!("settings saved to {}", path.display());
     }
-
-    fn run_instr(&mut self, instr: u8) {
-        match instr {
-            // NOP
-            0x00 => {
-                self.z80.m = 1;
-                self.z80.t = 4;
-            },
-            // LD ** BC
-            0x01 => {
-                self.z80.c = self.mmu.rb(self.z80.pc);
-                self.z80.b = self.mmu.rb(self.z80.pc + 1);
-                self.z80.m = 3;
-                self.z80.t = 12;
-            },
-            _ => todo!("Instruction not implemented"),
-        }
-        self.z80.pc += 1;
+    let save_dir = options.save_dir.as_deref().unwrap_or(Path::new("."));
+    let mut roms: Vec<(String, Vec<u8>)> = options
+        .roms
+        .iter()
+        .map(|path| read_rom(path).map(|data| (path.clone(), data)))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| fail(&e));
+    // both players on one ROM, the second one saves to <rom>-2.sav
+    if options.two_player && roms.len() == 1 {
+        let (path, data) = roms[0].clone();
+        let second = Path::new(&path);
+        let name = format!("{}-2", second.file_stem().unwrap_or_default().to_string_lossy());
+        let second = second.with_file_name(name).with_extension(second.extension().unwrap_or_default());
+        roms.push((second.to_string_lossy().into_owned(), data));
     }
-}
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    assert!(!args.is_empty(), "Expected path to ROM");
-    let rom_data_result = fs::read(args.first().unwrap());
-    assert!(
-        rom_data_result.is_ok_and(|r| r.len() > 0x014f),
-        "Expected file to exist and have data"
-    );
-    let rom_data = rom_data_result.unwrap();
-    let mut gb = GB::new(&rom_data);
-    loop {
-        gb.cycle()
+    let bootrom = options.bootrom.as_ref().map(|path| {
+        fs::read(path).unwrap_or_else(|e| fail(&format!("can't read boot ROM {}: {}", path, e)))
+    });
+    let mut palettes = PaletteSettings::default();
+    if let Some(path) = &options.palette_file {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("can't read {}: {}", path, e)));
+        palettes.add_user(palette::parse_palettes(&text).unwrap_or_else(|e| fail(&format!("{}: {}", path, e))));
+    }
+    if let Some(name) = &options.palette {
+        if !palettes.select(name) {
+            fail(&format!("unknown palette {}", name));
+        }
+    } else if let Some(name) = roms.first().and_then(|(_, data)| bootpalette::lookup(data)) {
+        // the colors a CGB would show the first game in
+        palettes.select(name);
+    }
+    let mut post = match &options.filter {
+        Some(spec) => PostProcess::parse(spec).unwrap_or_else(|e| fail(&e)),
+        None => PostProcess::default(),
+    };
+    if let Some(scale) = options.scale {
+        post.scale = scale;
+    }
+    if let Some(out_dir) = &options.report {
+        let (rom_path, rom_data) = &roms[0];
+        report::run(rom_path, rom_data, out_dir.as_ref(), &palettes, &post, options.skip_idle)
+            .unwrap_or_else(|e| fail(&format!("can't write report to {}: {}", out_dir, e)));
+        return;
+    }
+    if options.test {
+        let mut passed = true;
+        for (rom_path, rom_data) in &roms {
+            let result = testrom::run(rom_data, testrom::DEFAULT_TIMEOUT_FRAMES, options.skip_idle);
+            println!("{}: {:?}", rom_path, result);
+            passed &= result == testrom::TestResult::Passed;
+        }
+        process::exit(if passed { 0 } else { 1 });
+    }
+    if options.debug || options.stdin_input {
+        // --script doesn't work with either
+        let (mut session, _) = open_session(&roms, &options, save_dir, bootrom.as_deref()).unwrap_or_else(|e| fail(&e));
+        if options.debug {
+            debugger::run(&mut session, save_dir);
+        } else {
+            // driven by another program, as fast as it sends input
+            let mut stdout = io::stdout().lock();
+            let frames = options.raw_frames.then_some(pipeline::Frames {
+                out: &mut stdout,
+                palettes: &palettes,
+                post: &post,
+                input_display: options.input_display.map(InputDisplay::new),
+            });
+            if let Err(e) = pipeline::run(session.active_mut(), io::stdin().lock(), &config.keys, frames) {
+                fail(&e.to_string());
+            }
+        }
+        finish(&options, &mut session, save_dir);
+        return;
+    }
+    // stdin takes `sram export|import [file]`, `cheat ...`, `state save|load [file]`, `pause`,
+    // `resume`, `blur`, `focus`, `menu` and `quit` while playing, with several ROMs Enter
+    // or `next` switches to the next one, a number to that one. in the --arcade chooser a
+    // number plays that game
+    let (tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut pads = gamepad::Gamepads::open(config.gamepad_map());
+    // presenting on its own thread, a slow reader of stdout doesn't hold up emulation
+    let (frames, presenter) = match options.raw_frames {
+        true => {
+            let (producer, consumer) = triple_buffer(Vec::new());
+            let (width, height) = options.hardware.display_size();
+            let width = if options.two_player { 2 * width } else { width };
+            (Some(producer), Some(thread::spawn(move || present(consumer, &post, (width, height)))))
+        }
+        false => (None, None),
+    };
+    let (vram, vram_presenter) = match &options.vram_view {
+        Some(path) => {
+            let (producer, consumer) = triple_buffer(Vec::new());
+            let path = path.clone();
+            (Some(producer), Some(thread::spawn(move || present_vram(consumer, &path))))
+        }
+        None => (None, None),
+    };
+    // the session can't leave the thread it's made on (observers, scripts), so
+    // it's opened there. the presenters end when it drops the producers
+    let (commands, received) = mpsc::channel();
+    thread::scope(|scope| {
+        let core = scope.spawn(|| {
+            // moved in, a Receiver can't be shared
+            let received = received;
+            let mut outputs = emulation::Outputs { frames, vram };
+            if let Some(dir) = &options.arcade {
+                arcade::run(dir, &options, save_dir, bootrom.as_deref(), &palettes, &mut outputs, &received)
+                    .unwrap_or_else(|e| fail(&e));
+                return;
+            }
+            let (mut session, script) = open_session(&roms, &options, save_dir, bootrom.as_deref()).unwrap_or_else(|e| fail(&e));
+            Emulation::new(&mut session, script, &options, save_dir, &palettes, &mut outputs).run(&received);
+            finish(&options, &mut session, save_dir);
+        });
+        emulation::ui(&mut pads, lines, commands, options.two_player, &core);
+    });
+    for presenter in [presenter, vram_presenter].into_iter().flatten() {
+        presenter.join().ok();
     }
 }
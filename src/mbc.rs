@@ -0,0 +1,433 @@
+// cartridge mappers (MBCs): writes to ROM [0000-7FFF] switch the bank seen at
+// [4000-7FFF], cartridge RAM [A000-BFFF] is reached through them. cartridges
+// without one (types 00, 08, 09) keep bank 1 mapped, ignore ROM writes and have
+// a plain 8KiB of RAM. other mappers (MMM01, MBC6, MBC7, the camera, TAMA5 and
+// HuC) aren't emulated, GB::new refuses their cartridges
+//
+// MBC1 (01-03) - up to 128 banks and 4 banks of RAM. [0000-1FFF] enables RAM
+// with 0A in the lower nibble, [2000-3FFF] takes bits 0-4 of the ROM bank, 0
+// meaning 1, [4000-5FFF] two more bits, and [6000-7FFF] bit 0 the mode: in mode
+// 1 those two bits also pick the RAM bank and the bank at [0000-3FFF]
+//
+// MBC2 (cartridge types 05, 06) - up to 16 banks. writes to [0000-3FFF] pick
+// the register with address bit 8: clear enables RAM with 0A in the lower
+// nibble, set selects the ROM bank from the lower nibble, 0 meaning 1. its own
+// RAM is 512 half-bytes, repeated through [A000-BFFF], the upper nibble reads 1s
+//
+// MBC3 (0F-13) - up to 128 banks and 4 banks of RAM, 0F and 10 have a clock
+// (rtc.rs). [0000-1FFF] enables RAM and clock with 0A, [2000-3FFF] takes the
+// ROM bank, 0 meaning 1, [4000-5FFF] shows RAM bank 0-3 or clock register
+// 08-0C at [A000-BFFF], and writing 0 then 1 to [6000-7FFF] latches the clock
+//
+// MBC5 (19-1E) - up to 512 banks and 16 of RAM. [0000-1FFF] enables RAM,
+// [2000-2FFF] takes the lower 8 bits of the ROM bank, [3000-3FFF] bit 8, bank 0
+// is bank 0 here, and [4000-5FFF] the RAM bank
+
+use crate::cartridge::Header;
+use crate::error::Error;
+use crate::rtc::{Rtc, RtcMode};
+use crate::savestate::{Component, StateError, StateReader, StateWriter};
+
+pub const BANK_SIZE: usize = 0x4000;
+const MBC2_RAM: usize = 512;
+const RAM_BANK_SIZE: usize = 0x2000;
+// sixteen banks of MBC5
+pub const MAX_RAM: usize = 16 * RAM_BANK_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+impl Kind {
+    fn of(cartridge_type: u8) -> Option<Kind> {
+        match cartridge_type {
+            0x00 | 0x08 | 0x09 => Some(Kind::None),
+            0x01..=0x03 => Some(Kind::Mbc1),
+            0x05 | 0x06 => Some(Kind::Mbc2),
+            0x0f..=0x13 => Some(Kind::Mbc3),
+            0x19..=0x1e => Some(Kind::Mbc5),
+            _ => None,
+        }
+    }
+}
+
+pub struct Mbc {
+    kind: Kind,
+    // at [4000-7FFF], MBC1's lower 5 bits only
+    rom_bank: u16,
+    ram_enabled: bool,
+    // the RAM bank or MBC3 clock register at [A000-BFFF], MBC1's upper 2 bits
+    ram_bank: u8,
+    // MBC1's mode 1, its upper bits bank RAM and [0000-3FFF] too
+    mode: bool,
+    // what the header declares
+    ram_size: usize,
+    rtc: Option<Rtc>,
+}
+
+impl Default for Mbc {
+    fn default() -> Self {
+        Mbc { kind: Kind::None, rom_bank: 1, ram_enabled: false, ram_bank: 0, mode: false, ram_size: 0, rtc: None }
+    }
+}
+
+impl Mbc {
+    pub fn new(header: &Header) -> Result<Self, Error> {
+        let kind = Kind::of(header.cartridge_type).ok_or(Error::UnsupportedMbc(header.cartridge_type))?;
+        let rtc = matches!(header.cartridge_type, 0x0f | 0x10).then(Rtc::default);
+        Ok(Mbc { kind, ram_size: header.ram_size.min(MAX_RAM), rtc, ..Default::default() })
+    }
+
+    // whether a cartridge of this type can run
+    pub fn emulated(cartridge_type: u8) -> bool {
+        Kind::of(cartridge_type).is_some()
+    }
+
+    // at [4000-7FFF]
+    pub fn rom_bank(&self) -> usize {
+        match self.kind {
+            Kind::Mbc1 => (self.ram_bank as usize) << 5 | self.rom_bank as usize,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    // at [0000-3FFF]
+    pub fn rom0_bank(&self) -> usize {
+        match self.kind {
+            Kind::Mbc1 if self.mode => (self.ram_bank as usize) << 5,
+            _ => 0,
+        }
+    }
+
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_mode(mode);
+        }
+    }
+
+    // returns true when another ROM bank was mapped
+    pub fn write_rom(&mut self, addr: u16, val: u8) -> bool {
+        match self.kind {
+            Kind::None => false,
+            Kind::Mbc1 => {
+                let banks = (self.rom_bank(), self.rom0_bank());
+                match addr {
+                    0x0000..=0x1fff => self.ram_enabled = val & 0x0f == 0x0a,
+                    0x2000..=0x3fff => self.rom_bank = (val as u16 & 0x1f).max(1),
+                    0x4000..=0x5fff => self.ram_bank = val & 0x03,
+                    _ => self.mode = val & 0x01 != 0,
+                }
+                banks != (self.rom_bank(), self.rom0_bank())
+            }
+            Kind::Mbc2 if addr >= 0x4000 => false,
+            Kind::Mbc2 if addr & 0x0100 == 0 => {
+                self.ram_enabled = val & 0x0f == 0x0a;
+                false
+            }
+            Kind::Mbc2 => {
+                let bank = (val as u16 & 0x0f).max(1);
+                std::mem::replace(&mut self.rom_bank, bank) != bank
+            }
+            Kind::Mbc3 => match addr {
+                0x0000..=0x1fff => {
+                    self.ram_enabled = val & 0x0f == 0x0a;
+                    false
+                }
+                0x2000..=0x3fff => {
+                    let bank = (val as u16 & 0x7f).max(1);
+                    std::mem::replace(&mut self.rom_bank, bank) != bank
+                }
+                0x4000..=0x5fff => {
+                    self.ram_bank = val & 0x0f;
+                    false
+                }
+                _ => {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch(val);
+                    }
+                    false
+                }
+            },
+            Kind::Mbc5 => {
+                let bank = match addr {
+                    0x0000..=0x1fff => {
+                        self.ram_enabled = val & 0x0f == 0x0a;
+                        return false;
+                    }
+                    0x2000..=0x2fff => self.rom_bank & 0x100 | val as u16,
+                    0x3000..=0x3fff => (val as u16 & 0x01) << 8 | self.rom_bank & 0xff,
+                    0x4000..=0x5fff => {
+                        self.ram_bank = val & 0x0f;
+                        return false;
+                    }
+                    _ => return false,
+                };
+                std::mem::replace(&mut self.rom_bank, bank) != bank
+            }
+        }
+    }
+
+    // bytes of cartridge RAM, what battery saves hold
+    pub fn ram_len(&self) -> usize {
+        match self.kind {
+            Kind::None => 0x2000,
+            Kind::Mbc2 => MBC2_RAM,
+            Kind::Mbc1 | Kind::Mbc3 | Kind::Mbc5 => self.ram_size,
+        }
+    }
+
+    // into RAM, None when a clock register or nothing is mapped
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        let offset = (addr - 0xa000) as usize;
+        match self.kind {
+            Kind::Mbc3 if self.ram_bank > 3 || self.ram_size == 0 => None,
+            Kind::Mbc3 => Some((self.ram_bank as usize * RAM_BANK_SIZE + offset) % self.ram_size),
+            Kind::Mbc1 | Kind::Mbc5 if self.ram_size == 0 => None,
+            Kind::Mbc1 => Some((self.mode as usize * self.ram_bank as usize * RAM_BANK_SIZE + offset) % self.ram_size),
+            Kind::Mbc5 => Some((self.ram_bank as usize * RAM_BANK_SIZE + offset) % self.ram_size),
+            Kind::None | Kind::Mbc2 => Some(offset % self.ram_len()),
+        }
+    }
+
+    // the clock register mapped, if any
+    fn rtc_register(&self) -> Option<u8> {
+        (self.kind == Kind::Mbc3 && self.rtc.is_some() && (0x08..=0x0c).contains(&self.ram_bank)).then_some(self.ram_bank)
+    }
+
+    pub fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        match self.kind {
+            Kind::None => ram[addr as usize - 0xa000],
+            _ if !self.ram_enabled => 0xff,
+            Kind::Mbc2 => ram[self.ram_offset(addr).unwrap()] | 0xf0,
+            Kind::Mbc1 | Kind::Mbc5 => self.ram_offset(addr).map_or(0xff, |offset| ram[offset]),
+            Kind::Mbc3 => match (self.ram_offset(addr), self.rtc_register(), &self.rtc) {
+                (Some(offset), ..) => ram[offset],
+                (None, Some(register), Some(rtc)) => rtc.read(register),
+                _ => 0xff,
+            },
+        }
+    }
+
+    pub fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) {
+        if self.kind == Kind::None || self.ram_enabled {
+            self.poke_ram(ram, addr, val);
+        }
+    }
+
+    // even while RAM is disabled, for the debugger and cheats
+    pub fn poke_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) {
+        match (self.ram_offset(addr), self.rtc_register()) {
+            (Some(offset), _) if self.kind == Kind::Mbc2 => ram[offset] = val & 0x0f,
+            (Some(offset), _) => ram[offset] = val,
+            (None, Some(register)) => self.rtc.as_mut().unwrap().write(register, val),
+            (None, None) => {}
+        }
+    }
+}
+
+// the kind comes from the header, only the registers are state
+impl Component for Mbc {
+    const TAG: [u8; 4] = *b"MBC ";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.rom_bank);
+        w.bool(self.ram_enabled);
+        w.u8(self.ram_bank);
+        w.bool(self.mode);
+        w.bool(self.rtc.is_some());
+        if let Some(rtc) = &self.rtc {
+            rtc.save(w);
+        }
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.rom_bank = r.u16()?;
+        self.ram_enabled = r.bool()?;
+        self.ram_bank = r.u8()?;
+        self.mode = r.bool()?;
+        let saved_rtc = r.bool()?;
+        match (saved_rtc, &mut self.rtc) {
+            (true, Some(rtc)) => rtc.load(r)?,
+            // made with another ROM than the one loaded, which the machine chunk catches
+            (true, None) => Rtc::default().load(r)?,
+            (false, _) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::micro_rom;
+    use crate::savestate;
+    use crate::GB;
+
+    #[test]
+    fn mbc2_banks_rom_and_nibble_ram() {
+        // 128KiB (8 banks), each bank after 0 starts with its number
+        let mut rom = micro_rom(
+            "
+            ld a, $03
+            ld ($2100), a  ; bit 8 set, ROM bank 3
+            ld a, ($4000)
+            ld b, a
+            ld a, $0a
+            ld ($0000), a  ; bit 8 clear, RAM on
+            ld a, $5c
+            ld ($a000), a
+            ld a, ($a200)  ; same half-byte, repeated
+            ld c, a
+            ld a, $00
+            ld ($3100), a  ; bank 0 maps bank 1
+            ",
+        );
+        rom[0x0147] = 0x06;
+        rom[0x0148] = 0x02;
+        rom.resize(8 * 0x4000, 0);
+        for bank in 1..8 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        let mut gb = GB::new(&rom).unwrap();
+        assert_eq!(gb.read_memory(0x4000, 1), [1]);
+        assert_eq!(gb.read_memory(0xa000, 1), [0xff], "RAM disabled");
+        for _ in 0..10 {
+            gb.cycle();
+        }
+        assert_eq!((gb.z80.b, gb.z80.c), (3, 0xfc));
+        assert_eq!(gb.cartridge_ram().len(), 512);
+        assert_eq!(gb.cartridge_ram()[0], 0x0c);
+
+        let state = savestate::save(&gb);
+        for _ in 0..2 {
+            gb.cycle();
+        }
+        assert_eq!(gb.read_memory(0x4000, 1), [1]);
+        savestate::load(&mut gb, &state).unwrap();
+        assert_eq!(gb.read_memory(0x4000, 1), [3]);
+    }
+
+    #[test]
+    fn mbc3_banks_ram_and_latches_the_clock() {
+        let mut rom = micro_rom(
+            "
+            ld a, $45
+            ld ($2000), a  ; ROM bank 69
+            ld a, $0a
+            ld ($0000), a
+            ld a, $02
+            ld ($4000), a  ; RAM bank 2
+            ld a, $77
+            ld ($a000), a
+            ld a, $0a
+            ld ($4000), a  ; hours
+            ld a, $05
+            ld ($a000), a
+            xor a
+            ld ($6000), a
+            inc a
+            ld ($6000), a  ; latch
+            ld a, ($a000)
+            ld b, a
+            ",
+        );
+        rom[0x0147] = 0x10;
+        rom[0x0148] = 0x06;
+        rom[0x0149] = 0x03;
+        rom.resize(128 * 0x4000, 0);
+        rom[69 * 0x4000] = 69;
+        let mut gb = GB::new(&rom).unwrap();
+        for _ in 0..18 {
+            gb.cycle();
+        }
+        assert_eq!(gb.read_memory(0x4000, 1), [69]);
+        assert_eq!(gb.z80.b, 5);
+        assert_eq!(gb.cartridge_ram().len(), 0x8000);
+        assert_eq!(gb.cartridge_ram()[2 * 0x2000], 0x77);
+        let footer = gb.rtc_footer().unwrap();
+        assert_eq!((footer[8], footer[28]), (5, 5));
+
+        let state = savestate::save(&gb);
+        gb.mmu.mbc.rtc_mut().unwrap().write(0x0a, 9);
+        savestate::load(&mut gb, &state).unwrap();
+        assert_eq!(gb.rtc_footer().unwrap()[8], 5);
+        assert_eq!(gb.read_memory(0xa000, 1), [5]);
+    }
+
+    #[test]
+    fn mbc1_banks_rom_ram_and_bank_0_in_mode_1() {
+        let mut rom = micro_rom(
+            "
+            ld a, $00
+            ld ($2000), a  ; bank 0 maps bank 1
+            ld a, ($4000)
+            ld b, a
+            ld a, $05
+            ld ($2000), a
+            ld a, $01
+            ld ($4000), a  ; upper bits, bank 37
+            ld a, ($4000)
+            ld c, a
+            ",
+        );
+        rom[0x0147] = 0x03;
+        rom[0x0148] = 0x06;
+        rom[0x0149] = 0x03;
+        rom.resize(128 * 0x4000, 0);
+        for bank in 1..128 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        let mut gb = GB::new(&rom).unwrap();
+        for _ in 0..10 {
+            gb.cycle();
+        }
+        assert_eq!((gb.z80.b, gb.z80.c), (1, 37));
+        // mode 1 maps RAM bank 1, and bank 32 at [0000-3FFF] from under the code
+        gb.mmu.wb(0x0000, 0x0a);
+        gb.mmu.wb(0x6000, 0x01);
+        gb.mmu.wb(0xa000, 0x66);
+        assert_eq!(gb.cartridge_ram()[0x2000], 0x66);
+        assert_eq!(gb.read_memory(0x0000, 1), [32]);
+
+        let state = savestate::save(&gb);
+        gb.mmu.wb(0x6000, 0);
+        assert_eq!(gb.read_memory(0x0000, 1), [rom[0]]);
+        savestate::load(&mut gb, &state).unwrap();
+        assert_eq!(gb.read_memory(0x0000, 2), [32, 0]);
+        assert_eq!(gb.read_memory(0x4000, 1), [37]);
+    }
+
+    #[test]
+    fn mbc5_banks_nine_bits_and_bank_0() {
+        let mut rom = micro_rom("nop");
+        rom[0x0147] = 0x1b;
+        rom[0x0148] = 0x08;
+        rom[0x0149] = 0x04;
+        rom.resize(512 * 0x4000, 0);
+        for bank in 0..512 {
+            rom[bank * 0x4000 + 1] = (bank >> 1) as u8;
+        }
+        let mut gb = GB::new(&rom).unwrap();
+        gb.mmu.wb(0x2000, 0x00);
+        assert_eq!(gb.read_memory(0x4001, 1), [0], "bank 0 is bank 0");
+        gb.mmu.wb(0x2000, 0xfe);
+        gb.mmu.wb(0x3000, 0x01);
+        assert_eq!(gb.read_memory(0x4001, 1), [0xff], "bank 510");
+        gb.mmu.wb(0x0000, 0x0a);
+        gb.mmu.wb(0x4000, 0x0f);
+        gb.mmu.wb(0xa000, 0x99);
+        assert_eq!(gb.cartridge_ram().len(), 0x20000);
+        assert_eq!(gb.cartridge_ram()[15 * 0x2000], 0x99);
+    }
+}
@@ -0,0 +1,118 @@
+// cheat-search style scanner over WRAM and HRAM, entered with `search` in the
+// debugger. every search narrows the candidates down and remembers their values,
+// so the next one can ask which of them went up, down or stayed the same
+
+use std::ops::RangeInclusive;
+
+use crate::GB;
+
+const SEARCHED: [RangeInclusive<u16>; 2] = [0xc000..=0xdfff, 0xff80..=0xfffe];
+
+// candidates printed by `search` without arguments
+const SHOWN: usize = 16;
+
+pub const HELP: &str = "\
+search commands (values in hex):
+  search start           remember all of WRAM and HRAM to compare against
+  search <val>           keep addresses holding val
+  search inc|dec         keep addresses whose value went up / down since the last search
+  search same|changed    keep addresses whose value stayed / didn't stay the same
+  search                 list candidates with their last and current value";
+
+#[derive(Default)]
+pub struct MemorySearch {
+    // address and value at the last search, None before the first
+    candidates: Option<Vec<(u16, u8)>>,
+}
+
+impl MemorySearch {
+    pub fn command(&mut self, gb: &GB, args: &[&str]) -> Result<String, String> {
+        let keep: Box<dyn Fn(u8, u8) -> bool> = match args.first().copied() {
+            None | Some("list") => return Ok(self.list(gb)),
+            Some("help") => return Ok(HELP.to_string()),
+            Some("start" | "reset") => {
+                self.candidates = Some(all(gb));
+                return Ok(format!("{} candidates", self.len()));
+            }
+            Some("inc") => Box::new(|old, new| new > old),
+            Some("dec") => Box::new(|old, new| new < old),
+            Some("same") => Box::new(|old, new| new == old),
+            Some("changed") => Box::new(|old, new| new != old),
+            Some(word) => {
+                let val = word.strip_prefix("0x").or_else(|| word.strip_prefix('$')).unwrap_or(word);
+                let val = u8::from_str_radix(val, 16).map_err(|_| format!("invalid value `{}`, try `search help`", word))?;
+                Box::new(move |_, new| new == val)
+            }
+        };
+        let exact = !matches!(args[0], "inc" | "dec" | "same" | "changed");
+        let candidates = match self.candidates.take() {
+            Some(candidates) => candidates,
+            None if exact => all(gb),
+            None => return Err("nothing to compare against yet, `search start` first".to_string()),
+        };
+        let kept = candidates
+            .into_iter()
+            .filter_map(|(addr, old)| {
+                let new = gb.mmu.peek(addr);
+                keep(old, new).then_some((addr, new))
+            })
+            .collect();
+        self.candidates = Some(kept);
+        Ok(self.list(gb))
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.as_ref().map_or(0, Vec::len)
+    }
+
+    fn list(&self, gb: &GB) -> String {
+        let Some(candidates) = &self.candidates else {
+            return "no search yet, `search help` lists commands".to_string();
+        };
+        let mut text = format!("{} candidates", candidates.len());
+        for &(addr, old) in candidates.iter().take(SHOWN) {
+            text += &format!("\n  {:04X}: {:02X} -> {:02X}", addr, old, gb.mmu.peek(addr));
+        }
+        if candidates.len() > SHOWN {
+            text += "\n  ...";
+        }
+        text
+    }
+}
+
+fn all(gb: &GB) -> Vec<(u16, u8)> {
+    SEARCHED.iter().flat_map(|range| range.clone()).map(|addr| (addr, gb.mmu.peek(addr))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+
+    #[test]
+    fn narrows_down_a_counter() {
+        // C123 counts up, C200 holds the same value
+        let rom = micro_rom("ld a, $07\nld ($c200), a\nld hl, $c123\nloop:\ninc (hl)\njr loop");
+        let mut gb = GB::new(&rom).unwrap();
+        let mut search = MemorySearch::default();
+        assert!(search.command(&gb, &["inc"]).is_err());
+        for _ in 0..3 {
+            gb.cycle();
+        }
+        search.command(&gb, &["start"]).unwrap();
+        assert_eq!(search.len(), 0x2000 + 0x7f);
+        for _ in 0..4 {
+            gb.cycle();
+        }
+        search.command(&gb, &["inc"]).unwrap();
+        assert_eq!(search.len(), 1);
+        assert!(search.command(&gb, &[]).unwrap().ends_with("C123: 02 -> 02"));
+        search.command(&gb, &["same"]).unwrap();
+        assert_eq!(search.len(), 1);
+
+        let mut exact = MemorySearch::default();
+        let text = exact.command(&gb, &["$07"]).unwrap();
+        assert_eq!(text, "1 candidates\n  C200: 07 -> 07");
+        assert!(exact.command(&gb, &["zz"]).is_err());
+    }
+}
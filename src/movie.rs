@@ -0,0 +1,136 @@
+// TAS movies: the savestate a recording began in and the buttons held in every
+// frame after it. emulation is deterministic, so playing them back from that
+// state repeats the run exactly, as long as nothing outside the movie (link
+// cable, cheats, stdin commands) changes the game. frames end at VBlank, or
+// after a frame's worth of time while the LCD is off
+//
+// file: "GBRM" | version u16 | state length u32 | savestate | one Buttons byte per frame
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::Buttons;
+
+const MAGIC: &[u8; 4] = b"GBRM";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    state: Vec<u8>,
+    frames: Vec<Buttons>,
+}
+
+// what GB does with a movie, see GB::record_movie / play_movie
+pub(crate) enum Deck {
+    Recording(Movie),
+    // index into frames of the buttons for the frame after the current one
+    Playing { movie: Movie, next: usize },
+}
+
+impl Movie {
+    pub(crate) fn new(state: Vec<u8>) -> Self {
+        Movie { state, frames: Vec::new() }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Movie, String> {
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return Err("not a gb-rust movie (missing GBRM header)".to_string());
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != VERSION {
+            return Err(format!("movie format version {} isn't supported, only {}", version, VERSION));
+        }
+        let len = u32::from_le_bytes([data[6], data[7], data[8], data[9]]) as usize;
+        let Some(state) = data.get(HEADER_LEN..HEADER_LEN + len) else {
+            return Err("movie is damaged: savestate cut short".to_string());
+        };
+        let frames = data[HEADER_LEN + len..].iter().map(|&b| Buttons::from_bits_truncate(b)).collect();
+        Ok(Movie { state: state.to_vec(), frames })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.state.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.state);
+        out.extend(self.frames.iter().map(|b| b.bits()));
+        out
+    }
+
+    pub fn state(&self) -> &[u8] {
+        &self.state
+    }
+
+    // buttons held in each frame, in order
+    pub fn frames(&self) -> &[Buttons] {
+        &self.frames
+    }
+
+    pub(crate) fn push(&mut self, buttons: Buttons) {
+        self.frames.push(buttons);
+    }
+}
+
+pub fn load(path: &Path) -> Result<Movie, String> {
+    let data = fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    Movie::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn save(path: &Path, movie: &Movie) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, movie.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::GB;
+
+    // adds up the d-pad bits of every P1 read at C000
+    const SUMS_PAD: &str = "
+        ld a, $91
+        ldh ($40), a
+        ld hl, $c000
+        loop:
+        ld a, $20
+        ldh ($00), a
+        ldh a, ($00)
+        add a, (hl)
+        ld (hl), a
+        jr loop
+        ";
+
+    #[test]
+    fn plays_back_what_was_recorded() {
+        let rom = micro_rom(SUMS_PAD);
+        let mut gb = GB::new(&rom).unwrap();
+        gb.run_frames(2);
+        gb.record_movie();
+        for buttons in [Buttons::RIGHT, Buttons::empty(), Buttons::UP | Buttons::LEFT, Buttons::DOWN] {
+            gb.set_buttons(buttons);
+            gb.run_frames(1);
+        }
+        let movie = gb.stop_movie().unwrap();
+        assert_eq!(movie.frames().len(), 4);
+        assert_eq!(movie.frames()[2], Buttons::UP | Buttons::LEFT);
+        let expected = (gb.read_memory(0xc000, 1), gb.cycles_elapsed(), gb.buttons());
+
+        let movie = Movie::parse(&movie.to_bytes()).unwrap();
+        let mut replay = GB::new(&rom).unwrap();
+        replay.play_movie(movie).unwrap();
+        assert!(replay.movie_playing());
+        // live input is ignored until the movie ends
+        replay.set_buttons(Buttons::A);
+        replay.run_frames(4);
+        assert_eq!((replay.read_memory(0xc000, 1), replay.cycles_elapsed(), replay.buttons()), expected);
+        assert!(!replay.movie_playing());
+
+        assert!(Movie::parse(b"GBRM\x01\x00\xff\x00\x00\x00").is_err());
+        assert!(Movie::parse(b"GBRS").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// hooks for tools built on the emulator (profilers, achievement trackers,
+// bots) to watch it run without patching it. GB::add_observer registers one,
+// every hook has an empty default so an observer only writes what it needs.
+// with none registered all that runs is a check of an empty list. polling
+// loops GB::set_skip_idle fast-forwards through don't reach on_instruction
+//
+// hooks see values, not the GB: an observer that wants to act on what it saw
+// shares state with the frontend, e.g. through an Rc<RefCell<_>>
+
+pub trait Observer {
+    // right before the CPU fetches opcode at pc
+    fn on_instruction(&mut self, _pc: u16, _opcode: u8) {}
+    // a write by the CPU, after it happened
+    fn on_memory_write(&mut self, _addr: u16, _val: u8) {}
+    // at VBlank, shades 0-3 of the finished frame, ppu::WIDTH x ppu::HEIGHT
+    fn on_frame(&mut self, _framebuffer: &[u8]) {}
+    // a serial transfer finished, the byte shifted out and the one shifted in
+    fn on_serial_byte(&mut self, _sent: u8, _received: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::serial::OutputCapture;
+    use crate::GB;
+
+    #[derive(Default)]
+    struct Seen {
+        instructions: Vec<(u16, u8)>,
+        writes: Vec<(u16, u8)>,
+        frames: usize,
+        serial: Vec<(u8, u8)>,
+    }
+
+    struct Recorder(Rc<RefCell<Seen>>);
+
+    impl Observer for Recorder {
+        fn on_instruction(&mut self, pc: u16, opcode: u8) {
+            self.0.borrow_mut().instructions.push((pc, opcode));
+        }
+        fn on_memory_write(&mut self, addr: u16, val: u8) {
+            self.0.borrow_mut().writes.push((addr, val));
+        }
+        fn on_frame(&mut self, framebuffer: &[u8]) {
+            assert_eq!(framebuffer.len(), crate::ppu::WIDTH * crate::ppu::HEIGHT);
+            self.0.borrow_mut().frames += 1;
+        }
+        fn on_serial_byte(&mut self, sent: u8, received: u8) {
+            self.0.borrow_mut().serial.push((sent, received));
+        }
+    }
+
+    #[test]
+    fn observers_see_the_game_run() {
+        let rom = micro_rom("ld a, $91\nldh ($40), a\nld a, $42\nldh ($01), a\nld a, $81\nldh ($02), a\nld ($c000), a\nloop: jr loop");
+        let mut gb = GB::new(&rom).unwrap();
+        gb.connect_serial(Box::new(OutputCapture::default()));
+        let seen = Rc::new(RefCell::new(Seen::default()));
+        gb.add_observer(Box::new(Recorder(seen.clone())));
+        gb.run_frames(2);
+
+        let seen = seen.borrow();
+        assert_eq!(seen.instructions[..3], [(0x0100, 0x3e), (0x0102, 0xe0), (0x0104, 0x3e)]);
+        assert_eq!(seen.writes, [(0xff40, 0x91), (0xff01, 0x42), (0xff02, 0x81), (0xc000, 0x81)]);
+        assert_eq!(seen.frames, 2);
+        // the capture answers FF
+        assert_eq!(seen.serial, [(0x42, 0xff)]);
+    }
+}
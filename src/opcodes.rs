@@ -0,0 +1,554 @@
+// SM83 opcode table shared by interpreter, assembler, disassembler and tracer
+//
+// mnemonic operands: d8 / d16 immediate data, a8 / a16 address, r8 signed offset
+// empty mnemonic marks opcodes which do not exist on the SM83
+
+use crate::cpu;
+
+// executes the opcode, which is passed along so related opcodes can share one
+// handler decoding registers from its bits. PC already points past the opcode
+pub type Handler = fn(&mut dyn cpu::Core, u8);
+
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    // bytes including opcode (and CB prefix)
+    pub length: u8,
+    // t-cycles, conditional instructions list the branch not taken case
+    pub cycles: u8,
+    // t-cycles when a conditional branch is taken, same as cycles otherwise
+    pub cycles_taken: u8,
+    pub handler: Handler,
+}
+
+const fn op(mnemonic: &'static str, length: u8, cycles: u8, handler: Handler) -> Opcode {
+    branch(mnemonic, length, cycles, cycles, handler)
+}
+
+const fn branch(mnemonic: &'static str, length: u8, cycles: u8, cycles_taken: u8, handler: Handler) -> Opcode {
+    Opcode {
+        mnemonic,
+        length,
+        cycles,
+        cycles_taken,
+        handler,
+    }
+}
+
+pub const OPCODES: [Opcode; 256] = [
+    op("NOP", 1, 4, cpu::nop), // 0x00
+    op("LD BC,d16", 3, 12, cpu::ld_rr_d16), // 0x01
+    op("LD (BC),A", 1, 8, cpu::ld_ind_a), // 0x02
+    op("INC BC", 1, 8, cpu::inc_rr), // 0x03
+    op("INC B", 1, 4, cpu::inc_r), // 0x04
+    op("DEC B", 1, 4, cpu::dec_r), // 0x05
+    op("LD B,d8", 2, 8, cpu::ld_r_d8), // 0x06
+    op("RLCA", 1, 4, cpu::rlca), // 0x07
+    op("LD (a16),SP", 3, 20, cpu::ld_a16_sp), // 0x08
+    op("ADD HL,BC", 1, 8, cpu::add_hl_rr), // 0x09
+    op("LD A,(BC)", 1, 8, cpu::ld_a_ind), // 0x0a
+    op("DEC BC", 1, 8, cpu::dec_rr), // 0x0b
+    op("INC C", 1, 4, cpu::inc_r), // 0x0c
+    op("DEC C", 1, 4, cpu::dec_r), // 0x0d
+    op("LD C,d8", 2, 8, cpu::ld_r_d8), // 0x0e
+    op("RRCA", 1, 4, cpu::rrca), // 0x0f
+    op("STOP d8", 2, 4, cpu::stop), // 0x10
+    op("LD DE,d16", 3, 12, cpu::ld_rr_d16), // 0x11
+    op("LD (DE),A", 1, 8, cpu::ld_ind_a), // 0x12
+    op("INC DE", 1, 8, cpu::inc_rr), // 0x13
+    op("INC D", 1, 4, cpu::inc_r), // 0x14
+    op("DEC D", 1, 4, cpu::dec_r), // 0x15
+    op("LD D,d8", 2, 8, cpu::ld_r_d8), // 0x16
+    op("RLA", 1, 4, cpu::rla), // 0x17
+    op("JR r8", 2, 12, cpu::jr), // 0x18
+    op("ADD HL,DE", 1, 8, cpu::add_hl_rr), // 0x19
+    op("LD A,(DE)", 1, 8, cpu::ld_a_ind), // 0x1a
+    op("DEC DE", 1, 8, cpu::dec_rr), // 0x1b
+    op("INC E", 1, 4, cpu::inc_r), // 0x1c
+    op("DEC E", 1, 4, cpu::dec_r), // 0x1d
+    op("LD E,d8", 2, 8, cpu::ld_r_d8), // 0x1e
+    op("RRA", 1, 4, cpu::rra), // 0x1f
+    branch("JR NZ,r8", 2, 8, 12, cpu::jr_cc), // 0x20
+    op("LD HL,d16", 3, 12, cpu::ld_rr_d16), // 0x21
+    op("LD (HL+),A", 1, 8, cpu::ld_ind_a), // 0x22
+    op("INC HL", 1, 8, cpu::inc_rr), // 0x23
+    op("INC H", 1, 4, cpu::inc_r), // 0x24
+    op("DEC H", 1, 4, cpu::dec_r), // 0x25
+    op("LD H,d8", 2, 8, cpu::ld_r_d8), // 0x26
+    op("DAA", 1, 4, cpu::daa), // 0x27
+    branch("JR Z,r8", 2, 8, 12, cpu::jr_cc), // 0x28
+    op("ADD HL,HL", 1, 8, cpu::add_hl_rr), // 0x29
+    op("LD A,(HL+)", 1, 8, cpu::ld_a_ind), // 0x2a
+    op("DEC HL", 1, 8, cpu::dec_rr), // 0x2b
+    op("INC L", 1, 4, cpu::inc_r), // 0x2c
+    op("DEC L", 1, 4, cpu::dec_r), // 0x2d
+    op("LD L,d8", 2, 8, cpu::ld_r_d8), // 0x2e
+    op("CPL", 1, 4, cpu::cpl), // 0x2f
+    branch("JR NC,r8", 2, 8, 12, cpu::jr_cc), // 0x30
+    op("LD SP,d16", 3, 12, cpu::ld_rr_d16), // 0x31
+    op("LD (HL-),A", 1, 8, cpu::ld_ind_a), // 0x32
+    op("INC SP", 1, 8, cpu::inc_rr), // 0x33
+    op("INC (HL)", 1, 12, cpu::inc_r), // 0x34
+    op("DEC (HL)", 1, 12, cpu::dec_r), // 0x35
+    op("LD (HL),d8", 2, 12, cpu::ld_r_d8), // 0x36
+    op("SCF", 1, 4, cpu::scf), // 0x37
+    branch("JR C,r8", 2, 8, 12, cpu::jr_cc), // 0x38
+    op("ADD HL,SP", 1, 8, cpu::add_hl_rr), // 0x39
+    op("LD A,(HL-)", 1, 8, cpu::ld_a_ind), // 0x3a
+    op("DEC SP", 1, 8, cpu::dec_rr), // 0x3b
+    op("INC A", 1, 4, cpu::inc_r), // 0x3c
+    op("DEC A", 1, 4, cpu::dec_r), // 0x3d
+    op("LD A,d8", 2, 8, cpu::ld_r_d8), // 0x3e
+    op("CCF", 1, 4, cpu::ccf), // 0x3f
+    op("LD B,B", 1, 4, cpu::ld_r_r), // 0x40
+    op("LD B,C", 1, 4, cpu::ld_r_r), // 0x41
+    op("LD B,D", 1, 4, cpu::ld_r_r), // 0x42
+    op("LD B,E", 1, 4, cpu::ld_r_r), // 0x43
+    op("LD B,H", 1, 4, cpu::ld_r_r), // 0x44
+    op("LD B,L", 1, 4, cpu::ld_r_r), // 0x45
+    op("LD B,(HL)", 1, 8, cpu::ld_r_r), // 0x46
+    op("LD B,A", 1, 4, cpu::ld_r_r), // 0x47
+    op("LD C,B", 1, 4, cpu::ld_r_r), // 0x48
+    op("LD C,C", 1, 4, cpu::ld_r_r), // 0x49
+    op("LD C,D", 1, 4, cpu::ld_r_r), // 0x4a
+    op("LD C,E", 1, 4, cpu::ld_r_r), // 0x4b
+    op("LD C,H", 1, 4, cpu::ld_r_r), // 0x4c
+    op("LD C,L", 1, 4, cpu::ld_r_r), // 0x4d
+    op("LD C,(HL)", 1, 8, cpu::ld_r_r), // 0x4e
+    op("LD C,A", 1, 4, cpu::ld_r_r), // 0x4f
+    op("LD D,B", 1, 4, cpu::ld_r_r), // 0x50
+    op("LD D,C", 1, 4, cpu::ld_r_r), // 0x51
+    op("LD D,D", 1, 4, cpu::ld_r_r), // 0x52
+    op("LD D,E", 1, 4, cpu::ld_r_r), // 0x53
+    op("LD D,H", 1, 4, cpu::ld_r_r), // 0x54
+    op("LD D,L", 1, 4, cpu::ld_r_r), // 0x55
+    op("LD D,(HL)", 1, 8, cpu::ld_r_r), // 0x56
+    op("LD D,A", 1, 4, cpu::ld_r_r), // 0x57
+    op("LD E,B", 1, 4, cpu::ld_r_r), // 0x58
+    op("LD E,C", 1, 4, cpu::ld_r_r), // 0x59
+    op("LD E,D", 1, 4, cpu::ld_r_r), // 0x5a
+    op("LD E,E", 1, 4, cpu::ld_r_r), // 0x5b
+    op("LD E,H", 1, 4, cpu::ld_r_r), // 0x5c
+    op("LD E,L", 1, 4, cpu::ld_r_r), // 0x5d
+    op("LD E,(HL)", 1, 8, cpu::ld_r_r), // 0x5e
+    op("LD E,A", 1, 4, cpu::ld_r_r), // 0x5f
+    op("LD H,B", 1, 4, cpu::ld_r_r), // 0x60
+    op("LD H,C", 1, 4, cpu::ld_r_r), // 0x61
+    op("LD H,D", 1, 4, cpu::ld_r_r), // 0x62
+    op("LD H,E", 1, 4, cpu::ld_r_r), // 0x63
+    op("LD H,H", 1, 4, cpu::ld_r_r), // 0x64
+    op("LD H,L", 1, 4, cpu::ld_r_r), // 0x65
+    op("LD H,(HL)", 1, 8, cpu::ld_r_r), // 0x66
+    op("LD H,A", 1, 4, cpu::ld_r_r), // 0x67
+    op("LD L,B", 1, 4, cpu::ld_r_r), // 0x68
+    op("LD L,C", 1, 4, cpu::ld_r_r), // 0x69
+    op("LD L,D", 1, 4, cpu::ld_r_r), // 0x6a
+    op("LD L,E", 1, 4, cpu::ld_r_r), // 0x6b
+    op("LD L,H", 1, 4, cpu::ld_r_r), // 0x6c
+    op("LD L,L", 1, 4, cpu::ld_r_r), // 0x6d
+    op("LD L,(HL)", 1, 8, cpu::ld_r_r), // 0x6e
+    op("LD L,A", 1, 4, cpu::ld_r_r), // 0x6f
+    op("LD (HL),B", 1, 8, cpu::ld_r_r), // 0x70
+    op("LD (HL),C", 1, 8, cpu::ld_r_r), // 0x71
+    op("LD (HL),D", 1, 8, cpu::ld_r_r), // 0x72
+    op("LD (HL),E", 1, 8, cpu::ld_r_r), // 0x73
+    op("LD (HL),H", 1, 8, cpu::ld_r_r), // 0x74
+    op("LD (HL),L", 1, 8, cpu::ld_r_r), // 0x75
+    op("HALT", 1, 4, cpu::halt), // 0x76
+    op("LD (HL),A", 1, 8, cpu::ld_r_r), // 0x77
+    op("LD A,B", 1, 4, cpu::ld_r_r), // 0x78
+    op("LD A,C", 1, 4, cpu::ld_r_r), // 0x79
+    op("LD A,D", 1, 4, cpu::ld_r_r), // 0x7a
+    op("LD A,E", 1, 4, cpu::ld_r_r), // 0x7b
+    op("LD A,H", 1, 4, cpu::ld_r_r), // 0x7c
+    op("LD A,L", 1, 4, cpu::ld_r_r), // 0x7d
+    op("LD A,(HL)", 1, 8, cpu::ld_r_r), // 0x7e
+    op("LD A,A", 1, 4, cpu::ld_r_r), // 0x7f
+    op("ADD A,B", 1, 4, cpu::alu_r), // 0x80
+    op("ADD A,C", 1, 4, cpu::alu_r), // 0x81
+    op("ADD A,D", 1, 4, cpu::alu_r), // 0x82
+    op("ADD A,E", 1, 4, cpu::alu_r), // 0x83
+    op("ADD A,H", 1, 4, cpu::alu_r), // 0x84
+    op("ADD A,L", 1, 4, cpu::alu_r), // 0x85
+    op("ADD A,(HL)", 1, 8, cpu::alu_r), // 0x86
+    op("ADD A,A", 1, 4, cpu::alu_r), // 0x87
+    op("ADC A,B", 1, 4, cpu::alu_r), // 0x88
+    op("ADC A,C", 1, 4, cpu::alu_r), // 0x89
+    op("ADC A,D", 1, 4, cpu::alu_r), // 0x8a
+    op("ADC A,E", 1, 4, cpu::alu_r), // 0x8b
+    op("ADC A,H", 1, 4, cpu::alu_r), // 0x8c
+    op("ADC A,L", 1, 4, cpu::alu_r), // 0x8d
+    op("ADC A,(HL)", 1, 8, cpu::alu_r), // 0x8e
+    op("ADC A,A", 1, 4, cpu::alu_r), // 0x8f
+    op("SUB B", 1, 4, cpu::alu_r), // 0x90
+    op("SUB C", 1, 4, cpu::alu_r), // 0x91
+    op("SUB D", 1, 4, cpu::alu_r), // 0x92
+    op("SUB E", 1, 4, cpu::alu_r), // 0x93
+    op("SUB H", 1, 4, cpu::alu_r), // 0x94
+    op("SUB L", 1, 4, cpu::alu_r), // 0x95
+    op("SUB (HL)", 1, 8, cpu::alu_r), // 0x96
+    op("SUB A", 1, 4, cpu::alu_r), // 0x97
+    op("SBC A,B", 1, 4, cpu::alu_r), // 0x98
+    op("SBC A,C", 1, 4, cpu::alu_r), // 0x99
+    op("SBC A,D", 1, 4, cpu::alu_r), // 0x9a
+    op("SBC A,E", 1, 4, cpu::alu_r), // 0x9b
+    op("SBC A,H", 1, 4, cpu::alu_r), // 0x9c
+    op("SBC A,L", 1, 4, cpu::alu_r), // 0x9d
+    op("SBC A,(HL)", 1, 8, cpu::alu_r), // 0x9e
+    op("SBC A,A", 1, 4, cpu::alu_r), // 0x9f
+    op("AND B", 1, 4, cpu::alu_r), // 0xa0
+    op("AND C", 1, 4, cpu::alu_r), // 0xa1
+    op("AND D", 1, 4, cpu::alu_r), // 0xa2
+    op("AND E", 1, 4, cpu::alu_r), // 0xa3
+    op("AND H", 1, 4, cpu::alu_r), // 0xa4
+    op("AND L", 1, 4, cpu::alu_r), // 0xa5
+    op("AND (HL)", 1, 8, cpu::alu_r), // 0xa6
+    op("AND A", 1, 4, cpu::alu_r), // 0xa7
+    op("XOR B", 1, 4, cpu::alu_r), // 0xa8
+    op("XOR C", 1, 4, cpu::alu_r), // 0xa9
+    op("XOR D", 1, 4, cpu::alu_r), // 0xaa
+    op("XOR E", 1, 4, cpu::alu_r), // 0xab
+    op("XOR H", 1, 4, cpu::alu_r), // 0xac
+    op("XOR L", 1, 4, cpu::alu_r), // 0xad
+    op("XOR (HL)", 1, 8, cpu::alu_r), // 0xae
+    op("XOR A", 1, 4, cpu::alu_r), // 0xaf
+    op("OR B", 1, 4, cpu::alu_r), // 0xb0
+    op("OR C", 1, 4, cpu::alu_r), // 0xb1
+    op("OR D", 1, 4, cpu::alu_r), // 0xb2
+    op("OR E", 1, 4, cpu::alu_r), // 0xb3
+    op("OR H", 1, 4, cpu::alu_r), // 0xb4
+    op("OR L", 1, 4, cpu::alu_r), // 0xb5
+    op("OR (HL)", 1, 8, cpu::alu_r), // 0xb6
+    op("OR A", 1, 4, cpu::alu_r), // 0xb7
+    op("CP B", 1, 4, cpu::alu_r), // 0xb8
+    op("CP C", 1, 4, cpu::alu_r), // 0xb9
+    op("CP D", 1, 4, cpu::alu_r), // 0xba
+    op("CP E", 1, 4, cpu::alu_r), // 0xbb
+    op("CP H", 1, 4, cpu::alu_r), // 0xbc
+    op("CP L", 1, 4, cpu::alu_r), // 0xbd
+    op("CP (HL)", 1, 8, cpu::alu_r), // 0xbe
+    op("CP A", 1, 4, cpu::alu_r), // 0xbf
+    branch("RET NZ", 1, 8, 20, cpu::ret_cc), // 0xc0
+    op("POP BC", 1, 12, cpu::pop), // 0xc1
+    branch("JP NZ,a16", 3, 12, 16, cpu::jp_cc), // 0xc2
+    op("JP a16", 3, 16, cpu::jp), // 0xc3
+    branch("CALL NZ,a16", 3, 12, 24, cpu::call_cc), // 0xc4
+    op("PUSH BC", 1, 16, cpu::push), // 0xc5
+    op("ADD A,d8", 2, 8, cpu::alu_d8), // 0xc6
+    op("RST $00", 1, 16, cpu::rst), // 0xc7
+    branch("RET Z", 1, 8, 20, cpu::ret_cc), // 0xc8
+    op("RET", 1, 16, cpu::ret), // 0xc9
+    branch("JP Z,a16", 3, 12, 16, cpu::jp_cc), // 0xca
+    op("PREFIX CB", 1, 4, cpu::prefix_cb), // 0xcb
+    branch("CALL Z,a16", 3, 12, 24, cpu::call_cc), // 0xcc
+    op("CALL a16", 3, 24, cpu::call), // 0xcd
+    op("ADC A,d8", 2, 8, cpu::alu_d8), // 0xce
+    op("RST $08", 1, 16, cpu::rst), // 0xcf
+    branch("RET NC", 1, 8, 20, cpu::ret_cc), // 0xd0
+    op("POP DE", 1, 12, cpu::pop), // 0xd1
+    branch("JP NC,a16", 3, 12, 16, cpu::jp_cc), // 0xd2
+    op("", 1, 4, cpu::illegal), // 0xd3
+    branch("CALL NC,a16", 3, 12, 24, cpu::call_cc), // 0xd4
+    op("PUSH DE", 1, 16, cpu::push), // 0xd5
+    op("SUB d8", 2, 8, cpu::alu_d8), // 0xd6
+    op("RST $10", 1, 16, cpu::rst), // 0xd7
+    branch("RET C", 1, 8, 20, cpu::ret_cc), // 0xd8
+    op("RETI", 1, 16, cpu::reti), // 0xd9
+    branch("JP C,a16", 3, 12, 16, cpu::jp_cc), // 0xda
+    op("", 1, 4, cpu::illegal), // 0xdb
+    branch("CALL C,a16", 3, 12, 24, cpu::call_cc), // 0xdc
+    op("", 1, 4, cpu::illegal), // 0xdd
+    op("SBC A,d8", 2, 8, cpu::alu_d8), // 0xde
+    op("RST $18", 1, 16, cpu::rst), // 0xdf
+    op("LDH (a8),A", 2, 12, cpu::ldh_a8_a), // 0xe0
+    op("POP HL", 1, 12, cpu::pop), // 0xe1
+    op("LD (C),A", 1, 8, cpu::ldh_c_a), // 0xe2
+    op("", 1, 4, cpu::illegal), // 0xe3
+    op("", 1, 4, cpu::illegal), // 0xe4
+    op("PUSH HL", 1, 16, cpu::push), // 0xe5
+    op("AND d8", 2, 8, cpu::alu_d8), // 0xe6
+    op("RST $20", 1, 16, cpu::rst), // 0xe7
+    op("ADD SP,r8", 2, 16, cpu::add_sp_r8), // 0xe8
+    op("JP (HL)", 1, 4, cpu::jp_hl), // 0xe9
+    op("LD (a16),A", 3, 16, cpu::ld_a16_a), // 0xea
+    op("", 1, 4, cpu::illegal), // 0xeb
+    op("", 1, 4, cpu::illegal), // 0xec
+    op("", 1, 4, cpu::illegal), // 0xed
+    op("XOR d8", 2, 8, cpu::alu_d8), // 0xee
+    op("RST $28", 1, 16, cpu::rst), // 0xef
+    op("LDH A,(a8)", 2, 12, cpu::ldh_a_a8), // 0xf0
+    op("POP AF", 1, 12, cpu::pop), // 0xf1
+    op("LD A,(C)", 1, 8, cpu::ldh_a_c), // 0xf2
+    op("DI", 1, 4, cpu::di), // 0xf3
+    op("", 1, 4, cpu::illegal), // 0xf4
+    op("PUSH AF", 1, 16, cpu::push), // 0xf5
+    op("OR d8", 2, 8, cpu::alu_d8), // 0xf6
+    op("RST $30", 1, 16, cpu::rst), // 0xf7
+    op("LD HL,SP+r8", 2, 12, cpu::ld_hl_sp_r8), // 0xf8
+    op("LD SP,HL", 1, 8, cpu::ld_sp_hl), // 0xf9
+    op("LD A,(a16)", 3, 16, cpu::ld_a_a16), // 0xfa
+    op("EI", 1, 4, cpu::ei), // 0xfb
+    op("", 1, 4, cpu::illegal), // 0xfc
+    op("", 1, 4, cpu::illegal), // 0xfd
+    op("CP d8", 2, 8, cpu::alu_d8), // 0xfe
+    op("RST $38", 1, 16, cpu::rst), // 0xff
+];
+
+// opcodes following PREFIX CB, cycles include the prefix
+pub const CB_OPCODES: [Opcode; 256] = [
+    op("RLC B", 2, 8, cpu::cb), // 0x00
+    op("RLC C", 2, 8, cpu::cb), // 0x01
+    op("RLC D", 2, 8, cpu::cb), // 0x02
+    op("RLC E", 2, 8, cpu::cb), // 0x03
+    op("RLC H", 2, 8, cpu::cb), // 0x04
+    op("RLC L", 2, 8, cpu::cb), // 0x05
+    op("RLC (HL)", 2, 16, cpu::cb), // 0x06
+    op("RLC A", 2, 8, cpu::cb), // 0x07
+    op("RRC B", 2, 8, cpu::cb), // 0x08
+    op("RRC C", 2, 8, cpu::cb), // 0x09
+    op("RRC D", 2, 8, cpu::cb), // 0x0a
+    op("RRC E", 2, 8, cpu::cb), // 0x0b
+    op("RRC H", 2, 8, cpu::cb), // 0x0c
+    op("RRC L", 2, 8, cpu::cb), // 0x0d
+    op("RRC (HL)", 2, 16, cpu::cb), // 0x0e
+    op("RRC A", 2, 8, cpu::cb), // 0x0f
+    op("RL B", 2, 8, cpu::cb), // 0x10
+    op("RL C", 2, 8, cpu::cb), // 0x11
+    op("RL D", 2, 8, cpu::cb), // 0x12
+    op("RL E", 2, 8, cpu::cb), // 0x13
+    op("RL H", 2, 8, cpu::cb), // 0x14
+    op("RL L", 2, 8, cpu::cb), // 0x15
+    op("RL (HL)", 2, 16, cpu::cb), // 0x16
+    op("RL A", 2, 8, cpu::cb), // 0x17
+    op("RR B", 2, 8, cpu::cb), // 0x18
+    op("RR C", 2, 8, cpu::cb), // 0x19
+    op("RR D", 2, 8, cpu::cb), // 0x1a
+    op("RR E", 2, 8, cpu::cb), // 0x1b
+    op("RR H", 2, 8, cpu::cb), // 0x1c
+    op("RR L", 2, 8, cpu::cb), // 0x1d
+    op("RR (HL)", 2, 16, cpu::cb), // 0x1e
+    op("RR A", 2, 8, cpu::cb), // 0x1f
+    op("SLA B", 2, 8, cpu::cb), // 0x20
+    op("SLA C", 2, 8, cpu::cb), // 0x21
+    op("SLA D", 2, 8, cpu::cb), // 0x22
+    op("SLA E", 2, 8, cpu::cb), // 0x23
+    op("SLA H", 2, 8, cpu::cb), // 0x24
+    op("SLA L", 2, 8, cpu::cb), // 0x25
+    op("SLA (HL)", 2, 16, cpu::cb), // 0x26
+    op("SLA A", 2, 8, cpu::cb), // 0x27
+    op("SRA B", 2, 8, cpu::cb), // 0x28
+    op("SRA C", 2, 8, cpu::cb), // 0x29
+    op("SRA D", 2, 8, cpu::cb), // 0x2a
+    op("SRA E", 2, 8, cpu::cb), // 0x2b
+    op("SRA H", 2, 8, cpu::cb), // 0x2c
+    op("SRA L", 2, 8, cpu::cb), // 0x2d
+    op("SRA (HL)", 2, 16, cpu::cb), // 0x2e
+    op("SRA A", 2, 8, cpu::cb), // 0x2f
+    op("SWAP B", 2, 8, cpu::cb), // 0x30
+    op("SWAP C", 2, 8, cpu::cb), // 0x31
+    op("SWAP D", 2, 8, cpu::cb), // 0x32
+    op("SWAP E", 2, 8, cpu::cb), // 0x33
+    op("SWAP H", 2, 8, cpu::cb), // 0x34
+    op("SWAP L", 2, 8, cpu::cb), // 0x35
+    op("SWAP (HL)", 2, 16, cpu::cb), // 0x36
+    op("SWAP A", 2, 8, cpu::cb), // 0x37
+    op("SRL B", 2, 8, cpu::cb), // 0x38
+    op("SRL C", 2, 8, cpu::cb), // 0x39
+    op("SRL D", 2, 8, cpu::cb), // 0x3a
+    op("SRL E", 2, 8, cpu::cb), // 0x3b
+    op("SRL H", 2, 8, cpu::cb), // 0x3c
+    op("SRL L", 2, 8, cpu::cb), // 0x3d
+    op("SRL (HL)", 2, 16, cpu::cb), // 0x3e
+    op("SRL A", 2, 8, cpu::cb), // 0x3f
+    op("BIT 0,B", 2, 8, cpu::cb), // 0x40
+    op("BIT 0,C", 2, 8, cpu::cb), // 0x41
+    op("BIT 0,D", 2, 8, cpu::cb), // 0x42
+    op("BIT 0,E", 2, 8, cpu::cb), // 0x43
+    op("BIT 0,H", 2, 8, cpu::cb), // 0x44
+    op("BIT 0,L", 2, 8, cpu::cb), // 0x45
+    op("BIT 0,(HL)", 2, 12, cpu::cb), // 0x46
+    op("BIT 0,A", 2, 8, cpu::cb), // 0x47
+    op("BIT 1,B", 2, 8, cpu::cb), // 0x48
+    op("BIT 1,C", 2, 8, cpu::cb), // 0x49
+    op("BIT 1,D", 2, 8, cpu::cb), // 0x4a
+    op("BIT 1,E", 2, 8, cpu::cb), // 0x4b
+    op("BIT 1,H", 2, 8, cpu::cb), // 0x4c
+    op("BIT 1,L", 2, 8, cpu::cb), // 0x4d
+    op("BIT 1,(HL)", 2, 12, cpu::cb), // 0x4e
+    op("BIT 1,A", 2, 8, cpu::cb), // 0x4f
+    op("BIT 2,B", 2, 8, cpu::cb), // 0x50
+    op("BIT 2,C", 2, 8, cpu::cb), // 0x51
+    op("BIT 2,D", 2, 8, cpu::cb), // 0x52
+    op("BIT 2,E", 2, 8, cpu::cb), // 0x53
+    op("BIT 2,H", 2, 8, cpu::cb), // 0x54
+    op("BIT 2,L", 2, 8, cpu::cb), // 0x55
+    op("BIT 2,(HL)", 2, 12, cpu::cb), // 0x56
+    op("BIT 2,A", 2, 8, cpu::cb), // 0x57
+    op("BIT 3,B", 2, 8, cpu::cb), // 0x58
+    op("BIT 3,C", 2, 8, cpu::cb), // 0x59
+    op("BIT 3,D", 2, 8, cpu::cb), // 0x5a
+    op("BIT 3,E", 2, 8, cpu::cb), // 0x5b
+    op("BIT 3,H", 2, 8, cpu::cb), // 0x5c
+    op("BIT 3,L", 2, 8, cpu::cb), // 0x5d
+    op("BIT 3,(HL)", 2, 12, cpu::cb), // 0x5e
+    op("BIT 3,A", 2, 8, cpu::cb), // 0x5f
+    op("BIT 4,B", 2, 8, cpu::cb), // 0x60
+    op("BIT 4,C", 2, 8, cpu::cb), // 0x61
+    op("BIT 4,D", 2, 8, cpu::cb), // 0x62
+    op("BIT 4,E", 2, 8, cpu::cb), // 0x63
+    op("BIT 4,H", 2, 8, cpu::cb), // 0x64
+    op("BIT 4,L", 2, 8, cpu::cb), // 0x65
+    op("BIT 4,(HL)", 2, 12, cpu::cb), // 0x66
+    op("BIT 4,A", 2, 8, cpu::cb), // 0x67
+    op("BIT 5,B", 2, 8, cpu::cb), // 0x68
+    op("BIT 5,C", 2, 8, cpu::cb), // 0x69
+    op("BIT 5,D", 2, 8, cpu::cb), // 0x6a
+    op("BIT 5,E", 2, 8, cpu::cb), // 0x6b
+    op("BIT 5,H", 2, 8, cpu::cb), // 0x6c
+    op("BIT 5,L", 2, 8, cpu::cb), // 0x6d
+    op("BIT 5,(HL)", 2, 12, cpu::cb), // 0x6e
+    op("BIT 5,A", 2, 8, cpu::cb), // 0x6f
+    op("BIT 6,B", 2, 8, cpu::cb), // 0x70
+    op("BIT 6,C", 2, 8, cpu::cb), // 0x71
+    op("BIT 6,D", 2, 8, cpu::cb), // 0x72
+    op("BIT 6,E", 2, 8, cpu::cb), // 0x73
+    op("BIT 6,H", 2, 8, cpu::cb), // 0x74
+    op("BIT 6,L", 2, 8, cpu::cb), // 0x75
+    op("BIT 6,(HL)", 2, 12, cpu::cb), // 0x76
+    op("BIT 6,A", 2, 8, cpu::cb), // 0x77
+    op("BIT 7,B", 2, 8, cpu::cb), // 0x78
+    op("BIT 7,C", 2, 8, cpu::cb), // 0x79
+    op("BIT 7,D", 2, 8, cpu::cb), // 0x7a
+    op("BIT 7,E", 2, 8, cpu::cb), // 0x7b
+    op("BIT 7,H", 2, 8, cpu::cb), // 0x7c
+    op("BIT 7,L", 2, 8, cpu::cb), // 0x7d
+    op("BIT 7,(HL)", 2, 12, cpu::cb), // 0x7e
+    op("BIT 7,A", 2, 8, cpu::cb), // 0x7f
+    op("RES 0,B", 2, 8, cpu::cb), // 0x80
+    op("RES 0,C", 2, 8, cpu::cb), // 0x81
+    op("RES 0,D", 2, 8, cpu::cb), // 0x82
+    op("RES 0,E", 2, 8, cpu::cb), // 0x83
+    op("RES 0,H", 2, 8, cpu::cb), // 0x84
+    op("RES 0,L", 2, 8, cpu::cb), // 0x85
+    op("RES 0,(HL)", 2, 16, cpu::cb), // 0x86
+    op("RES 0,A", 2, 8, cpu::cb), // 0x87
+    op("RES 1,B", 2, 8, cpu::cb), // 0x88
+    op("RES 1,C", 2, 8, cpu::cb), // 0x89
+    op("RES 1,D", 2, 8, cpu::cb), // 0x8a
+    op("RES 1,E", 2, 8, cpu::cb), // 0x8b
+    op("RES 1,H", 2, 8, cpu::cb), // 0x8c
+    op("RES 1,L", 2, 8, cpu::cb), // 0x8d
+    op("RES 1,(HL)", 2, 16, cpu::cb), // 0x8e
+    op("RES 1,A", 2, 8, cpu::cb), // 0x8f
+    op("RES 2,B", 2, 8, cpu::cb), // 0x90
+    op("RES 2,C", 2, 8, cpu::cb), // 0x91
+    op("RES 2,D", 2, 8, cpu::cb), // 0x92
+    op("RES 2,E", 2, 8, cpu::cb), // 0x93
+    op("RES 2,H", 2, 8, cpu::cb), // 0x94
+    op("RES 2,L", 2, 8, cpu::cb), // 0x95
+    op("RES 2,(HL)", 2, 16, cpu::cb), // 0x96
+    op("RES 2,A", 2, 8, cpu::cb), // 0x97
+    op("RES 3,B", 2, 8, cpu::cb), // 0x98
+    op("RES 3,C", 2, 8, cpu::cb), // 0x99
+    op("RES 3,D", 2, 8, cpu::cb), // 0x9a
+    op("RES 3,E", 2, 8, cpu::cb), // 0x9b
+    op("RES 3,H", 2, 8, cpu::cb), // 0x9c
+    op("RES 3,L", 2, 8, cpu::cb), // 0x9d
+    op("RES 3,(HL)", 2, 16, cpu::cb), // 0x9e
+    op("RES 3,A", 2, 8, cpu::cb), // 0x9f
+    op("RES 4,B", 2, 8, cpu::cb), // 0xa0
+    op("RES 4,C", 2, 8, cpu::cb), // 0xa1
+    op("RES 4,D", 2, 8, cpu::cb), // 0xa2
+    op("RES 4,E", 2, 8, cpu::cb), // 0xa3
+    op("RES 4,H", 2, 8, cpu::cb), // 0xa4
+    op("RES 4,L", 2, 8, cpu::cb), // 0xa5
+    op("RES 4,(HL)", 2, 16, cpu::cb), // 0xa6
+    op("RES 4,A", 2, 8, cpu::cb), // 0xa7
+    op("RES 5,B", 2, 8, cpu::cb), // 0xa8
+    op("RES 5,C", 2, 8, cpu::cb), // 0xa9
+    op("RES 5,D", 2, 8, cpu::cb), // 0xaa
+    op("RES 5,E", 2, 8, cpu::cb), // 0xab
+    op("RES 5,H", 2, 8, cpu::cb), // 0xac
+    op("RES 5,L", 2, 8, cpu::cb), // 0xad
+    op("RES 5,(HL)", 2, 16, cpu::cb), // 0xae
+    op("RES 5,A", 2, 8, cpu::cb), // 0xaf
+    op("RES 6,B", 2, 8, cpu::cb), // 0xb0
+    op("RES 6,C", 2, 8, cpu::cb), // 0xb1
+    op("RES 6,D", 2, 8, cpu::cb), // 0xb2
+    op("RES 6,E", 2, 8, cpu::cb), // 0xb3
+    op("RES 6,H", 2, 8, cpu::cb), // 0xb4
+    op("RES 6,L", 2, 8, cpu::cb), // 0xb5
+    op("RES 6,(HL)", 2, 16, cpu::cb), // 0xb6
+    op("RES 6,A", 2, 8, cpu::cb), // 0xb7
+    op("RES 7,B", 2, 8, cpu::cb), // 0xb8
+    op("RES 7,C", 2, 8, cpu::cb), // 0xb9
+    op("RES 7,D", 2, 8, cpu::cb), // 0xba
+    op("RES 7,E", 2, 8, cpu::cb), // 0xbb
+    op("RES 7,H", 2, 8, cpu::cb), // 0xbc
+    op("RES 7,L", 2, 8, cpu::cb), // 0xbd
+    op("RES 7,(HL)", 2, 16, cpu::cb), // 0xbe
+    op("RES 7,A", 2, 8, cpu::cb), // 0xbf
+    op("SET 0,B", 2, 8, cpu::cb), // 0xc0
+    op("SET 0,C", 2, 8, cpu::cb), // 0xc1
+    op("SET 0,D", 2, 8, cpu::cb), // 0xc2
+    op("SET 0,E", 2, 8, cpu::cb), // 0xc3
+    op("SET 0,H", 2, 8, cpu::cb), // 0xc4
+    op("SET 0,L", 2, 8, cpu::cb), // 0xc5
+    op("SET 0,(HL)", 2, 16, cpu::cb), // 0xc6
+    op("SET 0,A", 2, 8, cpu::cb), // 0xc7
+    op("SET 1,B", 2, 8, cpu::cb), // 0xc8
+    op("SET 1,C", 2, 8, cpu::cb), // 0xc9
+    op("SET 1,D", 2, 8, cpu::cb), // 0xca
+    op("SET 1,E", 2, 8, cpu::cb), // 0xcb
+    op("SET 1,H", 2, 8, cpu::cb), // 0xcc
+    op("SET 1,L", 2, 8, cpu::cb), // 0xcd
+    op("SET 1,(HL)", 2, 16, cpu::cb), // 0xce
+    op("SET 1,A", 2, 8, cpu::cb), // 0xcf
+    op("SET 2,B", 2, 8, cpu::cb), // 0xd0
+    op("SET 2,C", 2, 8, cpu::cb), // 0xd1
+    op("SET 2,D", 2, 8, cpu::cb), // 0xd2
+    op("SET 2,E", 2, 8, cpu::cb), // 0xd3
+    op("SET 2,H", 2, 8, cpu::cb), // 0xd4
+    op("SET 2,L", 2, 8, cpu::cb), // 0xd5
+    op("SET 2,(HL)", 2, 16, cpu::cb), // 0xd6
+    op("SET 2,A", 2, 8, cpu::cb), // 0xd7
+    op("SET 3,B", 2, 8, cpu::cb), // 0xd8
+    op("SET 3,C", 2, 8, cpu::cb), // 0xd9
+    op("SET 3,D", 2, 8, cpu::cb), // 0xda
+    op("SET 3,E", 2, 8, cpu::cb), // 0xdb
+    op("SET 3,H", 2, 8, cpu::cb), // 0xdc
+    op("SET 3,L", 2, 8, cpu::cb), // 0xdd
+    op("SET 3,(HL)", 2, 16, cpu::cb), // 0xde
+    op("SET 3,A", 2, 8, cpu::cb), // 0xdf
+    op("SET 4,B", 2, 8, cpu::cb), // 0xe0
+    op("SET 4,C", 2, 8, cpu::cb), // 0xe1
+    op("SET 4,D", 2, 8, cpu::cb), // 0xe2
+    op("SET 4,E", 2, 8, cpu::cb), // 0xe3
+    op("SET 4,H", 2, 8, cpu::cb), // 0xe4
+    op("SET 4,L", 2, 8, cpu::cb), // 0xe5
+    op("SET 4,(HL)", 2, 16, cpu::cb), // 0xe6
+    op("SET 4,A", 2, 8, cpu::cb), // 0xe7
+    op("SET 5,B", 2, 8, cpu::cb), // 0xe8
+    op("SET 5,C", 2, 8, cpu::cb), // 0xe9
+    op("SET 5,D", 2, 8, cpu::cb), // 0xea
+    op("SET 5,E", 2, 8, cpu::cb), // 0xeb
+    op("SET 5,H", 2, 8, cpu::cb), // 0xec
+    op("SET 5,L", 2, 8, cpu::cb), // 0xed
+    op("SET 5,(HL)", 2, 16, cpu::cb), // 0xee
+    op("SET 5,A", 2, 8, cpu::cb), // 0xef
+    op("SET 6,B", 2, 8, cpu::cb), // 0xf0
+    op("SET 6,C", 2, 8, cpu::cb), // 0xf1
+    op("SET 6,D", 2, 8, cpu::cb), // 0xf2
+    op("SET 6,E", 2, 8, cpu::cb), // 0xf3
+    op("SET 6,H", 2, 8, cpu::cb), // 0xf4
+    op("SET 6,L", 2, 8, cpu::cb), // 0xf5
+    op("SET 6,(HL)", 2, 16, cpu::cb), // 0xf6
+    op("SET 6,A", 2, 8, cpu::cb), // 0xf7
+    op("SET 7,B", 2, 8, cpu::cb), // 0xf8
+    op("SET 7,C", 2, 8, cpu::cb), // 0xf9
+    op("SET 7,D", 2, 8, cpu::cb), // 0xfa
+    op("SET 7,E", 2, 8, cpu::cb), // 0xfb
+    op("SET 7,H", 2, 8, cpu::cb), // 0xfc
+    op("SET 7,L", 2, 8, cpu::cb), // 0xfd
+    op("SET 7,(HL)", 2, 16, cpu::cb), // 0xfe
+    op("SET 7,A", 2, 8, cpu::cb), // 0xff
+];
@@ -0,0 +1,54 @@
+// on-screen text in a 3x5 font, for script overlays and the --arcade chooser.
+// letters are upper case only, what the font lacks shows as `?`
+
+use crate::palette::Rgb;
+
+// a glyph and the column after it
+pub const ADVANCE: usize = 4;
+// a line of glyphs and the row after it
+pub const LINE_HEIGHT: usize = 6;
+
+// 3x5 glyphs for ` ` to `_`, rows top down, the high bit of each row on the left
+const FONT: [u16; 64] = [
+    0x0000, 0x2482, 0x5a00, 0x5f7d, 0x3c9e, 0x52a5, 0x2aab, 0x2400,
+    0x1491, 0x4494, 0x0aa8, 0x05d0, 0x0014, 0x01c0, 0x0002, 0x12a4,
+    0x7b6f, 0x2c97, 0x73e7, 0x72cf, 0x5bc9, 0x79cf, 0x79ef, 0x7252,
+    0x7bef, 0x7bcf, 0x0410, 0x0414, 0x1511, 0x0e38, 0x4454, 0x72c2,
+    0x7be7, 0x2bed, 0x6bae, 0x3923, 0x6b6e, 0x79a7, 0x79a4, 0x396b,
+    0x5bed, 0x7497, 0x126a, 0x5bad, 0x4927, 0x5fed, 0x6b6d, 0x2b6a,
+    0x6ba4, 0x2b73, 0x6bad, 0x388e, 0x7492, 0x5b6f, 0x5b6a, 0x5bfd,
+    0x5aad, 0x5a92, 0x72a7, 0x3493, 0x4889, 0x6496, 0x2a00, 0x0007,
+];
+
+// packed RGB, width pixels a row. x, y is the top left of the first glyph, a
+// newline goes back to x one line down, what's off the frame is cut
+pub fn draw_text(rgb: &mut [u8], width: usize, x: i64, y: i64, text: &str, color: &Rgb) {
+    let (mut col, mut row) = (x, y);
+    for c in text.chars() {
+        if c == '\n' {
+            (col, row) = (x, row + LINE_HEIGHT as i64);
+            continue;
+        }
+        let glyph = match c.to_ascii_uppercase() {
+            c @ ' '..='_' => FONT[c as usize - 0x20],
+            _ => FONT[usize::from(b'?' - 0x20)],
+        };
+        for bit in 0..15 {
+            if glyph & (0x4000 >> bit) != 0 {
+                fill(rgb, width, col + bit % 3, row + bit / 3, 1, 1, color);
+            }
+        }
+        col += ADVANCE as i64;
+    }
+}
+
+// a rectangle, cut at the edges of the frame
+pub fn fill(rgb: &mut [u8], width: usize, x: i64, y: i64, w: i64, h: i64, color: &Rgb) {
+    let height = (rgb.len() / 3 / width) as i64;
+    for row in y.max(0)..(y + h).min(height) {
+        for col in x.max(0)..(x + w).min(width as i64) {
+            let i = (row as usize * width + col as usize) * 3;
+            rgb[i..i + 3].copy_from_slice(color);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+// colors for the PPU's 2-bit shades, and remapping of CGB colors, including
+// presets for low vision and color vision deficiencies
+
+use std::borrow::Cow;
+
+pub type Rgb = [u8; 3];
+
+pub struct Palette {
+    pub name: Cow<'static, str>,
+    // shade 0 (lightest) to 3
+    pub shades: [Rgb; 4],
+}
+
+pub const PRESETS: [Palette; 17] = [
+    Palette {
+        name: Cow::Borrowed("grey"),
+        shades: [[0xff, 0xff, 0xff], [0xaa, 0xaa, 0xaa], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]],
+    },
+    // original DMG screen
+    Palette {
+        name: Cow::Borrowed("dmg-green"),
+        shades: [[0x9b, 0xbc, 0x0f], [0x8b, 0xac, 0x0f], [0x30, 0x62, 0x30], [0x0f, 0x38, 0x0f]],
+    },
+    // widest luminance steps, hue changes as a second cue
+    Palette {
+        name: Cow::Borrowed("high-contrast"),
+        shades: [[0xff, 0xff, 0xff], [0xff, 0xd8, 0x00], [0x00, 0x40, 0xff], [0x00, 0x00, 0x00]],
+    },
+    Palette {
+        name: Cow::Borrowed("high-contrast-dark"),
+        shades: [[0x00, 0x00, 0x00], [0x00, 0x40, 0xff], [0xff, 0xd8, 0x00], [0xff, 0xff, 0xff]],
+    },
+    // blue / orange ramp, safe for protanopia and deuteranopia
+    Palette {
+        name: Cow::Borrowed("red-green-safe"),
+        shades: [[0xff, 0xf5, 0xe0], [0xf0, 0xa0, 0x30], [0x30, 0x70, 0xc0], [0x10, 0x18, 0x30]],
+    },
+    // red / teal ramp, safe for tritanopia
+    Palette {
+        name: Cow::Borrowed("blue-yellow-safe"),
+        shades: [[0xf8, 0xf8, 0xf8], [0xf0, 0x80, 0x80], [0x00, 0x90, 0x90], [0x20, 0x20, 0x20]],
+    },
+    // the CGB boot ROM's colors for DMG games, the background ones: picked for
+    // the game by bootpalette.rs or on the CGB by holding the buttons while it starts
+    // Up
+    Palette {
+        name: Cow::Borrowed("cgb-brown"),
+        shades: [[0xff, 0xff, 0xff], [0xff, 0xad, 0x63], [0x84, 0x31, 0x00], [0x00, 0x00, 0x00]],
+    },
+    // Up+A
+    Palette {
+        name: Cow::Borrowed("cgb-red"),
+        shades: [[0xff, 0xff, 0xff], [0xff, 0x84, 0x84], [0x94, 0x3a, 0x3a], [0x00, 0x00, 0x00]],
+    },
+    // Up+B
+    Palette {
+        name: Cow::Borrowed("cgb-dark-brown"),
+        shades: [[0xff, 0xe6, 0xc5], [0xce, 0x9c, 0x84], [0x84, 0x6b, 0x29], [0x5a, 0x31, 0x08]],
+    },
+    // Down
+    Palette {
+        name: Cow::Borrowed("cgb-pastel"),
+        shades: [[0xff, 0xff, 0xa5], [0xff, 0x94, 0x94], [0x94, 0x94, 0xff], [0x00, 0x00, 0x00]],
+    },
+    // Down+A
+    Palette {
+        name: Cow::Borrowed("cgb-orange"),
+        shades: [[0xff, 0xff, 0xff], [0xff, 0xff, 0x00], [0xff, 0x00, 0x00], [0x00, 0x00, 0x00]],
+    },
+    // Down+B
+    Palette {
+        name: Cow::Borrowed("cgb-yellow"),
+        shades: [[0xff, 0xff, 0xff], [0xff, 0xff, 0x00], [0x7b, 0x4a, 0x00], [0x00, 0x00, 0x00]],
+    },
+    // Left
+    Palette {
+        name: Cow::Borrowed("cgb-blue"),
+        shades: [[0xff, 0xff, 0xff], [0x63, 0xa5, 0xff], [0x00, 0x00, 0xff], [0x00, 0x00, 0x00]],
+    },
+    // Left+A
+    Palette {
+        name: Cow::Borrowed("cgb-dark-blue"),
+        shades: [[0xff, 0xff, 0xff], [0x8c, 0x8c, 0xde], [0x52, 0x52, 0x8c], [0x00, 0x00, 0x00]],
+    },
+    // Left+B
+    Palette {
+        name: Cow::Borrowed("cgb-grey"),
+        shades: [[0xff, 0xff, 0xff], [0xa5, 0xa5, 0xa5], [0x52, 0x52, 0x52], [0x00, 0x00, 0x00]],
+    },
+    // Right
+    Palette {
+        name: Cow::Borrowed("cgb-green"),
+        shades: [[0xff, 0xff, 0xff], [0x52, 0xff, 0x00], [0xff, 0x42, 0x00], [0x00, 0x00, 0x00]],
+    },
+    // Right+A
+    Palette {
+        name: Cow::Borrowed("cgb-dark-green"),
+        shades: [[0xff, 0xff, 0xff], [0x7b, 0xff, 0x31], [0x00, 0x63, 0xc5], [0x00, 0x00, 0x00]],
+    },
+];
+
+// applied to full color (CGB) output
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ColorRemap {
+    #[default]
+    None,
+    HighContrast,
+    // daltonization for the given deficiency
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+pub const REMAPS: [(&str, ColorRemap); 5] = [
+    ("none", ColorRemap::None),
+    ("high-contrast", ColorRemap::HighContrast),
+    ("protanopia", ColorRemap::Protanopia),
+    ("deuteranopia", ColorRemap::Deuteranopia),
+    ("tritanopia", ColorRemap::Tritanopia),
+];
+
+// how the deficiency sees colors (Machado et al. 2009, full severity)
+const PROTANOPIA: [[f32; 3]; 3] = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+const DEUTERANOPIA: [[f32; 3]; 3] = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.01182, 0.04294, 0.968881],
+];
+const TRITANOPIA: [[f32; 3]; 3] = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.147602],
+    [0.004733, 0.691367, 0.3039],
+];
+// moves lost color information into channels that are still perceived
+const ERROR_SHIFT: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+impl ColorRemap {
+    pub fn apply(self, rgb: Rgb) -> Rgb {
+        let color = rgb.map(|c| c as f32);
+        let out = match self {
+            ColorRemap::None => return rgb,
+            ColorRemap::HighContrast => color.map(|c| (c - 128.0) * 1.6 + 128.0),
+            ColorRemap::Protanopia => daltonize(color, &PROTANOPIA),
+            ColorRemap::Deuteranopia => daltonize(color, &DEUTERANOPIA),
+            ColorRemap::Tritanopia => daltonize(color, &TRITANOPIA),
+        };
+        out.map(|c| c.round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+fn mul(matrix: &[[f32; 3]; 3], color: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * color[0] + row[1] * color[1] + row[2] * color[2])
+}
+
+fn daltonize(color: [f32; 3], simulation: &[[f32; 3]; 3]) -> [f32; 3] {
+    let seen = mul(simulation, color);
+    let error = [color[0] - seen[0], color[1] - seen[1], color[2] - seen[2]];
+    let shift = mul(&ERROR_SHIFT, error);
+    [color[0] + shift[0], color[1] + shift[1], color[2] + shift[2]]
+}
+
+// user palettes, one per line, shades lightest first, `#` starts a comment line
+//   lcd-blue: #e0f8f8 #88c0d0 #346888 #081820
+pub fn parse_palettes(text: &str) -> Result<Vec<Palette>, String> {
+    let mut palettes = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let (name, colors) = line.split_once(':').ok_or_else(|| error("expected `name: 4 colors`"))?;
+        let colors: Vec<&str> = colors.split_whitespace().collect();
+        if colors.len() != 4 {
+            return Err(error(&format!("expected 4 colors, got {}", colors.len())));
+        }
+        let mut shades = [[0; 3]; 4];
+        for (shade, color) in shades.iter_mut().zip(colors) {
+            let hex = color.trim_start_matches('#');
+            let rgb = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)
+                .ok_or_else(|| error(&format!("invalid color `{}`, expected rrggbb", color)))?;
+            *shade = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+        }
+        palettes.push(Palette {
+            name: Cow::Owned(name.trim().to_string()),
+            shades,
+        });
+    }
+    Ok(palettes)
+}
+
+// palette choice, can be changed while running
+pub struct PaletteSettings {
+    // index into presets followed by user palettes
+    dmg: usize,
+    user: Vec<Palette>,
+    pub cgb_remap: ColorRemap,
+}
+
+impl Default for PaletteSettings {
+    fn default() -> Self {
+        PaletteSettings {
+            dmg: 0,
+            user: Vec::new(),
+            cgb_remap: ColorRemap::None,
+        }
+    }
+}
+
+impl PaletteSettings {
+    pub fn dmg(&self) -> &Palette {
+        self.palettes().nth(self.dmg).unwrap()
+    }
+
+    pub fn palettes(&self) -> impl Iterator<Item = &Palette> {
+        PRESETS.iter().chain(&self.user)
+    }
+
+    // user palettes take part in select / next_dmg after the presets
+    pub fn add_user(&mut self, palettes: Vec<Palette>) {
+        self.user.extend(palettes);
+    }
+
+    // DMG preset or CGB remap by name, returns false when unknown
+    pub fn select(&mut self, name: &str) -> bool {
+        let index = self.palettes().position(|p| p.name == name);
+        if let Some(index) = index {
+            self.dmg = index;
+            return true;
+        }
+        match REMAPS.iter().find(|(remap_name, _)| *remap_name == name) {
+            Some((_, remap)) => {
+                self.cgb_remap = *remap;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn next_dmg(&mut self) {
+        self.dmg = (self.dmg + 1) % (PRESETS.len() + self.user.len());
+    }
+
+    // shades framebuffer to packed RGB
+    pub fn colorize(&self, shades: &[u8]) -> Vec<u8> {
+        let palette = self.dmg();
+        shades.iter().flat_map(|&shade| palette.shades[shade as usize & 0x03]).collect()
+    }
+
+    // remaps packed RGB of a CGB frame in place
+    pub fn remap(&self, rgb: &mut [u8]) {
+        if self.cgb_remap == ColorRemap::None {
+            return;
+        }
+        for pixel in rgb.chunks_exact_mut(3) {
+            let out = self.cgb_remap.apply([pixel[0], pixel[1], pixel[2]]);
+            pixel.copy_from_slice(&out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daltonization_keeps_greys() {
+        for (_, remap) in REMAPS.iter().filter(|(_, r)| *r != ColorRemap::HighContrast) {
+            for grey in [0x00, 0x55, 0xaa, 0xff] {
+                assert_eq!(remap.apply([grey; 3]), [grey; 3], "{:?}", remap);
+            }
+        }
+    }
+
+    #[test]
+    fn presets_get_darker_with_shade() {
+        let luma = |[r, g, b]: Rgb| 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+        for palette in PRESETS.iter().filter(|p| !p.name.ends_with("-dark")) {
+            let lumas = palette.shades.map(luma);
+            assert!(lumas.windows(2).all(|w| w[0] > w[1]), "{}", palette.name);
+        }
+    }
+
+    #[test]
+    fn parses_user_palettes() {
+        let text = "# comment\n\nlcd-blue: #e0f8f8 88c0d0 #346888 #081820\n";
+        let mut settings = PaletteSettings::default();
+        settings.add_user(parse_palettes(text).unwrap());
+        assert!(settings.select("lcd-blue"));
+        assert_eq!(settings.dmg().shades[1], [0x88, 0xc0, 0xd0]);
+        settings.next_dmg();
+        assert_eq!(settings.dmg().name, "grey");
+
+        assert_eq!(parse_palettes("x: 000000 ffffff").err().unwrap(), "line 1: expected 4 colors, got 2");
+        assert!(parse_palettes("x: 000000 ffffff fff 000000").is_err());
+    }
+}
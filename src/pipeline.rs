@@ -0,0 +1,72 @@
+// drives the emulator from another program: every input line holds the buttons
+// for one frame (see KeyBindings::parse), optionally each finished frame is written
+// back as raw RGB, width * height * 3 bytes, size depends on the post-processing
+
+use std::io::{self, BufRead, Write};
+
+use crate::inputdisplay::InputDisplay;
+use crate::palette::PaletteSettings;
+use crate::postprocess::PostProcess;
+use crate::joypad::KeyBindings;
+use crate::GB;
+
+pub struct Frames<'a> {
+    pub out: &'a mut dyn Write,
+    pub palettes: &'a PaletteSettings,
+    pub post: &'a PostProcess,
+    // drawn into every frame before post-processing
+    pub input_display: Option<InputDisplay>,
+}
+
+// runs until input ends, returns frames run
+pub fn run(gb: &mut GB, input: impl BufRead, keys: &KeyBindings, mut frames: Option<Frames>) -> io::Result<u64> {
+    let mut count = 0;
+    for (number, line) in input.lines().enumerate() {
+        let buttons = keys
+            .parse(line?.trim())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("input line {}: {}", number + 1, e)))?;
+        gb.set_buttons(buttons);
+        gb.run_frames(1);
+        count += 1;
+        if let Some(frames) = frames.as_mut() {
+            let (width, height) = gb.display_size();
+            let mut rgb = gb.display(frames.palettes);
+            if let Some(display) = frames.input_display.as_mut() {
+                display.push(gb.frame_buttons());
+                display.draw(&mut rgb, width);
+            }
+            let (_, _, pixels) = frames.post.apply(&rgb, width as u32, height as u32);
+            frames.out.write_all(&pixels)?;
+            frames.out.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::{ppu, Buttons};
+
+    #[test]
+    fn runs_one_frame_per_line() {
+        let rom = micro_rom("xor a\nldh ($40), a");
+        let mut gb = GB::new(&rom).unwrap();
+        let mut out = Vec::new();
+        let palettes = PaletteSettings::default();
+        let post = PostProcess::default();
+        let frames = Frames { out: &mut out, palettes: &palettes, post: &post, input_display: None };
+        // no jumps to keep the CPU inside the NOP sled, two frames fit
+        let mut keys = KeyBindings::default();
+        keys.bind("h", Buttons::LEFT);
+        let count = run(&mut gb, "\nh\n".as_bytes(), &keys, Some(frames)).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(gb.frames_elapsed(), 0);
+        assert_eq!(gb.buttons(), Buttons::LEFT);
+        assert_eq!(out.len(), 2 * ppu::WIDTH * ppu::HEIGHT * 3);
+
+        let err = run(&mut gb, "a+start jump\n".as_bytes(), &keys, None).unwrap_err();
+        assert_eq!(err.to_string(), "input line 1: unknown button `jump`");
+    }
+}
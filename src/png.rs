@@ -0,0 +1,86 @@
+// minimal PNG encoder, image data is stored in uncompressed deflate blocks
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+// channels: 1 grayscale, 3 RGB, 4 RGBA - 8 bits each
+pub fn encode(width: u32, height: u32, channels: u8, pixels: &[u8]) -> Vec<u8> {
+    let color_type = match channels {
+        1 => 0,
+        3 => 2,
+        4 => 6,
+        _ => panic!("Unsupported PNG channel count {}", channels),
+    };
+    let stride = width as usize * channels as usize;
+    assert_eq!(pixels.len(), stride * height as usize, "PNG pixel data size mismatch");
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // bit depth, color type, compression, filter, interlace
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    // every row starts with filter type 0
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in pixels.chunks(stride) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // CMF / FLG: deflate, 32K window, no dictionary
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = match data.is_empty() {
+        true => vec![&[]],
+        false => data.chunks(MAX_STORED_BLOCK).collect(),
+    };
+    let last = blocks.len() - 1;
+    for (i, block) in blocks.iter().enumerate() {
+        out.push((i == last) as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,115 @@
+// filters applied to the colorized frame when presenting it, the emulated
+// framebuffer itself stays untouched. upscalers from videofilter.rs run first,
+// the integer scale and darkening effects on their output
+
+use crate::videofilter::{self, FilterChain, Image, VideoFilter};
+
+// brightness in 1/256 of the darkened rows / grid lines
+const SCANLINE_LEVEL: u32 = 160;
+const GRID_LEVEL: u32 = 200;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PostProcess {
+    // upscalers in order, e.g. scale2x
+    pub filters: FilterChain,
+    // integer upscale factor, 1 keeps 160x144
+    pub scale: u32,
+    // darken the bottom row of every pixel, every other line at 1x
+    pub scanlines: bool,
+    // darken gaps between pixels like the DMG's LCD matrix, needs 2x or more
+    pub lcd_grid: bool,
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        PostProcess {
+            filters: FilterChain::default(),
+            scale: 1,
+            scanlines: false,
+            lcd_grid: false,
+        }
+    }
+}
+
+impl PostProcess {
+    // comma separated filter names, e.g. `hq2x,scanlines,lcd-grid,3x`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut post = PostProcess::default();
+        for name in spec.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "scanlines" => post.scanlines = true,
+                "lcd-grid" => post.lcd_grid = true,
+                _ => match (videofilter::builtin(name), name.strip_suffix('x').and_then(|n| n.parse().ok())) {
+                    (Some(filter), _) => post.filters.push(filter),
+                    (None, Some(scale @ 1..=8)) => post.scale = scale,
+                    _ => {
+                        return Err(format!(
+                            "unknown filter `{}`, expected scanlines, lcd-grid, 1x-8x, scale2x, scale3x, hq2x or nearest2x-8x",
+                            name
+                        ))
+                    }
+                },
+            }
+        }
+        Ok(post)
+    }
+
+    // packed RGB frame in, (width, height, packed RGB) out
+    pub fn apply(&self, rgb: &[u8], width: u32, height: u32) -> (u32, u32, Vec<u8>) {
+        if !self.filters.is_empty() {
+            let image = self.filters.apply(&Image::from_rgb(rgb, width, height));
+            return self.effects(&image.to_rgb(), image.width, image.height);
+        }
+        self.effects(rgb, width, height)
+    }
+
+    fn effects(&self, rgb: &[u8], width: u32, height: u32) -> (u32, u32, Vec<u8>) {
+        let scale = self.scale;
+        let (out_width, out_height) = (width * scale, height * scale);
+        let mut out = Vec::with_capacity((out_width * out_height * 3) as usize);
+        for y in 0..out_height {
+            let (sub_y, src_y) = (y % scale, y / scale);
+            for x in 0..out_width {
+                let (sub_x, src_x) = (x % scale, x / scale);
+                let mut level = 256;
+                let last_row = match scale {
+                    1 => y % 2 == 1,
+                    _ => sub_y == scale - 1,
+                };
+                if self.scanlines && last_row {
+                    level = level * SCANLINE_LEVEL / 256;
+                }
+                if self.lcd_grid && scale > 1 && (sub_x == scale - 1 || sub_y == scale - 1) {
+                    level = level * GRID_LEVEL / 256;
+                }
+                let i = ((src_y * width + src_x) * 3) as usize;
+                out.extend(rgb[i..i + 3].iter().map(|&c| (c as u32 * level / 256) as u8));
+            }
+        }
+        (out_width, out_height, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_and_darkens() {
+        let post = PostProcess::parse("2x,scanlines").unwrap();
+        assert_eq!(post, PostProcess { scale: 2, scanlines: true, ..PostProcess::default() });
+        let (width, height, out) = post.apply(&[200, 100, 0, 0, 0, 0], 2, 1);
+        assert_eq!((width, height), (4, 2));
+        assert_eq!(&out[0..6], &[200, 100, 0, 200, 100, 0]);
+        assert_eq!(&out[12..15], &[125, 62, 0]);
+        assert!(PostProcess::parse("blur").is_err());
+    }
+
+    #[test]
+    fn upscalers_run_before_scaling() {
+        let post = PostProcess::parse("scale2x,2x").unwrap();
+        assert_eq!(post.filters.name(), "scale2x");
+        let (width, height, out) = post.apply(&[0; 2 * 3], 2, 1);
+        assert_eq!((width, height, out.len()), (8, 4, 8 * 4 * 3));
+    }
+}
@@ -0,0 +1,646 @@
+use std::ops::Range;
+
+use crate::fifo::PixelFifo;
+use crate::savestate::{Component, StateError, StateReader, StateWriter};
+use crate::scanlines::{ScanlineCapture, ScanlineRegs};
+use crate::{Interrupts, MMU};
+
+// dots (t-cycles) per mode
+const OAM_SCAN_DOTS: u32 = 80;
+const TRANSFER_DOTS: u32 = 172;
+const TRANSFER_DOTS_END: u32 = OAM_SCAN_DOTS + TRANSFER_DOTS;
+const LINE_DOTS: u32 = 456;
+const VISIBLE_LINES: u8 = 144;
+const LINES: u8 = 154;
+pub const FRAME_DOTS: u64 = LINE_DOTS as u64 * LINES as u64;
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = VISIBLE_LINES as usize;
+pub(crate) const SPRITES_PER_LINE: usize = 10;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Transfer = 3,
+}
+
+// how lines are drawn. Scanline draws each line whole as mode 3 starts, with
+// the registers of that moment, and is the faster one. Fifo draws pixel by
+// pixel through mode 3, so writes in the middle of a line (raster effects in
+// demos and games like Prehistorik Man) land where they do on hardware, and
+// mode 3 takes as long as it does there
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    #[default]
+    Scanline,
+    Fifo,
+}
+
+impl Renderer {
+    pub fn parse(name: &str) -> Result<Renderer, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "scanline" => Ok(Renderer::Scanline),
+            "fifo" => Ok(Renderer::Fifo),
+            _ => Err(format!("unknown renderer `{}`, expected scanline or fifo", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Renderer::Scanline => "scanline",
+            Renderer::Fifo => "fifo",
+        }
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Default)]
+    pub struct PPUEvents: u8 {
+        const NONE = 0x00;
+        const HBLANK = 0x01;
+        const VBLANK = 0x02;
+    }
+}
+
+pub struct PPU {
+    mode: Mode,
+    // dots spent in current line
+    dots: u32,
+    line: u8,
+    // internal line counter of window, only advances on lines showing it
+    window_line: u8,
+    // OR of all enabled STAT sources, interrupt fires on rising edge only
+    stat_line: bool,
+    // shades 0-3 after palette mapping, row by row
+    framebuffer: [u8; WIDTH * HEIGHT],
+    // rows drawn differently since take_changed_lines, and the current row
+    // before mode 3 drew it
+    changed: [bool; HEIGHT],
+    row_before: [u8; WIDTH],
+    // optional register capture for raster effect debugging
+    capture: Option<Box<ScanlineCapture>>,
+    renderer: Renderer,
+    fifo: PixelFifo,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        PPU {
+            mode: Default::default(),
+            dots: 0,
+            line: 0,
+            window_line: 0,
+            stat_line: false,
+            framebuffer: [0; WIDTH * HEIGHT],
+            changed: [true; HEIGHT],
+            row_before: [0; WIDTH],
+            capture: None,
+            renderer: Renderer::Scanline,
+            fifo: Default::default(),
+        }
+    }
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn framebuffer(&self) -> &[u8; WIDTH * HEIGHT] {
+        &self.framebuffer
+    }
+
+    // runs of rows that changed since the last call, so a frontend only uploads
+    // those. all of them at first and after loading a state
+    pub fn take_changed_lines(&mut self) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for (line, changed) in self.changed.iter_mut().enumerate() {
+            if !std::mem::take(changed) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.end == line => run.end += 1,
+                _ => runs.push(line..line + 1),
+            }
+        }
+        runs
+    }
+
+    pub fn set_scanline_capture(&mut self, enabled: bool) {
+        self.capture = match enabled {
+            true => Some(Default::default()),
+            false => None,
+        };
+    }
+
+    pub fn scanline_capture(&self) -> Option<&ScanlineCapture> {
+        self.capture.as_deref()
+    }
+
+    // power-on state, the renderer and scanline capture stay as they are
+    pub fn reset(&mut self) {
+        let (renderer, capture) = (self.renderer, self.capture.take());
+        *self = PPU { renderer, capture, ..Default::default() };
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    // takes effect from the next line, the current one ends as the old
+    // renderer would have ended it
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        if renderer == Renderer::Fifo && self.renderer == Renderer::Scanline {
+            self.fifo.finish_line();
+        }
+        self.renderer = renderer;
+    }
+
+    // advances PPU by t-cycles, returns modes entered on the way
+    pub(crate) fn step(&mut self, mmu: &mut MMU, t: u32) -> PPUEvents {
+        // LCDC bit 7 - LCD off keeps PPU at start of frame
+        if mmu.io[0x40] & 0x80 == 0 {
+            self.mode = Mode::HBlank;
+            self.dots = 0;
+            self.line = 0;
+            self.stat_line = false;
+            mmu.io[0x44] = 0;
+            mmu.io[0x41] &= !0x03;
+            self.fifo.start_frame();
+            return PPUEvents::NONE;
+        }
+        match self.renderer {
+            Renderer::Scanline => self.advance(mmu, t),
+            // mode 3 ends when the FIFO finished the line, one dot at a time
+            Renderer::Fifo => (0..t).fold(PPUEvents::NONE, |events, _| events | self.advance(mmu, 1)),
+        }
+    }
+
+    // t-cycles until the mode or LY changes, 1 while the FIFO decides when
+    // mode 3 ends. stepping fewer than that at once is the same as one by one
+    pub(crate) fn next_event(&self, mmu: &MMU) -> u32 {
+        if mmu.io[0x40] & 0x80 == 0 {
+            return u32::MAX;
+        }
+        let (mode, end) = match self.line {
+            0..VISIBLE_LINES => match self.dots {
+                0..OAM_SCAN_DOTS => (Mode::OamScan, OAM_SCAN_DOTS),
+                OAM_SCAN_DOTS..TRANSFER_DOTS_END => (Mode::Transfer, TRANSFER_DOTS_END),
+                _ => (Mode::HBlank, LINE_DOTS),
+            },
+            _ => (Mode::VBlank, LINE_DOTS),
+        };
+        match mode != self.mode || (self.renderer == Renderer::Fifo && self.mode == Mode::Transfer) {
+            true => 1,
+            false => end - self.dots,
+        }
+    }
+
+    fn advance(&mut self, mmu: &mut MMU, t: u32) -> PPUEvents {
+        let fifo = self.renderer == Renderer::Fifo;
+        let mut events = PPUEvents::NONE;
+        self.dots += t;
+        loop {
+            let mode = match self.line {
+                0..VISIBLE_LINES => match self.dots {
+                    0..OAM_SCAN_DOTS => Mode::OamScan,
+                    _ if fifo => match self.mode {
+                        Mode::OamScan => Mode::Transfer,
+                        Mode::Transfer if !self.fifo.done() => Mode::Transfer,
+                        _ => Mode::HBlank,
+                    },
+                    OAM_SCAN_DOTS..TRANSFER_DOTS_END => Mode::Transfer,
+                    _ => Mode::HBlank,
+                },
+                _ => Mode::VBlank,
+            };
+            if mode != self.mode {
+                match mode {
+                    Mode::HBlank => {
+                        events |= PPUEvents::HBLANK;
+                        let row = &self.framebuffer[self.line as usize * WIDTH..(self.line as usize + 1) * WIDTH];
+                        self.changed[self.line as usize] |= *row != self.row_before;
+                        if fifo && self.fifo.showed_window() {
+                            self.window_line += 1;
+                        }
+                    }
+                    Mode::VBlank => {
+                        events |= PPUEvents::VBLANK;
+                        mmu.request_interrupt(Interrupts::VBLANK);
+                        self.window_line = 0;
+                        self.fifo.start_frame();
+                        if let Some(capture) = self.capture.as_mut() {
+                            capture.finish_frame();
+                        }
+                    }
+                    Mode::Transfer => {
+                        let row = &self.framebuffer[self.line as usize * WIDTH..(self.line as usize + 1) * WIDTH];
+                        self.row_before.copy_from_slice(row);
+                        if let Some(capture) = self.capture.as_mut() {
+                            capture.record(self.line, ScanlineRegs::from_io(mmu.io.bytes()));
+                        }
+                        match fifo {
+                            true => self.fifo.start_line(mmu, self.line),
+                            false => self.render_line(mmu),
+                        }
+                    }
+                    Mode::OamScan => {}
+                }
+                self.mode = mode;
+            }
+            if fifo && self.mode == Mode::Transfer {
+                let row = &mut self.framebuffer[self.line as usize * WIDTH..(self.line as usize + 1) * WIDTH];
+                self.fifo.tick(mmu, self.line, self.window_line, row);
+            }
+            self.update_stat(mmu);
+            if self.dots < LINE_DOTS {
+                break;
+            }
+            self.dots -= LINE_DOTS;
+            self.line = (self.line + 1) % LINES;
+        }
+        events
+    }
+
+    // [FF41] STAT - bit 0-1 mode, bit 2 LYC=LY, bit 3-6 mode 0/1/2 and LYC interrupt enable
+    fn update_stat(&mut self, mmu: &mut MMU) {
+        let coincidence = self.line == mmu.io[0x45];
+        let stat = (mmu.io[0x41] & 0x78) | (coincidence as u8) << 2 | self.mode as u8;
+        mmu.io[0x44] = self.line;
+        mmu.io[0x41] = stat;
+
+        let line = match self.mode {
+            Mode::HBlank => stat & 0x08 != 0,
+            Mode::VBlank => stat & 0x10 != 0,
+            Mode::OamScan => stat & 0x20 != 0,
+            Mode::Transfer => false,
+        } || (coincidence && stat & 0x40 != 0);
+        // sources sharing one high period only raise a single interrupt ("STAT blocking")
+        if line && !self.stat_line {
+            mmu.request_interrupt(Interrupts::LCD_STAT);
+        }
+        self.stat_line = line;
+    }
+
+    fn render_line(&mut self, mmu: &MMU) {
+        let lcdc = mmu.io[0x40];
+        let ly = self.line;
+        let row = &mut self.framebuffer[ly as usize * WIDTH..(ly as usize + 1) * WIDTH];
+        // color indices before palette, sprites need them for priority
+        let mut bg_colors = [0u8; WIDTH];
+
+        // LCDC bit 0 - BG and window enable
+        if lcdc & 0x01 != 0 {
+            let scy = mmu.io[0x42];
+            let scx = mmu.io[0x43];
+            let wy = mmu.io[0x4a];
+            let wx = mmu.io[0x4b];
+            // LCDC bit 5 - window enable
+            let window = lcdc & 0x20 != 0 && ly >= wy && wx <= 166;
+            for (x, color) in bg_colors.iter_mut().enumerate() {
+                let x = x as u8;
+                *color = match window && x + 7 >= wx {
+                    // LCDC bit 6 - window tile map
+                    true => tile_pixel(mmu, lcdc, lcdc & 0x40 != 0, x + 7 - wx, self.window_line),
+                    // LCDC bit 3 - BG tile map
+                    false => tile_pixel(mmu, lcdc, lcdc & 0x08 != 0, x.wrapping_add(scx), ly.wrapping_add(scy)),
+                };
+            }
+            if window {
+                self.window_line += 1;
+            }
+        }
+        let bgp = mmu.io[0x47];
+        for (shade, color) in row.iter_mut().zip(bg_colors) {
+            *shade = palette_shade(bgp, color);
+        }
+
+        // LCDC bit 1 - sprites enable
+        if lcdc & 0x02 != 0 {
+            let height = sprite_height(lcdc);
+            let mut sprites: Vec<&[u8]> = mmu
+                .sprites
+                .chunks(4)
+                .filter(|s| (ly as u16 + 16) >= s[0] as u16 && (ly as u16 + 16) < s[0] as u16 + height as u16)
+                .take(SPRITES_PER_LINE)
+                .collect();
+            // lower X wins, ties go to lower OAM index
+            sprites.sort_by_key(|s| s[1]);
+            // first opaque sprite pixel owns the spot, even when hidden behind BG
+            let mut taken = [false; WIDTH];
+            for sprite in sprites {
+                let (y, x, mut tile, attrs) = (sprite[0], sprite[1], sprite[2], sprite[3]);
+                let mut line = ly + 16 - y;
+                if attrs & 0x40 != 0 {
+                    line = height - 1 - line;
+                }
+                if height == 16 {
+                    tile &= 0xfe;
+                }
+                let addr = tile as usize * 16 + line as usize * 2;
+                let (lo, hi) = (mmu.graphics[addr], mmu.graphics[addr + 1]);
+                let obp = if attrs & 0x10 != 0 { mmu.io[0x49] } else { mmu.io[0x48] };
+                for px in 0..8u8 {
+                    let screen_x = x as i16 - 8 + px as i16;
+                    if !(0..WIDTH as i16).contains(&screen_x) || taken[screen_x as usize] {
+                        continue;
+                    }
+                    let bit = if attrs & 0x20 != 0 { px } else { 7 - px };
+                    let color = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+                    // color 0 is transparent
+                    if color == 0 {
+                        continue;
+                    }
+                    taken[screen_x as usize] = true;
+                    // bit 7 puts sprite behind BG colors 1-3
+                    if attrs & 0x80 == 0 || bg_colors[screen_x as usize] == 0 {
+                        row[screen_x as usize] = palette_shade(obp, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// scanline capture is a debugging aid and the renderer a setting, neither is
+// saved. the FIFO's state is, states taken with either renderer load in both
+impl Component for PPU {
+    const TAG: [u8; 4] = *b"PPU ";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.mode as u8);
+        w.u32(self.dots);
+        w.u8(self.line);
+        w.u8(self.window_line);
+        w.bool(self.stat_line);
+        w.bytes(&self.framebuffer);
+        self.fifo.save(w);
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.mode = match r.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Transfer,
+            mode => return Err(StateError::Corrupt(format!("invalid PPU mode {}", mode))),
+        };
+        self.dots = r.u32()?;
+        self.line = r.u8()?;
+        self.window_line = r.u8()?;
+        self.stat_line = r.bool()?;
+        r.bytes(&mut self.framebuffer)?;
+        self.changed = [true; HEIGHT];
+        self.fifo.load(r)?;
+        Ok(())
+    }
+}
+
+// color index of BG / window pixel at x, y of selected 32x32 tile map
+fn tile_pixel(mmu: &MMU, lcdc: u8, high_map: bool, x: u8, y: u8) -> u8 {
+    let map = if high_map { 0x1c00 } else { 0x1800 };
+    let tile = mmu.graphics[map + (y as usize / 8) * 32 + x as usize / 8];
+    let addr = tile_row(lcdc, tile, y);
+    let bit = 7 - x % 8;
+    ((mmu.graphics[addr + 1] >> bit) & 1) << 1 | ((mmu.graphics[addr] >> bit) & 1)
+}
+
+// VRAM offset of row y % 8 of a BG / window tile, LCDC bit 4 - tiles at 8000
+// indexed unsigned, or at 9000 indexed signed
+pub(crate) fn tile_row(lcdc: u8, tile: u8, y: u8) -> usize {
+    let tile_addr = match lcdc & 0x10 != 0 {
+        true => tile as usize * 16,
+        false => (0x1000 + (tile as i8 as i32) * 16) as usize,
+    };
+    tile_addr + (y as usize % 8) * 2
+}
+
+// LCDC bit 2 - 8x16 sprites
+pub(crate) fn sprite_height(lcdc: u8) -> u8 {
+    if lcdc & 0x04 != 0 {
+        16
+    } else {
+        8
+    }
+}
+
+pub(crate) fn palette_shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::GB;
+
+    fn lcd_on(stat: u8, lyc: u8) -> (PPU, MMU) {
+        let mut mmu = MMU::new();
+        mmu.io[0x40] = 0x91;
+        mmu.io[0x41] = stat;
+        mmu.io[0x45] = lyc;
+        (PPU::new(), mmu)
+    }
+
+    fn run(rom: &[u8], instructions: usize) -> GB {
+        run_overclocked(rom, instructions, 1)
+    }
+
+    fn run_overclocked(rom: &[u8], instructions: usize, overclock: u32) -> GB {
+        let mut gb = GB::new(rom).unwrap();
+        gb.set_overclock(overclock).unwrap();
+        for _ in 0..instructions {
+            gb.cycle();
+        }
+        gb
+    }
+
+    #[test]
+    fn stat_reports_oam_scan_after_lcd_on() {
+        let rom = micro_rom(
+            "
+            ld a, $91
+            ldh ($40), a  ; LCD on
+            ldh a, ($41)
+            ldh ($80), a
+            ",
+        );
+        let gb = run(&rom, 4);
+        assert_eq!(gb.mmu.rb(0xff80) & 0x03, 2);
+    }
+
+    #[test]
+    fn ly_advances_every_456_dots() {
+        let rom = micro_rom("ld a, $91\nldh ($40), a");
+        // LCDC is written by the last access of LDH, PPU counts from the following NOPs
+        let gb = run(&rom, 2 + 456 * 3 / 4);
+        assert_eq!(gb.mmu.rb(0xff44), 3);
+    }
+
+    #[test]
+    fn overclock_keeps_ppu_at_nominal_speed() {
+        let rom = micro_rom("ld a, $91\nldh ($40), a");
+        // NOPs after LCD on take 2 dots each at 2x
+        let gb = run_overclocked(&rom, 2 + 456 * 3 / 2, 2);
+        assert_eq!(gb.mmu.rb(0xff44), 3);
+        // LD A,d8 and LDH before LCD on add (8 + 12) / 2
+        assert_eq!(gb.clockT, 20 / 2 + 456 * 3);
+    }
+
+    #[test]
+    fn lyc_match_sets_coincidence_and_interrupt() {
+        let (mut ppu, mut mmu) = lcd_on(0x40, 2);
+        ppu.step(&mut mmu, LINE_DOTS * 2 - 4);
+        assert_eq!(mmu.io[0x41] & 0x04, 0);
+        assert_eq!(mmu.io[0x0f] & Interrupts::LCD_STAT.bits(), 0);
+        ppu.step(&mut mmu, 4);
+        assert_eq!(mmu.io[0x44], 2);
+        assert_eq!(mmu.io[0x41] & 0x04, 0x04);
+        assert_ne!(mmu.io[0x0f] & Interrupts::LCD_STAT.bits(), 0);
+    }
+
+    #[test]
+    fn stat_blocking_merges_adjacent_sources() {
+        // HBlank and OAM scan sources both enabled, line stays high across the boundary
+        let (mut ppu, mut mmu) = lcd_on(0x28, 0xff);
+        ppu.step(&mut mmu, TRANSFER_DOTS_END);
+        assert_ne!(mmu.io[0x0f] & Interrupts::LCD_STAT.bits(), 0);
+        mmu.io[0x0f] = 0;
+        ppu.step(&mut mmu, LINE_DOTS - TRANSFER_DOTS_END);
+        assert_eq!(mmu.io[0x41] & 0x03, Mode::OamScan as u8);
+        assert_eq!(mmu.io[0x0f] & Interrupts::LCD_STAT.bits(), 0);
+        // line drops during transfer, next HBlank fires again
+        ppu.step(&mut mmu, OAM_SCAN_DOTS);
+        ppu.step(&mut mmu, TRANSFER_DOTS);
+        assert_ne!(mmu.io[0x0f] & Interrupts::LCD_STAT.bits(), 0);
+    }
+
+    #[test]
+    fn vblank_requests_interrupt() {
+        let (mut ppu, mut mmu) = lcd_on(0, 0xff);
+        ppu.step(&mut mmu, LINE_DOTS * VISIBLE_LINES as u32);
+        assert_eq!(mmu.io[0x41] & 0x03, Mode::VBlank as u8);
+        assert_ne!(mmu.io[0x0f] & Interrupts::VBLANK.bits(), 0);
+    }
+
+    #[test]
+    fn cpu_locked_out_of_vram_and_oam() {
+        let (mut ppu, mut mmu) = lcd_on(0, 0xff);
+        mmu.graphics[0] = 0x12;
+        mmu.sprites[0] = 0x34;
+        ppu.step(&mut mmu, 4);
+        assert_eq!(mmu.rb(0x8000), 0x12);
+        assert_eq!(mmu.rb(0xfe00), 0xff);
+        mmu.wb(0xfe00, 0);
+        assert_eq!(mmu.sprites[0], 0x34);
+        ppu.step(&mut mmu, OAM_SCAN_DOTS);
+        assert_eq!(mmu.rb(0x8000), 0xff);
+        mmu.wb(0x8000, 0);
+        assert_eq!(mmu.graphics[0], 0x12);
+        ppu.step(&mut mmu, TRANSFER_DOTS);
+        assert_eq!(mmu.rb(0x8000), 0x12);
+        assert_eq!(mmu.rb(0xfe00), 0x34);
+    }
+
+    #[test]
+    fn tracks_changed_lines() {
+        let (mut ppu, mut mmu) = lcd_on(0, 0xff);
+        mmu.io[0x47] = 0xe4;
+        let frame = |ppu: &mut PPU, mmu: &mut MMU| {
+            for _ in 0..FRAME_DOTS / 4 {
+                ppu.step(mmu, 4);
+            }
+            ppu.take_changed_lines()
+        };
+        // everything at first, in one run
+        let lines = frame(&mut ppu, &mut mmu);
+        assert_eq!((lines.len(), &lines[0]), (1, &(0..HEIGHT)));
+        assert!(frame(&mut ppu, &mut mmu).is_empty());
+        // tile 0's rows 2 and 5 show on every eighth line
+        mmu.graphics[4] = 0xff;
+        mmu.graphics[10] = 0x01;
+        let lines = frame(&mut ppu, &mut mmu);
+        assert_eq!(lines.len(), 2 * HEIGHT / 8);
+        assert_eq!(lines[..3], [2..3, 5..6, 10..11]);
+    }
+
+    #[test]
+    fn stat_write_keeps_read_only_bits() {
+        let (mut ppu, mut mmu) = lcd_on(0, 0);
+        ppu.step(&mut mmu, 4);
+        mmu.wb(0xff41, 0xff);
+        assert_eq!(mmu.rb(0xff41), 0xfe);
+        mmu.wb(0xff41, 0);
+        assert_eq!(mmu.rb(0xff41), 0x80 | 0x04 | Mode::OamScan as u8);
+    }
+
+    fn fifo_on(scx: u8) -> (PPU, MMU) {
+        let (mut ppu, mut mmu) = lcd_on(0, 0xff);
+        ppu.set_renderer(Renderer::Fifo);
+        mmu.io[0x43] = scx;
+        (ppu, mmu)
+    }
+
+    #[test]
+    fn fifo_mode_3_length_follows_scx() {
+        for (scx, dots) in [(0, TRANSFER_DOTS), (8, TRANSFER_DOTS), (5, TRANSFER_DOTS + 5)] {
+            let (mut ppu, mut mmu) = fifo_on(scx);
+            ppu.step(&mut mmu, OAM_SCAN_DOTS + dots - 1);
+            assert_eq!(mmu.io[0x41] & 0x03, Mode::Transfer as u8, "SCX {}", scx);
+            ppu.step(&mut mmu, 1);
+            assert_eq!(mmu.io[0x41] & 0x03, Mode::HBlank as u8, "SCX {}", scx);
+        }
+    }
+
+    #[test]
+    fn fifo_shows_palette_writes_mid_line() {
+        let (mut ppu, mut mmu) = fifo_on(0);
+        // pixel x leaves the FIFO 12 dots into mode 3
+        ppu.step(&mut mmu, OAM_SCAN_DOTS + 12 + 80);
+        mmu.io[0x47] = 0x03;
+        ppu.step(&mut mmu, LINE_DOTS);
+        assert_eq!(ppu.framebuffer()[..81], [0; 81]);
+        assert_eq!(ppu.framebuffer()[81..WIDTH], [3; WIDTH - 81]);
+        assert_eq!(ppu.framebuffer()[WIDTH], 3);
+    }
+
+    #[test]
+    fn fifo_draws_static_screens_like_scanline() {
+        let frame = |renderer| {
+            let (mut ppu, mut mmu) = lcd_on(0, 0xff);
+            ppu.set_renderer(renderer);
+            // LCDC: window at 9C00, 8000 tiles, sprites on
+            mmu.io[0x40] = 0xf3;
+            mmu.io[0x42] = 5;
+            mmu.io[0x43] = 3;
+            mmu.io[0x47] = 0xe4;
+            mmu.io[0x48] = 0xd2;
+            mmu.io[0x49] = 0x1b;
+            mmu.io[0x4a] = 40;
+            mmu.io[0x4b] = 90;
+            for (i, byte) in mmu.graphics[..0x1000].iter_mut().enumerate() {
+                *byte = ((i * 37) ^ (i >> 3)) as u8;
+            }
+            for (i, tile) in mmu.graphics[0x1800..0x2000].iter_mut().enumerate() {
+                *tile = (i * 7) as u8;
+            }
+            // overlapping, partly off screen, behind BG, flipped, OBP1
+            for (i, sprite) in [[16, 4, 1, 0], [20, 30, 2, 0x80], [24, 34, 3, 0x70], [60, 100, 4, 0], [60, 104, 5, 0x10]]
+                .iter()
+                .enumerate()
+            {
+                mmu.sprites[i * 4..i * 4 + 4].copy_from_slice(sprite);
+            }
+            for _ in 0..FRAME_DOTS / 4 {
+                ppu.step(&mut mmu, 4);
+            }
+            *ppu.framebuffer()
+        };
+        let (scanline, fifo) = (frame(Renderer::Scanline), frame(Renderer::Fifo));
+        for y in 0..HEIGHT {
+            assert_eq!(scanline[y * WIDTH..(y + 1) * WIDTH], fifo[y * WIDTH..(y + 1) * WIDTH], "line {}", y);
+        }
+    }
+}
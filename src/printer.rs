@@ -0,0 +1,311 @@
+// Game Boy Printer on the serial port, prints go to PNG files. the game sends
+// packets, the printer answers every byte, the last two with its id and status:
+//
+//   88 33 | command compression len_lo len_hi | data | checksum_lo checksum_hi | 00 00
+//
+// commands: 01 clears the buffer, 04 adds image data (2 rows of 20 tiles per
+// packet, RLE compressed if asked), 02 prints it, 0F only asks for the status.
+// a print with a margin after it ends the sheet and writes it out, prints
+// without one (Pokémon's Pokédex pages) continue on the same sheet
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::png;
+use crate::serial::SerialDevice;
+use crate::Error;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const HEADER: usize = 6;
+const DEVICE_ID: u8 = 0x81;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+
+// status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const BUSY: u8 = 0x02;
+const FULL: u8 = 0x04;
+const UNPROCESSED: u8 = 0x08;
+
+const WIDTH: usize = 160;
+// a row of 20 tiles, 8 pixels high
+const TILE_ROW: usize = 20 * 16;
+const BUFFER_SIZE: usize = 0x2280;
+// status inquiries answered busy after a print, games wait for it to clear
+const BUSY_INQUIRIES: u8 = 4;
+// white to black
+const GRAYS: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+pub struct Printer {
+    dir: PathBuf,
+    // bytes of the current packet so far
+    packet: Vec<u8>,
+    status: u8,
+    busy: u8,
+    // decompressed tile data waiting to be printed
+    buffer: Vec<u8>,
+    // grayscale rows of the sheet not cut off yet
+    sheet: Vec<u8>,
+    printed: usize,
+    // sheets that couldn't be written, for take_error
+    errors: Vec<Error>,
+}
+
+impl Printer {
+    // sheets go to <dir>/print_<n>.png
+    pub fn new(dir: &Path) -> Self {
+        Printer {
+            dir: dir.to_path_buf(),
+            packet: Vec::new(),
+            status: 0,
+            busy: 0,
+            buffer: Vec::new(),
+            sheet: Vec::new(),
+            printed: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    // sheets written so far
+    pub fn printed(&self) -> usize {
+        self.printed
+    }
+
+    fn data_len(&self) -> usize {
+        u16::from_le_bytes([self.packet[4], self.packet[5]]) as usize
+    }
+
+    fn handle(&mut self) {
+        let len = self.data_len();
+        let (body, checksum) = self.packet[2..].split_at(HEADER - 2 + len);
+        let sum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        if sum != u16::from_le_bytes([checksum[0], checksum[1]]) {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+        let (command, compressed) = (self.packet[2], self.packet[3] & 0x01 != 0);
+        let data = self.packet[HEADER..HEADER + len].to_vec();
+        match command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            }
+            DATA => {
+                match compressed {
+                    true => decompress(&data, &mut self.buffer),
+                    false => self.buffer.extend_from_slice(&data),
+                }
+                self.buffer.truncate(BUFFER_SIZE);
+                self.status |= UNPROCESSED;
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= FULL;
+                }
+            }
+            PRINT if data.len() == 4 => {
+                let (copies, margins, palette) = (data[0], data[1], data[2]);
+                // 00 works like E4 on the real printer
+                let palette = if palette == 0 { 0xe4 } else { palette };
+                for _ in 0..copies {
+                    self.print(palette);
+                }
+                self.buffer.clear();
+                self.status = (self.status & !(UNPROCESSED | FULL)) | BUSY;
+                self.busy = BUSY_INQUIRIES;
+                if margins & 0x0f != 0 {
+                    if let Err(e) = self.cut() {
+                        self.errors.push(e);
+                    }
+                }
+            }
+            // status inquiry, and what we don't know answers the same
+            _ => {}
+        }
+    }
+
+    // tile rows of the buffer onto the sheet
+    fn print(&mut self, palette: u8) {
+        for tiles in self.buffer.chunks_exact(TILE_ROW) {
+            for line in 0..8 {
+                for tile in tiles.chunks_exact(16) {
+                    let (lo, hi) = (tile[line * 2], tile[line * 2 + 1]);
+                    for bit in (0..8).rev() {
+                        let color = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+                        self.sheet.push(GRAYS[(palette >> (color * 2) & 0x03) as usize]);
+                    }
+                }
+            }
+        }
+    }
+
+    // writes the sheet out, like tearing it off
+    fn cut(&mut self) -> Result<(), Error> {
+        if self.sheet.is_empty() {
+            return Ok(());
+        }
+        let sheet = std::mem::take(&mut self.sheet);
+        let height = (sheet.len() / WIDTH) as u32;
+        let path = (1..).map(|n| self.dir.join(format!("print_{:03}.png", n))).find(|p| !p.exists()).unwrap();
+        let written = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&path, png::encode(WIDTH as u32, height, 1, &sheet)));
+        written.map_err(|source| Error::PrintIo { path, source })?;
+        self.printed += 1;
+        Ok(())
+    }
+}
+
+// RLE: a control byte with bit 7 set repeats the next byte (n & 7F) + 2 times,
+// otherwise n + 1 literal bytes follow
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        match control & 0x80 != 0 {
+            true => {
+                let Some(&byte) = data.get(i) else { return };
+                out.extend(std::iter::repeat_n(byte, (control & 0x7f) as usize + 2));
+                i += 1;
+            }
+            false => {
+                let end = (i + control as usize + 1).min(data.len());
+                out.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, out: u8) -> u8 {
+        let n = self.packet.len();
+        if n < 2 && out != MAGIC[n] {
+            // not in sync, wait for the magic bytes
+            self.packet.clear();
+            if out == MAGIC[0] {
+                self.packet.push(out);
+            }
+            return 0x00;
+        }
+        self.packet.push(out);
+        if n < HEADER {
+            return 0x00;
+        }
+        let len = self.data_len();
+        match n - HEADER {
+            i if i == len + 1 => {
+                self.handle();
+                0x00
+            }
+            i if i == len + 2 => DEVICE_ID,
+            i if i == len + 3 => {
+                self.packet.clear();
+                let status = self.status;
+                // busy a few inquiries long, then the print is done
+                if self.busy > 0 {
+                    self.busy -= 1;
+                    if self.busy == 0 {
+                        self.status &= !BUSY;
+                    }
+                }
+                status
+            }
+            _ => 0x00,
+        }
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        (!self.errors.is_empty()).then(|| self.errors.remove(0))
+    }
+
+    // what's on the sheet when the game is turned off still comes out
+    fn power_off(&mut self) {
+        if let Err(e) = self.cut() {
+            self.errors.push(e);
+        }
+    }
+}
+
+// also without power_off, a failure then goes unreported
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.cut().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    // the printer's answer to each byte of the packet
+    fn send(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x88, 0x33, command, compression];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        let sum = packet[2..].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        packet.extend_from_slice(&sum.to_le_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.iter().map(|&b| printer.transfer(b)).collect()
+    }
+
+    fn status(printer: &mut Printer, command: u8, data: &[u8]) -> u8 {
+        let replies = send(printer, command, 0, data);
+        assert_eq!(replies[replies.len() - 2], DEVICE_ID);
+        replies[replies.len() - 1]
+    }
+
+    #[test]
+    fn prints_sheets_to_png() {
+        let dir = env::temp_dir().join(format!("gb-rust-printer-{}", std::process::id()));
+        let mut printer = Printer::new(&dir);
+        assert_eq!(status(&mut printer, INIT, &[]), 0);
+        // 2 tile rows, one uncompressed, one as a single RLE run of FF (color 3)
+        assert_eq!(status(&mut printer, DATA, &[0; TILE_ROW]), UNPROCESSED);
+        let run = [0x80 | 0x7f, 0xff, 0x80 | 0x7f, 0xff, 0x80 | (TILE_ROW - 2 * 0x81 - 2) as u8, 0xff];
+        assert_eq!(send(&mut printer, DATA, 1, &run).last(), Some(&UNPROCESSED));
+        // a broken checksum is reported
+        let mut replies = vec![0x88, 0x33, 0x0f, 0, 0, 0, 0xff, 0xff, 0, 0].into_iter().map(|b| printer.transfer(b));
+        assert_eq!(replies.nth(9), Some(UNPROCESSED | CHECKSUM_ERROR));
+
+        // no margin after, the sheet continues
+        assert_eq!(status(&mut printer, PRINT, &[1, 0x10, 0xe4, 0x40]), BUSY);
+        assert_eq!(printer.printed(), 0);
+        assert_eq!(printer.sheet.len(), 16 * WIDTH);
+        assert_eq!((printer.sheet[0], printer.sheet[8 * WIDTH]), (0xff, 0x00));
+        for _ in 1..BUSY_INQUIRIES {
+            assert_eq!(status(&mut printer, 0x0f, &[]), BUSY);
+        }
+        assert_eq!(status(&mut printer, 0x0f, &[]), 0);
+
+        send(&mut printer, DATA, 0, &[0xff; TILE_ROW]);
+        send(&mut printer, PRINT, 0, &[1, 0x03, 0xe4, 0x40]);
+        assert_eq!(printer.printed(), 1);
+        let png = fs::read(dir.join("print_001.png")).unwrap();
+        // IHDR width and height
+        assert_eq!(png[16..24], [0, 0, 0, 160, 0, 0, 0, 24]);
+        assert!(printer.take_error().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn returns_sheets_it_cannot_write() {
+        // the sheets' directory is a file
+        let file = env::temp_dir().join(format!("gb-rust-printer-file-{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+        let mut printer = Printer::new(&file);
+        send(&mut printer, DATA, 0, &[0xff; TILE_ROW]);
+        send(&mut printer, PRINT, 0, &[1, 0x03, 0xe4, 0x40]);
+        send(&mut printer, DATA, 0, &[0xff; TILE_ROW]);
+        send(&mut printer, PRINT, 0, &[1, 0x00, 0xe4, 0x40]);
+        assert!(printer.take_error().unwrap().to_string().starts_with("can't write print"));
+        assert!(printer.take_error().is_none());
+        // the sheet left on the printer as the game is turned off
+        printer.power_off();
+        assert!(matches!(printer.take_error(), Some(Error::PrintIo { .. })));
+        assert_eq!(printer.printed(), 0);
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+// gameplay videos: an animated GIF written by gb-rust itself, or any file
+// ffmpeg makes from raw RGB frames on its stdin (.mp4, .webm, frame_%05d.png
+// sequences...). there's no APU yet, so recordings are silent; ffmpeg gets an
+// audio input once there are samples to give it
+//
+// GIF delays are hundredths of a second and many viewers slow down anything
+// shorter than 2, frames coming faster than that are dropped, repeated ones
+// make the previous one last longer
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use crate::gif;
+
+// hundredths of a second
+const MIN_GIF_DELAY: f64 = 2.0;
+
+enum Output {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        // the frame on screen and when it went up, in hundredths of a second
+        shown: Option<(Vec<u8>, f64)>,
+    },
+    Ffmpeg { process: Child, stdin: ChildStdin },
+}
+
+pub struct Recorder {
+    output: Output,
+    path: PathBuf,
+    fps: f64,
+    frames: u64,
+}
+
+// first <rom>-<n>.gif not taken yet
+pub fn default_path(save_dir: &Path, rom: &str) -> PathBuf {
+    let stem = Path::new(rom).file_stem().unwrap_or_default().to_string_lossy();
+    (1..).map(|n| save_dir.join(format!("{}-{:03}.gif", stem, n))).find(|path| !path.exists()).unwrap()
+}
+
+impl Recorder {
+    // .gif is encoded here, anything else goes through ffmpeg
+    pub fn start(path: &Path, width: u16, height: u16, fps: f64) -> io::Result<Recorder> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let gif = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+        let output = match gif {
+            true => Output::Gif { encoder: gif::Encoder::new(BufWriter::new(File::create(path)?), width, height)?, shown: None },
+            false => {
+                let mut process = Command::new("ffmpeg")
+                    .args(["-loglevel", "error", "-y", "-f", "rawvideo", "-pixel_format", "rgb24"])
+                    .args(["-video_size", &format!("{}x{}", width, height), "-framerate", &fps.to_string(), "-i", "-"])
+                    .arg(path)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| io::Error::new(e.kind(), format!("can't run ffmpeg: {}", e)))?;
+                let stdin = process.stdin.take().unwrap();
+                Output::Ffmpeg { process, stdin }
+            }
+        };
+        Ok(Recorder { output, path: path.to_path_buf(), fps, frames: 0 })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // packed RGB of the next frame
+    pub fn frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let now = self.frames as f64 * 100.0 / self.fps;
+        self.frames += 1;
+        match &mut self.output {
+            Output::Gif { encoder, shown } => match shown {
+                Some((last, since)) if last.as_slice() == rgb || now - *since < MIN_GIF_DELAY => Ok(()),
+                Some((last, since)) => {
+                    encoder.frame(last, (now.round() - since.round()) as u16)?;
+                    *shown = Some((rgb.to_vec(), now));
+                    Ok(())
+                }
+                None => {
+                    *shown = Some((rgb.to_vec(), now));
+                    Ok(())
+                }
+            },
+            Output::Ffmpeg { stdin, .. } => stdin.write_all(rgb),
+        }
+    }
+
+    // finishes the file, returns the frames recorded
+    pub fn stop(self) -> io::Result<u64> {
+        let end = self.frames as f64 * 100.0 / self.fps;
+        match self.output {
+            Output::Gif { mut encoder, shown } => {
+                if let Some((last, since)) = shown {
+                    let delay = (end.round() - since.round()).max(MIN_GIF_DELAY);
+                    encoder.frame(&last, delay as u16)?;
+                }
+                encoder.finish()?;
+            }
+            Output::Ffmpeg { mut process, stdin } => {
+                // end of input, ffmpeg writes the rest and exits
+                drop(stdin);
+                let status = process.wait()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!("ffmpeg failed ({})", status)));
+                }
+            }
+        }
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn records_gifs_at_most_every_other_frame() {
+        let dir = env::temp_dir().join(format!("gb-rust-recorder-{}", std::process::id()));
+        let path = default_path(&dir, "game.gb");
+        assert_eq!(path, dir.join("game-001.gif"));
+        let mut recorder = Recorder::start(&path, 2, 1, 60.0).unwrap();
+        // 1.67 hundredths per frame: the second frame is dropped, the repeat lengthens
+        // the third, the last lasts the shortest delay
+        let frames = [[0u8; 6], [1; 6], [2; 6], [2; 6], [3; 6]];
+        for rgb in &frames {
+            recorder.frame(rgb).unwrap();
+        }
+        assert_eq!(recorder.stop().unwrap(), 5);
+        let data = fs::read(&path).unwrap();
+        // graphic control extensions, the delay after the packed byte
+        let delays: Vec<u16> = data
+            .windows(6)
+            .filter(|w| w[..4] == [0x21, 0xf9, 0x04, 0x04])
+            .map(|w| u16::from_le_bytes([w[4], w[5]]))
+            .collect();
+        assert_eq!(delays, [3, 4, 2]);
+        assert_eq!(default_path(&dir, "game.gb"), dir.join("game-002.gif"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
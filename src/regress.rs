@@ -0,0 +1,184 @@
+// `gb-rust regress <file>`: runs each ROM the file lists headless from where the
+// DMG boot ROM leaves off for a number of frames and compares the FNV-1a hash of the screen then with
+// the golden one, which catches PPU and CPU changes that test ROMs printing
+// pass or fail don't. --update writes the hashes this build gives instead, for
+// new entries (hash `-`) or after a change that's meant to alter the picture
+//
+//   # ROM (relative to this file), frames, hash
+//   roms/tetris.gb 600 6b1f0a9d2e83c417
+//
+// frames count frame boundaries, which go on while the LCD is off, so a run is
+// the same on every machine. there's no APU yet, only the screen is hashed
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{cartridge, throttle, RtcMode, GB};
+
+#[derive(Debug)]
+struct Golden {
+    // index into the file's lines
+    line: usize,
+    rom: String,
+    frames: u64,
+    hash: Option<u64>,
+}
+
+struct Outcome {
+    golden: Golden,
+    // hash this build gives, or why the ROM didn't run
+    found: Result<u64, String>,
+}
+
+pub struct Report {
+    outcomes: Vec<Outcome>,
+    updated: bool,
+}
+
+impl Report {
+    // all matched, or updated without errors
+    pub fn passed(&self) -> bool {
+        self.outcomes.iter().all(|o| match &o.found {
+            Ok(hash) => self.updated || o.golden.hash == Some(*hash),
+            Err(_) => false,
+        })
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Golden>, String> {
+    let mut goldens = Vec::new();
+    for (line, text) in text.lines().enumerate() {
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", line + 1, message);
+        let [rom, frames, hash] = text.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(error("expected `rom frames hash`"));
+        };
+        let frames = frames.parse().map_err(|_| error(&format!("invalid frame count `{}`", frames)))?;
+        let hash = match hash {
+            "-" => None,
+            _ => Some(u64::from_str_radix(hash, 16).map_err(|_| error(&format!("invalid hash `{}`", hash)))?),
+        };
+        goldens.push(Golden { line, rom: rom.to_string(), frames, hash });
+    }
+    Ok(goldens)
+}
+
+// the screen after frames from the post-boot state, on a DMG without boot ROM
+pub fn frame_hash(rom: &[u8], frames: u64) -> Result<u64, String> {
+    let mut rom = rom.to_vec();
+    cartridge::fit_rom(&mut rom)?;
+    let mut gb = GB::new(&rom)?;
+    gb.set_rtc_mode(RtcMode::Emulated);
+    gb.run_frames(frames);
+    if let Some(e) = gb.crash() {
+        return Err(e.to_string());
+    }
+    Ok(throttle::frame_hash(gb.screenshot()))
+}
+
+pub fn run(path: &Path, update: bool) -> Result<Report, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    let goldens = parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let outcomes: Vec<Outcome> = goldens
+        .into_iter()
+        .map(|golden| {
+            let rom: PathBuf = base.join(&golden.rom);
+            let found = fs::read(&rom)
+                .map_err(|e| format!("can't read {}: {}", rom.display(), e))
+                .and_then(|data| frame_hash(&data, golden.frames));
+            Outcome { golden, found }
+        })
+        .collect();
+    if update {
+        let mut lines: Vec<String> = text.lines().map(String::from).collect();
+        for outcome in &outcomes {
+            if let Ok(hash) = outcome.found {
+                lines[outcome.golden.line] = format!("{} {} {:016x}", outcome.golden.rom, outcome.golden.frames, hash);
+            }
+        }
+        fs::write(path, lines.join("\n") + "\n").map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+    }
+    Ok(Report { outcomes, updated: update })
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for outcome in &self.outcomes {
+            let Golden { rom, frames, hash, .. } = &outcome.golden;
+            match (&outcome.found, hash) {
+                (Err(e), _) => writeln!(f, "  FAIL  {} frame {}: {}", rom, frames, e)?,
+                (Ok(found), _) if self.updated => writeln!(f, "  set   {} frame {}: {:016x}", rom, frames, found)?,
+                (Ok(found), Some(hash)) if found == hash => writeln!(f, "  ok    {} frame {}", rom, frames)?,
+                (Ok(found), Some(hash)) => {
+                    writeln!(f, "  FAIL  {} frame {}: hash {:016x}, golden {:016x}", rom, frames, found, hash)?
+                }
+                (Ok(found), None) => writeln!(f, "  FAIL  {} frame {}: no golden hash, {:016x} now", rom, frames, found)?,
+            }
+        }
+        let passed = self.outcomes.iter().filter(|o| o.found.is_ok()).count();
+        match self.updated {
+            true => writeln!(f, "gb-rust regress: updated {} of {} hashes", passed, self.outcomes.len()),
+            false => {
+                let matched = self.outcomes.iter().filter(|o| o.found.as_ref().ok() == o.golden.hash.as_ref()).count();
+                writeln!(f, "gb-rust regress: {} of {} screens match", matched, self.outcomes.len())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::asm::{assemble, micro_rom};
+
+    #[test]
+    fn compares_and_updates_goldens() {
+        let dir = env::temp_dir().join(format!("gb-rust-regress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // a tile of shade 3 in the top left corner from frame 2 on
+        let rom = micro_rom(
+            "ld a, $91\nldh ($40), a\nld a, $e4\nldh ($47), a\nwait: ldh a, ($44)\ncp $90\njr nz, wait\n\
+             ld a, $ff\nld hl, $8010\nld b, 16\nfill: ld (hl+), a\ndec b\njr nz, fill\nld a, 1\nld ($9800), a\nloop: jr loop",
+        );
+        fs::write(dir.join("tile.gb"), &rom).unwrap();
+        let goldens = dir.join("goldens.txt");
+        fs::write(&goldens, "# test\ntile.gb 1 -\ntile.gb 3 -\nmissing.gb 1 0\n").unwrap();
+
+        let report = run(&goldens, false).unwrap();
+        assert!(!report.passed());
+        let report = run(&goldens, true).unwrap();
+        assert!(report.to_string().contains("updated 2 of 3"), "{}", report);
+        let text = fs::read_to_string(&goldens).unwrap();
+        assert!(text.starts_with("# test\ntile.gb 1 "));
+        assert!(text.ends_with("missing.gb 1 0\n"));
+
+        fs::write(&goldens, text.replace("missing.gb 1 0\n", "")).unwrap();
+        let report = run(&goldens, false).unwrap();
+        assert!(report.passed(), "{}", report);
+        let hashes: Vec<_> = report.outcomes.iter().map(|o| o.found.clone().unwrap()).collect();
+        assert_ne!(hashes[0], hashes[1]);
+        assert!(parse("tile.gb ten -").unwrap_err().starts_with("line 1: invalid frame count"));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn hashes_games_that_rely_on_the_boot_rom() {
+        // the LCD and palette as the boot ROM left them, a VBlank handler puts
+        // a tile on screen once the first VBlank it runs for has come
+        let setup = "xor a\nldh ($0f), a\nld a, 1\nldh ($ff), a\nei\nwait: halt\njr wait";
+        let mut rom = micro_rom(setup);
+        let handler = assemble("ld a, $ff\nld hl, $8010\nld b, 16\nfill: ld (hl+), a\ndec b\njr nz, fill\n\
+             ld a, 1\nld ($9800), a\nreti", 0x40)
+        .unwrap();
+        rom[0x40..0x40 + handler.len()].copy_from_slice(&handler);
+        let blank = frame_hash(&micro_rom(setup), 3).unwrap();
+        assert_ne!(frame_hash(&rom, 3).unwrap(), blank);
+    }
+}
@@ -0,0 +1,136 @@
+use std::any::Any;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::cartridge::Header;
+use crate::palette::PaletteSettings;
+use crate::png;
+use crate::postprocess::PostProcess;
+use crate::{CLOCK_HZ, GB};
+
+const RUN_SECONDS: u64 = 120;
+// emulated seconds at which screenshots are taken, last one is also taken on crash
+const SCREENSHOT_SECONDS: [u64; 2] = [30, RUN_SECONDS];
+
+
+struct Crash {
+    message: String,
+    pc: u16,
+}
+
+// runs ROM headless from the DMG post-boot state, as games expect it without
+// a boot ROM, and writes report.json + screenshots into out_dir
+pub fn run(
+    rom_path: &str,
+    rom_data: &[u8],
+    out_dir: &Path,
+    palettes: &PaletteSettings,
+    post: &PostProcess,
+    skip_idle: bool,
+) -> io::Result<()> {
+    fs::create_dir_all(out_dir)?;
+    let header = Header::parse(rom_data);
+    let mut gb = GB::new(rom_data).map_err(io::Error::other)?;
+    gb.set_skip_idle(skip_idle);
+    let mut screenshots = Vec::new();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut next_screenshot = 0;
+        while gb.cycles_elapsed() < RUN_SECONDS * CLOCK_HZ && gb.crash().is_none() {
+            gb.cycle();
+            if gb.cycles_elapsed() >= SCREENSHOT_SECONDS[next_screenshot] * CLOCK_HZ {
+                screenshots.push(screenshot(&gb, out_dir, next_screenshot, palettes, post)?);
+                next_screenshot += 1;
+            }
+        }
+        Ok::<_, io::Error>(())
+    }));
+    let crash = match result {
+        Ok(finished) => {
+            finished?;
+            match gb.crash() {
+                Some(e) => {
+                    screenshots.push(screenshot(&gb, out_dir, screenshots.len(), palettes, post)?);
+                    Some(Crash { message: e.to_string(), pc: gb.z80.lockup.map_or(gb.z80.pc, |(_, addr)| addr) })
+                }
+                None => None,
+            }
+        }
+        Err(payload) => {
+            screenshots.push(screenshot(&gb, out_dir, screenshots.len(), palettes, post)?);
+            Some(Crash {
+                message: panic_message(payload),
+                pc: gb.z80.pc,
+            })
+        }
+    };
+
+    let mut json = String::new();
+    json.push_str("{\n");
+    writeln!(json, "  \"rom\": {},", json_string(rom_path)).unwrap();
+    writeln!(json, "  \"title\": {},", json_string(&header.title)).unwrap();
+    writeln!(json, "  \"cartridge_type\": \"{:#04x}\",", header.cartridge_type).unwrap();
+    writeln!(json, "  \"mapper\": \"{}\",", header.mapper()).unwrap();
+    writeln!(json, "  \"rom_size\": {},", header.rom_size).unwrap();
+    writeln!(json, "  \"ram_size\": {},", header.ram_size).unwrap();
+    writeln!(json, "  \"cgb\": {},", header.cgb).unwrap();
+    writeln!(json, "  \"emulated_seconds\": {:.2},", gb.emulated_time().as_secs_f64()).unwrap();
+    writeln!(json, "  \"frames\": {},", gb.frames_elapsed()).unwrap();
+    match &crash {
+        Some(crash) => writeln!(
+            json,
+            "  \"crash\": {{ \"message\": {}, \"pc\": \"{:#06x}\" }},",
+            json_string(&crash.message),
+            crash.pc
+        ),
+        None => writeln!(json, "  \"crash\": null,"),
+    }
+    .unwrap();
+    let unimplemented = &gb.mmu.unimplemented;
+    let opcodes: Vec<String> = unimplemented.opcodes().map(|op| format!("\"{:#04x}\"", op)).collect();
+    let io_reads: Vec<String> = unimplemented.io_reads().map(|addr| format!("\"{:#06x}\"", addr)).collect();
+    let io_writes: Vec<String> = unimplemented.io_writes().map(|addr| format!("\"{:#06x}\"", addr)).collect();
+    writeln!(json, "  \"unimplemented_opcodes\": [{}],", opcodes.join(", ")).unwrap();
+    writeln!(json, "  \"unimplemented_io_reads\": [{}],", io_reads.join(", ")).unwrap();
+    writeln!(json, "  \"unimplemented_io_writes\": [{}],", io_writes.join(", ")).unwrap();
+    let screenshots: Vec<String> = screenshots.iter().map(|name| json_string(name)).collect();
+    writeln!(json, "  \"screenshots\": [{}]", screenshots.join(", ")).unwrap();
+    json.push_str("}\n");
+    fs::write(out_dir.join("report.json"), json)
+}
+
+fn screenshot(gb: &GB, out_dir: &Path, index: usize, palettes: &PaletteSettings, post: &PostProcess) -> io::Result<String> {
+    let (width, height) = gb.display_size();
+    let (width, height, pixels) = post.apply(&gb.display(palettes), width as u32, height as u32);
+    let name = format!("screenshot_{}.png", index + 1);
+    let data = png::encode(width, height, 3, &pixels);
+    fs::write(out_dir.join(&name), data)?;
+    Ok(name)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
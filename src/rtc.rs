@@ -0,0 +1,270 @@
+// MBC3 real time clock: seconds, minutes, hours and a 9 bit day counter, the
+// game reads the copy the last latch took. it counts on one of two clocks: the
+// host's, so like the battery powered original it has moved on by the time the
+// game is started again, or the emulated one, which only runs with the game and
+// at its speed, the same on every run as movies need
+//
+// it's brought up to date when a game writes to the cartridge, the only way to
+// latch or change it. .sav files end in the 48 byte footer BGB and
+// VisualBoyAdvance write: the live and the latched registers as u32 each, then
+// the unix time of the save as u64. the older 44 byte one with a u32 time loads too
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+pub const FOOTER_LEN: usize = 48;
+const OLD_FOOTER_LEN: usize = 44;
+
+const NANOS: u64 = 1_000_000_000;
+const DAY: u64 = 86_400;
+
+// the day counter's bit 8, stopped and overflowed flags in DH
+const DAY_HIGH: u8 = 0x01;
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+// bits each of S M H DL DH has
+const MASKS: [u8; 5] = [0x3f, 0x3f, 0x1f, 0xff, 0xc1];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RtcMode {
+    // follows the host clock, also while the emulator isn't running
+    Host,
+    // runs with emulated time, the same on every run
+    #[default]
+    Emulated,
+}
+
+impl RtcMode {
+    pub fn parse(name: &str) -> Result<RtcMode, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "host" => Ok(RtcMode::Host),
+            "emulated" => Ok(RtcMode::Emulated),
+            _ => Err(format!("unknown RTC mode `{}`, expected host or emulated", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RtcMode::Host => "host",
+            RtcMode::Emulated => "emulated",
+        }
+    }
+}
+
+fn host_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rtc {
+    mode: RtcMode,
+    // S M H DL DH
+    live: [u8; 5],
+    latched: [u8; 5],
+    // the last latch write was 0, a 1 now latches
+    latch_armed: bool,
+    // nanoseconds on the mode's clock the live registers count up to, emulated
+    // since power on or host since the unix epoch. None starts counting now
+    synced: Option<u64>,
+}
+
+impl Rtc {
+    // counting starts over on the new clock
+    pub fn set_mode(&mut self, mode: RtcMode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.synced = None;
+        }
+    }
+
+    fn now(&self, emulated: Duration) -> u64 {
+        match self.mode {
+            RtcMode::Host => host_nanos(),
+            RtcMode::Emulated => emulated.as_nanos() as u64,
+        }
+    }
+
+    // counts the whole seconds since the last sync, emulated is the time since power on
+    pub fn sync(&mut self, emulated: Duration) {
+        let now = self.now(emulated);
+        let synced = match self.synced {
+            // a savestate from later on or another clock, start from here
+            Some(synced) if synced <= now => synced,
+            _ => {
+                self.synced = Some(now);
+                return;
+            }
+        };
+        let seconds = (now - synced) / NANOS;
+        self.synced = Some(synced + seconds * NANOS);
+        if self.live[4] & HALT == 0 {
+            self.advance(seconds);
+        }
+    }
+
+    fn in_range(&self) -> bool {
+        self.live[0] < 60 && self.live[1] < 60 && self.live[2] < 24
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // a value the game set out of range counts up to the register's width and
+        // wraps without carrying, step by step until all are in range again
+        while seconds > 0 && !self.in_range() {
+            self.tick();
+            seconds -= 1;
+        }
+        let [s, m, h, dl, dh] = self.live.map(u64::from);
+        let total = s + m * 60 + h * 3600 + (dl | (dh & 1) << 8) * DAY + seconds;
+        let days = total / DAY;
+        self.live[0] = (total % 60) as u8;
+        self.live[1] = (total / 60 % 60) as u8;
+        self.live[2] = (total / 3600 % 24) as u8;
+        self.live[3] = days as u8;
+        let carry = if days > 0x1ff { DAY_CARRY } else { 0 };
+        self.live[4] = (self.live[4] & (HALT | DAY_CARRY)) | carry | ((days >> 8) as u8 & DAY_HIGH);
+    }
+
+    // one second the way the counters do it
+    fn tick(&mut self) {
+        for (i, limit) in [(0, 60), (1, 60), (2, 24)] {
+            self.live[i] = (self.live[i] + 1) & MASKS[i];
+            if self.live[i] != limit {
+                return;
+            }
+            self.live[i] = 0;
+        }
+        let days = (self.live[3] as u16 | ((self.live[4] & DAY_HIGH) as u16) << 8) + 1;
+        self.live[3] = days as u8;
+        self.live[4] = (self.live[4] & !DAY_HIGH) | ((days >> 8) as u8 & DAY_HIGH);
+        if days > 0x1ff {
+            self.live[4] |= DAY_CARRY;
+        }
+    }
+
+    // register 08 (seconds) to 0C (DH) as latched
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - 0x08) as usize]
+    }
+
+    // sets the live register, synced up to now
+    pub fn write(&mut self, register: u8, val: u8) {
+        let i = (register - 0x08) as usize;
+        self.live[i] = val & MASKS[i];
+    }
+
+    // [6000-7FFF], writing 0 then 1 copies the live registers to the latched ones
+    pub fn latch(&mut self, val: u8) {
+        if self.latch_armed && val == 1 {
+            self.latched = self.live;
+        }
+        self.latch_armed = val == 0;
+    }
+
+    // for the .sav file, synced up to now
+    pub fn footer(&self) -> [u8; FOOTER_LEN] {
+        let mut footer = [0; FOOTER_LEN];
+        for (i, &reg) in self.live.iter().chain(&self.latched).enumerate() {
+            footer[i * 4] = reg;
+        }
+        footer[40..].copy_from_slice(&(host_nanos() / NANOS).to_le_bytes());
+        footer
+    }
+
+    // on the host clock the time since the save passes on the next sync
+    pub fn load_footer(&mut self, footer: &[u8]) -> Result<(), String> {
+        let saved = match footer.len() {
+            FOOTER_LEN => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            OLD_FOOTER_LEN => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            len => return Err(format!("RTC footer of {} bytes, expected {} or {}", len, FOOTER_LEN, OLD_FOOTER_LEN)),
+        };
+        let reg = |i: usize| footer[i * 4] & MASKS[i % 5];
+        self.live = std::array::from_fn(reg);
+        self.latched = std::array::from_fn(|i| reg(i + 5));
+        self.synced = match self.mode {
+            RtcMode::Host => Some(saved.saturating_mul(NANOS)),
+            RtcMode::Emulated => None,
+        };
+        Ok(())
+    }
+
+    // the mode isn't machine state, it stays as set
+    pub fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.live);
+        w.bytes(&self.latched);
+        w.bool(self.latch_armed);
+        w.bool(self.synced.is_some());
+        w.u64(self.synced.unwrap_or_default());
+    }
+
+    pub fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(&mut self.live)?;
+        r.bytes(&mut self.latched)?;
+        self.latch_armed = r.bool()?;
+        let synced = r.bool()?;
+        let at = r.u64()?;
+        self.synced = synced.then_some(at);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn counts_and_carries() {
+        let mut rtc = Rtc::default();
+        rtc.sync(seconds(0));
+        // a day, an hour, a minute and a second, over the 9 bit day counter
+        rtc.write(0x0b, 0xff);
+        rtc.write(0x0c, 0x01);
+        rtc.sync(seconds(DAY + 3661) + Duration::from_millis(500));
+        rtc.latch(0);
+        rtc.latch(1);
+        assert_eq!([0x08, 0x09, 0x0a, 0x0b, 0x0c].map(|r| rtc.read(r)), [1, 1, 1, 0, 0x80]);
+        // the half second still counts
+        rtc.sync(seconds(DAY + 3662));
+        assert_eq!(rtc.live[0], 2);
+
+        // stopped, then 63 seconds wraps to 0 without carrying
+        rtc.write(0x0c, HALT);
+        rtc.sync(seconds(DAY + 4000));
+        assert_eq!(rtc.live[0], 2);
+        rtc.write(0x08, 62);
+        rtc.write(0x0c, 0);
+        rtc.sync(seconds(DAY + 4003));
+        assert_eq!(rtc.live[..2], [1, 1]);
+        // latching needs the 0 first
+        rtc.latch(1);
+        assert_eq!(rtc.read(0x0c), 0x80);
+    }
+
+    #[test]
+    fn footer_round_trips() {
+        let mut rtc = Rtc::default();
+        rtc.sync(seconds(0));
+        rtc.write(0x0a, 23);
+        rtc.latch(0);
+        rtc.latch(1);
+        let footer = rtc.footer();
+        assert_eq!(footer[8..12], [23, 0, 0, 0]);
+        assert_eq!(footer[28..32], [23, 0, 0, 0]);
+
+        // saved two hours ago
+        let mut old = footer[..OLD_FOOTER_LEN].to_vec();
+        let then = (host_nanos() / NANOS - 7200) as u32;
+        old[40..].copy_from_slice(&then.to_le_bytes());
+        let mut host = Rtc::default();
+        host.set_mode(RtcMode::Host);
+        host.load_footer(&old).unwrap();
+        host.sync(seconds(0));
+        assert_eq!(host.live[2..4], [1, 1]);
+        assert_eq!(host.latched, rtc.latched);
+        assert!(host.load_footer(&footer[..40]).is_err());
+    }
+}
@@ -0,0 +1,527 @@
+// savestates - versioned header followed by LZ4 compressed, tagged component chunks
+//
+// header: "GBRS" | format version u16 | compression u8 | payload length u32
+// chunk:  tag [u8; 4] | component version u8 | length u32 | data
+//
+// Bump FORMAT_VERSION when the chunk list changes and add a shim to MIGRATIONS
+// rewriting the chunks of the previous version. Layout changes inside a single
+// component bump its VERSION instead, its load() gets the version it was saved with.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::cartridge::Header;
+use crate::joypad::Joypad;
+use crate::lz4;
+use crate::mbc::Mbc;
+use crate::sgb::Sgb;
+use crate::{Flags, GB, MMU, Z80};
+
+const MAGIC: &[u8; 4] = b"GBRS";
+pub const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 11;
+
+// payload compression
+const STORED: u8 = 0;
+const LZ4: u8 = 1;
+
+type Tag = [u8; 4];
+type Chunks = BTreeMap<Tag, (u8, Vec<u8>)>;
+
+type Migration = fn(&mut Chunks) -> Result<(), StateError>;
+// MIGRATIONS[n - 1] upgrades chunks written by format version n to n + 1
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize - 1] = [];
+
+const MACHINE_TAG: Tag = *b"GB  ";
+const MACHINE_VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    NotASavestate,
+    NewerFormat { found: u16 },
+    UnknownCompression(u8),
+    Corrupt(String),
+    MissingComponent(Tag),
+    NewerComponent { tag: Tag, found: u8, supported: u8 },
+    WrongRom { state: String, loaded: String },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotASavestate => write!(f, "not a gb-rust savestate (missing GBRS header)"),
+            StateError::NewerFormat { found } => write!(
+                f,
+                "savestate format version {} is newer than the supported version {}, update gb-rust to load it",
+                found, FORMAT_VERSION
+            ),
+            StateError::UnknownCompression(method) => {
+                write!(f, "savestate uses unknown compression method {}, file is damaged or from a newer gb-rust", method)
+            }
+            StateError::Corrupt(reason) => write!(f, "savestate is damaged: {}", reason),
+            StateError::MissingComponent(tag) => {
+                write!(f, "savestate has no `{}` component, file is damaged or was not written by gb-rust", tag_name(tag))
+            }
+            StateError::NewerComponent { tag, found, supported } => write!(
+                f,
+                "savestate component `{}` has version {}, this gb-rust only reads up to {}, update gb-rust to load it",
+                tag_name(tag),
+                found,
+                supported
+            ),
+            StateError::WrongRom { state, loaded } => {
+                write!(f, "savestate was made with ROM {}, but ROM {} is loaded", state, loaded)
+            }
+        }
+    }
+}
+
+fn tag_name(tag: &Tag) -> String {
+    String::from_utf8_lossy(tag).trim_end().to_string()
+}
+
+// part of the machine stored as one chunk
+pub trait Component {
+    const TAG: Tag;
+    const VERSION: u8;
+    fn save(&self, w: &mut StateWriter);
+    // version is the one the chunk was saved with, never newer than VERSION
+    fn load(&mut self, r: &mut StateReader, version: u8) -> Result<(), StateError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+    pub fn bool(&mut self, val: bool) {
+        self.buf.push(val as u8);
+    }
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+    pub fn u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+    pub fn u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+    pub fn bytes(&mut self, val: &[u8]) {
+        self.buf.extend_from_slice(val);
+    }
+}
+
+pub struct StateReader<'a> {
+    tag: Tag,
+    data: &'a [u8],
+}
+
+impl StateReader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut out = [0; N];
+        self.bytes(&mut out)?;
+        Ok(out)
+    }
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take::<1>()?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        if self.data.len() < out.len() {
+            return Err(StateError::Corrupt(format!("`{}` component is truncated", tag_name(&self.tag))));
+        }
+        let (head, rest) = self.data.split_at(out.len());
+        out.copy_from_slice(head);
+        self.data = rest;
+        Ok(())
+    }
+}
+
+impl Component for Z80 {
+    const TAG: Tag = *b"CPU ";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        for reg in [self.a, self.f.bits(), self.b, self.c, self.d, self.e, self.h, self.l, self.m, self.t] {
+            w.u8(reg);
+        }
+        w.u16(self.sp);
+        w.u16(self.pc);
+        for flag in [self.ime, self.ei_pending, self.halted, self.halt_bug] {
+            w.bool(flag);
+        }
+        let (op, addr) = self.lockup.unwrap_or_default();
+        w.bool(self.lockup.is_some());
+        w.u8(op);
+        w.u16(addr);
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.a = r.u8()?;
+        self.f = Flags::from_bits_truncate(r.u8()?);
+        for reg in [&mut self.b, &mut self.c, &mut self.d, &mut self.e, &mut self.h, &mut self.l, &mut self.m, &mut self.t] {
+            *reg = r.u8()?;
+        }
+        self.sp = r.u16()?;
+        self.pc = r.u16()?;
+        for flag in [&mut self.ime, &mut self.ei_pending, &mut self.halted, &mut self.halt_bug] {
+            *flag = r.bool()?;
+        }
+        let locked = r.bool()?;
+        let (op, addr) = (r.u8()?, r.u16()?);
+        self.lockup = locked.then_some((op, addr));
+        Ok(())
+    }
+}
+
+// memory and plain IO registers, peripherals with own state are separate components
+impl Component for MMU {
+    const TAG: Tag = *b"MEM ";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.booted);
+        w.bytes(&self.graphics);
+        w.bytes(&self.external_ram);
+        w.bytes(&self.ram);
+        w.bytes(&self.sprites);
+        w.bytes(self.io.bytes());
+        w.bytes(&self.work_ram);
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.booted = r.bool()?;
+        r.bytes(&mut self.graphics)?;
+        r.bytes(&mut self.external_ram)?;
+        r.bytes(&mut self.ram)?;
+        // no telling what the state's WRAM was written by
+        if let Some(written) = self.wram_written.as_mut() {
+            written.fill(!0);
+        }
+        r.bytes(&mut self.sprites)?;
+        r.bytes(self.io.bytes_mut())?;
+        r.bytes(&mut self.work_ram)?;
+        Ok(())
+    }
+}
+
+// <save_dir>/<rom file stem>.state
+pub fn default_path(save_dir: &Path, rom: &str) -> PathBuf {
+    let stem = Path::new(rom).file_stem().unwrap_or_default();
+    save_dir.join(format!("{}.state", stem.to_string_lossy()))
+}
+
+pub fn save(gb: &GB) -> Vec<u8> {
+    let payload = payload(gb);
+    let mut out = header(LZ4, payload.len());
+    out.extend_from_slice(&lz4::compress(&payload));
+    out
+}
+
+// same layout without compression, every state of a build has the same size
+// as libretro frontends expect
+pub fn save_stored(gb: &GB) -> Vec<u8> {
+    let payload = payload(gb);
+    let mut out = header(STORED, payload.len());
+    out.extend_from_slice(&payload);
+    out
+}
+
+fn header(compression: u8, payload_len: usize) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.push(compression);
+    out.extend_from_slice(&(payload_len as u32).to_le_bytes());
+    out
+}
+
+fn payload(gb: &GB) -> Vec<u8> {
+    let mut payload = Vec::new();
+    chunk(&mut payload, MACHINE_TAG, MACHINE_VERSION, |w| {
+        w.bytes(&rom_id(&gb.mmu.rom));
+        w.u64(gb.clockT);
+        w.u32(gb.overclock_remainder);
+        w.u64(gb.frames);
+    });
+    component(&mut payload, &gb.z80);
+    component(&mut payload, &gb.mmu);
+    component(&mut payload, &gb.mmu.joypad);
+    component(&mut payload, &gb.mmu.timer);
+    component(&mut payload, &gb.mmu.serial);
+    component(&mut payload, &gb.mmu.hdma);
+    component(&mut payload, &gb.mmu.mbc);
+    component(&mut payload, &gb.mmu.sgb);
+    component(&mut payload, &gb.ppu);
+    payload
+}
+
+// state is only changed once the whole file checked out
+pub fn load(gb: &mut GB, data: &[u8]) -> Result<(), StateError> {
+    if data.len() < HEADER_LEN || &data[0..4] != MAGIC {
+        return Err(StateError::NotASavestate);
+    }
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version == 0 {
+        return Err(StateError::NotASavestate);
+    }
+    if version > FORMAT_VERSION {
+        return Err(StateError::NewerFormat { found: version });
+    }
+    let len = u32::from_le_bytes([data[7], data[8], data[9], data[10]]) as usize;
+    let payload = match data[6] {
+        STORED if data.len() - HEADER_LEN == len => data[HEADER_LEN..].to_vec(),
+        STORED => return Err(StateError::Corrupt("payload length does not match header".to_string())),
+        LZ4 => lz4::decompress(&data[HEADER_LEN..], len).map_err(StateError::Corrupt)?,
+        method => return Err(StateError::UnknownCompression(method)),
+    };
+
+    let mut chunks = parse_chunks(&payload)?;
+    for migrate in &MIGRATIONS[version as usize - 1..] {
+        migrate(&mut chunks)?;
+    }
+
+    for (tag, supported) in [
+        (MACHINE_TAG, MACHINE_VERSION),
+        (Z80::TAG, Z80::VERSION),
+        (MMU::TAG, MMU::VERSION),
+        (Joypad::TAG, Joypad::VERSION),
+        (crate::timer::Timer::TAG, crate::timer::Timer::VERSION),
+        (crate::serial::Serial::TAG, crate::serial::Serial::VERSION),
+        (crate::hdma::HDMA::TAG, crate::hdma::HDMA::VERSION),
+        (Mbc::TAG, Mbc::VERSION),
+        (Sgb::TAG, Sgb::VERSION),
+        (crate::ppu::PPU::TAG, crate::ppu::PPU::VERSION),
+    ] {
+        match chunks.get(&tag) {
+            None => return Err(StateError::MissingComponent(tag)),
+            Some(&(found, _)) if found > supported => {
+                return Err(StateError::NewerComponent { tag, found, supported })
+            }
+            Some(_) => {}
+        }
+    }
+
+    let (_, machine) = &chunks[&MACHINE_TAG];
+    let mut r = StateReader { tag: MACHINE_TAG, data: machine };
+    let state_rom = r.take::<18>()?;
+    let loaded_rom = rom_id(&gb.mmu.rom);
+    if state_rom != loaded_rom {
+        return Err(StateError::WrongRom { state: describe_rom(&state_rom), loaded: describe_rom(&loaded_rom) });
+    }
+    let clocks = (r.u64()?, r.u32()?);
+    let frames = r.u64()?;
+    finish(&r)?;
+
+    // dry run into scratch copies first so a damaged chunk leaves the running game alone
+    restore_all(&chunks, &mut Z80::default(), &mut MMU::new(), &mut crate::ppu::PPU::new())?;
+    (gb.clockT, gb.overclock_remainder) = clocks;
+    gb.frames = frames;
+    gb.frame_start = gb.clockT;
+    restore_all(&chunks, &mut gb.z80, &mut gb.mmu, &mut gb.ppu)?;
+    gb.mmu.map_rom_bank();
+    Ok(())
+}
+
+// components load in place, keeping what isn't machine state (serial device, scanline capture)
+fn restore_all(chunks: &Chunks, z80: &mut Z80, mmu: &mut MMU, ppu: &mut crate::ppu::PPU) -> Result<(), StateError> {
+    restore(chunks, z80)?;
+    restore(chunks, mmu)?;
+    restore(chunks, &mut mmu.joypad)?;
+    restore(chunks, &mut mmu.timer)?;
+    restore(chunks, &mut mmu.serial)?;
+    restore(chunks, &mut mmu.hdma)?;
+    restore(chunks, &mut mmu.mbc)?;
+    restore(chunks, &mut mmu.sgb)?;
+    restore(chunks, ppu)
+}
+
+fn chunk(out: &mut Vec<u8>, tag: Tag, version: u8, f: impl FnOnce(&mut StateWriter)) {
+    let mut w = StateWriter::default();
+    f(&mut w);
+    out.extend_from_slice(&tag);
+    out.push(version);
+    out.extend_from_slice(&(w.buf.len() as u32).to_le_bytes());
+    out.extend_from_slice(&w.buf);
+}
+
+fn component<C: Component>(out: &mut Vec<u8>, c: &C) {
+    chunk(out, C::TAG, C::VERSION, |w| c.save(w));
+}
+
+fn restore<C: Component>(chunks: &Chunks, c: &mut C) -> Result<(), StateError> {
+    let (version, data) = &chunks[&C::TAG];
+    let mut r = StateReader { tag: C::TAG, data };
+    c.load(&mut r, *version)?;
+    finish(&r)
+}
+
+fn finish(r: &StateReader) -> Result<(), StateError> {
+    match r.data.len() {
+        0 => Ok(()),
+        n => Err(StateError::Corrupt(format!("{} unexpected bytes at end of `{}` component", n, tag_name(&r.tag)))),
+    }
+}
+
+fn parse_chunks(mut payload: &[u8]) -> Result<Chunks, StateError> {
+    let mut chunks = Chunks::new();
+    while !payload.is_empty() {
+        if payload.len() < 9 {
+            return Err(StateError::Corrupt("truncated component header".to_string()));
+        }
+        let tag: Tag = payload[0..4].try_into().unwrap();
+        let version = payload[4];
+        let len = u32::from_le_bytes(payload[5..9].try_into().unwrap()) as usize;
+        let data = payload[9..]
+            .get(..len)
+            .ok_or_else(|| StateError::Corrupt(format!("`{}` component is truncated", tag_name(&tag))))?;
+        chunks.insert(tag, (version, data.to_vec()));
+        payload = &payload[9 + len..];
+    }
+    Ok(chunks)
+}
+
+// [0134-0143] title and [014E-014F] global checksum
+fn rom_id(rom: &[u8]) -> [u8; 18] {
+    let mut id = [0; 18];
+    id[..16].copy_from_slice(&rom[0x134..0x144]);
+    id[16..].copy_from_slice(&rom[0x14e..0x150]);
+    id
+}
+
+fn describe_rom(id: &[u8; 18]) -> String {
+    let mut header = vec![0; 0x150];
+    header[0x134..0x144].copy_from_slice(&id[..16]);
+    format!("\"{}\" (checksum {:02X}{:02X})", Header::parse(&header).title, id[16], id[17])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::hdma::HDMA;
+
+    #[test]
+    fn lz4_round_trips() {
+        let mut data = vec![0u8; 5000];
+        data.extend((0..3000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8));
+        data.extend(b"abcabcabcabcabcabcabcabcabcabc");
+        data.extend([7; 300]);
+        for len in [0, 1, 5, 12, 13, 17, 100, data.len()] {
+            let packed = lz4::compress(&data[..len]);
+            assert_eq!(lz4::decompress(&packed, len).unwrap(), &data[..len]);
+        }
+        assert!(lz4::compress(&data).len() < data.len() / 2);
+        // a damaged header's length is an error, not an allocation
+        assert!(lz4::decompress(&lz4::compress(b"short"), u32::MAX as usize).is_err());
+    }
+
+    #[test]
+    fn restores_machine() {
+        let rom = micro_rom("ld a, $91\nldh ($40), a\nld a, $42\nldh ($80), a");
+        let mut gb = GB::new(&rom).unwrap();
+        for _ in 0..4 {
+            gb.cycle();
+        }
+        let state = save(&gb);
+        assert!(state.len() < 4096);
+        for _ in 0..1000 {
+            gb.cycle();
+        }
+        let (pc, clock, ly) = (gb.z80.pc, gb.clockT, gb.mmu.io[0x44]);
+        load(&mut gb, &state).unwrap();
+        assert_eq!(gb.z80.a, 0x42);
+        assert_eq!(gb.mmu.rb(0xff80), 0x42);
+        for _ in 0..1000 {
+            gb.cycle();
+        }
+        assert_eq!((gb.z80.pc, gb.clockT, gb.mmu.io[0x44]), (pc, clock, ly));
+        // uncompressed states keep their size
+        let stored = save_stored(&gb);
+        gb.cycle();
+        assert_eq!(save_stored(&gb).len(), stored.len());
+        load(&mut gb, &stored).unwrap();
+        assert_eq!(gb.clockT, clock);
+    }
+
+    #[test]
+    fn rejects_newer_format() {
+        let rom = micro_rom("nop");
+        let mut gb = GB::new(&rom).unwrap();
+        let mut state = save(&gb);
+        state[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(load(&mut gb, &state), Err(StateError::NewerFormat { found: FORMAT_VERSION + 1 }));
+        assert_eq!(load(&mut gb, b"not a state"), Err(StateError::NotASavestate));
+    }
+
+    fn unpack(state: &[u8]) -> Chunks {
+        let len = u32::from_le_bytes(state[7..11].try_into().unwrap()) as usize;
+        parse_chunks(&lz4::decompress(&state[HEADER_LEN..], len).unwrap()).unwrap()
+    }
+
+    fn pack_stored(chunks: &Chunks) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for (tag, (version, data)) in chunks {
+            chunk(&mut payload, *tag, *version, |w| w.bytes(data));
+        }
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.push(STORED);
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend(payload);
+        out
+    }
+
+    #[test]
+    fn checks_components() {
+        let rom = micro_rom("nop");
+        let mut gb = GB::new(&rom).unwrap();
+        let chunks = unpack(&save(&gb));
+        assert_eq!(load(&mut gb, &pack_stored(&chunks)), Ok(()));
+
+        let mut missing = unpack(&save(&gb));
+        missing.remove(&MMU::TAG);
+        assert_eq!(load(&mut gb, &pack_stored(&missing)), Err(StateError::MissingComponent(MMU::TAG)));
+
+        let mut newer = unpack(&save(&gb));
+        newer.get_mut(&Z80::TAG).unwrap().0 = Z80::VERSION + 1;
+        assert_eq!(
+            load(&mut gb, &pack_stored(&newer)),
+            Err(StateError::NewerComponent { tag: Z80::TAG, found: Z80::VERSION + 1, supported: Z80::VERSION })
+        );
+
+        let mut truncated = unpack(&save(&gb));
+        truncated.get_mut(&MMU::TAG).unwrap().1.pop();
+        gb.z80.a = 0x12;
+        assert!(matches!(load(&mut gb, &pack_stored(&truncated)), Err(StateError::Corrupt(_))));
+        assert_eq!(gb.z80.a, 0x12);
+
+        // an HBlank transfer with no blocks left
+        let mut hdma = unpack(&save(&gb));
+        hdma.get_mut(&HDMA::TAG).unwrap().1[5] = 1;
+        assert!(matches!(load(&mut gb, &pack_stored(&hdma)), Err(StateError::Corrupt(_))));
+    }
+
+    #[test]
+    fn rejects_other_rom() {
+        let rom = micro_rom("nop");
+        let mut other = micro_rom("nop");
+        other[0x134..0x138].copy_from_slice(b"ZELD");
+        let state = save(&GB::new(&rom).unwrap());
+        let mut gb = GB::new(&other).unwrap();
+        let err = load(&mut gb, &state).unwrap_err();
+        assert!(matches!(err, StateError::WrongRom { .. }));
+        assert!(err.to_string().contains("ZELD"));
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+
+// visible lines per frame
+const LINES: usize = 144;
+
+// registers latched at start of mode 3 of a scanline
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanlineRegs {
+    pub lcdc: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub bgp: u8,
+    pub wy: u8,
+    pub wx: u8,
+}
+
+impl ScanlineRegs {
+    pub fn from_io(io: &[u8]) -> Self {
+        ScanlineRegs {
+            lcdc: io[0x40],
+            scy: io[0x42],
+            scx: io[0x43],
+            bgp: io[0x47],
+            wy: io[0x4a],
+            wx: io[0x4b],
+        }
+    }
+}
+
+// per-scanline registers of the frame being drawn and of the last finished one
+pub struct ScanlineCapture {
+    current: [ScanlineRegs; LINES],
+    last: [ScanlineRegs; LINES],
+}
+
+impl Default for ScanlineCapture {
+    fn default() -> Self {
+        ScanlineCapture {
+            current: [Default::default(); LINES],
+            last: [Default::default(); LINES],
+        }
+    }
+}
+
+impl ScanlineCapture {
+    pub fn record(&mut self, line: u8, regs: ScanlineRegs) {
+        if let Some(entry) = self.current.get_mut(line as usize) {
+            *entry = regs;
+        }
+    }
+
+    pub fn finish_frame(&mut self) {
+        self.last = self.current;
+    }
+
+    pub fn last_frame(&self) -> &[ScanlineRegs; LINES] {
+        &self.last
+    }
+}
+
+// table of last frame, SCX / SCY plotted next to each line to make wobble visible
+impl fmt::Display for ScanlineCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, " LY LCDC SCY SCX BGP  WY  WX  SCX (x) / SCY (y)")?;
+        for (ly, regs) in self.last.iter().enumerate() {
+            let mut graph = [b' '; 64];
+            graph[(regs.scy / 4) as usize] = b'y';
+            graph[(regs.scx / 4) as usize] = b'x';
+            writeln!(
+                f,
+                "{:3} {:02X}   {:3} {:3} {:02X}  {:3} {:3} |{}",
+                ly,
+                regs.lcdc,
+                regs.scy,
+                regs.scx,
+                regs.bgp,
+                regs.wy,
+                regs.wx,
+                String::from_utf8_lossy(&graph).trim_end()
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+// when the peripherals next need to run, on the clockT timeline. each one is
+// stepped when its event comes due (a PPU mode or LY change, a timer or serial
+// interrupt), when the CPU touches its registers, and at the end of every
+// instruction so the rest of the emulator sees it up to date. stepping several
+// ticks at once is the same as one by one as long as no event falls in between
+//
+// a register write makes the peripheral due on the next tick, which is when it
+// would have seen the write stepping tick by tick. every instruction starts
+// from a fresh schedule, so whatever happened in between (savestates, debugger
+// pokes, resets) is picked up without telling the scheduler
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Source {
+    Ppu,
+    Serial,
+    Timer,
+}
+
+impl Source {
+    // the order peripherals step in when due on the same tick
+    pub(crate) const ALL: [Source; 3] = [Source::Ppu, Source::Serial, Source::Timer];
+
+    // the one whose registers are at addr, if any
+    pub(crate) fn at(addr: u16) -> Option<Source> {
+        match addr {
+            0xff01..=0xff02 => Some(Source::Serial),
+            0xff04..=0xff07 => Some(Source::Timer),
+            0xff40..=0xff4b => Some(Source::Ppu),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Scheduler {
+    // clockT each source was stepped up to, and when its next event is
+    synced: [u64; 3],
+    due: [u64; 3],
+    // registers written and not stepped since
+    written: [bool; 3],
+}
+
+impl Scheduler {
+    // stepped up to now, next event until t-cycles later
+    pub(crate) fn schedule(&mut self, source: Source, now: u64, until: u32) {
+        let until = if self.written[source as usize] { 1 } else { until };
+        self.synced[source as usize] = now;
+        self.due[source as usize] = now.saturating_add(until as u64);
+    }
+
+    pub(crate) fn written(&mut self, source: Source, now: u64) {
+        self.written[source as usize] = true;
+        self.due[source as usize] = self.due[source as usize].min(now + 1);
+    }
+
+    // t-cycles the source is behind now, it counts as stepped after this
+    pub(crate) fn catch_up(&mut self, source: Source, now: u64) -> u32 {
+        let behind = now - std::mem::replace(&mut self.synced[source as usize], now);
+        if behind > 0 {
+            self.written[source as usize] = false;
+        }
+        behind as u32
+    }
+
+    pub(crate) fn due(&self, source: Source, now: u64) -> bool {
+        self.due[source as usize] <= now
+    }
+
+    // t-cycles from now until the earliest event
+    pub(crate) fn until_next(&self, now: u64) -> u64 {
+        self.due.iter().min().unwrap().saturating_sub(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_due_events() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(Source::Ppu, 100, 80);
+        scheduler.schedule(Source::Serial, 100, u32::MAX);
+        scheduler.schedule(Source::Timer, 100, 30);
+        assert_eq!(scheduler.until_next(110), 20);
+        assert!(!scheduler.due(Source::Timer, 129) && scheduler.due(Source::Timer, 130));
+        assert_eq!(scheduler.catch_up(Source::Timer, 134), 34);
+        assert_eq!(scheduler.catch_up(Source::Timer, 134), 0);
+        scheduler.schedule(Source::Timer, 134, 1000);
+        // a write is seen on the next tick, even after the next instruction began
+        scheduler.written(Source::Ppu, 134);
+        scheduler.schedule(Source::Ppu, 134, 46);
+        assert!(scheduler.due(Source::Ppu, 135));
+        assert_eq!(scheduler.catch_up(Source::Ppu, 138), 4);
+        scheduler.schedule(Source::Ppu, 138, 46);
+        assert_eq!(scheduler.until_next(138), 46);
+        assert_eq!(Source::at(0xff44), Some(Source::Ppu));
+        assert_eq!(Source::at(0xff00), None);
+    }
+}
@@ -0,0 +1,92 @@
+// screenshots on request, next to the battery saves as <rom>-<n>.png, and
+// --dump-frames, every nth frame as <dir>/frame_<frame>.png to compare runs or
+// put together a video, e.g. ffmpeg -pattern_type glob -i 'frame_*.png'
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::palette::PaletteSettings;
+use crate::GB;
+
+// first <rom>-<n>.png not taken yet
+pub fn default_path(save_dir: &Path, rom: &str) -> PathBuf {
+    let stem = Path::new(rom).file_stem().unwrap_or_default().to_string_lossy();
+    (1..).map(|n| save_dir.join(format!("{}-{:03}.png", stem, n))).find(|path| !path.exists()).unwrap()
+}
+
+pub fn save(gb: &GB, palettes: &PaletteSettings, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, gb.screenshot_png(palettes))
+}
+
+// `screenshot [file]` on stdin, file relative to the save directory
+pub fn command(gb: &GB, palettes: &PaletteSettings, save_dir: &Path, rom: &str, args: &[&str]) -> Result<String, String> {
+    let path = match args {
+        [] => default_path(save_dir, rom),
+        [file] => save_dir.join(file),
+        _ => return Err("expected `screenshot [file]`".to_string()),
+    };
+    save(gb, palettes, &path).map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+    Ok(format!("screenshot saved to {}", path.display()))
+}
+
+pub struct FrameDump {
+    dir: PathBuf,
+    every: u64,
+}
+
+impl FrameDump {
+    pub fn new(dir: &Path, every: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(FrameDump { dir: dir.to_path_buf(), every: every.max(1) })
+    }
+
+    // call once per frame, writes it if its number is a multiple of every
+    pub fn frame(&self, gb: &GB, palettes: &PaletteSettings) -> io::Result<Option<PathBuf>> {
+        let frame = gb.frames_elapsed();
+        if !frame.is_multiple_of(self.every) {
+            return Ok(None);
+        }
+        let path = self.dir.join(format!("frame_{:06}.png", frame));
+        fs::write(&path, gb.screenshot_png(palettes))?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::asm::micro_rom;
+
+    #[test]
+    fn numbers_screenshots_and_dumps_every_nth_frame() {
+        let dir = env::temp_dir().join(format!("gb-rust-screenshot-{}", std::process::id()));
+        let rom = micro_rom("ld a, $91\nldh ($40), a\nloop: jr loop");
+        let mut gb = GB::new(&rom).unwrap();
+        let palettes = PaletteSettings::default();
+
+        assert_eq!(command(&gb, &palettes, &dir, "roms/game.gb", &[]).unwrap(), format!("screenshot saved to {}", dir.join("game-001.png").display()));
+        assert_eq!(default_path(&dir, "game.gb"), dir.join("game-002.png"));
+        let png = fs::read(dir.join("game-001.png")).unwrap();
+        // IHDR width and height
+        assert_eq!(png[16..24], [0, 0, 0, 160, 0, 0, 0, 144]);
+
+        let frames = dir.join("frames");
+        let dump = FrameDump::new(&frames, 3).unwrap();
+        let mut written = Vec::new();
+        for _ in 0..7 {
+            gb.run_frames(1);
+            written.extend(dump.frame(&gb, &palettes).unwrap());
+        }
+        let mut names: Vec<_> = fs::read_dir(&frames).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["frame_000003.png", "frame_000006.png"]);
+        assert_eq!(written, [frames.join("frame_000003.png"), frames.join("frame_000006.png")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
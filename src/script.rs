@@ -0,0 +1,249 @@
+// --script: a script in interp.rs's language alongside the game, for bots,
+// practice overlays and automated tests. the script's top level runs once when
+// it's loaded, its on_frame function, if it defines one, at every VBlank. it
+// can use
+//
+//   read8(addr) read16(addr) write8(addr, value)  memory as the debugger sees it
+//   press(buttons)          held next frame on top of the player's, `a+right`
+//   frame_count()           frames since power on
+//   draw_text(x, y, text [, color])  draw_rect(x, y, width, height [, color])
+//   draw_pixel(x, y [, color])       shown until the next on_frame, colors are
+//                                    0xRRGGBB, white by default
+//   save_state(slot) load_state(slot)  savestates kept in memory, any name
+//   quit()                  ends the emulation, for test scripts
+//
+// coordinates are the game screen's, an SGB border is to the top left of 0, 0
+
+use std::collections::HashMap;
+
+use crate::interp::{number_arg, Host, Interpreter, Value};
+use crate::palette::Rgb;
+use crate::{osd, ppu, savestate, Buttons, GB};
+
+const FUNCTIONS: [&str; 11] = [
+    "read8",
+    "read16",
+    "write8",
+    "press",
+    "frame_count",
+    "draw_text",
+    "draw_rect",
+    "draw_pixel",
+    "save_state",
+    "load_state",
+    "quit",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Shape {
+    Rect { x: i64, y: i64, width: i64, height: i64, color: Rgb },
+    Text { x: i64, y: i64, text: String, color: Rgb },
+}
+
+pub struct Script {
+    interp: Interpreter,
+    overlay: Overlay,
+}
+
+// what the script's functions change, apart from the GB
+#[derive(Default)]
+struct Overlay {
+    shapes: Vec<Shape>,
+    buttons: Buttons,
+    states: HashMap<String, Vec<u8>>,
+    output: Vec<String>,
+    quit: bool,
+}
+
+struct Bindings<'a> {
+    gb: &'a mut GB,
+    overlay: &'a mut Overlay,
+}
+
+impl Script {
+    // runs the script's top level, name is what errors start with
+    pub fn load(name: &str, source: &str, gb: &mut GB) -> Result<Script, String> {
+        let mut script = Script { interp: Interpreter::new(name, &FUNCTIONS), overlay: Overlay::default() };
+        script.interp.run(source, &mut Bindings { gb, overlay: &mut script.overlay })?;
+        Ok(script)
+    }
+
+    // at VBlank, calls on_frame. what it drew and pressed the frame before is gone
+    pub fn frame(&mut self, gb: &mut GB) -> Result<(), String> {
+        self.overlay.shapes.clear();
+        self.overlay.buttons = Buttons::empty();
+        self.interp.call("on_frame", &[], &mut Bindings { gb, overlay: &mut self.overlay })?;
+        Ok(())
+    }
+
+    // pressed by the script for the coming frame
+    pub fn buttons(&self) -> Buttons {
+        self.overlay.buttons
+    }
+
+    // the script called quit
+    pub fn quit(&self) -> bool {
+        self.overlay.quit
+    }
+
+    // lines printed since the last call
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.overlay.output)
+    }
+
+    // packed RGB, width pixels a row, the screen or the SGB picture around it
+    pub fn draw(&self, rgb: &mut [u8], width: usize) {
+        let height = rgb.len() / 3 / width;
+        let (left, top) = ((width - ppu::WIDTH) as i64 / 2, (height - ppu::HEIGHT) as i64 / 2);
+        for shape in &self.overlay.shapes {
+            match shape {
+                Shape::Rect { x, y, width: w, height: h, color } => osd::fill(rgb, width, x + left, y + top, *w, *h, color),
+                Shape::Text { x, y, text, color } => osd::draw_text(rgb, width, x + left, y + top, text, color),
+            }
+        }
+    }
+}
+
+fn integer_arg(name: &str, args: &[Value], i: usize) -> Result<i64, String> {
+    let n = number_arg(name, args, i)?;
+    match n.fract() == 0.0 {
+        true => Ok(n as i64),
+        false => Err(format!("bad argument #{} to '{}' (number has no integer representation)", i + 1, name)),
+    }
+}
+
+fn address_arg(name: &str, args: &[Value], i: usize) -> Result<u16, String> {
+    let addr = integer_arg(name, args, i)?;
+    u16::try_from(addr).map_err(|_| format!("bad argument #{} to '{}' (address {} out of range)", i + 1, name, addr))
+}
+
+fn string_arg(name: &str, args: &[Value], i: usize) -> Result<String, String> {
+    match args.get(i) {
+        Some(value @ (Value::Str(_) | Value::Number(_))) => Ok(value.to_string()),
+        other => Err(format!(
+            "bad argument #{} to '{}' (string expected, got {})",
+            i + 1,
+            name,
+            other.map_or("no value", Value::type_name)
+        )),
+    }
+}
+
+// 0xRRGGBB, white without one
+fn color_arg(name: &str, args: &[Value], i: usize) -> Result<Rgb, String> {
+    match args.get(i) {
+        None | Some(Value::Nil) => Ok([255; 3]),
+        Some(_) => {
+            let [_, r, g, b] = (integer_arg(name, args, i)? as u32).to_be_bytes();
+            Ok([r, g, b])
+        }
+    }
+}
+
+impl Host for Bindings<'_> {
+    fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+        let int = |i| integer_arg(name, args, i);
+        match name {
+            "read8" => Ok(Value::Number(self.gb.read_memory(address_arg(name, args, 0)?, 1)[0] as f64)),
+            "read16" => {
+                let bytes = self.gb.read_memory(address_arg(name, args, 0)?, 2);
+                Ok(Value::Number(u16::from_le_bytes([bytes[0], bytes[1]]) as f64))
+            }
+            "write8" => {
+                self.gb.mmu.poke(address_arg(name, args, 0)?, int(1)? as u8)?;
+                Ok(Value::Nil)
+            }
+            "press" => {
+                self.overlay.buttons |= Buttons::parse(&string_arg(name, args, 0)?)?;
+                Ok(Value::Nil)
+            }
+            "frame_count" => Ok(Value::Number(self.gb.frames_elapsed() as f64)),
+            "draw_text" => {
+                let (x, y, text, color) = (int(0)?, int(1)?, string_arg(name, args, 2)?, color_arg(name, args, 3)?);
+                self.overlay.shapes.push(Shape::Text { x, y, text, color });
+                Ok(Value::Nil)
+            }
+            "draw_rect" => {
+                let (x, y, width, height) = (int(0)?, int(1)?, int(2)?, int(3)?);
+                let color = color_arg(name, args, 4)?;
+                self.overlay.shapes.push(Shape::Rect { x, y, width, height, color });
+                Ok(Value::Nil)
+            }
+            "draw_pixel" => {
+                let (x, y, color) = (int(0)?, int(1)?, color_arg(name, args, 2)?);
+                self.overlay.shapes.push(Shape::Rect { x, y, width: 1, height: 1, color });
+                Ok(Value::Nil)
+            }
+            "save_state" => {
+                let slot = string_arg(name, args, 0)?;
+                self.overlay.states.insert(slot, savestate::save(self.gb));
+                Ok(Value::Nil)
+            }
+            "load_state" => {
+                let slot = string_arg(name, args, 0)?;
+                let state = self.overlay.states.get(&slot).ok_or_else(|| format!("no state saved in slot {}", slot))?;
+                savestate::load(self.gb, state).map_err(|e| format!("can't load state {}: {}", slot, e))?;
+                Ok(Value::Nil)
+            }
+            "quit" => {
+                self.overlay.quit = true;
+                Ok(Value::Nil)
+            }
+            _ => Err(format!("no function {}", name)),
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        self.overlay.output.push(text.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+
+    #[test]
+    fn scripts_watch_and_drive_the_game() {
+        // keeps the buttons row of P1 at C000
+        let rom = micro_rom("ld a, $91\nldh ($40), a\nloop: ld a, $10\nldh ($00), a\nldh a, ($00)\nld ($c000), a\njr loop");
+        let mut gb = GB::new(&rom).unwrap();
+        let source = "
+            print('loaded')
+            seen = 0
+            function on_frame()
+                if read8(0xc000) & 1 == 0 then seen = seen + 1 end
+                if frame_count() >= 2 and frame_count() < 5 then press('a') end
+                if frame_count() == 3 then save_state('three') end
+                write8(0xc001, 0x34)
+                write8(0xc002, read8(0xc001) // 0x34 * 0x12)
+                draw_text(0, 0, 'a:' .. seen, 0xff0000)
+                draw_rect(158, 142, 4, 4)
+                if frame_count() == 8 then print(format('%d %04x', seen, read16(0xc001))) quit() end
+            end
+        ";
+        let mut script = Script::load("bot.lua", source, &mut gb).unwrap();
+        assert_eq!(script.take_output(), ["loaded"]);
+        while !script.quit() {
+            gb.run_frames(1);
+            script.frame(&mut gb).unwrap();
+            gb.set_buttons(script.buttons());
+        }
+        // pressed for three frames
+        assert_eq!(script.take_output(), ["3 1234"]);
+
+        let mut rgb = vec![0; ppu::WIDTH * ppu::HEIGHT * 3];
+        script.draw(&mut rgb, ppu::WIDTH);
+        let pixel = |x: usize, y: usize| rgb[(y * ppu::WIDTH + x) * 3..][..3].to_vec();
+        // `A` is a 3x5 glyph with a hole at the top corners
+        assert_eq!((pixel(0, 0), pixel(1, 0), pixel(0, 1)), (vec![0; 3], vec![255, 0, 0], vec![255, 0, 0]));
+        // cut at the edge of the screen
+        assert_eq!((pixel(159, 143), pixel(157, 143)), (vec![255; 3], vec![0; 3]));
+
+        let elapsed = gb.frames_elapsed();
+        script.interp.run("load_state('three')", &mut Bindings { gb: &mut gb, overlay: &mut script.overlay }).unwrap();
+        assert!(gb.frames_elapsed() < elapsed);
+        let error = script.interp.run("load_state('none')", &mut Bindings { gb: &mut gb, overlay: &mut script.overlay });
+        assert_eq!(error.unwrap_err(), "bot.lua:1: no state saved in slot none");
+    }
+}
@@ -0,0 +1,263 @@
+// built-in micro-programs for the hardware quirks this build emulates, `gb-rust
+// self-check` runs them and prints which behave like a real DMG. each program
+// leaves its result at FF80 and spins, the check compares it with what
+// hardware gives
+
+use std::fmt;
+
+use crate::asm::{assemble, micro_rom};
+use crate::GB;
+
+struct Check {
+    name: &'static str,
+    // what hardware does
+    quirk: &'static str,
+    program: &'static str,
+    // handlers at interrupt vectors
+    vectors: &'static [(u16, &'static str)],
+    frames: u64,
+    // at FF80 afterwards
+    expect: &'static [u8],
+}
+
+const CHECKS: &[Check] = &[
+    Check {
+        name: "EI delay",
+        quirk: "interrupts are enabled after the instruction following EI",
+        program: "
+            ld sp, $fffe
+            ld a, $01
+            ldh ($ff), a  ; VBlank enabled and requested
+            ldh ($0f), a
+            ei
+            inc b
+            inc b
+            stop: jr stop
+            ",
+        vectors: &[(0x40, "ld a, b\nldh ($80), a\nstop: jr stop")],
+        frames: 1,
+        expect: &[1],
+    },
+    Check {
+        name: "HALT bug",
+        quirk: "HALT with IME off and an interrupt pending runs the next byte twice",
+        program: "
+            ld a, $01
+            ldh ($ff), a
+            ldh ($0f), a
+            halt
+            inc b
+            ld a, b
+            ldh ($80), a
+            stop: jr stop
+            ",
+        vectors: &[],
+        frames: 1,
+        expect: &[2],
+    },
+    Check {
+        name: "HALT wake-up",
+        quirk: "an enabled interrupt ends HALT even with IME off, without being serviced",
+        program: "
+            ld a, $01
+            ldh ($ff), a
+            ld a, $91
+            ldh ($40), a
+            xor a
+            ldh ($0f), a
+            halt
+            ld a, $01
+            ldh ($80), a
+            ldh a, ($0f)
+            and $01
+            ldh ($81), a
+            stop: jr stop
+            ",
+        vectors: &[],
+        frames: 2,
+        expect: &[1, 1],
+    },
+    Check {
+        name: "DIV reset tick",
+        quirk: "writing DIV while the TIMA counter bit is set ticks TIMA",
+        program: "
+            ld a, $05
+            ldh ($07), a  ; TIMA every 16 t-cycles
+            xor a
+            ldh ($04), a
+            ldh ($05), a  ; counter bit 3 falls after this once
+            ldh ($04), a  ; and with this again
+            ldh a, ($05)
+            ldh ($80), a
+            stop: jr stop
+            ",
+        vectors: &[],
+        frames: 1,
+        expect: &[2],
+    },
+    Check {
+        name: "STAT blocking",
+        quirk: "STAT sources sharing one high period raise a single interrupt",
+        program: "
+            xor a
+            ldh ($40), a  ; from the top of a frame
+            ld c, a
+            ld a, $28
+            ldh ($41), a  ; HBlank and OAM scan sources
+            ld a, $02
+            ldh ($ff), a
+            ld a, $91
+            ldh ($40), a
+            xor a
+            ldh ($0f), a
+            ei
+            loop:
+            halt
+            ld a, c
+            ldh ($80), a
+            jr loop
+            ",
+        vectors: &[(0x48, "inc c\nreti")],
+        frames: 1,
+        // one per line up to VBlank, not one per mode
+        expect: &[144],
+    },
+    Check {
+        name: "VRAM lock",
+        quirk: "VRAM reads FF while the PPU draws (mode 3)",
+        program: "
+            ld a, $42
+            ld ($8000), a
+            ld a, $91
+            ldh ($40), a
+            wait:
+            ldh a, ($41)
+            and $03
+            cp $03
+            jr nz, wait
+            ld a, ($8000)
+            ldh ($80), a
+            stop: jr stop
+            ",
+        vectors: &[],
+        frames: 2,
+        expect: &[0xff],
+    },
+    Check {
+        name: "OAM lock",
+        quirk: "OAM reads FF during OAM scan and drawing (modes 2 and 3)",
+        program: "
+            ld a, $42
+            ld ($fe00), a
+            ld a, $91
+            ldh ($40), a
+            wait:
+            ldh a, ($41)
+            and $03
+            cp $02
+            jr nz, wait
+            ld a, ($fe00)
+            ldh ($80), a
+            stop: jr stop
+            ",
+        vectors: &[],
+        frames: 2,
+        expect: &[0xff],
+    },
+    Check {
+        name: "F lower nibble",
+        quirk: "the lower 4 bits of F don't exist and read 0",
+        program: "
+            ld bc, $12ff
+            push bc
+            pop af
+            push af
+            pop bc
+            ld a, c
+            ldh ($80), a
+            stop: jr stop
+            ",
+        vectors: &[],
+        frames: 1,
+        expect: &[0xf0],
+    },
+    Check {
+        name: "echo RAM",
+        quirk: "[E000-FDFF] mirrors work RAM",
+        program: "
+            ld a, $5a
+            ld ($c123), a
+            ld a, ($e123)
+            ldh ($80), a
+            stop: jr stop
+            ",
+        vectors: &[],
+        frames: 1,
+        expect: &[0x5a],
+    },
+];
+
+pub struct CheckResult {
+    pub name: &'static str,
+    pub quirk: &'static str,
+    // what went wrong
+    pub outcome: Result<(), String>,
+}
+
+pub struct Report {
+    pub results: Vec<CheckResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.outcome.is_ok())
+    }
+}
+
+pub fn run() -> Report {
+    Report { results: CHECKS.iter().map(|check| CheckResult { name: check.name, quirk: check.quirk, outcome: run_check(check) }).collect() }
+}
+
+fn run_check(check: &Check) -> Result<(), String> {
+    let mut rom = micro_rom(check.program);
+    for &(vector, handler) in check.vectors {
+        let code = assemble(handler, vector).map_err(|e| format!("handler at {:04X}: {}", vector, e))?;
+        rom[vector as usize..vector as usize + code.len()].copy_from_slice(&code);
+    }
+    let mut gb = GB::new(&rom)?;
+    gb.run_frames(check.frames);
+    if let Some(e) = gb.crash() {
+        return Err(e.to_string());
+    }
+    let found = gb.read_memory(0xff80, check.expect.len());
+    match found == check.expect {
+        true => Ok(()),
+        false => Err(format!("FF80 holds {:02X?}, hardware gives {:02X?}", found, check.expect)),
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let passed = self.results.iter().filter(|r| r.outcome.is_ok()).count();
+        writeln!(f, "gb-rust self-check: {} of {} quirks behave like hardware", passed, self.results.len())?;
+        for result in &self.results {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "  ok    {:<16} {}", result.name, result.quirk)?,
+                Err(e) => writeln!(f, "  FAIL  {:<16} {} ({})", result.name, result.quirk, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_quirk_is_emulated() {
+        let report = run();
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.results.len(), CHECKS.len());
+    }
+}
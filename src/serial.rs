@@ -0,0 +1,572 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::savestate::{Component, StateError, StateReader, StateWriter};
+use crate::Error;
+
+// 8192Hz shift clock, CGB fast mode shifts at 262144Hz
+const BIT_T: u32 = 512;
+const FAST_BIT_T: u32 = 16;
+// longest a networked peer gets to answer a transfer, then the cable counts
+// as unplugged for that byte. a peer that paused or vanished without closing
+// the connection doesn't hang the game
+pub const LINK_TIMEOUT: Duration = Duration::from_millis(500);
+
+// other end of the link cable
+pub trait SerialDevice {
+    // GB drives the clock and shifted out a whole byte, returns byte shifted in
+    fn transfer(&mut self, out: u8) -> u8;
+
+    // GB waits for external clock with out in SB, returns byte shifted in once the device clocked it
+    fn external_transfer(&mut self, out: u8) -> Option<u8> {
+        let _ = out;
+        None
+    }
+
+    // GB started clocking out, transfer() follows when the 8 bits are done. a
+    // device on a slow line can send now and hide the round trip behind them
+    fn start(&mut self, out: u8) {
+        let _ = out;
+    }
+
+    // what went wrong since the last call, oldest first, e.g. output the
+    // device couldn't write
+    fn take_error(&mut self) -> Option<Error> {
+        None
+    }
+
+    // the game is about to be turned off, the device finishes what it holds
+    fn power_off(&mut self) {}
+
+    // t-cycles of emulated time went by, for devices that keep the time
+    fn elapse(&mut self, t: u32) {
+        let _ = t;
+    }
+}
+
+// nothing plugged in, line is pulled high
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _out: u8) -> u8 {
+        0xff
+    }
+}
+
+// cable plugged back into the same GB
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn transfer(&mut self, out: u8) -> u8 {
+        out
+    }
+}
+
+// collects sent bytes, e.g. Blargg test ROM text output
+#[derive(Clone, Default)]
+pub struct OutputCapture {
+    pub output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SerialDevice for OutputCapture {
+    fn transfer(&mut self, out: u8) -> u8 {
+        self.output.borrow_mut().push(out);
+        0xff
+    }
+}
+
+// byte per transfer over TCP, the clocking side sends first and the other answers.
+// the byte goes out as the transfer starts, so only a round trip longer than
+// the ~1ms a byte takes at 8192Hz stalls the clocking side, and one longer than
+// LINK_TIMEOUT reads FF
+pub struct TcpSerial {
+    stream: TcpStream,
+    // sent at the start of the transfer, only the answer is left
+    sent: bool,
+    // answers that timed out, skipped when they come in after all
+    late: usize,
+}
+
+impl TcpSerial {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(LINK_TIMEOUT))?;
+        stream.set_write_timeout(Some(LINK_TIMEOUT))?;
+        Ok(TcpSerial { stream, sent: false, late: 0 })
+    }
+}
+
+impl SerialDevice for TcpSerial {
+    fn transfer(&mut self, out: u8) -> u8 {
+        let mut buf = [0xff];
+        self.stream.set_nonblocking(false).ok();
+        if !std::mem::take(&mut self.sent) && self.stream.write_all(&[out]).is_err() {
+            return 0xff;
+        }
+        loop {
+            if self.stream.read_exact(&mut buf).is_err() {
+                self.late += 1;
+                return 0xff;
+            }
+            match self.late {
+                0 => return buf[0],
+                _ => self.late -= 1,
+            }
+        }
+    }
+
+    fn start(&mut self, out: u8) {
+        self.stream.set_nonblocking(false).ok();
+        self.sent = self.stream.write_all(&[out]).is_ok();
+    }
+
+    fn external_transfer(&mut self, out: u8) -> Option<u8> {
+        let mut buf = [0];
+        self.stream.set_nonblocking(true).ok()?;
+        match self.stream.read(&mut buf) {
+            Ok(1) => {
+                self.stream.set_nonblocking(false).ok()?;
+                self.stream.write_all(&[out]).ok()?;
+                Some(buf[0])
+            }
+            _ => None,
+        }
+    }
+}
+
+// `host:port` connects, `:port` waits for the other side to connect
+pub fn open_stream(addr: &str) -> io::Result<TcpStream> {
+    match addr.strip_prefix(':') {
+        Some(port) => Ok(TcpListener::bind(("0.0.0.0", port.parse().map_err(|_| io::ErrorKind::InvalidInput)?))?.accept()?.0),
+        None => TcpStream::connect(addr),
+    }
+}
+
+// BGB link protocol 1.4 (bgb.bircd.org/bgblink.html), also spoken by other emulators.
+// 8 byte packets: command, b2, b3, b4, u32 LE timestamp in 2MiHz ticks. the
+// timestamp is emulated time, half the t-cycles, and goes out as SYNC3 about
+// once a frame while no byte does, which the peer paces its emulation by
+const BGB_VERSION: u8 = 1;
+const BGB_JOYPAD: u8 = 101;
+const BGB_SYNC1: u8 = 104;
+const BGB_SYNC2: u8 = 105;
+const BGB_SYNC3: u8 = 106;
+const BGB_STATUS: u8 = 108;
+const BGB_WANT_DISCONNECT: u8 = 109;
+// status b2: running
+const BGB_RUNNING: u8 = 0x01;
+// 2MiHz ticks between SYNC3 timestamp updates, a frame
+const BGB_SYNC_TICKS: u32 = crate::ppu::FRAME_DOTS as u32 / 2;
+
+pub struct BgbLink {
+    stream: TcpStream,
+    // partial packet read while polling for external clock
+    pending: Vec<u8>,
+    // packets read while only keeping time, for the next transfer
+    queued: VecDeque<[u8; 8]>,
+    // emulated time in 2MiHz ticks, and a t-cycle left over
+    timestamp: u32,
+    odd_cycle: bool,
+    // when the last packet went out
+    sent_at: u32,
+    // SYNC2 answers that timed out, skipped when they come in after all
+    late: usize,
+}
+
+impl BgbLink {
+    // exchanges version and status packets before any transfer
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(LINK_TIMEOUT))?;
+        stream.set_write_timeout(Some(LINK_TIMEOUT))?;
+        let mut link = BgbLink {
+            stream,
+            pending: Vec::new(),
+            queued: VecDeque::new(),
+            timestamp: 0,
+            odd_cycle: false,
+            sent_at: 0,
+            late: 0,
+        };
+        link.send(BGB_VERSION, 1, 4, 0)?;
+        match link.receive(true)? {
+            Some([BGB_VERSION, 1, 4, 0, ..]) => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "peer doesn't speak BGB link protocol 1.4")),
+        }
+        link.send(BGB_STATUS, BGB_RUNNING, 0, 0)?;
+        Ok(link)
+    }
+
+    fn send(&mut self, command: u8, b2: u8, b3: u8, b4: u8) -> io::Result<()> {
+        let mut packet = [command, b2, b3, b4, 0, 0, 0, 0];
+        packet[4..].copy_from_slice(&(self.timestamp & 0x7fff_ffff).to_le_bytes());
+        self.sent_at = self.timestamp;
+        self.stream.set_nonblocking(false)?;
+        self.stream.write_all(&packet)
+    }
+
+    // next packet that needs handling, None when not blocking and nothing arrived.
+    // keepalive and status traffic is answered here. blocking gives up after
+    // LINK_TIMEOUT with TimedOut
+    fn receive(&mut self, block: bool) -> io::Result<Option<[u8; 8]>> {
+        match self.queued.pop_front() {
+            Some(packet) => Ok(Some(packet)),
+            None => self.read(block),
+        }
+    }
+
+    fn read(&mut self, block: bool) -> io::Result<Option<[u8; 8]>> {
+        let deadline = Instant::now() + LINK_TIMEOUT;
+        loop {
+            while self.pending.len() < 8 {
+                self.stream.set_nonblocking(!block)?;
+                let mut buf = [0; 8];
+                let want = 8 - self.pending.len();
+                match self.stream.read(&mut buf[..want]) {
+                    Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                    Err(e) if !block && e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                    // the read timeout, WouldBlock on Unix
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                        return Err(io::ErrorKind::TimedOut.into())
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            let packet: [u8; 8] = self.pending.drain(..).collect::<Vec<u8>>().try_into().unwrap();
+            match packet[0] {
+                // plain timestamp updates get acknowledged, acks (b2 = 1) don't
+                BGB_SYNC3 if packet[1] == 0 => self.send(BGB_SYNC3, 1, 0, 0)?,
+                BGB_SYNC3 | BGB_STATUS | BGB_JOYPAD => {}
+                BGB_WANT_DISCONNECT => return Err(io::ErrorKind::ConnectionAborted.into()),
+                _ => return Ok(Some(packet)),
+            }
+            // a peer sending nothing but keepalives doesn't hold the game either
+            if block && Instant::now() >= deadline {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+    }
+}
+
+impl SerialDevice for BgbLink {
+    fn transfer(&mut self, out: u8) -> u8 {
+        if self.send(BGB_SYNC1, out, 0x81, 0).is_err() {
+            return 0xff;
+        }
+        loop {
+            match self.receive(true) {
+                Ok(Some([BGB_SYNC2, _, ..])) if self.late > 0 => self.late -= 1,
+                Ok(Some([BGB_SYNC2, byte, ..])) => return byte,
+                Ok(_) => {}
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::TimedOut {
+                        self.late += 1;
+                    }
+                    return 0xff;
+                }
+            }
+        }
+    }
+
+    fn elapse(&mut self, t: u32) {
+        let t = t as u64 + self.odd_cycle as u64;
+        self.odd_cycle = t % 2 == 1;
+        self.timestamp = self.timestamp.wrapping_add((t / 2) as u32);
+        if self.timestamp.wrapping_sub(self.sent_at) < BGB_SYNC_TICKS {
+            return;
+        }
+        // answers the peer's own updates too, what else came in waits for a transfer
+        if self.send(BGB_SYNC3, 0, 0, 0).is_ok() {
+            while let Ok(Some(packet)) = self.read(false) {
+                self.queued.push_back(packet);
+            }
+        }
+    }
+
+    fn external_transfer(&mut self, out: u8) -> Option<u8> {
+        match self.receive(false) {
+            Ok(Some([BGB_SYNC1, byte, ..])) => {
+                self.send(BGB_SYNC2, out, 0x80, 0).ok()?;
+                Some(byte)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LinkWire {
+    // byte offered by each end waiting for external clock
+    ready: [Option<u8>; 2],
+    // bytes clocked into each end by the other one
+    inbox: [VecDeque<u8>; 2],
+}
+
+// one end of a cable between two GB instances in the same process
+pub struct LinkPort {
+    wire: Rc<RefCell<LinkWire>>,
+    side: usize,
+}
+
+pub fn link_pair() -> (LinkPort, LinkPort) {
+    let wire: Rc<RefCell<LinkWire>> = Default::default();
+    (LinkPort { wire: wire.clone(), side: 0 }, LinkPort { wire, side: 1 })
+}
+
+impl SerialDevice for LinkPort {
+    fn transfer(&mut self, out: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let peer = 1 - self.side;
+        match wire.ready[peer].take() {
+            Some(byte) => {
+                wire.inbox[peer].push_back(out);
+                byte
+            }
+            None => 0xff,
+        }
+    }
+
+    fn external_transfer(&mut self, out: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        match wire.inbox[self.side].pop_front() {
+            Some(byte) => Some(byte),
+            None => {
+                wire.ready[self.side] = Some(out);
+                None
+            }
+        }
+    }
+}
+
+// [FF01-FF02] serial port
+pub struct Serial {
+    // SB
+    data: u8,
+    // SC bit 7 - transfer in progress, bit 1 - CGB fast clock, bit 0 - internal clock
+    control: u8,
+    // t-cycles until transfer finishes (internal clock) or device is polled again (external)
+    countdown: u32,
+    device: Box<dyn SerialDevice>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Serial {
+            data: 0,
+            control: 0,
+            countdown: 0,
+            device: Box::new(Disconnected),
+        }
+    }
+}
+
+impl Serial {
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.device.take_error()
+    }
+
+    pub fn power_off(&mut self) {
+        self.device.power_off();
+    }
+
+    // registers back to power-on, the device stays connected
+    pub fn reset(&mut self) {
+        (self.data, self.control, self.countdown) = (0, 0, 0);
+    }
+
+    pub fn rb(&self, addr: u16, cgb: bool) -> u8 {
+        match addr {
+            0xff01 => self.data,
+            _ => match cgb {
+                true => self.control | 0x7c,
+                false => self.control | 0x7e,
+            },
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, val: u8, cgb: bool) {
+        match addr {
+            0xff01 => self.data = val,
+            _ => {
+                self.control = val & if cgb { 0x83 } else { 0x81 };
+                self.countdown = match self.control & 0x03 {
+                    0x03 => 8 * FAST_BIT_T,
+                    0x01 => 8 * BIT_T,
+                    _ => 0,
+                };
+                if self.control & 0x81 == 0x81 {
+                    self.device.start(self.data);
+                }
+            }
+        }
+    }
+
+    // advances shift clock, returns true when a transfer finished and the interrupt should fire
+    // t-cycles until step returns true or asks the device again
+    pub fn next_interrupt(&self) -> u32 {
+        match self.control & 0x80 {
+            0 => u32::MAX,
+            _ => self.countdown.max(1),
+        }
+    }
+
+    pub fn step(&mut self, t: u32) -> bool {
+        self.device.elapse(t);
+        if self.control & 0x80 == 0 {
+            return false;
+        }
+        self.countdown = self.countdown.saturating_sub(t);
+        if self.countdown > 0 {
+            return false;
+        }
+        let received = match self.control & 0x01 {
+            1 => Some(self.device.transfer(self.data)),
+            _ => {
+                self.countdown = BIT_T;
+                self.device.external_transfer(self.data)
+            }
+        };
+        match received {
+            Some(byte) => {
+                self.data = byte;
+                self.control &= 0x7f;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// connected device is not part of the state and stays plugged in
+impl Component for Serial {
+    const TAG: [u8; 4] = *b"SERL";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.data);
+        w.u8(self.control);
+        w.u32(self.countdown);
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.data = r.u8()?;
+        self.control = r.u8()?;
+        self.countdown = r.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn packet(stream: &mut TcpStream) -> [u8; 8] {
+        let mut buf = [0; 8];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn tcp_link_sends_when_the_transfer_starts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut serial = Serial::default();
+        serial.connect(Box::new(TcpSerial::new(open_stream(&addr.to_string()).unwrap()).unwrap()));
+        let mut peer = listener.accept().unwrap().0;
+        serial.wb(0xff01, 0x42, false);
+        serial.wb(0xff02, 0x81, false);
+        // on the wire before any bit is shifted
+        let mut buf = [0];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x42]);
+        peer.write_all(&[0x99]).unwrap();
+        assert!(serial.step(8 * BIT_T));
+        assert_eq!(serial.rb(0xff01, false), 0x99);
+        // and sent only once
+        peer.set_nonblocking(true).unwrap();
+        assert_eq!(peer.read(&mut buf).map_err(|e| e.kind()), Err(io::ErrorKind::WouldBlock));
+    }
+
+    #[test]
+    fn tcp_link_times_out_on_a_silent_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut link = TcpSerial::new(open_stream(&listener.local_addr().unwrap().to_string()).unwrap()).unwrap();
+        // accepts and stays connected, but never answers
+        let (mut peer, _) = listener.accept().unwrap();
+        let started = std::time::Instant::now();
+        assert_eq!(link.transfer(0x42), 0xff);
+        assert!(started.elapsed() < 4 * LINK_TIMEOUT);
+        // the late answer doesn't pass for the next one
+        peer.write_all(&[0x11, 0x22]).unwrap();
+        assert_eq!(link.transfer(0x43), 0x22);
+    }
+
+    #[test]
+    fn bgb_link_handshake_and_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // plays the other emulator, receiving as slave
+        let peer = thread::spawn(move || {
+            let mut stream = bgb_peer(&listener);
+            stream.write_all(&[BGB_SYNC3, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+            assert_eq!(packet(&mut stream)[..3], [BGB_SYNC1, 0x42, 0x81]);
+            assert_eq!(packet(&mut stream)[..2], [BGB_SYNC3, 1]);
+            stream.write_all(&[BGB_SYNC2, 0x99, 0x80, 0, 0, 0, 0, 0]).unwrap();
+            // now clocking us
+            stream.write_all(&[BGB_SYNC1, 0x17, 0x81, 0, 0, 0, 0, 0]).unwrap();
+            assert_eq!(packet(&mut stream)[..2], [BGB_SYNC2, 0x55]);
+        });
+        let mut link = BgbLink::new(open_stream(&addr.to_string()).unwrap()).unwrap();
+        assert_eq!(link.transfer(0x42), 0x99);
+        let received = loop {
+            if let Some(byte) = link.external_transfer(0x55) {
+                break byte;
+            }
+        };
+        assert_eq!(received, 0x17);
+        peer.join().unwrap();
+    }
+
+    // the other emulator's side of the handshake
+    fn bgb_peer(listener: &TcpListener) -> TcpStream {
+        let mut stream = listener.accept().unwrap().0;
+        assert_eq!(packet(&mut stream)[..4], [BGB_VERSION, 1, 4, 0]);
+        stream.write_all(&[BGB_VERSION, 1, 4, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(packet(&mut stream)[0], BGB_STATUS);
+        stream
+    }
+
+    #[test]
+    fn bgb_link_keeps_emulated_time_and_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = thread::spawn(move || {
+            let mut stream = bgb_peer(&listener);
+            // a frame of emulated time, sent as a timestamp update
+            assert_eq!(packet(&mut stream), [BGB_SYNC3, 0, 0, 0, 0x28, 0x89, 0, 0]);
+            stream.write_all(&[BGB_SYNC3, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+            // then it never answers the byte
+            assert_eq!(packet(&mut stream)[..2], [BGB_SYNC1, 0x42]);
+            // connected until the link gave up
+            assert_eq!(packet(&mut stream)[0], BGB_STATUS);
+        });
+        let mut link = BgbLink::new(open_stream(&addr.to_string()).unwrap()).unwrap();
+        link.elapse(1001);
+        link.elapse(crate::ppu::FRAME_DOTS as u32 - 1001);
+        assert_eq!(link.timestamp, 35112);
+        let started = Instant::now();
+        assert_eq!(link.transfer(0x42), 0xff);
+        assert!(started.elapsed() < 4 * LINK_TIMEOUT);
+        link.send(BGB_STATUS, 0, 0, 0).unwrap();
+        peer.join().unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+// several cartridges loaded at once, each with its own GB so RAM and state are kept when switching.
+// the first two can share a link cable and run side by side for two players
+
+use std::fs;
+use std::path::Path;
+
+use crate::ppu::PPUEvents;
+use crate::serial::link_pair;
+use crate::{cartridge, freeze, sram, Error, GB};
+
+pub struct Session {
+    games: Vec<(String, GB)>,
+    active: usize,
+    // games 0 and 1 are cabled together
+    linked: bool,
+}
+
+impl Session {
+    // roms as (name, data), at least one
+    pub fn new(roms: &[(String, Vec<u8>)]) -> Result<Self, Error> {
+        if roms.is_empty() {
+            return Err(Error::NoRom);
+        }
+        let games = roms.iter().map(|(name, data)| Ok((name.clone(), GB::new(data)?))).collect::<Result<_, Error>>()?;
+        Ok(Session { games, active: 0, linked: false })
+    }
+
+    pub fn active(&self) -> &GB {
+        &self.games[self.active].1
+    }
+
+    pub fn active_mut(&mut self) -> &mut GB {
+        &mut self.games[self.active].1
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn active_name(&self) -> &str {
+        &self.games[self.active].0
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.games.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    // returns false when index is out of range
+    pub fn switch(&mut self, index: usize) -> bool {
+        if index >= self.games.len() {
+            return false;
+        }
+        self.active = index;
+        true
+    }
+
+    pub fn next(&mut self) {
+        self.active = (self.active + 1) % self.games.len();
+    }
+
+    pub fn games(&self) -> impl Iterator<Item = (&str, &GB)> {
+        self.games.iter().map(|(name, gb)| (name.as_str(), gb))
+    }
+
+    pub fn games_mut(&mut self) -> impl Iterator<Item = &mut GB> {
+        self.games.iter_mut().map(|(_, gb)| gb)
+    }
+
+    // puts the cartridge in path into the active game's slot while running, from
+    // power on, see GB::change_cartridge. the battery save of the one taken out
+    // is written first, the new one's and its frozen addresses load from save_dir
+    // as at startup. returns what to tell the user
+    pub fn load(&mut self, save_dir: &Path, path: &str) -> Result<String, String> {
+        let mut data = fs::read(path).map_err(|e| format!("can't read ROM {}: {}", path, e))?;
+        let fitted = cartridge::fit_rom(&mut data).map_err(|e| format!("{} is {}", path, e))?;
+        let (name, gb) = &self.games[self.active];
+        if gb.header().battery() {
+            sram::export(gb, &sram::default_path(save_dir, name)).map_err(|e| format!("can't write {}", e))?;
+        }
+        let frozen = freeze::load(&freeze::default_path(save_dir, path))?;
+        GB::check_rom(&data).map_err(|e| format!("{}: {}", path, e))?;
+        let gb = &mut self.games[self.active].1;
+        gb.change_cartridge(&data).map_err(|e| format!("{}: {}", path, e))?;
+        self.games[self.active].0 = path.to_string();
+        let gb = &mut self.games[self.active].1;
+        gb.set_frozen(frozen).map_err(|e| format!("{}: {}", path, e))?;
+        let sav = sram::default_path(save_dir, path);
+        if gb.header().battery() && sav.exists() {
+            sram::import(gb, &sav).map_err(|e| e.to_string())?;
+        }
+        Ok(match fitted {
+            Some(warning) => format!("loaded {} ({})", path, warning),
+            None => format!("loaded {}", path),
+        })
+    }
+
+    // link cable between games 0 and 1, false with only one game
+    pub fn link_first_two(&mut self) -> bool {
+        if self.games.len() < 2 {
+            return false;
+        }
+        let (first, second) = link_pair();
+        self.games[0].1.connect_serial(Box::new(first));
+        self.games[1].1.connect_serial(Box::new(second));
+        self.linked = true;
+        true
+    }
+
+    pub fn linked(&self) -> bool {
+        self.linked
+    }
+
+    // runs an instruction of whichever linked game is behind, so the two stay
+    // an instruction apart and bytes cross the cable when they would between
+    // two consoles. returns which game ran and its PPU modes entered
+    pub fn cycle_linked(&mut self) -> (usize, PPUEvents) {
+        let side = match self.games[1].1.cycles_elapsed() < self.games[0].1.cycles_elapsed() {
+            true => 1,
+            false => 0,
+        };
+        (side, self.games[side].1.cycle())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::RtcMode;
+
+    // leaves the received byte at FF80
+    fn transfer_rom(out: u8, control: u8) -> Vec<u8> {
+        micro_rom(&format!(
+            "ld a, ${:02x}
+            ldh ($01), a
+            ld a, ${:02x}
+            ldh ($02), a
+            wait:
+            ldh a, ($02)
+            and $80
+            jr nz, wait
+            ldh a, ($01)
+            ldh ($80), a
+            stop: jr stop",
+            out, control
+        ))
+    }
+
+    #[test]
+    fn linked_games_trade_bytes() {
+        // the first clocks the transfer, the second waits for it
+        let roms = [("a.gb".to_string(), transfer_rom(0x42, 0x81)), ("b.gb".to_string(), transfer_rom(0x17, 0x80))];
+        let mut session = Session::new(&roms).unwrap();
+        assert!(session.link_first_two());
+        for _ in 0..10_000 {
+            session.cycle_linked();
+        }
+        let [a, b] = [0, 1].map(|i| session.games[i].1.read_memory(0xff80, 1)[0]);
+        assert_eq!((a, b), (0x17, 0x42));
+        let (first, second) = (session.games[0].1.cycles_elapsed(), session.games[1].1.cycles_elapsed());
+        assert!(first.abs_diff(second) <= 24);
+        assert!(matches!(Session::new(&roms[..0]), Err(Error::NoRom)));
+    }
+
+    #[test]
+    fn loads_another_cartridge() {
+        let dir = env::temp_dir().join(format!("gb-rust-session-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // MBC1 with 8KiB of battery backed RAM, 42 goes to A000 and C000
+        let mut first = micro_rom("ld a, $0a\nld ($0000), a\nld a, $42\nld ($a000), a\nld ($c000), a\nloop: jr loop");
+        (first[0x0147], first[0x0149]) = (0x03, 0x02);
+        let roms = [("first.gb".to_string(), first)];
+        let mut session = Session::new(&roms).unwrap();
+        let gb = session.active_mut();
+        gb.set_rtc_mode(RtcMode::Host);
+        for _ in 0..20 {
+            gb.cycle();
+        }
+        assert_eq!(gb.read_memory(0xc000, 1), [0x42]);
+
+        let second = dir.join("second.gb").to_string_lossy().into_owned();
+        fs::write(&second, micro_rom("loop: jr loop")).unwrap();
+        fs::write(dir.join("bad.gb"), [0; 0x100]).unwrap();
+        assert!(session.load(&dir, &dir.join("bad.gb").to_string_lossy()).is_err());
+        assert_eq!(session.active_name(), "first.gb");
+        assert_eq!(session.load(&dir, &second), Ok(format!("loaded {}", second)));
+        assert_eq!(session.active_name(), second);
+        assert_eq!(fs::read(dir.join("first.sav")).unwrap()[0], 0x42);
+        let gb = session.active();
+        assert_eq!((gb.z80.pc, gb.z80.sp, gb.frames_elapsed()), (0x0100, 0xfffe, 0));
+        assert_eq!(gb.read_memory(0xc000, 1), [0]);
+        assert!(!gb.header().battery());
+        assert_eq!(gb.rtc_mode(), RtcMode::Host);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
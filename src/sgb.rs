@@ -0,0 +1,531 @@
+// Super Game Boy: games that set the SGB flag in their header ([0146] = 03,
+// [014B] = 33) send it command packets over the joypad port and it colors
+// the screen and draws a border around it, 256x224 like the SNES shows it
+//
+// a packet is 16 bytes sent bit by bit, low bit first, by P1 writes: 00 starts
+// it, then 20 is a 0 and 10 a 1, each followed by 30. the first byte is the
+// command * 8 + packets, up to 7 packets make up one command
+//
+// the *_TRN commands move 4KiB at once through the screen: the SGB reads the
+// next frame back out of the LCD, 2 bits per pixel, tiles in rows of 20. sound
+// (SOUND, SOU_TRN) and the SNES program commands (DATA_SND/TRN, JUMP) are ignored
+
+use crate::palette::Rgb;
+use crate::ppu::{HEIGHT as SCREEN_HEIGHT, WIDTH as SCREEN_WIDTH};
+use crate::savestate::{Component, StateError, StateReader, StateWriter};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 224;
+// top left of the Game Boy screen inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// attribute cells are 8x8 pixels, 20x18 of them
+const CELLS_X: usize = SCREEN_WIDTH / 8;
+const CELLS_Y: usize = SCREEN_HEIGHT / 8;
+const CELLS: usize = CELLS_X * CELLS_Y;
+// cells packed 4 to a byte
+const ATTRIBUTE_FILE: usize = CELLS / 4;
+const ATTRIBUTE_FILES: usize = 45;
+const TRANSFER: usize = 0x1000;
+// PCT_TRN: the 32x28 border map, then the colors of border palettes 4-7
+const BORDER_COLORS: usize = 0x800;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0a;
+const PAL_TRN: u8 = 0x0b;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// MASK_EN: what the screen shows while the game sets up the next picture
+const MASK_FREEZE: u8 = 1;
+const MASK_BLACK: u8 = 2;
+const MASK_COLOR_0: u8 = 3;
+
+pub struct Sgb {
+    // hardware is an SGB and the cartridge asks for it
+    enabled: bool,
+    // P1 bits 4-5 at the last write
+    lines: u8,
+    // bits of the current packet received, None between packets
+    bit: Option<usize>,
+    packet: [u8; 16],
+    // packets of the current command so far
+    command: Vec<u8>,
+    // MLT_REQ: 1, 2 or 4 joypads, the one P1 reads next
+    players: u8,
+    player: u8,
+    // RGB555, color 0 is shared by all four
+    palettes: [[u16; 4]; 4],
+    // PAL_TRN: 512 palettes for PAL_SET to choose from
+    system_palettes: Vec<u8>,
+    // palette of every cell
+    attributes: [u8; CELLS],
+    attribute_files: Vec<u8>,
+    // CHR_TRN: 256 SNES tiles, 4 bits per pixel
+    border_tiles: Vec<u8>,
+    // PCT_TRN
+    border: Vec<u8>,
+    // until the first palette command the screen keeps the frontend's colors
+    colorized: bool,
+    mask: u8,
+    // the last frame when MASK_EN froze the screen
+    frozen: Vec<u8>,
+    // command waiting for the next frame, and whether that frame began yet
+    transfer: Option<(u8, u8, bool)>,
+    // shades of the last frame, a freeze keeps them
+    last_frame: Vec<u8>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Sgb {
+            enabled: false,
+            lines: 0x30,
+            bit: None,
+            packet: [0; 16],
+            command: Vec::new(),
+            players: 1,
+            player: 0,
+            palettes: [[0; 4]; 4],
+            system_palettes: vec![0; TRANSFER],
+            attributes: [0; CELLS],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE],
+            border_tiles: vec![0; 2 * TRANSFER],
+            border: vec![0; TRANSFER],
+            colorized: false,
+            mask: 0,
+            frozen: Vec::new(),
+            transfer: None,
+            last_frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+}
+
+// 5 bits per channel, red lowest
+fn rgb(color: u16) -> Rgb {
+    [0, 5, 10].map(|shift| {
+        let c = (color >> shift) as u8 & 0x1f;
+        (c << 3) | (c >> 2)
+    })
+}
+
+impl Sgb {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // back to power-on state, keeping whether there is an SGB
+    pub fn reset(&mut self) {
+        *self = Sgb { enabled: self.enabled, ..Sgb::default() };
+    }
+
+    // lower nibble of P1 with both groups deselected, 0F - the joypad number
+    pub fn joypad_id(&self) -> Option<u8> {
+        (self.enabled && self.players > 1 && self.lines == 0x30).then_some(0x0f - self.player)
+    }
+
+    pub fn write_p1(&mut self, val: u8) {
+        if !self.enabled {
+            return;
+        }
+        let lines = val & 0x30;
+        match lines {
+            0x00 => {
+                self.bit = Some(0);
+                self.packet = [0; 16];
+            }
+            0x10 | 0x20 if self.lines == 0x30 => {
+                if let Some(bit) = self.bit {
+                    self.packet[bit / 8] |= ((lines == 0x10) as u8) << (bit % 8);
+                    self.bit = Some(bit + 1);
+                    if bit == 127 {
+                        self.bit = None;
+                        self.receive_packet();
+                    }
+                }
+            }
+            // the next joypad when P15 goes back high, as reading them all ends
+            0x30 if self.lines & 0x20 == 0 && self.players > 1 => self.player = (self.player + 1) % self.players,
+            _ => {}
+        }
+        self.lines = lines;
+    }
+
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * 16 {
+            let command = std::mem::take(&mut self.command);
+            self.run(&command);
+        }
+    }
+
+    fn run(&mut self, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]);
+        match data[0] >> 3 {
+            command @ (PAL01 | PAL23 | PAL03 | PAL12) => {
+                let (first, second) = [(0, 1), (2, 3), (0, 3), (1, 2)][command as usize];
+                for palette in &mut self.palettes {
+                    palette[0] = color(0);
+                }
+                for i in 1..4 {
+                    self.palettes[first][i] = color(i);
+                    self.palettes[second][i] = color(i + 3);
+                }
+                self.colorized = true;
+            }
+            ATTR_BLK => {
+                for block in data[2..].chunks_exact(6).take(data[1] as usize) {
+                    self.attribute_block(block);
+                }
+            }
+            ATTR_LIN => {
+                for &line in data[2..].iter().take(data[1] as usize) {
+                    let (n, palette) = ((line & 0x1f) as usize, (line >> 5) & 0x03);
+                    for cell in 0..CELLS {
+                        let (x, y) = (cell % CELLS_X, cell / CELLS_X);
+                        // bit 7 horizontal, a row of cells, else a column
+                        if (line & 0x80 != 0 && y == n) || (line & 0x80 == 0 && x == n) {
+                            self.attributes[cell] = palette;
+                        }
+                    }
+                }
+            }
+            ATTR_DIV => {
+                let (flags, at) = (data[1], data[2] as usize);
+                for cell in 0..CELLS {
+                    let (x, y) = (cell % CELLS_X, cell / CELLS_X);
+                    let pos = if flags & 0x40 != 0 { y } else { x };
+                    let shift = match pos.cmp(&at) {
+                        std::cmp::Ordering::Less => 2,
+                        std::cmp::Ordering::Equal => 4,
+                        std::cmp::Ordering::Greater => 0,
+                    };
+                    self.attributes[cell] = (flags >> shift) & 0x03;
+                }
+            }
+            ATTR_CHR => {
+                let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+                let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+                let vertical = data[5] & 0x01 != 0;
+                for i in 0..count.min(CELLS).min((data.len() - 6) * 4) {
+                    if x >= CELLS_X || y >= CELLS_Y {
+                        break;
+                    }
+                    self.attributes[y * CELLS_X + x] = (data[6 + i / 4] >> (6 - i % 4 * 2)) & 0x03;
+                    match vertical {
+                        true if y + 1 == CELLS_Y => (x, y) = (x + 1, 0),
+                        true => y += 1,
+                        false if x + 1 == CELLS_X => (x, y) = (0, y + 1),
+                        false => x += 1,
+                    }
+                }
+            }
+            PAL_SET => {
+                for (i, palette) in self.palettes.iter_mut().enumerate() {
+                    let n = (color(i) & 0x1ff) as usize;
+                    for (c, out) in palette.iter_mut().enumerate() {
+                        let at = (n * 4 + c) * 2;
+                        *out = u16::from_le_bytes([self.system_palettes[at], self.system_palettes[at + 1]]);
+                    }
+                }
+                // color 0 comes from the first one
+                let shared = self.palettes[0][0];
+                for palette in &mut self.palettes {
+                    palette[0] = shared;
+                }
+                self.colorized = true;
+                self.attribute_set(data[9]);
+            }
+            ATTR_SET => self.attribute_set(data[1] | 0x80),
+            MLT_REQ => {
+                self.players = [1, 2, 1, 4][data[1] as usize & 0x03];
+                self.player = 0;
+            }
+            MASK_EN => {
+                self.mask = data[1] & 0x03;
+                if self.mask == MASK_FREEZE {
+                    self.frozen = self.last_frame.clone();
+                }
+            }
+            command @ (PAL_TRN | CHR_TRN | PCT_TRN | ATTR_TRN) => self.transfer = Some((command, data[1], false)),
+            _ => {}
+        }
+    }
+
+    fn attribute_block(&mut self, block: &[u8]) {
+        let (control, palettes) = (block[0] & 0x07, block[1]);
+        let (left, top, right, bottom) = (block[2] as usize, block[3] as usize, block[4] as usize, block[5] as usize);
+        // only inside or only outside paints the surrounding line too
+        let (line, line_palette) = match control {
+            0x01 => (true, palettes & 0x03),
+            0x04 => (true, (palettes >> 4) & 0x03),
+            _ => (control & 0x02 != 0, (palettes >> 2) & 0x03),
+        };
+        for cell in 0..CELLS {
+            let (x, y) = (cell % CELLS_X, cell / CELLS_X);
+            let within = (left..=right).contains(&x) && (top..=bottom).contains(&y);
+            let on_line = within && (x == left || x == right || y == top || y == bottom);
+            if on_line {
+                if line {
+                    self.attributes[cell] = line_palette;
+                }
+            } else if within {
+                if control & 0x01 != 0 {
+                    self.attributes[cell] = palettes & 0x03;
+                }
+            } else if control & 0x04 != 0 {
+                self.attributes[cell] = (palettes >> 4) & 0x03;
+            }
+        }
+    }
+
+    // bits 0-5 attribute file, bit 6 ends a mask, bit 7 set applies the file
+    fn attribute_set(&mut self, flags: u8) {
+        if flags & 0x80 != 0 {
+            let file = (flags & 0x3f) as usize;
+            if file < ATTRIBUTE_FILES {
+                let packed = &self.attribute_files[file * ATTRIBUTE_FILE..][..ATTRIBUTE_FILE];
+                for (cell, attribute) in self.attributes.iter_mut().enumerate() {
+                    *attribute = (packed[cell / 4] >> (6 - cell % 4 * 2)) & 0x03;
+                }
+            }
+        }
+        if flags & 0x40 != 0 {
+            self.mask = 0;
+        }
+    }
+
+    // at VBlank with the frame just finished
+    pub fn frame(&mut self, shades: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+        self.last_frame.copy_from_slice(shades);
+        match self.transfer {
+            // the frame the command was sent in may still show the old picture
+            Some((command, arg, false)) => self.transfer = Some((command, arg, true)),
+            Some((command, arg, true)) => {
+                self.transfer = None;
+                let data = screen_data(shades);
+                match command {
+                    PAL_TRN => self.system_palettes.copy_from_slice(&data),
+                    CHR_TRN => self.border_tiles[(arg as usize & 1) * TRANSFER..][..TRANSFER].copy_from_slice(&data),
+                    PCT_TRN => self.border.copy_from_slice(&data),
+                    _ => self.attribute_files.copy_from_slice(&data[..ATTRIBUTE_FILES * ATTRIBUTE_FILE]),
+                }
+            }
+            None => {}
+        }
+    }
+
+    // packed RGB, WIDTH x HEIGHT: the border with the shades of the screen in their
+    // palettes inside. before the game sent colors the screen has the fallback ones
+    pub fn render(&self, shades: &[u8], fallback: &[Rgb; 4]) -> Vec<u8> {
+        let screen: Vec<[Rgb; 4]> = self.palettes.iter().map(|palette| palette.map(rgb)).collect();
+        let backdrop = if self.colorized { screen[0][0] } else { fallback[0] };
+        let mut out = backdrop.repeat(WIDTH * HEIGHT);
+        for ty in 0..28 {
+            for tx in 0..32 {
+                let at = (ty * 32 + tx) * 2;
+                let entry = u16::from_le_bytes([self.border[at], self.border[at + 1]]);
+                let tile = &self.border_tiles[(entry & 0xff) as usize * 32..][..32];
+                let palette = ((entry >> 10) & 0x03) as usize;
+                for y in 0..8 {
+                    let row = if entry & 0x8000 != 0 { 7 - y } else { y };
+                    let planes = [tile[row * 2], tile[row * 2 + 1], tile[16 + row * 2], tile[17 + row * 2]];
+                    for x in 0..8 {
+                        let bit = if entry & 0x4000 != 0 { x } else { 7 - x };
+                        let color = planes.iter().enumerate().fold(0, |c, (i, plane)| c | ((plane >> bit) & 1) << i) as usize;
+                        // color 0 shows the backdrop through
+                        if color != 0 {
+                            let at = BORDER_COLORS + (palette * 16 + color) * 2;
+                            let i = ((ty * 8 + y) * WIDTH + tx * 8 + x) * 3;
+                            out[i..i + 3].copy_from_slice(&rgb(u16::from_le_bytes([self.border[at], self.border[at + 1]])));
+                        }
+                    }
+                }
+            }
+        }
+        let shades = if self.mask == MASK_FREEZE && !self.frozen.is_empty() { &self.frozen } else { shades };
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let shade = shades[y * SCREEN_WIDTH + x] as usize & 0x03;
+                let color = match (self.mask, self.colorized) {
+                    (MASK_BLACK, _) => [0; 3],
+                    (MASK_COLOR_0, _) => backdrop,
+                    (_, true) => screen[self.attributes[y / 8 * CELLS_X + x / 8] as usize][shade],
+                    (_, false) => fallback[shade],
+                };
+                let i = ((SCREEN_Y + y) * WIDTH + SCREEN_X + x) * 3;
+                out[i..i + 3].copy_from_slice(&color);
+            }
+        }
+        out
+    }
+}
+
+// 256 tiles of 2bpp data as read from the screen, tiles left to right in rows of 20
+fn screen_data(shades: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; TRANSFER];
+    for (tile, bytes) in data.chunks_exact_mut(16).enumerate() {
+        let (tx, ty) = (tile % CELLS_X * 8, tile / CELLS_X * 8);
+        for line in 0..8 {
+            for px in 0..8 {
+                let shade = shades[(ty + line) * SCREEN_WIDTH + tx + px];
+                bytes[line * 2] |= (shade & 1) << (7 - px);
+                bytes[line * 2 + 1] |= ((shade >> 1) & 1) << (7 - px);
+            }
+        }
+    }
+    data
+}
+
+impl Component for Sgb {
+    const TAG: [u8; 4] = *b"SGB ";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.lines);
+        w.u8(self.bit.map_or(0xff, |bit| bit as u8));
+        w.bytes(&self.packet);
+        w.u8(self.command.len() as u8);
+        w.bytes(&self.command);
+        w.u8(self.players);
+        w.u8(self.player);
+        for color in self.palettes.as_flattened() {
+            w.u16(*color);
+        }
+        w.bytes(&self.system_palettes);
+        w.bytes(&self.attributes);
+        w.bytes(&self.attribute_files);
+        w.bytes(&self.border_tiles);
+        w.bytes(&self.border);
+        w.bool(self.colorized);
+        w.u8(self.mask);
+        w.bool(!self.frozen.is_empty());
+        w.bytes(&self.frozen);
+        let (command, arg, armed) = self.transfer.unwrap_or_default();
+        w.bool(self.transfer.is_some());
+        w.u8(command);
+        w.u8(arg);
+        w.bool(armed);
+        w.bytes(&self.last_frame);
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.lines = r.u8()? & 0x30;
+        self.bit = match r.u8()? {
+            0xff => None,
+            bit => Some(bit as usize & 0x7f),
+        };
+        r.bytes(&mut self.packet)?;
+        self.command = vec![0; r.u8()? as usize];
+        r.bytes(&mut self.command)?;
+        self.players = r.u8()?;
+        self.player = r.u8()?;
+        if ![1, 2, 4].contains(&self.players) {
+            return Err(StateError::Corrupt(format!("SGB with {} joypads", self.players)));
+        }
+        self.player %= self.players;
+        for color in self.palettes.as_flattened_mut() {
+            *color = r.u16()?;
+        }
+        r.bytes(&mut self.system_palettes)?;
+        r.bytes(&mut self.attributes)?;
+        for attribute in &mut self.attributes {
+            *attribute &= 0x03;
+        }
+        r.bytes(&mut self.attribute_files)?;
+        r.bytes(&mut self.border_tiles)?;
+        r.bytes(&mut self.border)?;
+        self.colorized = r.bool()?;
+        self.mask = r.u8()? & 0x03;
+        self.frozen = match r.bool()? {
+            true => vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            false => Vec::new(),
+        };
+        r.bytes(&mut self.frozen)?;
+        let transfer = r.bool()?;
+        let (command, arg, armed) = (r.u8()?, r.u8()?, r.bool()?);
+        self.transfer = transfer.then_some((command, arg, armed));
+        r.bytes(&mut self.last_frame)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::palette::PaletteSettings;
+    use crate::{Hardware, GB};
+
+    // a reset pulse, then the 128 bits through P1 as games send them
+    fn send(gb: &mut GB, command: u8, args: &[u8]) {
+        let mut packet = [0; 16];
+        packet[0] = command << 3 | 1;
+        packet[1..=args.len()].copy_from_slice(args);
+        gb.mmu.wb(0xff00, 0x00);
+        gb.mmu.wb(0xff00, 0x30);
+        for bit in 0..128 {
+            let one = packet[bit / 8] >> (bit % 8) & 1 != 0;
+            gb.mmu.wb(0xff00, if one { 0x10 } else { 0x20 });
+            gb.mmu.wb(0xff00, 0x30);
+        }
+    }
+
+    #[test]
+    fn colors_the_screen_from_packets() {
+        let mut rom = micro_rom("loop: jr loop");
+        rom[0x0146] = 0x03;
+        rom[0x014b] = 0x33;
+        let mut gb = GB::new(&rom).unwrap();
+        assert_eq!(gb.display_size(), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        gb.set_hardware(Hardware::Sgb2);
+        assert_eq!(gb.display_size(), (WIDTH, HEIGHT));
+        let palettes = PaletteSettings::default();
+        let fallback = palettes.dmg().shades;
+        // the frontend's colors until the game sends its own
+        let shades = [1; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let (left, right) = (((SCREEN_Y * WIDTH) + SCREEN_X) * 3, ((SCREEN_Y * WIDTH) + SCREEN_X + 159) * 3);
+        assert_eq!(gb.mmu.sgb.render(&shades, &fallback)[left..left + 3], fallback[1]);
+
+        // red shared, palette 0 green, palette 1 blue, then the right half in palette 1
+        send(&mut gb, PAL01, &[0x1f, 0x00, 0xe0, 0x03, 0xe0, 0x03, 0xe0, 0x03, 0x00, 0x7c, 0x00, 0x7c, 0x00, 0x7c]);
+        send(&mut gb, ATTR_DIV, &[0x01, 10]);
+        let out = gb.mmu.sgb.render(&shades, &fallback);
+        assert_eq!((&out[..3], &out[left..left + 3], &out[right..right + 3]), (&[255, 0, 0][..], &[0, 255, 0][..], &[0, 0, 255][..]));
+        send(&mut gb, MASK_EN, &[MASK_BLACK]);
+        assert_eq!(gb.mmu.sgb.render(&shades, &fallback)[left..left + 3], [0, 0, 0]);
+
+        // two joypads, the id counts down as P15 goes back high
+        assert_eq!(gb.mmu.rb(0xff00) & 0x0f, 0x0f);
+        send(&mut gb, MLT_REQ, &[0x01]);
+        assert_eq!(gb.mmu.rb(0xff00) & 0x0f, 0x0f);
+        gb.mmu.wb(0xff00, 0x10);
+        gb.mmu.wb(0xff00, 0x30);
+        assert_eq!(gb.mmu.rb(0xff00) & 0x0f, 0x0e);
+
+        // tiles come off the frame after the one the command was sent in
+        send(&mut gb, CHR_TRN, &[0x01]);
+        gb.mmu.sgb.frame(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        gb.mmu.sgb.frame(&[3; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert!(gb.mmu.sgb.border_tiles[..TRANSFER].iter().all(|&b| b == 0));
+        assert!(gb.mmu.sgb.border_tiles[TRANSFER..].iter().all(|&b| b == 0xff));
+    }
+}
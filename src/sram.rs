@@ -0,0 +1,114 @@
+// cartridge RAM as a plain .sav file, the raw bytes from A000 on that save
+// editors and randomizers read and write. works while the game runs: exports
+// go to a temporary file first, so a tool watching the file never sees half of one.
+// a cartridge with a clock has its registers after the RAM, as BGB writes them
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{Error, GB};
+
+// <save_dir>/<rom file stem>.sav
+pub fn default_path(save_dir: &Path, rom: &str) -> PathBuf {
+    let stem = Path::new(rom).file_stem().unwrap_or_default();
+    save_dir.join(format!("{}.sav", stem.to_string_lossy()))
+}
+
+// returns bytes written
+pub fn export(gb: &GB, path: &Path) -> Result<usize, Error> {
+    let write = || -> io::Result<usize> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut data = gb.cartridge_ram().to_vec();
+        data.extend(gb.rtc_footer().into_iter().flatten());
+        let tmp = path.with_extension("sav.tmp");
+        fs::write(&tmp, &data)?;
+        fs::rename(&tmp, path)?;
+        Ok(data.len())
+    };
+    write().map_err(|source| Error::SaveIo { path: path.to_path_buf(), source })
+}
+
+// a smaller file, e.g. of a 2KiB cartridge, fills RAM from A000 on and leaves the rest.
+// a clock footer is only read after all of RAM
+pub fn import(gb: &mut GB, path: &Path) -> Result<usize, Error> {
+    let data = fs::read(path).map_err(|source| Error::SaveIo { path: path.to_path_buf(), source })?;
+    let capacity = gb.cartridge_ram().len();
+    let (ram, footer) = data.split_at(data.len().min(capacity));
+    if !footer.is_empty() && gb.load_rtc_footer(footer).is_err() {
+        return Err(Error::SaveTooLarge { path: path.to_path_buf(), len: data.len(), capacity });
+    }
+    gb.cartridge_ram_mut()[..ram.len()].copy_from_slice(ram);
+    Ok(data.len())
+}
+
+// `export [file]` or `import [file]` for the debugger and stdin while playing,
+// relative paths are inside the save directory
+pub fn command(gb: &mut GB, save_dir: &Path, rom: &str, args: &[&str]) -> Result<String, String> {
+    let path = match args.get(1) {
+        Some(file) => save_dir.join(file),
+        None => default_path(save_dir, rom),
+    };
+    match args.first().copied() {
+        Some("export") => {
+            let len = export(gb, &path)?;
+            Ok(format!("exported {} bytes of cartridge RAM to {}", len, path.display()))
+        }
+        Some("import") => {
+            let len = import(gb, &path)?;
+            Ok(format!("imported {} bytes of cartridge RAM from {}", len, path.display()))
+        }
+        _ => Err("expected `sram export [file]` or `sram import [file]`".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::asm::micro_rom;
+
+    #[test]
+    fn exports_and_imports_while_running() {
+        let dir = env::temp_dir().join(format!("gb-rust-sram-{}", std::process::id()));
+        let rom = micro_rom("ld hl, $a000\nloop:\ninc (hl)\njr loop");
+        let mut gb = GB::new(&rom).unwrap();
+        for _ in 0..9 {
+            gb.cycle();
+        }
+        let message = command(&mut gb, &dir, "roms/game.gb", &["export"]).unwrap();
+        assert!(message.starts_with("exported 8192 bytes"));
+        let saved = fs::read(dir.join("game.sav")).unwrap();
+        assert_eq!(saved[..2], [4, 0]);
+
+        // the game keeps counting from what the editor put in
+        fs::write(dir.join("edited.sav"), [0x40]).unwrap();
+        command(&mut gb, &dir, "roms/game.gb", &["import", "edited.sav"]).unwrap();
+        for _ in 0..2 {
+            gb.cycle();
+        }
+        assert_eq!(gb.read_memory(0xa000, 1), [0x41]);
+        fs::write(dir.join("big.sav"), vec![0; 0x8000]).unwrap();
+        assert!(command(&mut gb, &dir, "game.gb", &["import", "big.sav"]).is_err());
+        assert!(command(&mut gb, &dir, "game.gb", &["dump"]).is_err());
+
+        // MBC3 with a clock and 8KiB, the clock after RAM round trips
+        let mut rom = micro_rom("loop: jr loop");
+        (rom[0x0147], rom[0x0149]) = (0x10, 0x02);
+        let mut gb = GB::new(&rom).unwrap();
+        gb.cartridge_ram_mut()[0] = 0x42;
+        gb.mmu.mbc.rtc_mut().unwrap().write(0x09, 30);
+        command(&mut gb, &dir, "clock.gb", &["export"]).unwrap();
+        let saved = fs::read(dir.join("clock.sav")).unwrap();
+        assert_eq!((saved.len(), saved[0], saved[0x2004]), (0x2000 + 48, 0x42, 30));
+        let mut gb = GB::new(&rom).unwrap();
+        assert_eq!(command(&mut gb, &dir, "clock.gb", &["import"]).unwrap(), format!("imported 8240 bytes of cartridge RAM from {}", dir.join("clock.sav").display()));
+        assert_eq!(gb.rtc_footer().unwrap()[4], 30);
+        fs::write(dir.join("odd.sav"), vec![0; 0x2000 + 7]).unwrap();
+        assert!(command(&mut gb, &dir, "clock.gb", &["import", "odd.sav"]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
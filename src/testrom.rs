@@ -0,0 +1,126 @@
+// headless runner for Blargg and Mooneye test ROMs
+//
+// Blargg ROMs print "Passed" / "Failed" over the serial port, Mooneye ROMs
+// execute LD B,B with Fibonacci numbers in B-L on success or 0x42 everywhere on failure
+
+use crate::serial::OutputCapture;
+use crate::GB;
+
+pub const DEFAULT_TIMEOUT_FRAMES: u64 = 60 * 120;
+
+// LD B,B used by Mooneye ROMs as a software breakpoint
+const MOONEYE_BREAKPOINT: u8 = 0x40;
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TestResult {
+    Passed,
+    // reason, serial output for Blargg ROMs
+    Failed(String),
+    Timeout(String),
+    Crashed(String),
+}
+
+// skip_idle fast-forwards through waits, see GB::set_skip_idle
+pub fn run(rom: &[u8], timeout_frames: u64, skip_idle: bool) -> TestResult {
+    let mut gb = match GB::new(rom) {
+        Ok(gb) => gb,
+        Err(e) => return TestResult::Failed(e.to_string()),
+    };
+    gb.set_skip_idle(skip_idle);
+    let capture = OutputCapture::default();
+    gb.mmu.serial.connect(Box::new(capture.clone()));
+    let serial_text = || String::from_utf8_lossy(&capture.output.borrow()).into_owned();
+
+    let finished = gb.run_until(timeout_frames, |gb| {
+        let text = capture.output.borrow();
+        contains(&text, b"Passed") || contains(&text, b"Failed") || mooneye_registers(gb).is_some() || gb.crash().is_some()
+    });
+    if let Some(e) = gb.crash() {
+        return TestResult::Crashed(e.to_string());
+    }
+    match finished {
+        false => TestResult::Timeout(serial_text()),
+        true => match mooneye_registers(&gb) {
+            Some(regs) if regs == FIBONACCI => TestResult::Passed,
+            Some(regs) => TestResult::Failed(format!("registers {:02X?}", regs)),
+            None if serial_text().contains("Passed") => TestResult::Passed,
+            None => TestResult::Failed(serial_text()),
+        },
+    }
+}
+
+// B C D E H L when stopped at the Mooneye breakpoint with a known result
+fn mooneye_registers(gb: &GB) -> Option<[u8; 6]> {
+    if gb.mmu.peek(gb.z80.pc) != MOONEYE_BREAKPOINT {
+        return None;
+    }
+    let z80 = &gb.z80;
+    let regs = [z80.b, z80.c, z80.d, z80.e, z80.h, z80.l];
+    (regs == FIBONACCI || regs == [0x42; 6]).then_some(regs)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+    use std::fmt::Write;
+
+    // prints text over the serial port, waiting out each 4096 t-cycle transfer with NOPs
+    fn serial_rom(text: &str) -> Vec<u8> {
+        let mut source = String::new();
+        for (i, c) in text.bytes().enumerate() {
+            let addr = 0x0100 + i * 0x0500;
+            writeln!(source, "org ${:04x}\nld a, ${:02x}\nldh ($01), a\nld a, $81\nldh ($02), a", addr, c).unwrap();
+        }
+        writeln!(source, "org ${:04x}", 0x0100 + text.len() * 0x0500).unwrap();
+        micro_rom(&source)
+    }
+
+    #[test]
+    fn passes_on_blargg_serial_output() {
+        assert_eq!(run(&serial_rom("cpu\nPassed"), 10, false), TestResult::Passed);
+        assert_eq!(run(&serial_rom("cpu\nPassed"), 10, true), TestResult::Passed);
+    }
+
+    #[test]
+    fn fails_on_blargg_serial_output() {
+        assert_eq!(run(&serial_rom("Failed"), 10, false), TestResult::Failed("Failed".to_string()));
+    }
+
+    #[test]
+    fn times_out_without_result() {
+        assert_eq!(run(&serial_rom("cpu"), 1, false), TestResult::Timeout("cpu".to_string()));
+    }
+
+    #[test]
+    fn checks_mooneye_registers() {
+        let rom = micro_rom("ld b, b");
+        let mut gb = GB::new(&rom).unwrap();
+        assert_eq!(mooneye_registers(&gb), None);
+        [gb.z80.b, gb.z80.c, gb.z80.d, gb.z80.e, gb.z80.h, gb.z80.l] = FIBONACCI;
+        assert_eq!(mooneye_registers(&gb), Some(FIBONACCI));
+    }
+
+    // runs every .gb file in $GB_TEST_ROMS, e.g. blargg cpu_instrs or mooneye acceptance
+    #[test]
+    #[ignore]
+    fn test_rom_directory() {
+        let dir = std::env::var("GB_TEST_ROMS").expect("Set GB_TEST_ROMS to a test ROM directory");
+        let mut failures = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "gb") {
+                let result = run(&std::fs::read(&path).unwrap(), DEFAULT_TIMEOUT_FRAMES, false);
+                if result != TestResult::Passed {
+                    failures.push(format!("{}: {:?}", path.display(), result));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
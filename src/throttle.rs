@@ -0,0 +1,117 @@
+// paces emulation to real time, and slows it down further while nobody is
+// watching or playing, to save battery
+//
+// idle means the frame has not changed for the configured time, or the
+// frontend reported focus loss (`blur` on stdin from the program showing the
+// frames). input ends idling and focus coming back ends the other, either
+// resumes full speed right away
+
+use std::time::{Duration, Instant};
+
+use crate::{ppu, CLOCK_HZ};
+
+// frames per second run while throttled
+const THROTTLED_FPS: u32 = 10;
+
+pub struct Throttle {
+    // multiple of real time, None runs as fast as possible
+    speed: Option<f64>,
+    // one frame at nominal speed, depends on the hardware's clock
+    frame_time: Duration,
+    // when the next frame is due
+    deadline: Instant,
+    // static screen time before throttling, None disables the idle check
+    idle_after: Option<Duration>,
+    // the window showing the frames
+    focused: bool,
+    last_hash: u64,
+    // when the screen last changed or input happened
+    last_activity: Instant,
+}
+
+impl Throttle {
+    pub fn new(speed: Option<f64>, idle_after: Option<Duration>) -> Self {
+        Throttle {
+            speed,
+            frame_time: frame_time(CLOCK_HZ),
+            deadline: Instant::now(),
+            idle_after,
+            focused: true,
+            last_hash: 0,
+            last_activity: Instant::now(),
+        }
+    }
+
+    // t-cycles per second of the emulated hardware, DMG by default
+    pub fn set_clock(&mut self, hz: u64) {
+        self.frame_time = frame_time(hz);
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        if focused {
+            self.input();
+        }
+    }
+
+    pub fn input(&mut self) {
+        self.last_activity = Instant::now();
+        // the frame after input is due now, not after a throttled frame's wait
+        self.deadline = self.deadline.min(Instant::now());
+    }
+
+    pub fn throttled(&self) -> bool {
+        !self.focused || self.idle_after.is_some_and(|after| self.last_activity.elapsed() >= after)
+    }
+
+    // call once per finished frame, returns how long to wait before the next one
+    pub fn frame(&mut self, framebuffer: &[u8]) -> Duration {
+        let hash = frame_hash(framebuffer);
+        if hash != self.last_hash {
+            self.last_hash = hash;
+            self.last_activity = Instant::now();
+        }
+        let frame_time = match (self.throttled(), self.speed) {
+            (true, _) => Duration::from_secs(1) / THROTTLED_FPS,
+            (false, Some(speed)) => self.frame_time.div_f64(speed),
+            (false, None) => Duration::ZERO,
+        };
+        self.deadline += frame_time;
+        let now = Instant::now();
+        // too slow to keep up, don't try to catch up later
+        if self.deadline <= now {
+            self.deadline = now;
+            return Duration::ZERO;
+        }
+        self.deadline - now
+    }
+}
+
+// 70224 t-cycles, 16.74ms on a DMG
+fn frame_time(hz: u64) -> Duration {
+    Duration::from_nanos(ppu::FRAME_DOTS * 1_000_000_000 / hz)
+}
+
+// FNV-1a
+pub fn frame_hash(framebuffer: &[u8]) -> u64 {
+    framebuffer.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttles_while_unfocused() {
+        let mut throttle = Throttle::new(None, None);
+        assert_eq!(throttle.frame(&[1]), Duration::ZERO);
+        throttle.set_focused(false);
+        assert!(throttle.throttled());
+        assert!(throttle.frame(&[2]) > Duration::from_millis(50));
+        // input doesn't bring focus back, but the focus does right away
+        throttle.input();
+        assert!(throttle.throttled());
+        throttle.set_focused(true);
+        assert_eq!(throttle.frame(&[3]), Duration::ZERO);
+    }
+}
@@ -0,0 +1,202 @@
+// [FF04-FF07] DIV / TIMA / TMA / TAC
+//
+// DIV is the upper byte of a 16-bit counter running at t-cycle rate, TIMA
+// increments when the counter bit selected by TAC falls, so DIV / TAC writes
+// can tick TIMA too
+
+use crate::savestate::{Component, StateError, StateReader, StateWriter};
+
+// counter bit watched by TIMA for TAC bits 0-1
+const TAC_BITS: [u16; 4] = [9, 3, 5, 7];
+// TIMA reads 0 for one m-cycle after overflow before TMA is loaded
+const RELOAD_DELAY: u8 = 4;
+
+#[derive(Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // t-cycles until TIMA reload after overflow, 0 when none pending
+    reload: u8,
+}
+
+impl Timer {
+    // as the DMG boot ROM leaves it, DIV reads AB at 0100
+    pub fn after_boot() -> Self {
+        Timer { counter: 0xabcc, ..Default::default() }
+    }
+
+    pub fn rb(&self, addr: u16) -> u8 {
+        match addr {
+            0xff04 => (self.counter >> 8) as u8,
+            0xff05 => self.tima,
+            0xff06 => self.tma,
+            _ => self.tac | 0xf8,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, val: u8) {
+        let before = self.input();
+        match addr {
+            0xff04 => self.counter = 0,
+            0xff05 => {
+                // write during reload delay cancels the reload
+                self.tima = val;
+                self.reload = 0;
+            }
+            0xff06 => self.tma = val,
+            _ => self.tac = val & 0x07,
+        }
+        if before && !self.input() {
+            self.increment();
+        }
+    }
+
+    // advances by t-cycles, returns true when the timer interrupt fires
+    pub fn step(&mut self, t: u32) -> bool {
+        // most steps only move the counter, skip going through them cycle by cycle
+        if self.reload == 0 && !self.falls_within(t) {
+            self.counter = self.counter.wrapping_add(t as u16);
+            return false;
+        }
+        let mut interrupt = false;
+        for _ in 0..t {
+            if self.reload > 0 {
+                self.reload -= 1;
+                if self.reload == 0 {
+                    self.tima = self.tma;
+                    interrupt = true;
+                }
+            }
+            let before = self.input();
+            self.counter = self.counter.wrapping_add(1);
+            if before && !self.input() {
+                self.increment();
+            }
+        }
+        interrupt
+    }
+
+    // t-cycles until step returns true, u32::MAX while the timer is stopped
+    pub fn next_interrupt(&self) -> u32 {
+        if self.reload > 0 {
+            return self.reload as u32;
+        }
+        if self.tac & 0x04 == 0 {
+            return u32::MAX;
+        }
+        // the next fall, one more each period until TIMA wraps, then the reload
+        let period = 2u32 << TAC_BITS[(self.tac & 0x03) as usize];
+        period - self.counter as u32 % period + (0xff - self.tima as u32) * period + RELOAD_DELAY as u32
+    }
+
+    // whether the selected counter bit falls in the next t-cycles, which
+    // happens each time the counter reaches a multiple of twice the bit
+    fn falls_within(&self, t: u32) -> bool {
+        let period = 2u32 << TAC_BITS[(self.tac & 0x03) as usize];
+        self.tac & 0x04 != 0 && self.counter as u32 % period + t >= period
+    }
+
+    // TIMA clock: selected counter bit while enabled
+    fn input(&self) -> bool {
+        self.tac & 0x04 != 0 && self.counter & (1 << TAC_BITS[(self.tac & 0x03) as usize]) != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload = RELOAD_DELAY;
+        }
+    }
+}
+
+impl Component for Timer {
+    const TAG: [u8; 4] = *b"TIMR";
+    const VERSION: u8 = 1;
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.counter);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+        w.u8(self.reload);
+    }
+    fn load(&mut self, r: &mut StateReader, _version: u8) -> Result<(), StateError> {
+        self.counter = r.u16()?;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()?;
+        self.reload = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tima_counts_at_selected_rate() {
+        let mut timer = Timer::default();
+        // enabled, counter bit 3 - every 16 t-cycles
+        timer.wb(0xff07, 0x05);
+        timer.step(16 * 10);
+        assert_eq!(timer.rb(0xff05), 10);
+    }
+
+    #[test]
+    fn div_reset_ticks_tima_on_falling_edge() {
+        let mut timer = Timer::default();
+        timer.wb(0xff07, 0x05);
+        timer.step(8);
+        timer.wb(0xff04, 0x42);
+        assert_eq!(timer.rb(0xff04), 0);
+        assert_eq!(timer.rb(0xff05), 1);
+    }
+
+    #[test]
+    fn overflow_reloads_tma_one_m_cycle_later() {
+        let mut timer = Timer::default();
+        timer.wb(0xff06, 0xab);
+        timer.wb(0xff05, 0xff);
+        timer.wb(0xff07, 0x05);
+        assert!(!timer.step(16));
+        assert_eq!(timer.rb(0xff05), 0x00);
+        assert!(timer.step(4));
+        assert_eq!(timer.rb(0xff05), 0xab);
+    }
+
+    #[test]
+    fn knows_when_the_next_interrupt_is() {
+        let mut timer = Timer::default();
+        assert_eq!(timer.next_interrupt(), u32::MAX);
+        timer.wb(0xff05, 0xfe);
+        timer.wb(0xff07, 0x05);
+        timer.step(3);
+        // 13 to the next increment, 16 more to the overflow, then the reload
+        assert_eq!(timer.next_interrupt(), 13 + 16 + 4);
+        assert!(!timer.step(32));
+        assert_eq!(timer.next_interrupt(), 1);
+        assert!(timer.step(1));
+    }
+
+    #[test]
+    fn skipping_quiet_steps_matches_single_cycles() {
+        let (mut stepped, mut single) = (Timer::default(), Timer::default());
+        for timer in [&mut stepped, &mut single] {
+            timer.wb(0xff06, 0xf0);
+            timer.wb(0xff07, 0x05);
+        }
+        let mut fired = (0, 0);
+        for t in (1..2000).map(|n| n % 7 * 4) {
+            fired.0 += stepped.step(t) as u32;
+            for _ in 0..t {
+                fired.1 += single.step(1) as u32;
+            }
+            assert_eq!((stepped.counter, stepped.tima, stepped.reload), (single.counter, single.tima, single.reload));
+        }
+        assert!(fired.0 > 0);
+        assert_eq!(fired.0, fired.1);
+    }
+}
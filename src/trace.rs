@@ -0,0 +1,294 @@
+// per-instruction trace in the format used by gameboy-doctor and other emulators:
+// A:00 F:11 B:22 C:33 D:44 E:55 H:66 L:77 SP:8888 PC:9999 PCMEM:AA,BB,CC,DD
+//
+// long captures can be written compressed instead and turned back into that
+// text by `gb-rust trace replay`: an 8 byte "GBTRACE" + version header, then
+// chunks of up to CHUNK_RECORDS instructions as
+//   raw length u32 | compressed length u32 | LZ4 block
+// each record 16 bytes: A F B C D E H L, SP and PC little-endian, PCMEM.
+// a crash loses at most the chunk in progress
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::lz4;
+use crate::{Z80, MMU};
+
+const MAGIC: &[u8; 8] = b"GBTRACE\x01";
+const RECORD_SIZE: usize = 16;
+const CHUNK_RECORDS: usize = 4096;
+
+// machine state before one instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    // A F B C D E H L
+    pub regs: [u8; 8],
+    pub sp: u16,
+    pub pc: u16,
+    // bytes at PC
+    pub pcmem: [u8; 4],
+}
+
+impl Record {
+    fn capture(z80: &Z80, mmu: &MMU) -> Self {
+        let pc = z80.pc;
+        let mem = |i: u16| mmu.peek(pc.wrapping_add(i));
+        Record {
+            regs: [z80.a, z80.f.bits(), z80.b, z80.c, z80.d, z80.e, z80.h, z80.l],
+            sp: z80.sp,
+            pc,
+            pcmem: [mem(0), mem(1), mem(2), mem(3)],
+        }
+    }
+
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut out = [0; RECORD_SIZE];
+        out[..8].copy_from_slice(&self.regs);
+        out[8..10].copy_from_slice(&self.sp.to_le_bytes());
+        out[10..12].copy_from_slice(&self.pc.to_le_bytes());
+        out[12..].copy_from_slice(&self.pcmem);
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Record {
+            regs: bytes[..8].try_into().unwrap(),
+            sp: u16::from_le_bytes([bytes[8], bytes[9]]),
+            pc: u16::from_le_bytes([bytes[10], bytes[11]]),
+            pcmem: bytes[12..16].try_into().unwrap(),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, flags, b, c, d, e, h, l] = self.regs;
+        let [m0, m1, m2, m3] = self.pcmem;
+        write!(
+            f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            a, flags, b, c, d, e, h, l, self.sp, self.pc, m0, m1, m2, m3
+        )
+    }
+}
+
+pub struct Tracer {
+    out: BufWriter<Box<dyn Write>>,
+    enabled: bool,
+    // records of the chunk in progress, None writes text
+    chunk: Option<Vec<u8>>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Tracer {
+            out: BufWriter::with_capacity(1 << 16, out),
+            enabled: true,
+            chunk: None,
+        }
+    }
+
+    pub fn compressed(out: Box<dyn Write>) -> io::Result<Self> {
+        let mut tracer = Self::new(out);
+        tracer.out.write_all(MAGIC)?;
+        tracer.chunk = Some(Vec::with_capacity(CHUNK_RECORDS * RECORD_SIZE));
+        Ok(tracer)
+    }
+
+    pub fn to_file(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(Box::new(File::create(path)?)))
+    }
+
+    pub fn compressed_file(path: &Path) -> io::Result<Self> {
+        Self::compressed(Box::new(File::create(path)?))
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        self.enabled = enabled;
+        self.flush()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // logs state before the instruction at PC executes
+    pub(crate) fn log(&mut self, z80: &Z80, mmu: &MMU) {
+        if !self.enabled {
+            return;
+        }
+        let record = Record::capture(z80, mmu);
+        // a failing trace file should not take emulation down
+        match self.chunk.as_mut() {
+            None => {
+                let _ = writeln!(self.out, "{}", record);
+            }
+            Some(chunk) => {
+                chunk.extend_from_slice(&record.to_bytes());
+                if chunk.len() == CHUNK_RECORDS * RECORD_SIZE {
+                    let _ = self.write_chunk();
+                }
+            }
+        }
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        let Some(chunk) = self.chunk.as_mut().filter(|c| !c.is_empty()) else {
+            return Ok(());
+        };
+        let compressed = lz4::compress(chunk);
+        let raw_len = chunk.len() as u32;
+        chunk.clear();
+        self.out.write_all(&raw_len.to_le_bytes())?;
+        self.out.write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.out.write_all(&compressed)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.out.flush()
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// reads back a compressed trace
+pub struct TraceReader<R: Read> {
+    input: R,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a compressed gb-rust trace"));
+        }
+        Ok(TraceReader { input, chunk: Vec::new(), pos: 0 })
+    }
+
+    // false at the end of the trace
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let mut lengths = [0; 8];
+        match self.input.read_exact(&mut lengths) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            result => result?,
+        }
+        let raw_len = u32::from_le_bytes(lengths[..4].try_into().unwrap()) as usize;
+        let compressed_len = u32::from_le_bytes(lengths[4..].try_into().unwrap()) as usize;
+        if !raw_len.is_multiple_of(RECORD_SIZE) || raw_len > CHUNK_RECORDS * RECORD_SIZE {
+            return Err(invalid("corrupt trace chunk"));
+        }
+        let mut compressed = vec![0; compressed_len];
+        self.input.read_exact(&mut compressed)?;
+        self.chunk = lz4::decompress(&compressed, raw_len).map_err(|e| invalid(&e))?;
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Record>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos == self.chunk.len() {
+            match self.next_chunk() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let record = Record::from_bytes(&self.chunk[self.pos..self.pos + RECORD_SIZE]);
+        self.pos += RECORD_SIZE;
+        Some(Ok(record))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// the whole trace as text, returns instructions written
+pub fn replay(input: impl Read, out: &mut impl Write) -> io::Result<u64> {
+    let mut count = 0;
+    for record in TraceReader::new(input)? {
+        writeln!(out, "{}", record?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// instructions whose text line contains every term, e.g. `PC:0150 A:00`, each
+// prefixed with its instruction number; returns how many matched
+pub fn grep(input: impl Read, terms: &[String], out: &mut impl Write) -> io::Result<u64> {
+    let terms: Vec<String> = terms.iter().map(|t| t.to_ascii_uppercase()).collect();
+    let mut count = 0;
+    for (number, record) in TraceReader::new(input)?.enumerate() {
+        let line = record?.to_string();
+        if terms.iter().all(|term| line.contains(term.as_str())) {
+            writeln!(out, "{}: {}", number, line)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::asm::micro_rom;
+    use crate::GB;
+
+    // Write into a buffer the test can still read after the tracer took it
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(tracer: Tracer, instructions: usize) {
+        let rom = micro_rom("xor a\nloop:\ninc a\njr loop");
+        let mut gb = GB::new(&rom).unwrap();
+        gb.set_tracer(Some(tracer));
+        for _ in 0..instructions {
+            gb.cycle();
+        }
+    }
+
+    #[test]
+    fn compressed_trace_replays_as_text() {
+        let (text, compressed) = (Shared::default(), Shared::default());
+        // more than one chunk
+        let instructions = CHUNK_RECORDS * 2 + 10;
+        trace(Tracer::new(Box::new(text.clone())), instructions);
+        trace(Tracer::compressed(Box::new(compressed.clone())).unwrap(), instructions);
+        let (text, compressed) = (text.0.lock().unwrap().clone(), compressed.0.lock().unwrap().clone());
+        assert!(compressed.len() < text.len() / 10);
+
+        let mut replayed = Vec::new();
+        assert_eq!(replay(compressed.as_slice(), &mut replayed).unwrap(), instructions as u64);
+        assert_eq!(replayed, text);
+
+        let mut found = Vec::new();
+        let terms = ["pc:0102".to_string(), "A:01".to_string()];
+        // A wraps around every 256 loops
+        assert_eq!(grep(compressed.as_slice(), &terms, &mut found).unwrap(), 17);
+        assert!(String::from_utf8(found).unwrap().starts_with("2: A:01 F:00"));
+        assert!(replay(text.as_slice(), &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+// hands frames from the emulation thread to a presentation thread. of the three
+// buffers the producer draws into one, the consumer shows another and the third
+// holds the newest finished frame; handing over swaps buffers under a lock held
+// only for the swap, so neither side waits for the other to finish drawing or
+// showing and a frame is never seen half written. frames the consumer
+// doesn't get to in time are replaced by newer ones
+
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+
+struct Middle<T> {
+    frame: T,
+    // published since the consumer last took it
+    fresh: bool,
+    // producer dropped
+    closed: bool,
+}
+
+struct Shared<T> {
+    middle: Mutex<Middle<T>>,
+    published: Condvar,
+}
+
+pub struct Producer<T> {
+    back: T,
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Consumer<T> {
+    front: T,
+    shared: Arc<Shared<T>>,
+}
+
+// all three buffers start out as init
+pub fn triple_buffer<T: Clone>(init: T) -> (Producer<T>, Consumer<T>) {
+    let shared = Arc::new(Shared {
+        middle: Mutex::new(Middle { frame: init.clone(), fresh: false, closed: false }),
+        published: Condvar::new(),
+    });
+    let producer = Producer { back: init.clone(), shared: shared.clone() };
+    (producer, Consumer { front: init, shared })
+}
+
+impl<T> Producer<T> {
+    // the buffer to draw the next frame into, holds some older frame
+    pub fn back(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    // makes the frame drawn into back() the newest one
+    pub fn publish(&mut self) {
+        let mut middle = self.shared.middle.lock().unwrap();
+        mem::swap(&mut self.back, &mut middle.frame);
+        middle.fresh = true;
+        self.shared.published.notify_one();
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.shared.middle.lock().unwrap().closed = true;
+        self.shared.published.notify_one();
+    }
+}
+
+impl<T> Consumer<T> {
+    // newest published frame, or the one returned last time when nothing new came
+    pub fn latest(&mut self) -> &T {
+        let mut middle = self.shared.middle.lock().unwrap();
+        if middle.fresh {
+            mem::swap(&mut self.front, &mut middle.frame);
+            middle.fresh = false;
+        }
+        drop(middle);
+        &self.front
+    }
+
+    // blocks until a frame newer than the last one returned is published,
+    // None once the producer is gone
+    pub fn wait(&mut self) -> Option<&T> {
+        let mut middle = self.shared.middle.lock().unwrap();
+        while !middle.fresh && !middle.closed {
+            middle = self.shared.published.wait(middle).unwrap();
+        }
+        if !middle.fresh {
+            return None;
+        }
+        mem::swap(&mut self.front, &mut middle.frame);
+        middle.fresh = false;
+        drop(middle);
+        Some(&self.front)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn consumer_gets_newest_whole_frame() {
+        let (mut producer, mut consumer) = triple_buffer(vec![0u8; 4]);
+        assert_eq!(consumer.latest(), &[0; 4]);
+        for n in 1..=3 {
+            producer.back().fill(n);
+            producer.publish();
+        }
+        // frames 1 and 2 were never shown
+        assert_eq!(consumer.latest(), &[3; 4]);
+        assert_eq!(consumer.latest(), &[3; 4]);
+
+        let presenter = thread::spawn(move || {
+            let mut shown = Vec::new();
+            while let Some(frame) = consumer.wait() {
+                assert!(frame.iter().all(|&b| b == frame[0]), "torn frame {:?}", frame);
+                shown.push(frame[0]);
+            }
+            shown
+        });
+        for n in 4..=200 {
+            producer.back().fill(n);
+            producer.publish();
+        }
+        drop(producer);
+        let shown = presenter.join().unwrap();
+        assert_eq!(shown.last(), Some(&200));
+        assert!(shown.windows(2).all(|w| w[0] < w[1]));
+    }
+}
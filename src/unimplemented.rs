@@ -0,0 +1,177 @@
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::fmt;
+
+// IO registers [FF00-FF7F] backed by an emulated subsystem, bit n = FF00 + n
+const IMPLEMENTED_IO: u128 = 1 << 0x00 // P1
+    | 1 << 0x01 // SB
+    | 1 << 0x02 // SC
+    | 0x0f << 0x04 // DIV, TIMA, TMA, TAC
+    | 1 << 0x0f // IF
+    | 1 << 0x40 // LCDC
+    | 1 << 0x41 // STAT
+    | 1 << 0x42 // SCY
+    | 1 << 0x43 // SCX
+    | 1 << 0x44 // LY
+    | 1 << 0x45 // LYC
+    | 1 << 0x47 // BGP
+    | 1 << 0x48 // OBP0
+    | 1 << 0x49 // OBP1
+    | 1 << 0x4a // WY
+    | 1 << 0x4b // WX
+    | 1 << 0x50 // boot ROM unmap
+    | 0x1f << 0x51; // HDMA1-5
+
+// Pan Docs names of the registers not in IMPLEMENTED_IO
+const IO_NAMES: [(u16, &str); 37] = [
+    (0xff10, "NR10"),
+    (0xff11, "NR11"),
+    (0xff12, "NR12"),
+    (0xff13, "NR13"),
+    (0xff14, "NR14"),
+    (0xff16, "NR21"),
+    (0xff17, "NR22"),
+    (0xff18, "NR23"),
+    (0xff19, "NR24"),
+    (0xff1a, "NR30"),
+    (0xff1b, "NR31"),
+    (0xff1c, "NR32"),
+    (0xff1d, "NR33"),
+    (0xff1e, "NR34"),
+    (0xff20, "NR41"),
+    (0xff21, "NR42"),
+    (0xff22, "NR43"),
+    (0xff23, "NR44"),
+    (0xff24, "NR50"),
+    (0xff25, "NR51"),
+    (0xff26, "NR52"),
+    (0xff46, "DMA"),
+    (0xff4c, "KEY0"),
+    (0xff4d, "KEY1"),
+    (0xff4f, "VBK"),
+    (0xff56, "RP"),
+    (0xff68, "BCPS"),
+    (0xff69, "BCPD"),
+    (0xff6a, "OCPS"),
+    (0xff6b, "OCPD"),
+    (0xff6c, "OPRI"),
+    (0xff70, "SVBK"),
+    (0xff72, "FF72"),
+    (0xff73, "FF73"),
+    (0xff74, "FF74"),
+    (0xff75, "FF75"),
+    (0xff76, "PCM12"),
+];
+
+// emulator features a game touched that are not emulated yet, IO accesses are
+// counted per register so the summary shows what games lean on most
+pub struct Unimplemented {
+    opcodes: BTreeSet<u8>,
+    // reads come through MMU::rb(&self)
+    io_reads: [Cell<u64>; 128],
+    io_writes: [u64; 128],
+}
+
+impl Default for Unimplemented {
+    fn default() -> Self {
+        Unimplemented {
+            opcodes: BTreeSet::new(),
+            io_reads: std::array::from_fn(|_| Cell::new(0)),
+            io_writes: [0; 128],
+        }
+    }
+}
+
+impl Unimplemented {
+    pub fn opcode(&mut self, opcode: u8) {
+        self.opcodes.insert(opcode);
+    }
+
+    pub fn io_read(&self, addr: u16) {
+        let n = (addr - 0xff00) as usize;
+        if IMPLEMENTED_IO & (1 << n) == 0 {
+            self.io_reads[n].set(self.io_reads[n].get() + 1);
+        }
+    }
+
+    pub fn io_write(&mut self, addr: u16) {
+        let n = (addr - 0xff00) as usize;
+        if IMPLEMENTED_IO & (1 << n) == 0 {
+            self.io_writes[n] += 1;
+        }
+    }
+
+    pub fn opcodes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.opcodes.iter().copied()
+    }
+
+    pub fn io_reads(&self) -> impl Iterator<Item = u16> + '_ {
+        self.io_counts().filter(|&(_, reads, _)| reads > 0).map(|(addr, _, _)| addr)
+    }
+
+    pub fn io_writes(&self) -> impl Iterator<Item = u16> + '_ {
+        self.io_counts().filter(|&(_, _, writes)| writes > 0).map(|(addr, _, _)| addr)
+    }
+
+    // address, reads and writes of every register touched
+    pub fn io_counts(&self) -> impl Iterator<Item = (u16, u64, u64)> + '_ {
+        (0..128)
+            .map(|n| (0xff00 + n as u16, self.io_reads[n].get(), self.io_writes[n]))
+            .filter(|&(_, reads, writes)| reads + writes > 0)
+    }
+}
+
+pub fn io_name(addr: u16) -> &'static str {
+    IO_NAMES.iter().find(|(a, _)| *a == addr).map_or("", |(_, name)| name)
+}
+
+// most used registers first
+impl fmt::Display for Unimplemented {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut counts: Vec<_> = self.io_counts().collect();
+        counts.sort_by_key(|&(addr, reads, writes)| (std::cmp::Reverse(reads + writes), addr));
+        match counts.is_empty() {
+            true => writeln!(f, "no unimplemented IO registers touched")?,
+            false => writeln!(f, "unimplemented IO registers touched:")?,
+        }
+        for (addr, reads, writes) in counts {
+            writeln!(f, "  {:04X} {:<5} {:>10} reads {:>10} writes", addr, io_name(addr), reads, writes)?;
+        }
+        if self.opcodes.is_empty() {
+            return Ok(());
+        }
+        let opcodes: Vec<String> = self.opcodes().map(|op| format!("{:02X}", op)).collect();
+        writeln!(f, "illegal opcodes run: {}", opcodes.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::micro_rom;
+    use crate::GB;
+
+    #[test]
+    fn counts_accesses_by_register() {
+        let rom = micro_rom(
+            "
+            ld a, $80
+            ldh ($26), a   ; NR52, sound on
+            ldh ($26), a
+            ldh a, ($26)
+            ldh a, ($46)   ; DMA
+            ldh a, ($0f)   ; IF is implemented
+            ",
+        );
+        let mut gb = GB::new(&rom).unwrap();
+        for _ in 0..6 {
+            gb.cycle();
+        }
+        let unimplemented = gb.unimplemented();
+        assert_eq!(unimplemented.io_counts().collect::<Vec<_>>(), [(0xff26, 1, 2), (0xff46, 1, 0)]);
+        assert_eq!(unimplemented.io_writes().collect::<Vec<_>>(), [0xff26]);
+        let summary = unimplemented.to_string();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[1].split_whitespace().collect::<Vec<_>>(), ["FF26", "NR52", "1", "reads", "2", "writes"]);
+        assert!(lines[2].starts_with("  FF46 DMA"));
+    }
+}
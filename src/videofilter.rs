@@ -0,0 +1,290 @@
+// pluggable upscalers for frontends without a GPU renderer. a filter takes an
+// RGBA frame and returns it scaled by a whole factor, filters chain so e.g.
+// `scale2x,nearest2x` gives a 4x frame. the first one gets the framebuffer
+// colorized from its shades, see Image::from_indexed
+
+// channels per pixel
+const RGBA: usize = 4;
+// hqx's YUV similarity thresholds
+const Y_THRESHOLD: i32 = 48;
+const U_THRESHOLD: i32 = 7;
+const V_THRESHOLD: i32 = 6;
+
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    // shades 0-3 picked from 4 colors, opaque
+    pub fn from_indexed(shades: &[u8], width: u32, height: u32, colors: &[[u8; 3]; 4]) -> Image {
+        let rgb: Vec<u8> = shades.iter().flat_map(|&shade| colors[shade as usize & 0x03]).collect();
+        Image::from_rgb(&rgb, width, height)
+    }
+
+    pub fn from_rgb(rgb: &[u8], width: u32, height: u32) -> Image {
+        let rgba = rgb.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xff]).collect();
+        Image { width, height, rgba }
+    }
+
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.rgba.chunks_exact(RGBA).flat_map(|p| [p[0], p[1], p[2]]).collect()
+    }
+
+    // coordinates outside repeat the edge
+    fn pixel(&self, x: i64, y: i64) -> [u8; 4] {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        let i = (y * self.width as usize + x) * RGBA;
+        [self.rgba[i], self.rgba[i + 1], self.rgba[i + 2], self.rgba[i + 3]]
+    }
+
+    // runs f for every source pixel, which returns the scale * scale pixels replacing it row by row
+    fn map_blocks(&self, scale: u32, f: impl Fn(i64, i64) -> Vec<[u8; 4]>) -> Image {
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut rgba = vec![0; (width * height) as usize * RGBA];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for (n, pixel) in f(x as i64, y as i64).into_iter().enumerate() {
+                    let (out_x, out_y) = (x * scale + n as u32 % scale, y * scale + n as u32 / scale);
+                    let i = (out_y * width + out_x) as usize * RGBA;
+                    rgba[i..i + RGBA].copy_from_slice(&pixel);
+                }
+            }
+        }
+        Image { width, height, rgba }
+    }
+}
+
+// Send so presenting can happen on its own thread
+pub trait VideoFilter: Send {
+    // as written in filter lists
+    fn name(&self) -> String;
+    // factor width and height grow by
+    fn scale(&self) -> u32;
+    fn apply(&self, input: &Image) -> Image;
+}
+
+// plain pixel repetition
+pub struct Nearest(pub u32);
+
+impl VideoFilter for Nearest {
+    fn name(&self) -> String {
+        format!("nearest{}x", self.0)
+    }
+    fn scale(&self) -> u32 {
+        self.0
+    }
+    fn apply(&self, input: &Image) -> Image {
+        let count = (self.0 * self.0) as usize;
+        input.map_blocks(self.0, |x, y| vec![input.pixel(x, y); count])
+    }
+}
+
+// AdvMAME2x / EPX, rounds off diagonal edges without new colors
+pub struct Scale2x;
+
+impl VideoFilter for Scale2x {
+    fn name(&self) -> String {
+        "scale2x".to_string()
+    }
+    fn scale(&self) -> u32 {
+        2
+    }
+    fn apply(&self, input: &Image) -> Image {
+        input.map_blocks(2, |x, y| {
+            let p = input.pixel(x, y);
+            let (a, b) = (input.pixel(x, y - 1), input.pixel(x + 1, y));
+            let (c, d) = (input.pixel(x - 1, y), input.pixel(x, y + 1));
+            vec![
+                if c == a && c != d && a != b { a } else { p },
+                if a == b && a != c && b != d { b } else { p },
+                if d == c && d != b && c != a { c } else { p },
+                if b == d && b != a && d != c { d } else { p },
+            ]
+        })
+    }
+}
+
+// AdvMAME3x
+pub struct Scale3x;
+
+impl VideoFilter for Scale3x {
+    fn name(&self) -> String {
+        "scale3x".to_string()
+    }
+    fn scale(&self) -> u32 {
+        3
+    }
+    fn apply(&self, input: &Image) -> Image {
+        input.map_blocks(3, |x, y| {
+            let at = |dx, dy| input.pixel(x + dx, y + dy);
+            let (a, b, c) = (at(-1, -1), at(0, -1), at(1, -1));
+            let (d, e, f) = (at(-1, 0), at(0, 0), at(1, 0));
+            let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+            let pick = |cond: bool, val| if cond { val } else { e };
+            vec![
+                pick(d == b && d != h && b != f, d),
+                pick((d == b && d != h && b != f && e != c) || (b == f && b != d && f != h && e != a), b),
+                pick(b == f && b != d && f != h, f),
+                pick((d == b && d != h && b != f && e != g) || (d == h && d != b && h != f && e != a), d),
+                e,
+                pick((b == f && b != d && f != h && e != i) || (h == f && h != d && f != b && e != c), f),
+                pick(d == h && d != b && h != f, d),
+                pick((d == h && d != b && h != f && e != i) || (h == f && h != d && f != b && e != g), h),
+                pick(h == f && h != d && f != b, f),
+            ]
+        })
+    }
+}
+
+// in the spirit of hq2x: neighbours compare in YUV with hqx's thresholds and
+// corners on an edge get blended instead of copied, without hq2x's full table
+pub struct Hq2x;
+
+impl VideoFilter for Hq2x {
+    fn name(&self) -> String {
+        "hq2x".to_string()
+    }
+    fn scale(&self) -> u32 {
+        2
+    }
+    fn apply(&self, input: &Image) -> Image {
+        input.map_blocks(2, |x, y| {
+            let p = input.pixel(x, y);
+            // vertical and horizontal neighbour of each corner
+            [(0, -1, -1, 0), (0, -1, 1, 0), (0, 1, -1, 0), (0, 1, 1, 0)]
+                .into_iter()
+                .map(|(vx, vy, hx, hy)| {
+                    let (v, h) = (input.pixel(x + vx, y + vy), input.pixel(x + hx, y + hy));
+                    match similar(v, h) && !similar(p, v) {
+                        true => blend(p, v, h),
+                        false => p,
+                    }
+                })
+                .collect()
+        })
+    }
+}
+
+fn yuv(p: [u8; 4]) -> (i32, i32, i32) {
+    let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+    (
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000,
+        (500 * r - 419 * g - 81 * b) / 1000,
+    )
+}
+
+fn similar(a: [u8; 4], b: [u8; 4]) -> bool {
+    let ((y1, u1, v1), (y2, u2, v2)) = (yuv(a), yuv(b));
+    (y1 - y2).abs() <= Y_THRESHOLD && (u1 - u2).abs() <= U_THRESHOLD && (v1 - v2).abs() <= V_THRESHOLD
+}
+
+// 2:1:1
+fn blend(p: [u8; 4], a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    let mut out = [0; 4];
+    for c in 0..4 {
+        out[c] = ((2 * p[c] as u32 + a[c] as u32 + b[c] as u32) / 4) as u8;
+    }
+    out
+}
+
+pub fn builtin(name: &str) -> Option<Box<dyn VideoFilter>> {
+    match name {
+        "scale2x" => Some(Box::new(Scale2x)),
+        "scale3x" => Some(Box::new(Scale3x)),
+        "hq2x" => Some(Box::new(Hq2x)),
+        _ => match name.strip_prefix("nearest")?.strip_suffix('x')?.parse() {
+            Ok(scale @ 1..=8) => Some(Box::new(Nearest(scale))),
+            _ => None,
+        },
+    }
+}
+
+// filters run in order, frontends may push their own
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn VideoFilter>>,
+}
+
+impl FilterChain {
+    pub fn push(&mut self, filter: Box<dyn VideoFilter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl VideoFilter for FilterChain {
+    fn name(&self) -> String {
+        self.filters.iter().map(|f| f.name()).collect::<Vec<_>>().join(",")
+    }
+    fn scale(&self) -> u32 {
+        self.filters.iter().map(|f| f.scale()).product()
+    }
+    fn apply(&self, input: &Image) -> Image {
+        let mut filters = self.filters.iter();
+        let Some(first) = filters.next() else {
+            return Image { width: input.width, height: input.height, rgba: input.rgba.clone() };
+        };
+        filters.fold(first.apply(input), |image, filter| filter.apply(&image))
+    }
+}
+
+impl PartialEq for FilterChain {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for FilterChain {}
+
+impl std::fmt::Debug for FilterChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FilterChain({})", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 3] = [0, 0, 0];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    // black staircase in the lower left
+    fn stairs() -> Image {
+        let shades = [3, 0, 0, 3, 3, 0, 3, 3, 3];
+        Image::from_indexed(&shades, 3, 3, &[WHITE, WHITE, WHITE, BLACK])
+    }
+
+    fn rows(image: &Image) -> Vec<String> {
+        let rgb = image.to_rgb();
+        let shades: Vec<char> = rgb.chunks_exact(3).map(|p| if p[0] == 0 { '#' } else if p[0] == 255 { '.' } else { '+' }).collect();
+        shades.chunks(image.width as usize).map(|row| row.iter().collect()).collect()
+    }
+
+    #[test]
+    fn scalers_round_off_diagonals() {
+        assert_eq!(rows(&Nearest(2).apply(&stairs())), ["##....", "##....", "####..", "####..", "######", "######"]);
+        assert_eq!(rows(&Scale2x.apply(&stairs())), ["##....", "###...", "###...", "#####.", "######", "######"]);
+        assert_eq!(rows(&Scale3x.apply(&stairs()))[1], "####.....");
+        // steps get a blended corner instead
+        assert_eq!(rows(&Hq2x.apply(&stairs())), ["##....", "##+...", "###+..", "####+.", "######", "######"]);
+    }
+
+    #[test]
+    fn chains_parse_and_multiply() {
+        let mut chain = FilterChain::default();
+        chain.push(builtin("scale2x").unwrap());
+        chain.push(builtin("nearest3x").unwrap());
+        assert_eq!((chain.name(), chain.scale()), ("scale2x,nearest3x".to_string(), 6));
+        let image = chain.apply(&stairs());
+        assert_eq!((image.width, image.height, image.rgba.len()), (18, 18, 18 * 18 * 4));
+        assert!(builtin("nearest9x").is_none() && builtin("xbrz").is_none());
+    }
+}
@@ -0,0 +1,203 @@
+// debug views of what the PPU draws from, rendered from live state: every tile
+// in VRAM, both background maps with the part on screen outlined, the 40 OAM
+// sprites and the three palettes, colored like the game screen
+//
+//   +-------+ +-----------+
+//   | tiles | | 9800 map  |
+//   | 16x24 | |           |
+//   +-------+ +-----------+
+//   | OAM   | | 9C00 map  |
+//   | 8x5   | |           |
+//   +-------+ |           |
+//   BGP OBP0  +-----------+
+//   OBP1
+
+use std::fmt::Write as _;
+
+use crate::palette::{PaletteSettings, Rgb};
+use crate::ppu::{tile_row, HEIGHT as SCREEN_HEIGHT, WIDTH as SCREEN_WIDTH};
+use crate::GB;
+
+const MAP_SIZE: usize = 256;
+// tiles in 16 columns of 24, the 384 tiles of [8000-97FF]
+const TILE_COLUMNS: usize = 16;
+const TILES: usize = 384;
+const OAM_Y: usize = 24 * 8 + GAP;
+// a cell holds an 8x16 sprite with a border
+const SPRITE_CELL: (usize, usize) = (16, 20);
+const PALETTE_Y: usize = OAM_Y + 5 * SPRITE_CELL.1 + GAP;
+const SWATCH: usize = 8;
+const MAP_X: usize = TILE_COLUMNS * 8 + GAP;
+const GAP: usize = 4;
+
+pub const WIDTH: usize = MAP_X + MAP_SIZE;
+pub const HEIGHT: usize = 2 * MAP_SIZE + GAP;
+
+const BACKGROUND: Rgb = [32, 32, 32];
+// behind transparent sprite pixels
+const CELL: Rgb = [64, 64, 64];
+const VIEWPORT: Rgb = [255, 0, 0];
+
+struct Canvas<'a> {
+    rgb: Vec<u8>,
+    palettes: &'a PaletteSettings,
+}
+
+impl Canvas<'_> {
+    fn set(&mut self, x: usize, y: usize, color: Rgb) {
+        let i = (y * WIDTH + x) * 3;
+        self.rgb[i..i + 3].copy_from_slice(&color);
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        for row in y..y + height {
+            for col in x..x + width {
+                self.set(col, row, color);
+            }
+        }
+    }
+
+    // color 0-3 through the DMG palette register palette
+    fn shade(&self, palette: u8, color: u8) -> Rgb {
+        self.palettes.dmg().shades[(palette >> (color * 2) & 0x03) as usize]
+    }
+
+    // 8 pixels of a tile row, color 0 left out when transparent
+    fn row(&mut self, (lo, hi): (u8, u8), x: usize, y: usize, palette: u8, transparent: bool) {
+        for px in 0..8 {
+            let bit = 7 - px;
+            let color = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+            if color != 0 || !transparent {
+                let rgb = self.shade(palette, color);
+                self.set(x + px, y, rgb);
+            }
+        }
+    }
+}
+
+// low and high byte of the tile row at graphics[addr], mirrored for X flip
+fn tile_data(gb: &GB, addr: usize, flip: bool) -> (u8, u8) {
+    let (lo, hi) = (gb.mmu.graphics[addr], gb.mmu.graphics[addr + 1]);
+    match flip {
+        true => (lo.reverse_bits(), hi.reverse_bits()),
+        false => (lo, hi),
+    }
+}
+
+// packed RGB, WIDTH x HEIGHT
+pub fn render(gb: &GB, palettes: &PaletteSettings) -> Vec<u8> {
+    let mut canvas = Canvas { rgb: BACKGROUND.repeat(WIDTH * HEIGHT), palettes };
+    let io = &gb.mmu.io;
+    let (lcdc, bgp) = (io[0x40], io[0x47]);
+    for tile in 0..TILES {
+        let (x, y) = (tile % TILE_COLUMNS * 8, tile / TILE_COLUMNS * 8);
+        for line in 0..8 {
+            canvas.row(tile_data(gb, tile * 16 + line * 2, false), x, y + line, bgp, false);
+        }
+    }
+    for (i, sprite) in gb.mmu.sprites.chunks(4).enumerate() {
+        let (x, y) = (i % 8 * SPRITE_CELL.0, OAM_Y + i / 8 * SPRITE_CELL.1);
+        canvas.fill(x + 1, y + 1, SPRITE_CELL.0 - 2, SPRITE_CELL.1 - 2, CELL);
+        let (tile, attrs) = (sprite[2] as usize, sprite[3]);
+        let palette = if attrs & 0x10 != 0 { io[0x49] } else { io[0x48] };
+        // 8x16 pairs, shown whole whatever LCDC says
+        let height = if lcdc & 0x04 != 0 { 16 } else { 8 };
+        let first = if height == 16 { tile & 0xfe } else { tile };
+        for line in 0..height {
+            let row = if attrs & 0x40 != 0 { height - 1 - line } else { line };
+            let data = tile_data(gb, first * 16 + row * 2, attrs & 0x20 != 0);
+            canvas.row(data, x + 4, y + 2 + line, palette, true);
+        }
+    }
+    for (row, palette) in [bgp, io[0x48], io[0x49]].into_iter().enumerate() {
+        for color in 0..4 {
+            let rgb = canvas.shade(palette, color as u8);
+            canvas.fill(color * (SWATCH + 2), PALETTE_Y + row * (SWATCH + 2), SWATCH, SWATCH, rgb);
+        }
+    }
+    for (map, base) in [0x1800, 0x1c00].into_iter().enumerate() {
+        let top = map * (MAP_SIZE + GAP);
+        for y in 0..MAP_SIZE {
+            for column in 0..32 {
+                let tile = gb.mmu.graphics[base + y / 8 * 32 + column];
+                let data = tile_data(gb, tile_row(lcdc, tile, y as u8), false);
+                canvas.row(data, MAP_X + column * 8, top + y, bgp, false);
+            }
+        }
+        // LCDC bit 3 - BG tile map, the screen shows SCX, SCY on, wrapping around
+        if (lcdc & 0x08 != 0) == (map == 1) {
+            let (scx, scy) = (io[0x43] as usize, io[0x42] as usize);
+            for i in 0..SCREEN_WIDTH {
+                let x = MAP_X + (scx + i) % MAP_SIZE;
+                canvas.set(x, top + scy, VIEWPORT);
+                canvas.set(x, top + (scy + SCREEN_HEIGHT - 1) % MAP_SIZE, VIEWPORT);
+            }
+            for i in 0..SCREEN_HEIGHT {
+                let y = top + (scy + i) % MAP_SIZE;
+                canvas.set(MAP_X + scx, y, VIEWPORT);
+                canvas.set(MAP_X + (scx + SCREEN_WIDTH - 1) % MAP_SIZE, y, VIEWPORT);
+            }
+        }
+    }
+    canvas.rgb
+}
+
+// OAM as text, a line per sprite with position, tile and attributes
+pub fn sprite_list(gb: &GB) -> String {
+    let mut out = String::new();
+    for (i, sprite) in gb.mmu.sprites.chunks(4).enumerate() {
+        let (y, x, tile, attrs) = (sprite[0], sprite[1], sprite[2], sprite[3]);
+        let flags: Vec<&str> = [(0x80, "behind"), (0x40, "yflip"), (0x20, "xflip"), (0x10, "obp1")]
+            .into_iter()
+            .filter(|&(bit, _)| attrs & bit != 0)
+            .map(|(_, name)| name)
+            .collect();
+        // OAM keeps X + 8 and Y + 16, so a sprite can be partly or fully off screen
+        let on_screen = (1..160).contains(&y) && (1..168).contains(&x);
+        writeln!(
+            out,
+            "{:2}  x {:3} y {:3}  tile {:02X}  {}{}",
+            i,
+            x as i16 - 8,
+            y as i16 - 16,
+            tile,
+            flags.join(" "),
+            if on_screen { "" } else { " (off screen)" }
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::micro_rom;
+
+    #[test]
+    fn renders_tiles_maps_and_sprites() {
+        let rom = micro_rom("nop");
+        let mut gb = GB::new(&rom).unwrap();
+        let palettes = PaletteSettings::default();
+        let shades = palettes.dmg().shades;
+        gb.mmu.io[0x40] = 0x91;
+        gb.mmu.io[0x47] = 0xe4;
+        // tile 1 all color 3, on the 9800 map at column 2
+        gb.mmu.graphics[16..32].fill(0xff);
+        gb.mmu.graphics[0x1802] = 1;
+        gb.mmu.sprites[..4].copy_from_slice(&[16, 8, 1, 0x00]);
+        gb.mmu.io[0x48] = 0x40;
+        let rgb = render(&gb, &palettes);
+        assert_eq!(rgb.len(), WIDTH * HEIGHT * 3);
+        let pixel = |x: usize, y: usize| -> Rgb { rgb[(y * WIDTH + x) * 3..][..3].try_into().unwrap() };
+        assert_eq!((pixel(8, 0), pixel(0, 0)), (shades[3], shades[0]));
+        assert_eq!(pixel(MAP_X + 16 + 1, 1), shades[3]);
+        // viewport at SCX = SCY = 0 on the 9800 map, not on 9C00
+        assert_eq!((pixel(MAP_X, 0), pixel(MAP_X + 159, 143)), (VIEWPORT, VIEWPORT));
+        assert_ne!(pixel(MAP_X, MAP_SIZE + GAP), VIEWPORT);
+        // sprite 0 through OBP0, color 3 is shade 1
+        assert_eq!(pixel(4, OAM_Y + 2), shades[1]);
+        assert_eq!(pixel(4, OAM_Y + 2 + 8), CELL);
+        assert!(sprite_list(&gb).starts_with(" 0  x   0 y   0  tile 01  \n 1  x  -8 y -16  tile 00   (off screen)\n"));
+    }
+}